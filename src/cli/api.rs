@@ -0,0 +1,660 @@
+//! The control API `craterun api` serves on a Unix socket, and the client
+//! `--host` sends commands to it with.
+//!
+//! The protocol is JSON-RPC 2.0 with one message per line. Each connection
+//! is served by a process of its own, so a request that blocks, such as
+//! `wait` or following logs, only holds up its own connection.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use nix::sys::signal::{signal, SigHandler, SIGCHLD};
+use nix::sys::stat::Mode;
+use nix::unistd::{fork, ForkResult};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::commands::{self, FollowedService, FOLLOW_INTERVAL};
+use super::output::{self, ContainerSummary, LogLine, OutputFormat, PsEntry};
+use super::spec::RunSpec;
+use super::Command;
+use crate::core::error;
+use crate::core::logs::{self, LogOptions, LogView};
+use crate::core::model::{ContainerMeta, ContainerStatus};
+use crate::core::state;
+
+/// Socket `craterun api` listens on unless told otherwise.
+pub const DEFAULT_SOCKET: &str = "/run/craterun.sock";
+/// Scheme of `--host` addresses, the only kind there is.
+const UNIX_SCHEME: &str = "unix://";
+
+/// JSON-RPC error codes.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Code of errors craterun reports for a valid request, such as a container
+/// that does not exist.
+const COMMAND_FAILED: i64 = -32000;
+/// Method of the notifications carrying the lines of a `logs` request.
+const LOG_NOTIFICATION: &str = "logs.line";
+
+/// Parse a `--host` address, `unix://PATH`, into the path of the socket.
+pub fn parse_host(s: &str) -> Result<PathBuf, String> {
+    match s.strip_prefix(UNIX_SCHEME) {
+        Some(path) if path.starts_with('/') => Ok(PathBuf::from(path)),
+        _ => Err(format!("expected {UNIX_SCHEME}/PATH/TO/SOCKET, got '{s}'")),
+    }
+}
+
+// ─── server ─────────────────────────────────────────────────────────────────
+
+/// A JSON-RPC request.
+#[derive(Debug, Deserialize)]
+struct Request {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// A JSON-RPC error: a code and a message, with the error chain as data
+/// for failed commands.
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        RpcError {
+            code: COMMAND_FAILED,
+            message: format!("{e:#}"),
+            data: Some(error::error_json(&e)),
+        }
+    }
+}
+
+/// Serve the API on `socket` until killed. The socket is only accessible
+/// to its owner; loosen its permissions to let others in.
+pub fn serve(socket: &Path) -> Result<()> {
+    if UnixStream::connect(socket).is_ok() {
+        bail!("{} is already being served", socket.display());
+    }
+    match fs::symlink_metadata(socket) {
+        Ok(meta) if meta.file_type().is_socket() => fs::remove_file(socket)
+            .with_context(|| format!("failed to remove stale {}", socket.display()))?,
+        Ok(_) => bail!("{} exists and is not a socket", socket.display()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("failed to stat {}", socket.display())),
+    }
+    // Created under a umask that leaves it to its owner alone, so that it is
+    // never open to others, not even until its permissions are set.
+    let umask = nix::sys::stat::umask(Mode::from_bits_truncate(0o177));
+    let listener = UnixListener::bind(socket);
+    nix::sys::stat::umask(umask);
+    let listener = listener.with_context(|| format!("failed to listen on {}", socket.display()))?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("failed to restrict {}", socket.display()))?;
+    state::ensure_state_dir()?;
+
+    // Connection handlers are reaped by the kernel.
+    // SAFETY: no handler of SIGCHLD is replaced, only its disposition.
+    unsafe { signal(SIGCHLD, SigHandler::SigIgn) }.context("failed to ignore SIGCHLD")?;
+    eprintln!("craterun api: listening on {}", socket.display());
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("craterun api: failed to accept a connection: {e}");
+                continue;
+            }
+        };
+        // SAFETY: the server is single-threaded, so the child may do anything.
+        match unsafe { fork() } {
+            Ok(ForkResult::Parent { .. }) => drop(stream),
+            Ok(ForkResult::Child) => {
+                drop(listener);
+                // Monitors of containers started here wait for them.
+                // SAFETY: as above.
+                let _ = unsafe { signal(SIGCHLD, SigHandler::SigDfl) };
+                let code = match serve_connection(stream) {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        eprintln!("craterun api: {e:#}");
+                        1
+                    }
+                };
+                std::process::exit(code);
+            }
+            Err(e) => eprintln!("craterun api: failed to fork a connection handler: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Answer the requests of a connection, one per line, until it closes.
+fn serve_connection(stream: UnixStream) -> Result<()> {
+    let mut out = stream.try_clone().context("failed to clone connection")?;
+    for line in BufReader::new(stream).lines() {
+        let line = line.context("failed to read request")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, result) = match serde_json::from_str::<Value>(&line) {
+            Err(e) => (Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string()))),
+            Ok(message) => match message.get("id").cloned() {
+                Some(id) => (id, answer(message, &mut out)),
+                // A notification is carried out without a word back, unless
+                // it is no request at all.
+                None => match answer(message, &mut io::sink()) {
+                    Err(e) if e.code == INVALID_REQUEST => (Value::Null, Err(e)),
+                    _ => continue,
+                },
+            },
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => {
+                let mut error = json!({ "code": e.code, "message": e.message });
+                if let Some(data) = e.data {
+                    error["data"] = data;
+                }
+                json!({ "jsonrpc": "2.0", "id": id, "error": error })
+            }
+        };
+        send(&mut out, &response)?;
+    }
+    Ok(())
+}
+
+/// Write `message` as a line.
+fn send(out: &mut impl Write, message: &Value) -> Result<()> {
+    writeln!(out, "{message}")
+        .and_then(|()| out.flush())
+        .context("failed to send response")
+}
+
+/// Carry out the request `message`, sending any notifications it makes to
+/// `out`, and return its result.
+fn answer(message: Value, out: &mut impl Write) -> Result<Value, RpcError> {
+    let request: Request = serde_json::from_value(message)
+        .map_err(|e| RpcError::new(INVALID_REQUEST, e.to_string()))?;
+    if request.jsonrpc != "2.0" {
+        return Err(RpcError::new(
+            INVALID_REQUEST,
+            "expected \"jsonrpc\": \"2.0\"",
+        ));
+    }
+    let params = request.params;
+    match request.method.as_str() {
+        "run" => Ok(api_run(parse_params(params)?)?),
+        "ps" => Ok(api_ps(parse_params(params)?)?),
+        "inspect" => {
+            let IdParams { id } = parse_params(params)?;
+            let meta = commands::inspected_meta(&id)?;
+            Ok(to_value(&commands::container_details(&meta)?)?)
+        }
+        "stop" => Ok(api_stop(parse_params(params)?)?),
+        "rm" => Ok(api_rm(parse_params(params)?)?),
+        "logs" => Ok(api_logs(parse_params(params)?, out)?),
+        "wait" => {
+            let IdParams { id } = parse_params(params)?;
+            Ok(api_wait(&id)?)
+        }
+        method => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method '{method}'"),
+        )),
+    }
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, RpcError> {
+    // Methods whose parameters are all optional may be called without any.
+    let params = match params {
+        Value::Null => json!({}),
+        params => params,
+    };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn to_value(value: &impl serde::Serialize) -> Result<Value> {
+    serde_json::to_value(value).context("failed to serialize result")
+}
+
+/// Parameters naming a container by ID, prefix, name or `latest`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IdParams {
+    id: String,
+}
+
+/// Parameters of `run`: the container's configuration, with the keys of a
+/// `run --config` file, and its name.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RunParams {
+    config: Value,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// Start a container in the background, returning `{"id": ...}` once it
+/// runs. Relative paths are relative to the server's working directory.
+fn api_run(params: RunParams) -> Result<Value> {
+    let spec = RunSpec::parse(params.config).context("invalid `config`")?;
+    if spec.cmd.is_empty() {
+        bail!("no `cmd` to run in `config`");
+    }
+    if let Some(name) = &params.name {
+        state::validate_container_name(name)?;
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        bail!("craterun only runs on Linux");
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let dir = std::env::current_dir().context("failed to get working directory")?;
+        let mut args = commands::spec_args(spec, &dir)?;
+        args.name = params.name;
+        args.quiet = true;
+        crate::platform::linux::check::preflight()?;
+        let id = commands::start_detached(args, None)?;
+        Ok(json!({ "id": id }))
+    }
+}
+
+/// Parameters of `ps`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PsParams {
+    /// Save the status of containers found to have exited, as `ps --sync`.
+    #[serde(default)]
+    sync: bool,
+}
+
+/// List the containers as `inspect` shows them, by ID. Broken containers
+/// are left out.
+fn api_ps(params: PsParams) -> Result<Value> {
+    let ids = state::list_containers()?;
+    let mut metas = Vec::with_capacity(ids.len());
+    for mut meta in state::load_metas(&ids).into_iter().filter_map(Result::ok) {
+        if params.sync {
+            state::refresh_status(&mut meta)?;
+        } else {
+            state::update_status(&mut meta)?;
+        }
+        metas.push(meta);
+    }
+    let details = metas
+        .iter()
+        .map(commands::container_details)
+        .collect::<Result<Vec<_>>>()?;
+    to_value(&details)
+}
+
+/// Parameters of `stop`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StopParams {
+    id: String,
+    /// Seconds to wait after the stop signal before sending SIGKILL.
+    #[serde(default = "default_stop_timeout")]
+    timeout: u64,
+}
+
+fn default_stop_timeout() -> u64 {
+    10
+}
+
+fn api_stop(params: StopParams) -> Result<Value> {
+    let id = state::resolve_id(&params.id)?;
+    let result = commands::stop_running(&id, Duration::from_secs(params.timeout))?;
+    Ok(json!({ "id": id, "result": result }))
+}
+
+/// Parameters of `rm`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RmParams {
+    id: String,
+    #[serde(default)]
+    force: bool,
+}
+
+fn api_rm(params: RmParams) -> Result<Value> {
+    let id = state::resolve_id(&params.id)?;
+    commands::remove_container(&id, params.force)?;
+    Ok(json!({ "id": id, "result": "removed" }))
+}
+
+/// Parameters of `logs`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LogsParams {
+    id: String,
+    /// Only send stdout.
+    #[serde(default)]
+    stdout: bool,
+    /// Only send stderr.
+    #[serde(default)]
+    stderr: bool,
+    /// Only send the last N lines of each stream.
+    #[serde(default)]
+    tail: Option<usize>,
+    /// Keep sending new lines until the container exits.
+    #[serde(default)]
+    follow: bool,
+}
+
+/// Send the container's log lines as `logs.line` notifications,
+/// `{"stream": ..., "line": ...}`, stdout's first, then return
+/// `{"lines": N}`.
+fn api_logs(params: LogsParams, out: &mut impl Write) -> Result<Value> {
+    if params.stdout && params.stderr {
+        bail!("`stdout` and `stderr` cannot both be set");
+    }
+    let id = state::resolve_id(&params.id)?;
+    let mut lines = 0;
+    let mut emit = |stream: usize, line: &str| {
+        let stream = ["stdout", "stderr"][stream];
+        if (stream == "stdout" && params.stderr) || (stream == "stderr" && params.stdout) {
+            return Ok(());
+        }
+        lines += 1;
+        let message = json!({
+            "jsonrpc": "2.0",
+            "method": LOG_NOTIFICATION,
+            "params": LogLine { stream, line },
+        });
+        send(out, &message)
+    };
+
+    // Following without a tail reads the logs from their start as they
+    // grow. Otherwise the lines asked for are read at once, and following
+    // picks up from where the logs ended before.
+    let from_start = params.follow && params.tail.is_none();
+    let followed = match params.follow {
+        true => Some(FollowedService::new(&id, &id, from_start)?),
+        false => None,
+    };
+    if !from_start {
+        let opts = LogOptions {
+            tail: params.tail,
+            ..LogOptions::default()
+        };
+        let LogView::Separate { stdout, stderr } = logs::read_logs(&id, opts)? else {
+            unreachable!("logs are only combined when asked to");
+        };
+        for (stream, text) in [stdout, stderr].into_iter().enumerate() {
+            for line in text.as_deref().unwrap_or_default().lines() {
+                emit(stream, line)?;
+            }
+        }
+    }
+    if let Some(mut followed) = followed {
+        loop {
+            let running = followed.is_running();
+            followed.read_new_lines(!running, &mut emit)?;
+            if !running {
+                break;
+            }
+            std::thread::sleep(FOLLOW_INTERVAL);
+        }
+    }
+    Ok(json!({ "lines": lines }))
+}
+
+/// Wait for the container to stop, then return `{"id", "exit_code"}`.
+fn api_wait(id_prefix: &str) -> Result<Value> {
+    let id = state::resolve_id(id_prefix)?;
+    loop {
+        let mut meta = state::load_meta(&id)?;
+        state::refresh_status(&mut meta)?;
+        if meta.status != ContainerStatus::Running {
+            return Ok(json!({ "id": id, "exit_code": meta.exit_code }));
+        }
+        std::thread::sleep(FOLLOW_INTERVAL);
+    }
+}
+
+// ─── client ─────────────────────────────────────────────────────────────────
+
+/// A connection to a `craterun api` server.
+struct Client {
+    stream: BufReader<UnixStream>,
+    next_id: u64,
+}
+
+impl Client {
+    fn connect(socket: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(socket)
+            .with_context(|| format!("failed to connect to {UNIX_SCHEME}{}", socket.display()))?;
+        Ok(Client {
+            stream: BufReader::new(stream),
+            next_id: 1,
+        })
+    }
+
+    /// Call `method` and return its result, passing the parameters of the
+    /// notifications sent before it to `on_notification`.
+    fn call(
+        &mut self,
+        method: &str,
+        params: Value,
+        mut on_notification: impl FnMut(Value) -> Result<()>,
+    ) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        send(self.stream.get_mut(), &request)?;
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = self
+                .stream
+                .read_line(&mut line)
+                .context("failed to read response")?;
+            if read == 0 {
+                bail!("the server closed the connection");
+            }
+            let mut message: Value =
+                serde_json::from_str(&line).context("failed to parse response")?;
+            if message.get("id").is_none() {
+                on_notification(message["params"].take())?;
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                bail!("{}", error["message"].as_str().unwrap_or("request failed"));
+            }
+            return Ok(message["result"].take());
+        }
+    }
+}
+
+/// Carry out `command` through the server on `socket`, printing its output
+/// as the command does locally. Only ps, inspect, stop, rm and logs can be.
+pub fn dispatch_remote(socket: &Path, command: Command, format: OutputFormat) -> Result<()> {
+    let mut client = Client::connect(socket)?;
+    let ignore = |_| Ok(());
+    match command {
+        Command::Ps {
+            summary,
+            sync,
+            sort,
+            reverse,
+            last,
+            no_humanize,
+        } => {
+            if summary {
+                bail!("ps --summary cannot be used with --host");
+            }
+            let listing = client.call("ps", json!({ "sync": sync }), ignore)?;
+            let metas: Vec<ContainerMeta> =
+                serde_json::from_value(listing).context("invalid ps result")?;
+            let mut entries: Vec<_> = metas.into_iter().map(Ok).collect();
+            commands::sort_ps_entries(&mut entries, sort, reverse);
+            entries.truncate(last.unwrap_or(usize::MAX));
+            let now = Utc::now();
+            if format == OutputFormat::Json {
+                let containers: Vec<_> = entries
+                    .iter()
+                    .flatten()
+                    .map(|meta| PsEntry::Container(ContainerSummary::new(meta, now)))
+                    .collect();
+                return output::print_json(&containers);
+            }
+            commands::print_ps_header();
+            for entry in &entries {
+                commands::print_ps_row(entry, now, !no_humanize);
+            }
+            Ok(())
+        }
+        Command::Inspect {
+            ids,
+            latest,
+            strict,
+        } => {
+            let ids = if latest {
+                vec![state::LATEST.to_string()]
+            } else {
+                ids
+            };
+            if let [id] = &ids[..] {
+                let details = client.call("inspect", json!({ "id": id }), ignore)?;
+                return output::print_json(&details);
+            }
+            let mut found = Vec::new();
+            for id in &ids {
+                match client.call("inspect", json!({ "id": id }), ignore) {
+                    Ok(details) => found.push(details),
+                    Err(e) if !strict => eprintln!("warning: leaving out '{id}': {e:#}"),
+                    Err(e) => return Err(e),
+                }
+            }
+            output::print_json(&found)
+        }
+        Command::Stop { containers, time } => {
+            let Some(id) = containers.as_str() else {
+                bail!("stop --all cannot be used with --host");
+            };
+            let result = client.call("stop", json!({ "id": id, "timeout": time }), ignore)?;
+            if format == OutputFormat::Json {
+                return output::print_json(&result);
+            }
+            let id = result["id"].as_str().unwrap_or("?");
+            match result["result"].as_str() {
+                Some("killed") => println!("Killed container {id} after {time}s"),
+                _ => println!("Stopped container {id}"),
+            }
+            Ok(())
+        }
+        Command::Rm {
+            ids,
+            latest,
+            filter,
+            force,
+        } => {
+            if !filter.is_empty() {
+                bail!("rm --filter cannot be used with --host");
+            }
+            let mut ids = ids;
+            if latest {
+                ids.push(state::LATEST.to_string());
+            }
+            let mut results = Vec::new();
+            for id in &ids {
+                results.push(client.call("rm", json!({ "id": id, "force": force }), ignore)?);
+            }
+            match format {
+                OutputFormat::Json if results.len() == 1 => output::print_json(&results[0]),
+                OutputFormat::Json => output::print_json(&results),
+                OutputFormat::Text => {
+                    for result in &results {
+                        println!("Removed container {}", result["id"].as_str().unwrap_or("?"));
+                    }
+                    Ok(())
+                }
+            }
+        }
+        Command::Logs {
+            container,
+            stdout,
+            stderr,
+            combine,
+            tail,
+            clear,
+        } => {
+            if clear {
+                bail!("logs --clear cannot be used with --host");
+            }
+            if combine && tail.is_some() {
+                bail!("logs --combine --tail cannot be used with --host");
+            }
+            let params = json!({
+                "id": container.as_str(),
+                "stdout": stdout,
+                "stderr": stderr,
+                "tail": tail,
+            });
+            client.call("logs", params, |line| {
+                match format {
+                    OutputFormat::Json => println!("{line}"),
+                    OutputFormat::Text => {
+                        let text = line["line"].as_str().unwrap_or_default();
+                        match line["stream"].as_str() {
+                            Some("stderr") if !combine => eprintln!("{text}"),
+                            _ => println!("{text}"),
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+            Ok(())
+        }
+        _ => bail!("only ps, inspect, stop, rm and logs can be used with --host"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts() {
+        assert_eq!(
+            parse_host("unix:///run/craterun.sock").unwrap(),
+            PathBuf::from("/run/craterun.sock")
+        );
+        for bad in [
+            "/run/craterun.sock",
+            "unix://run/craterun.sock",
+            "tcp://localhost:2375",
+        ] {
+            assert!(parse_host(bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -1,183 +1,2293 @@
+use std::cmp::Ordering;
 use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Mutex;
+use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
 
-use crate::cli::{Cli, Command};
-use crate::core::model::{ContainerConfig, ContainerStatus};
-use crate::core::state;
+use crate::cli::api;
+use crate::cli::output::{
+    self, BrokenContainer, ChangeLine, ContainerCounts, ContainerDetails, ContainerResult,
+    ContainerSummary, ContainerUsage, ContainersUsage, DiskUsageReport, ExecSummary, ImageResult,
+    ImageSummary, ImageUsage, ImagesUsage, LogLine, NetworkResult, NetworkSummary, OutputFormat,
+    PruneResult, PsEntry, SystemInfo,
+};
+use crate::cli::project::{Project, Service};
+use crate::cli::spec::RunSpec;
+use crate::cli::{
+    Cli, Command, ConfigCommand, ContainerSelection, LimitArgs, NetworkCommand, PsSort, RunArgs,
+    SystemCommand,
+};
+use crate::core::model::{
+    ContainerConfig, ContainerExit, ContainerFilter, ContainerMeta, ContainerStatus, HealthCheck,
+    NamespaceKind, NamespaceMode, PidsLimit, ResourceLimits, ServiceLabel, StorageLimit, Subnet,
+    Ulimit, UlimitKind, DEFAULT_HOSTNAME, DEFAULT_PATH,
+};
+use crate::core::cmdfile;
+use crate::core::env::{self, EnvSources};
+use crate::core::error::NewerSchemaError;
+use crate::core::exec;
+use crate::core::image;
+use crate::core::ipam;
+use crate::core::logs::{self, LogOptions, LogView};
+use crate::core::network;
+use crate::core::registry::{self, Layer, PullPolicy, Reference};
+use crate::core::state::{self, STDERR_LOG, STDOUT_LOG};
+use crate::util::units::{self, ByteSize};
 
 /// Dispatch a parsed CLI command to the appropriate handler.
 pub fn dispatch(cli: Cli) -> Result<()> {
+    let format = cli.format;
+    if let Some(socket) = &cli.host {
+        return api::dispatch_remote(socket, cli.command, format);
+    }
     match cli.command {
-        Command::Run {
-            rootfs,
-            memory,
-            cpu,
-            pids,
-            uid,
-            gid,
-            hostname,
+        Command::Run(args) => cmd_run(*args),
+        Command::Ps {
+            summary,
+            sync,
+            sort,
+            reverse,
+            last,
+            no_humanize,
+        } => cmd_ps(
+            PsOptions {
+                summary,
+                sync,
+                sort,
+                reverse,
+                last,
+                humanize: !no_humanize,
+            },
+            format,
+        ),
+        Command::Rm {
+            mut ids,
+            latest,
+            filter,
+            force,
+        } => {
+            if latest {
+                ids.push(state::LATEST.to_string());
+            }
+            cmd_rm(&ids, &filter, force, format)
+        }
+        Command::Prune { broken } => cmd_prune(broken, format),
+        Command::Logs {
+            container,
+            clear: true,
+            ..
+        } => cmd_logs_clear(container.as_str(), format),
+        Command::Logs {
+            container,
+            stdout,
+            stderr,
+            combine,
+            tail,
+            clear: false,
+        } => cmd_logs(
+            container.as_str(),
+            LogOptions {
+                stdout_only: stdout,
+                stderr_only: stderr,
+                combine,
+                tail,
+            },
+            format,
+        ),
+        Command::Inspect {
+            ids,
+            latest,
+            strict,
+        } => {
+            let ids = if latest {
+                vec![state::LATEST.to_string()]
+            } else {
+                ids
+            };
+            cmd_inspect(&ids, strict)
+        }
+        Command::Diff { container } => cmd_diff(container.as_str(), format),
+        Command::Check { json } => cmd_check(json || format == OutputFormat::Json),
+        Command::Update { container, limits } => {
+            cmd_update(container.as_str(), resource_limits(limits), format)
+        }
+        Command::Import {
+            tarball,
+            name,
+            verify,
+        } => cmd_import(&tarball, &name, verify.as_deref(), format),
+        Command::Commit { container, name } => cmd_commit(&container, &name, format),
+        Command::Pull { reference, name } => cmd_pull(&reference, name.as_deref(), format),
+        Command::Images => cmd_images(format),
+        Command::Rmi { name } => cmd_rmi(&name, format),
+        Command::Network { command } => match command {
+            NetworkCommand::Create { name, subnet } => cmd_network_create(&name, subnet, format),
+            NetworkCommand::Ls => cmd_network_ls(format),
+            NetworkCommand::Rm { name, force } => cmd_network_rm(&name, force, format),
+        },
+        Command::System { command } => match command {
+            SystemCommand::Df {
+                detailed,
+                max_log_size,
+            } => cmd_system_df(detailed, max_log_size.as_ref(), format),
+            SystemCommand::Info => cmd_system_info(format),
+        },
+        Command::Config { command } => match command {
+            ConfigCommand::Validate { path } => cmd_config_validate(&path),
+            ConfigCommand::Show { container } => cmd_config_show(container.as_str(), format),
+        },
+        Command::Up { file, detach } => cmd_up(&file, detach),
+        Command::Down { file, time } => cmd_down(&file, Duration::from_secs(time)),
+        Command::Api { socket } => api::serve(&socket),
+        Command::Stop { containers, time } => {
+            cmd_stop(&containers, Duration::from_secs(time), format)
+        }
+        Command::Kill { containers } => cmd_kill(&containers, format),
+        Command::Exec {
+            container,
+            detach,
+            list,
             cmd,
-        } => cmd_run(ContainerConfig {
-            rootfs,
+        } => cmd_exec(container.as_str(), &cmd, detach, list, format),
+    }
+}
+
+// ─── run ────────────────────────────────────────────────────────────────────
+
+/// Options that affect how `run` behaves but are not part of the container's
+/// configuration.
+struct RunOptions {
+    dry_run: bool,
+    quiet: bool,
+    cidfile: Option<PathBuf>,
+    pidfile: Option<PathBuf>,
+}
+
+/// Split parsed `run` arguments into the container config and run options,
+/// picking the container's environment from the host's.
+fn run_config(mut args: RunArgs) -> Result<(ContainerConfig, RunOptions)> {
+    if let Some(path) = args.config.clone() {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        RunSpec::load(&path)?
+            .apply(&mut args, dir)
+            .with_context(|| format!("invalid {}", path.display()))?;
+        if args.cmd.is_empty() && args.cmd_file.is_none() {
+            bail!(
+                "no command to run: give it after --, with --cmd-file or as `cmd` in {}",
+                path.display()
+            );
+        }
+    }
+    for var in &args.preserve_env {
+        env::validate_env_name(var).context("invalid --preserve-env")?;
+    }
+    if let Some(name) = &args.name {
+        if let Some(id) = state::find_named(name)? {
+            bail!("the name '{name}' is already used by container {id}");
+        }
+    }
+    let ulimits = ulimits(&args)?;
+    let cmd = match &args.cmd_file {
+        Some(path) => {
+            if args.attach_stdin && path.as_os_str() == cmdfile::STDIN_PATH {
+                bail!(
+                    "--cmd-file - reads the command from stdin, so stdin cannot also be \
+                     attached with --attach-stdin"
+                );
+            }
+            cmdfile::read_cmd_file(path, args.null)?
+        }
+        None => args.cmd,
+    };
+    let sources = EnvSources {
+        preserve: args.preserve_env,
+        preserve_all: args.preserve_env_all,
+        env_files: args.env_files,
+        env: args.env,
+    };
+    let env = env::resolve_env(Vec::new(), std::env::vars(), &sources)?;
+
+    let hostname = match args.hostname {
+        Some(_) if !args.uts.is_new() => {
+            bail!("--hostname cannot be set with --uts {}", args.uts)
+        }
+        Some(hostname) => hostname,
+        None => shared_hostname(&args.uts)?,
+    };
+
+    let rootfs = match &args.image {
+        Some(reference) => image_rootfs(reference, args.pull, args.quiet)?,
+        None => args
+            .rootfs
+            .context("--rootfs or --image is required, or `rootfs` or `image` in the --config")?,
+    };
+
+    let config = ContainerConfig {
+        name: args.name,
+        service: None,
+        rootfs,
+        cmd,
+        argv0: args.argv0,
+        init_path: args.init_path,
+        hostname,
+        domainname: args.domainname,
+        limits: ResourceLimits {
+            pids: args.limits.pids.or(Some(PidsLimit::DEFAULT)),
+            ..resource_limits(args.limits)
+        },
+        ulimits,
+        oom_score_adj: args.oom_score_adj,
+        umask: args.umask,
+        timezone: args.timezone,
+        devices: args.devices,
+        tmpfs: args.tmpfs,
+        shm_size: args.shm_size.map(|size| size.bytes),
+        storage: args.storage_size.map(|size| StorageLimit {
+            size: size.bytes,
+            driver: args.storage_driver,
+        }),
+        mount_propagation: args.mount_propagation,
+        proc_opts: args.proc_opts.unwrap_or_default(),
+        keyring: args.keyring,
+        userns: args.userns,
+        cgroup_ns: args.cgroup_ns,
+        sysctls: args.sysctls,
+        env,
+        env_host: sources.preserve_all,
+        user: args.user,
+        net: if args.network.is_some() {
+            NamespaceMode::Bridge
+        } else {
+            args.net
+        },
+        pid_ns: args.pid,
+        ipc: args.ipc,
+        uts: args.uts,
+        network: args.network,
+        ip: args.ip,
+        mac_address: args.mac_address,
+        ports: args.publish,
+        health: args.health_cmd.map(|cmd| HealthCheck {
             cmd,
-            hostname,
-            memory,
-            cpu,
-            pids,
-            uid,
-            gid,
+            interval: args.health_interval,
+            retries: args.health_retries,
         }),
-        Command::Ps => cmd_ps(),
-        Command::Rm { id, force } => cmd_rm(&id, force),
-        Command::Logs { id } => cmd_logs(&id),
-        Command::Inspect { id } => cmd_inspect(&id),
-        Command::Exec { id, cmd } => cmd_exec(&id, &cmd),
+        stop_signal: args.stop_signal,
+        no_pivot: args.no_pivot,
+        allow_chroot_fallback: args.allow_chroot_fallback,
+        no_preflight: args.no_preflight,
+        attach_stdin: args.attach_stdin,
+        uid: args.uid,
+        gid: args.gid,
+        invocation: std::env::args_os()
+            .skip(1)
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect(),
+    };
+    let opts = RunOptions {
+        dry_run: args.dry_run,
+        quiet: args.quiet,
+        cidfile: args.cidfile,
+        pidfile: args.pidfile,
+    };
+    Ok((config, opts))
+}
+
+/// The hostname a container sees in the UTS namespace selected by `uts`.
+fn shared_hostname(uts: &NamespaceMode) -> Result<String> {
+    match uts {
+        NamespaceMode::Private | NamespaceMode::Bridge | NamespaceMode::Slirp => {
+            Ok(DEFAULT_HOSTNAME.to_string())
+        }
+        NamespaceMode::Host => {
+            let hostname = nix::unistd::gethostname().context("failed to get the hostname")?;
+            Ok(hostname.to_string_lossy().into_owned())
+        }
+        NamespaceMode::Container(prefix) => {
+            let id = state::resolve_id(prefix).context("--uts")?;
+            Ok(state::load_meta(&id)?.hostname)
+        }
+    }
+}
+
+/// The `--ulimit`s, with `--no-core` or `--core-limit` added as a `core`
+/// limit.
+/// The rootfs of `run --image REF`, pulling the image as `pull` says.
+fn image_rootfs(reference: &str, pull: PullPolicy, quiet: bool) -> Result<String> {
+    let progress = !quiet && std::io::stderr().is_terminal();
+    state::ensure_state_dir()?;
+    let image = registry::resolve_image(reference, pull, |i, count, layer| {
+        if progress {
+            print_layer_progress(i, count, layer);
+        }
+    })?;
+    Ok(image.rootfs.display().to_string())
+}
+
+/// Tell the user on stderr which layer of a pull is being downloaded.
+fn print_layer_progress(i: usize, count: usize, layer: &Layer) {
+    // Short form like Docker's layer IDs: the first 12 hex digits.
+    let digest = layer
+        .digest
+        .strip_prefix("sha256:")
+        .unwrap_or(&layer.digest);
+    eprintln!(
+        "Pulling layer {i}/{count} {} ({})",
+        &digest[..12.min(digest.len())],
+        units::format_bytes(layer.size)
+    );
+}
+
+fn ulimits(args: &RunArgs) -> Result<Vec<Ulimit>> {
+    let mut ulimits = args.ulimits.clone();
+    if let Some(size) = args.no_core.then_some(0).or(args.core_limit) {
+        if ulimits.iter().any(|u| u.kind == UlimitKind::Core) {
+            bail!("--no-core and --core-limit cannot be combined with --ulimit core");
+        }
+        ulimits.push(Ulimit {
+            kind: UlimitKind::Core,
+            soft: size,
+            hard: size,
+        });
+    }
+    Ok(ulimits)
+}
+
+fn resource_limits(args: LimitArgs) -> ResourceLimits {
+    ResourceLimits {
+        memory: args.memory.as_ref().map(|m| m.bytes),
+        memory_spec: args.memory.map(|m| m.original),
+        memory_swappiness: args.memory_swappiness,
+        cpu: args.cpu.or(args.cpus),
+        pids: args.pids,
+        cpuset_cpus: args.cpuset_cpus,
+        cpuset_mems: args.cpuset_mems,
+    }
+}
+
+fn cmd_run(args: RunArgs) -> Result<()> {
+    let (config, opts) = run_config(args)?;
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        bail!("craterun only runs on Linux");
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        use crate::cli::progress::StatusLine;
+        use crate::platform::linux::process::{run_container, SetupPhase};
+        use std::cell::{Cell, RefCell};
+
+        if opts.dry_run {
+            let plan = crate::platform::linux::process::plan_container(&config)?;
+            let json =
+                serde_json::to_string_pretty(&plan).context("failed to serialize run plan")?;
+            println!("{json}");
+            return Ok(());
+        }
+
+        if let Some(path) = &opts.pidfile {
+            remove_stale_pidfile(path)?;
+        }
+
+        // Created before the container, so that an existing cidfile fails the
+        // run before anything starts; the ID is written once it is known.
+        let mut cidfile = match &opts.cidfile {
+            Some(path) => Some((path, create_new_file(path, "cidfile")?)),
+            None => None,
+        };
+        let ready = crate::platform::linux::check::preflight()
+            .and_then(|()| state::ensure_state_dir().map(drop));
+        if let Err(e) = ready {
+            if let Some(path) = &opts.cidfile {
+                let _ = fs::remove_file(path);
+            }
+            return Err(e);
+        }
+        let pidfile_written = Cell::new(false);
+
+        // Shared by both callbacks: the second clears the line the first draws.
+        let status = RefCell::new(StatusLine::new(opts.quiet));
+        let on_progress = |phase: Option<SetupPhase>| {
+            status.borrow_mut().update(phase.map(SetupPhase::description));
+        };
+        // The files are complete on disk before the ID is printed, so anything
+        // waiting for the ID can read them.
+        let result = run_container(&config, on_progress, |meta| {
+            status.borrow_mut().clear();
+            if let Some((path, file)) = cidfile.take() {
+                write_synced(file, path, &meta.id, "cidfile")?;
+            }
+            if let Some(path) = &opts.pidfile {
+                let file = create_new_file(path, "pidfile")?;
+                pidfile_written.set(true);
+                write_synced(file, path, &format!("{}\n", meta.pid), "pidfile")?;
+            }
+            eprintln!("{}", meta.id);
+            Ok(())
+        });
+        // A cidfile still empty names no container.
+        if let (Some(path), Err(_)) = (&opts.cidfile, &result) {
+            if fs::metadata(path).is_ok_and(|m| m.len() == 0) {
+                let _ = fs::remove_file(path);
+            }
+        }
+        if let (Some(path), true) = (&opts.pidfile, pidfile_written.get()) {
+            let _ = fs::remove_file(path);
+        }
+        drop(status);
+        let result = result.context("failed to run container")?;
+
+        // The exit code alone reads the same as the command exiting with it.
+        if let ContainerExit::Killed { .. } = result.exit {
+            eprintln!("craterun: container {}", result.exit);
+        }
+        std::process::exit(result.exit_code);
+    }
+}
+
+/// Create the new file `path`, refusing to overwrite an existing file.
+/// `what` names the file in errors.
+fn create_new_file(path: &Path, what: &str) -> Result<fs::File> {
+    let created = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path);
+    match created {
+        Ok(file) => Ok(file),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            bail!("{what} {} already exists", path.display())
+        }
+        Err(e) => Err(e).with_context(|| format!("failed to create {what} {}", path.display())),
+    }
+}
+
+/// Write `contents` to `file`, created at `path`, and sync it to disk.
+fn write_synced(mut file: fs::File, path: &Path, contents: &str, what: &str) -> Result<()> {
+    file.write_all(contents.as_bytes())
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("failed to write {what} {}", path.display()))
+}
+
+/// Remove the pidfile `path` left behind by a container that is gone.
+/// Refuses if it names a running process or does not hold a PID.
+fn remove_stale_pidfile(path: &Path) -> Result<()> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read pidfile {}", path.display()))
+        }
+    };
+    match contents.trim().parse::<u32>() {
+        Ok(pid) if state::pid_alive(pid) => {
+            bail!("pidfile {} already exists and PID {pid} is running", path.display())
+        }
+        Ok(pid) => {
+            eprintln!(
+                "warning: replacing stale pidfile {} (PID {pid} is not running)",
+                path.display()
+            );
+            fs::remove_file(path)
+                .with_context(|| format!("failed to remove stale pidfile {}", path.display()))
+        }
+        Err(_) => bail!(
+            "pidfile {} already exists and does not hold a PID",
+            path.display()
+        ),
+    }
+}
+
+// ─── ps ─────────────────────────────────────────────────────────────────────
+
+/// How many containers `ps` loads at a time before printing their rows.
+const PS_BATCH: usize = 256;
+/// Age up to which `ps` shows CREATED as a relative time; older containers
+/// show their creation date.
+const PS_RELATIVE_CREATED: Duration = Duration::from_secs(365 * 24 * 3600);
+
+/// How `ps` selects, orders and shows containers.
+struct PsOptions {
+    summary: bool,
+    sync: bool,
+    sort: PsSort,
+    reverse: bool,
+    last: Option<usize>,
+    humanize: bool,
+}
+
+fn cmd_ps(opts: PsOptions, format: OutputFormat) -> Result<()> {
+    let PsOptions {
+        summary,
+        sync,
+        sort,
+        reverse,
+        last,
+        humanize,
+    } = opts;
+    let now = Utc::now();
+    let text = format == OutputFormat::Text;
+    let limit = last.unwrap_or(usize::MAX);
+    // By ID, the order they are listed in, rows are printed as they load;
+    // any other order needs them all first.
+    let stream = text && sort == PsSort::Id && !reverse;
+    if text {
+        print_ps_header();
+    }
+
+    // Loaded a batch at a time, so streamed rows appear while the rest load.
+    let ids = state::list_containers()?;
+    let mut entries = Vec::with_capacity(ids.len());
+    for batch in ids.chunks(PS_BATCH) {
+        for (id, loaded) in batch.iter().zip(state::load_metas(batch)) {
+            let entry = match loaded {
+                // Only `--sync` persists a status found to be stale, so a
+                // plain `ps` does not write to every container it lists.
+                Ok(mut meta) => {
+                    if sync {
+                        state::refresh_status(&mut meta)?;
+                    } else {
+                        state::update_status(&mut meta)?;
+                    }
+                    Ok(meta)
+                }
+                Err(_) if state::is_starting(id) => continue,
+                Err(e) => {
+                    eprintln!("warning: cannot load container {id}: {e:#}");
+                    Err(BrokenContainer::new(id, &e))
+                }
+            };
+            if stream && entries.len() < limit {
+                print_ps_row(&entry, now, humanize);
+            }
+            entries.push(entry);
+        }
+    }
+    if !stream {
+        sort_ps_entries(&mut entries, sort, reverse);
+    }
+    let shown = &entries[..entries.len().min(limit)];
+
+    if !text {
+        let containers: Vec<_> = shown
+            .iter()
+            .map(|entry| match entry {
+                Ok(meta) => PsEntry::Container(ContainerSummary::new(meta, now)),
+                Err(broken) => PsEntry::Broken(broken),
+            })
+            .collect();
+        if summary {
+            return output::print_json(&output::PsListing {
+                summary: ps_totals(&entries)?,
+                containers,
+            });
+        }
+        return output::print_json(&containers);
+    }
+
+    if !stream {
+        for entry in shown {
+            print_ps_row(entry, now, humanize);
+        }
+    }
+    if summary {
+        let totals = ps_totals(&entries)?;
+        println!();
+        print!(
+            "{} containers: {} running, {} stopped",
+            totals.containers, totals.running, totals.stopped
+        );
+        if totals.broken > 0 {
+            print!(", {} broken", totals.broken);
+        }
+        println!();
+        println!("Disk usage: {}", units::format_bytes(totals.disk_usage));
+    }
+
+    Ok(())
+}
+
+/// Order `ps` entries by `sort`, then by ID, reversed with `reverse`.
+/// Broken containers, which have no creation time or status, come last.
+pub(super) fn sort_ps_entries(
+    entries: &mut [Result<ContainerMeta, BrokenContainer>],
+    sort: PsSort,
+    reverse: bool,
+) {
+    let created = |entry: &Result<ContainerMeta, BrokenContainer>| {
+        entry.as_ref().ok().map(|meta| meta.created_at)
+    };
+    let status_rank = |entry: &Result<ContainerMeta, BrokenContainer>| match entry {
+        Ok(meta) => match meta.status {
+            ContainerStatus::Running => 0,
+            ContainerStatus::Created => 1,
+            ContainerStatus::Stopped => 2,
+        },
+        Err(_) => 3,
+    };
+    // Named containers by name, then unnamed ones, then broken ones.
+    fn name(entry: &Result<ContainerMeta, BrokenContainer>) -> (u8, &str) {
+        match entry {
+            Ok(meta) => meta.name.as_deref().map_or((1, ""), |name| (0, name)),
+            Err(_) => (2, ""),
+        }
+    }
+    fn id(entry: &Result<ContainerMeta, BrokenContainer>) -> &str {
+        match entry {
+            Ok(meta) => &meta.id,
+            Err(broken) => &broken.id,
+        }
+    }
+
+    entries.sort_by(|a, b| {
+        let order = match sort {
+            // Newest first: `None`, for broken entries, sorts last.
+            PsSort::Created => created(b).cmp(&created(a)),
+            PsSort::Status => status_rank(a)
+                .cmp(&status_rank(b))
+                .then_with(|| created(b).cmp(&created(a))),
+            PsSort::Name => name(a).cmp(&name(b)),
+            PsSort::Id => Ordering::Equal,
+        };
+        order.then_with(|| id(a).cmp(id(b)))
+    });
+    if reverse {
+        entries.reverse();
+    }
+}
+
+pub(super) fn print_ps_header() {
+    println!(
+        "{:<18} {:<8} {:<32} {:<24} {:<10} {:<24} COMMAND",
+        "CONTAINER ID", "PID", "STATUS", "CREATED", "DURATION", "PORTS"
+    );
+}
+
+/// Print the `ps` row of a container, or of a broken one. With `humanize`,
+/// CREATED says how long ago the container was created.
+pub(super) fn print_ps_row(
+    entry: &Result<ContainerMeta, BrokenContainer>,
+    now: DateTime<Utc>,
+    humanize: bool,
+) {
+    let meta = match entry {
+        Ok(meta) => meta,
+        Err(broken) => {
+            println!(
+                "{:<18} {:<8} {:<32} {:<24} {:<10} {:<24} -",
+                broken.id, "-", broken.status, "-", "-", "-"
+            );
+            return;
+        }
+    };
+    let pid_str = if meta.pid > 0 {
+        meta.pid.to_string()
+    } else {
+        "-".to_string()
+    };
+
+    let created = if humanize {
+        units::format_time_ago(meta.created_at, now, PS_RELATIVE_CREATED)
+    } else {
+        meta.created_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    };
+    let duration = meta
+        .run_duration(now)
+        .map_or_else(|| "-".to_string(), units::format_duration);
+    let ports = meta.port_summaries().join(", ");
+    let cmd_str = meta.cmd.join(" ");
+    let cmd_display = if cmd_str.len() > 40 {
+        format!("{}...", &cmd_str[..37])
+    } else {
+        cmd_str
+    };
+
+    println!(
+        "{:<18} {:<8} {:<32} {:<24} {:<10} {:<24} {}",
+        &meta.id[..16.min(meta.id.len())],
+        pid_str,
+        meta.status_summary(now),
+        created,
+        duration,
+        if ports.is_empty() { "-" } else { &ports },
+        cmd_display
+    );
+}
+
+/// Count the `ps` entries by status and add up their state directories'
+/// disk usage.
+fn ps_totals(entries: &[Result<ContainerMeta, BrokenContainer>]) -> Result<output::PsTotals> {
+    let mut totals = output::PsTotals {
+        containers: entries.len(),
+        running: 0,
+        stopped: 0,
+        broken: 0,
+        disk_usage: 0,
+    };
+    for entry in entries {
+        let id = match entry {
+            Ok(meta) if meta.status == ContainerStatus::Running => {
+                totals.running += 1;
+                &meta.id
+            }
+            Ok(meta) => {
+                totals.stopped += 1;
+                &meta.id
+            }
+            Err(broken) => {
+                totals.broken += 1;
+                &broken.id
+            }
+        };
+        totals.disk_usage += state::disk_usage(id)?.total;
+    }
+    Ok(totals)
+}
+
+// ─── rm ─────────────────────────────────────────────────────────────────────
+
+/// Remove the containers given by `id_prefixes` and those matching
+/// `filters`. A single container given by ID is reported as one result;
+/// otherwise every container removed is, and the first failure is returned
+/// once the rest have been tried.
+fn cmd_rm(
+    id_prefixes: &[String],
+    filters: &[ContainerFilter],
+    force: bool,
+    format: OutputFormat,
+) -> Result<()> {
+    let mut ids = Vec::new();
+    for prefix in id_prefixes {
+        ids.push(state::resolve_id(prefix)?);
+    }
+    if !filters.is_empty() {
+        ids.extend(state::filter_containers(filters)?);
+    }
+    let mut seen = std::collections::HashSet::new();
+    ids.retain(|id| seen.insert(id.clone()));
+
+    if filters.is_empty() && ids.len() == 1 {
+        remove_container(&ids[0], force)?;
+        return print_removed(&ids[0], format);
+    }
+
+    // Refuse up front rather than after removing some of the others.
+    if !force {
+        let running: Vec<&str> = ids
+            .iter()
+            .filter(|id| {
+                state::load_meta(id).is_ok_and(|mut meta| {
+                    state::refresh_status(&mut meta).is_ok()
+                        && meta.status == ContainerStatus::Running
+                })
+            })
+            .map(String::as_str)
+            .collect();
+        if !running.is_empty() {
+            bail!(
+                "container(s) {} still running. Use --force to remove running containers.",
+                running.join(", ")
+            );
+        }
+    }
+
+    let mut removed = Vec::new();
+    let mut first_err = None;
+    for id in &ids {
+        match remove_container(id, force) {
+            Ok(()) => removed.push(id.as_str()),
+            Err(e) => {
+                eprintln!("craterun: {e:#}");
+                first_err.get_or_insert(e);
+            }
+        }
+    }
+    match format {
+        OutputFormat::Json => {
+            let results: Vec<_> = removed
+                .iter()
+                .map(|id| ContainerResult {
+                    id,
+                    result: "removed",
+                })
+                .collect();
+            output::print_json(&results)?;
+        }
+        OutputFormat::Text => {
+            for id in &removed {
+                println!("Removed container {id}");
+            }
+        }
+    }
+    match first_err {
+        Some(e) => Err(e.context(format!(
+            "failed to remove {} of {} container(s)",
+            ids.len() - removed.len(),
+            ids.len()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Remove container `id`; with `force` even if it is running or broken.
+pub(super) fn remove_container(id: &str, force: bool) -> Result<()> {
+    let mut meta = match state::load_meta(id) {
+        Ok(meta) => meta,
+        Err(e) if force => {
+            eprintln!("warning: removing container {id} without its metadata: {e:#}");
+            return remove_broken(id);
+        }
+        Err(e) => {
+            return Err(e.context(format!(
+                "cannot load container {id}; use --force to remove it anyway"
+            )))
+        }
+    };
+    state::refresh_status(&mut meta)?;
+
+    if meta.status == ContainerStatus::Running {
+        if !force {
+            bail!(
+                "container {id} is still running. Use --force to remove a running container."
+            );
+        }
+        for (dependent, kind) in state::namespace_dependents(id)? {
+            let effect = match kind {
+                NamespaceKind::Pid => "; its processes will be killed",
+                _ => "",
+            };
+            eprintln!("warning: container {dependent} shares the {kind} namespace of {id}{effect}");
+        }
+        // Kill the process first.
+        #[cfg(target_os = "linux")]
+        {
+            crate::platform::linux::process::kill_container(meta.pid)?;
+        }
+    }
+
+    remove_stopped(&meta)
+}
+
+/// Tear down and remove container `meta`, which is no longer running.
+fn remove_stopped(meta: &ContainerMeta) -> Result<()> {
+    let id = &meta.id;
+    // Kill what is left in the cgroup, such as exec sessions outliving a
+    // container that shares the host's PID namespace, then unmount the
+    // overlay and detach the image (if any), and remove the cgroup.
+    #[cfg(target_os = "linux")]
+    {
+        crate::platform::linux::cgroups::kill_cgroup(id)?;
+        let loop_device = meta.loop_device.as_deref().map(Path::new);
+        crate::platform::linux::process::teardown_rootfs(id, loop_device)?;
+        if let Some(pid) = meta.slirp_pid {
+            crate::platform::linux::slirp::kill_leaked(pid);
+        }
+        let _ = crate::platform::linux::cgroups::remove_cgroup(id);
+    }
+    if let Some(network) = &meta.network {
+        ipam::release(network, id)?;
+    }
+
+    // Remove state directory.
+    state::remove_container_dir(id)
+}
+
+/// Remove broken container `id`, whose metadata cannot be loaded. Without
+/// its PID, any processes left are killed through its cgroup. An address it
+/// held on a network is freed with its directory.
+fn remove_broken(id: &str) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        crate::platform::linux::cgroups::kill_cgroup(id)?;
+        crate::platform::linux::process::teardown_rootfs(id, None)?;
+        let _ = crate::platform::linux::cgroups::remove_cgroup(id);
+    }
+    state::remove_container_dir(id)
+}
+
+fn print_removed(id: &str, format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Json => output::print_json(&ContainerResult {
+            id,
+            result: "removed",
+        }),
+        OutputFormat::Text => {
+            println!("Removed container {id}");
+            Ok(())
+        }
+    }
+}
+
+// ─── prune ──────────────────────────────────────────────────────────────────
+
+fn cmd_prune(broken: bool, format: OutputFormat) -> Result<()> {
+    // Each container with the space its removal frees.
+    let mut removed = Vec::new();
+    let mut failed = 0;
+    let mut first_err = None;
+    for id in state::list_containers()? {
+        match prune_container(&id, broken) {
+            Ok(Some(reclaimed)) => removed.push((id, reclaimed)),
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("craterun: {e:#}");
+                failed += 1;
+                first_err.get_or_insert(e);
+            }
+        }
+    }
+
+    match format {
+        OutputFormat::Json => {
+            let results: Vec<_> = removed
+                .iter()
+                .map(|(id, reclaimed)| PruneResult {
+                    id,
+                    result: "removed",
+                    reclaimed: *reclaimed,
+                })
+                .collect();
+            output::print_json(&results)?;
+        }
+        OutputFormat::Text => {
+            for (id, _) in &removed {
+                println!("Removed container {id}");
+            }
+            let reclaimed = removed.iter().map(|(_, bytes)| bytes).sum();
+            println!("Reclaimed {}", units::format_bytes(reclaimed));
+        }
+    }
+    match first_err {
+        Some(e) => Err(e.context(format!("failed to remove {failed} container(s)"))),
+        None => Ok(()),
+    }
+}
+
+/// Remove container `id` if `prune` should: when stopped, or with `broken`
+/// when its metadata cannot be loaded. Returns the bytes freed if removed.
+fn prune_container(id: &str, broken: bool) -> Result<Option<u64>> {
+    match state::load_meta(id) {
+        Ok(mut meta) if !broken => {
+            state::refresh_status(&mut meta)?;
+            if meta.status == ContainerStatus::Running {
+                return Ok(None);
+            }
+            let usage = state::disk_usage(id)?;
+            remove_stopped(&meta)?;
+            Ok(Some(usage.total))
+        }
+        Ok(_) => Ok(None),
+        Err(_) if !broken || state::is_starting(id) => Ok(None),
+        Err(e) if e.is::<NewerSchemaError>() => {
+            eprintln!("warning: skipping {e}");
+            Ok(None)
+        }
+        Err(_) => {
+            // Checked again under the lock its writers take, so that one
+            // saving it at the same time is not mistaken for breakage.
+            let _lock = state::lock_meta(id)?;
+            if state::load_meta(id).is_ok() {
+                return Ok(None);
+            }
+            let usage = state::disk_usage(id)?;
+            remove_broken(id)?;
+            Ok(Some(usage.total))
+        }
+    }
+}
+
+// ─── stop / kill ────────────────────────────────────────────────────────────
+
+/// Most containers `stop --all` and `kill --all` act on at once. Stopping
+/// one is mostly waiting for it, so this is not tied to the number of CPUs.
+const BULK_THREADS: usize = 8;
+
+fn cmd_stop(
+    containers: &ContainerSelection,
+    timeout: Duration,
+    format: OutputFormat,
+) -> Result<()> {
+    let print = |id: &str, result: &str| {
+        if result == "killed" {
+            println!("Killed container {id} after {}s", timeout.as_secs());
+        } else {
+            println!("Stopped container {id}");
+        }
+    };
+    let stop = |id: &str| stop_running(id, timeout);
+    match containers.as_str() {
+        Some(id_prefix) => {
+            let id = state::resolve_id(id_prefix)?;
+            print_result(&id, stop(&id)?, format, print)
+        }
+        None => for_each_running(&containers.filter, "stop", stop, format, print),
+    }
+}
+
+fn cmd_kill(containers: &ContainerSelection, format: OutputFormat) -> Result<()> {
+    let print = |id: &str, _: &str| println!("Killed container {id}");
+    match containers.as_str() {
+        Some(id_prefix) => {
+            let id = state::resolve_id(id_prefix)?;
+            print_result(&id, kill_running(&id)?, format, print)
+        }
+        None => for_each_running(&containers.filter, "kill", kill_running, format, print),
+    }
+}
+
+/// Stop running container `id`: `"killed"` if it needed SIGKILL after
+/// `timeout`, else `"stopped"`.
+pub(super) fn stop_running(id: &str, timeout: Duration) -> Result<&'static str> {
+    let meta = running_meta(id)?;
+    let signal = match &meta.stop_signal {
+        Some(name) => crate::util::signal::parse_signal(name)
+            .with_context(|| format!("invalid stop signal of container {id}"))?,
+        None => nix::sys::signal::Signal::SIGTERM,
+    };
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (signal, timeout);
+        bail!("stop is only supported on Linux");
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let killed = crate::platform::linux::process::stop_container(meta.pid, signal, timeout)
+            .with_context(|| format!("failed to stop container {id}"))?;
+        Ok(if killed { "killed" } else { "stopped" })
+    }
+}
+
+/// Kill running container `id` with SIGKILL.
+fn kill_running(id: &str) -> Result<&'static str> {
+    let meta = running_meta(id)?;
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = meta;
+        bail!("kill is only supported on Linux");
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        crate::platform::linux::process::kill_running(meta.pid)
+            .with_context(|| format!("failed to kill container {id}"))?;
+        Ok("killed")
+    }
+}
+
+/// The metadata of container `id`, with its status refreshed, if it is
+/// running.
+fn running_meta(id: &str) -> Result<ContainerMeta> {
+    let mut meta = state::load_meta(id)?;
+    state::refresh_status(&mut meta)?;
+    if meta.status != ContainerStatus::Running {
+        bail!("container {id} is not running");
+    }
+    Ok(meta)
+}
+
+/// Print the `result` of `stop` or `kill` on container `id`, as text with
+/// `print`.
+fn print_result(
+    id: &str,
+    result: &'static str,
+    format: OutputFormat,
+    print: impl Fn(&str, &str),
+) -> Result<()> {
+    match format {
+        OutputFormat::Json => output::print_json(&ContainerResult { id, result }),
+        OutputFormat::Text => {
+            print(id, result);
+            Ok(())
+        }
+    }
+}
+
+/// Apply `op` to every running container matching `filters`, up to
+/// [`BULK_THREADS`] at a time, for `stop --all` and `kill --all`. Results
+/// are printed as text with `print` as they come, or as JSON once all are
+/// in. Failures are reported as they come too, and the first is returned
+/// once every container has been tried.
+fn for_each_running(
+    filters: &[ContainerFilter],
+    verb: &str,
+    op: impl Fn(&str) -> Result<&'static str> + Sync,
+    format: OutputFormat,
+    print: impl Fn(&str, &str) + Sync,
+) -> Result<()> {
+    let mut filters = filters.to_vec();
+    filters.push(ContainerFilter::Status(ContainerStatus::Running));
+    let ids = state::filter_containers(&filters)?;
+
+    let next = AtomicUsize::new(0);
+    let done = Mutex::new(Vec::new());
+    let first_err = Mutex::new(None);
+    std::thread::scope(|scope| {
+        for _ in 0..ids.len().min(BULK_THREADS) {
+            scope.spawn(|| {
+                while let Some(id) = ids.get(next.fetch_add(1, atomic::Ordering::Relaxed)) {
+                    match op(id) {
+                        Ok(result) => {
+                            if format == OutputFormat::Text {
+                                print(id, result);
+                            }
+                            done.lock().unwrap().push((id.as_str(), result));
+                        }
+                        Err(e) => {
+                            eprintln!("craterun: {e:#}");
+                            first_err.lock().unwrap().get_or_insert(e);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let mut done = done.into_inner().unwrap();
+    if format == OutputFormat::Json {
+        done.sort();
+        let results: Vec<_> = done
+            .iter()
+            .map(|&(id, result)| ContainerResult { id, result })
+            .collect();
+        output::print_json(&results)?;
+    }
+    match first_err.into_inner().unwrap() {
+        Some(e) => Err(e.context(format!(
+            "failed to {verb} {} of {} container(s)",
+            ids.len() - done.len(),
+            ids.len()
+        ))),
+        None => Ok(()),
+    }
+}
+
+// ─── logs ───────────────────────────────────────────────────────────────────
+
+fn cmd_logs(id_prefix: &str, opts: LogOptions, format: OutputFormat) -> Result<()> {
+    let id = state::resolve_id(id_prefix)?;
+
+    if format == OutputFormat::Json {
+        // Each line is tagged with its stream, so there is nothing to merge.
+        let opts = LogOptions {
+            combine: false,
+            ..opts
+        };
+        let LogView::Separate { stdout, stderr } = logs::read_logs(&id, opts)? else {
+            unreachable!("logs are only combined when asked to");
+        };
+        for (stream, text) in [("stdout", stdout), ("stderr", stderr)] {
+            for line in text.as_deref().unwrap_or_default().lines() {
+                output::print_json_line(&LogLine { stream, line })?;
+            }
+        }
+        return Ok(());
+    }
+
+    match logs::read_logs(&id, opts)? {
+        LogView::Separate { stdout, stderr } => {
+            if let Some(stdout) = stdout {
+                print!("{stdout}");
+            }
+            if let Some(stderr) = stderr {
+                eprint!("{stderr}");
+            }
+        }
+        LogView::Combined(combined) => print!("{combined}"),
+    }
+
+    Ok(())
+}
+
+fn cmd_logs_clear(id_prefix: &str, format: OutputFormat) -> Result<()> {
+    let id = state::resolve_id(id_prefix)?;
+    let mut meta = state::load_meta(&id)?;
+    state::refresh_status(&mut meta)?;
+
+    // The monitor holds the log files open for appending while the container
+    // runs, so only a stopped container's logs can be cleared safely.
+    if meta.status == ContainerStatus::Running {
+        bail!("container {id} is still running; stop it before clearing its logs");
+    }
+
+    logs::clear_logs(&id)?;
+    match format {
+        OutputFormat::Json => output::print_json(&ContainerResult {
+            id: &id,
+            result: "logs_cleared",
+        }),
+        OutputFormat::Text => {
+            println!("Cleared logs of container {id}");
+            Ok(())
+        }
+    }
+}
+
+// ─── inspect ────────────────────────────────────────────────────────────────
+
+fn cmd_inspect(id_prefixes: &[String], strict: bool) -> Result<()> {
+    // Already JSON, whatever the output format.
+    if let [id_prefix] = id_prefixes {
+        let meta = inspected_meta(id_prefix)?;
+        return output::print_json(&container_details(&meta)?);
+    }
+
+    let mut metas = Vec::new();
+    for id_prefix in id_prefixes {
+        match inspected_meta(id_prefix) {
+            Ok(meta) => metas.push(meta),
+            Err(e) if !strict => eprintln!("warning: leaving out '{id_prefix}': {e:#}"),
+            Err(e) => return Err(e),
+        }
+    }
+    let details = metas
+        .iter()
+        .map(container_details)
+        .collect::<Result<Vec<_>>>()?;
+    output::print_json(&details)
+}
+
+/// The metadata of the container `id_prefix` refers to, with its status
+/// refreshed.
+pub(super) fn inspected_meta(id_prefix: &str) -> Result<ContainerMeta> {
+    let id = state::resolve_id(id_prefix)?;
+    let mut meta = state::load_meta(&id)?;
+    state::refresh_status(&mut meta)?;
+    Ok(meta)
+}
+
+/// What `inspect` prints of container `meta`.
+pub(super) fn container_details(meta: &ContainerMeta) -> Result<ContainerDetails<'_>> {
+    Ok(ContainerDetails {
+        meta,
+        duration: meta.run_duration(Utc::now()).map(units::format_duration),
+        exit: meta.exit().map(|exit| exit.to_string()),
+        exec_sessions: exec::list_sessions(&meta.id)?.len(),
+    })
+}
+
+// ─── config ─────────────────────────────────────────────────────────────────
+
+fn cmd_config_validate(path: &Path) -> Result<()> {
+    RunSpec::load(path)?;
+    println!("{} is valid", path.display());
+    Ok(())
+}
+
+fn cmd_config_show(id_prefix: &str, format: OutputFormat) -> Result<()> {
+    let id = state::resolve_id(id_prefix)?;
+    let meta = state::load_meta(&id)?;
+    let spec = RunSpec::from_meta(&meta);
+    match format {
+        OutputFormat::Json => output::print_json(&spec),
+        OutputFormat::Text => {
+            print!("{}", spec.to_toml()?);
+            Ok(())
+        }
+    }
+}
+
+// ─── up / down ──────────────────────────────────────────────────────────────
+
+/// How long `up` gives an outdated service's container to stop before it is
+/// killed and recreated.
+const UP_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often `up` and the API's `logs` look for new lines in followed logs.
+pub(super) const FOLLOW_INTERVAL: Duration = Duration::from_millis(200);
+/// Prefix of the report of a detached container monitor that failed to
+/// start its container.
+const MONITOR_ERROR: &str = "error: ";
+
+fn cmd_up(file: &Path, detach: bool) -> Result<()> {
+    let project = Project::load(file)?;
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (project, detach);
+        bail!("up is only supported on Linux");
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        crate::platform::linux::check::preflight()?;
+        state::ensure_state_dir()?;
+        if !network::list_networks()?
+            .iter()
+            .any(|n| n.name == project.name)
+        {
+            let subnet = match project.subnet {
+                Some(subnet) => subnet,
+                None => network::free_subnet()?,
+            };
+            create_bridged_network(&project.name, subnet)?;
+            println!("Created network {}", project.name);
+        }
+
+        let existing = project_containers(&project.name)?;
+        let mut followed = Vec::new();
+        for service in &project.services {
+            let name = project.container_name(service);
+            let current = existing.iter().find(|meta| {
+                meta.service
+                    .as_ref()
+                    .is_some_and(|label| label.service == service.name)
+            });
+            if let Some(meta) = current {
+                let running = meta.status == ContainerStatus::Running;
+                if running
+                    && meta.service.as_ref().map(|label| &label.config_hash)
+                        == Some(&service.config_hash)
+                {
+                    println!("Container {name} is up to date");
+                    followed.push(FollowedService::new(&service.name, &meta.id, false)?);
+                    continue;
+                }
+                if running {
+                    stop_running(&meta.id, UP_STOP_TIMEOUT)?;
+                }
+                remove_container(&meta.id, false)?;
+                println!("Removed outdated container {name}");
+            }
+
+            let label = ServiceLabel {
+                project: project.name.clone(),
+                service: service.name.clone(),
+                config_hash: service.config_hash.clone(),
+            };
+            let id = start_detached(service_args(&project, service)?, Some(label))
+                .with_context(|| format!("failed to start service '{}'", service.name))?;
+            println!("Started container {name} ({id})");
+            followed.push(FollowedService::new(&service.name, &id, true)?);
+        }
+
+        if detach {
+            return Ok(());
+        }
+        follow_services(&mut followed)
+    }
+}
+
+fn cmd_down(file: &Path, timeout: Duration) -> Result<()> {
+    let project = Project::load(file)?;
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (project, timeout);
+        bail!("down is only supported on Linux");
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        // Services no longer in the file first, then the others in reverse
+        // start order.
+        let mut containers = project_containers(&project.name)?;
+        containers.sort_by_key(|meta| {
+            let label = meta.service.as_ref().expect("selected by their label");
+            let position = project
+                .services
+                .iter()
+                .position(|s| s.name == label.service);
+            std::cmp::Reverse(position.unwrap_or(usize::MAX))
+        });
+        for meta in &containers {
+            let name = meta.name.as_deref().unwrap_or(&meta.id);
+            if meta.status == ContainerStatus::Running {
+                stop_running(&meta.id, timeout)?;
+                println!("Stopped container {name}");
+            }
+            remove_container(&meta.id, false)?;
+            println!("Removed container {name}");
+        }
+
+        if network::list_networks()?
+            .iter()
+            .any(|n| n.name == project.name)
+        {
+            remove_bridged_network(&project.name, false)?;
+            println!("Removed network {}", project.name);
+        }
+        Ok(())
+    }
+}
+
+/// The `run` arguments of `service`: its configuration, on the project's
+/// network unless it picks its own, under its container name.
+fn service_args(project: &Project, service: &Service) -> Result<RunArgs> {
+    let own_network = service.spec.net.is_some() || service.spec.network.is_some();
+    let mut args = spec_args(service.spec.clone(), &project.dir)?;
+    if !own_network {
+        args.network = Some(project.name.clone());
+    }
+    args.name = Some(project.container_name(service));
+    Ok(args)
+}
+
+/// The `run` arguments that `spec` configures, with relative paths relative
+/// to `dir`, and the others left at their defaults.
+pub(super) fn spec_args(spec: RunSpec, dir: &Path) -> Result<RunArgs> {
+    // Parsed as `run --config -` for the defaults of the other arguments;
+    // `spec` then stands in for the file.
+    let Command::Run(mut args) =
+        crate::cli::try_parse_from(["craterun", "run", "--config", "-"])?.command
+    else {
+        unreachable!("parsed as `run`");
+    };
+    args.config = None;
+    spec.apply(&mut args, dir)?;
+    Ok(*args)
+}
+
+/// The containers of the services of `project`, with their status
+/// refreshed.
+fn project_containers(project: &str) -> Result<Vec<ContainerMeta>> {
+    let ids = state::list_containers()?;
+    let mut containers = Vec::new();
+    for mut meta in state::load_metas(&ids).into_iter().filter_map(Result::ok) {
+        if meta
+            .service
+            .as_ref()
+            .is_some_and(|label| label.project == project)
+        {
+            state::refresh_status(&mut meta)?;
+            containers.push(meta);
+        }
+    }
+    Ok(containers)
+}
+
+/// Start a container configured by `args`, for `service` if given, in the
+/// background, and return its ID once it runs.
+#[cfg(target_os = "linux")]
+pub(super) fn start_detached(args: RunArgs, service: Option<ServiceLabel>) -> Result<String> {
+    let (mut config, _) = run_config(args)?;
+    config.service = service;
+    run_detached(&config)
+}
+
+/// Start the container of `config` under a monitor that detaches from the
+/// CLI and waits for it as `run` does in the foreground. Returns the
+/// container's ID once it runs.
+#[cfg(target_os = "linux")]
+fn run_detached(config: &ContainerConfig) -> Result<String> {
+    use std::io::Read;
+
+    let (started_read, started_write) =
+        nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC).context("failed to create pipe")?;
+
+    // SAFETY: the CLI is single-threaded, so the child may do anything.
+    match unsafe { nix::unistd::fork() }.context("failed to fork container monitor")? {
+        nix::unistd::ForkResult::Parent { .. } => {
+            drop(started_write);
+            // The monitor reports the container's ID once it runs, or why it
+            // could not start it; the pipe closes either way.
+            let mut report = String::new();
+            fs::File::from(started_read)
+                .read_to_string(&mut report)
+                .context("failed to wait for container monitor")?;
+            match report.strip_prefix(MONITOR_ERROR) {
+                Some(error) => bail!("{error}"),
+                None if report.is_empty() => {
+                    bail!("the container monitor exited before starting the container")
+                }
+                None => Ok(report),
+            }
+        }
+        nix::unistd::ForkResult::Child => {
+            drop(started_read);
+            let mut started = Some(fs::File::from(started_write));
+            let code = match detached_monitor(config, &mut started) {
+                Ok(code) => code,
+                Err(e) => {
+                    if let Some(mut started) = started.take() {
+                        let _ = write!(started, "{MONITOR_ERROR}{e:#}");
+                    }
+                    1
+                }
+            };
+            std::process::exit(code);
+        }
+    }
+}
+
+/// The monitor of `run_detached`: detach from the CLI, run the container,
+/// report its ID on `started` once it runs, and return its exit code.
+#[cfg(target_os = "linux")]
+fn detached_monitor(config: &ContainerConfig, started: &mut Option<fs::File>) -> Result<i32> {
+    use crate::platform::linux::process::run_container;
+    use std::os::unix::io::AsRawFd;
+
+    nix::unistd::setsid().context("failed to start a new session")?;
+    let null = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .context("failed to open /dev/null")?;
+    for fd in 0..=2 {
+        nix::unistd::dup2(null.as_raw_fd(), fd).context("failed to redirect stdio")?;
     }
+    let result = run_container(
+        config,
+        |_| {},
+        |meta| {
+            if let Some(mut started) = started.take() {
+                started
+                    .write_all(meta.id.as_bytes())
+                    .context("failed to report container ID")?;
+            }
+            Ok(())
+        },
+    )?;
+    Ok(result.exit_code)
 }
 
-// ─── run ────────────────────────────────────────────────────────────────────
+/// A container whose logs are followed, for `up` the container of a
+/// service.
+pub(super) struct FollowedService {
+    name: String,
+    id: String,
+    /// How much of its stdout and stderr logs has been printed.
+    printed: [u64; 2],
+}
+
+impl FollowedService {
+    /// Follow container `id` of service `name` from the start of its logs,
+    /// or else from their current end.
+    pub(super) fn new(name: &str, id: &str, from_start: bool) -> Result<Self> {
+        let mut printed = [0; 2];
+        if !from_start {
+            for (printed, log) in printed.iter_mut().zip([STDOUT_LOG, STDERR_LOG]) {
+                *printed = fs::metadata(state::log_path(id, log)?).map_or(0, |m| m.len());
+            }
+        }
+        Ok(FollowedService {
+            name: name.to_string(),
+            id: id.to_string(),
+            printed,
+        })
+    }
+
+    /// Pass the lines added to the logs since last time to `emit` with
+    /// their stream, 0 for stdout and 1 for stderr: only complete ones,
+    /// unless `all`.
+    pub(super) fn read_new_lines(
+        &mut self,
+        all: bool,
+        mut emit: impl FnMut(usize, &str) -> Result<()>,
+    ) -> Result<()> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        for (stream, log) in [STDOUT_LOG, STDERR_LOG].into_iter().enumerate() {
+            let path = state::log_path(&self.id, log)?;
+            let mut file = match fs::File::open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to open {}", path.display()))
+                }
+            };
+            let printed = &mut self.printed[stream];
+            // Shorter than what was printed once cleared with `logs --clear`.
+            if file.metadata().is_ok_and(|m| m.len() < *printed) {
+                *printed = 0;
+            }
+            let mut new = Vec::new();
+            file.seek(SeekFrom::Start(*printed))
+                .and_then(|_| file.read_to_end(&mut new))
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let end = match all {
+                true => new.len(),
+                false => new.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1),
+            };
+            *printed += end as u64;
+            for line in String::from_utf8_lossy(&new[..end]).lines() {
+                emit(stream, line)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn is_running(&self) -> bool {
+        state::load_meta(&self.id).is_ok_and(|mut meta| {
+            state::refresh_status(&mut meta).is_ok() && meta.status == ContainerStatus::Running
+        })
+    }
+}
+
+/// Print the services' new log lines as they come until all of their
+/// containers have exited.
+fn follow_services(services: &mut [FollowedService]) -> Result<()> {
+    let width = services.iter().map(|s| s.name.len()).max().unwrap_or(0);
+    loop {
+        // Checked before reading, so that the last lines of a container
+        // that has just exited are still printed.
+        let running = services.iter().any(FollowedService::is_running);
+        for service in services.iter_mut() {
+            let name = service.name.clone();
+            service.read_new_lines(!running, |stream, line| {
+                match stream {
+                    0 => println!("{name:<width$} | {line}"),
+                    _ => eprintln!("{name:<width$} | {line}"),
+                }
+                Ok(())
+            })?;
+        }
+        if !running {
+            return Ok(());
+        }
+        std::thread::sleep(FOLLOW_INTERVAL);
+    }
+}
+
+// ─── diff ───────────────────────────────────────────────────────────────────
+
+fn cmd_diff(id_prefix: &str, format: OutputFormat) -> Result<()> {
+    let id = state::resolve_id(id_prefix)?;
+    let mut meta = state::load_meta(&id)?;
+    state::refresh_status(&mut meta)?;
+
+    // Only an image rootfs gets an overlay recording the container's changes,
+    // and the overlay goes away with the container.
+    if meta.loop_device.is_none() {
+        bail!(
+            "container {id} changes its directory rootfs {} in place, so there is nothing to \
+             compare against; diff needs a container started from a squashfs or EROFS image",
+            meta.rootfs
+        );
+    }
+    if meta.status != ContainerStatus::Running {
+        bail!(
+            "container {id} has exited, and its changes were discarded with its overlay; diff \
+             it while it is running"
+        );
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = format;
+        bail!("diff is only supported on Linux");
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        // The image is seen through its mount under the overlay.
+        let overlay = state::overlay_dir(&id)?;
+        let (upper, lower) = (overlay.join("upper"), overlay.join("lower"));
+        use crate::platform::linux::diff::diff_overlay;
 
-fn cmd_run(config: ContainerConfig) -> Result<()> {
+        if format == OutputFormat::Json {
+            let mut changes = Vec::new();
+            diff_overlay(&upper, &lower, |change| {
+                changes.push(change);
+                Ok(())
+            })?;
+            let changes: Vec<_> = changes.iter().map(ChangeLine::from).collect();
+            return output::print_json(&changes);
+        }
+        // Print as the tree is walked, so large trees start showing at once.
+        let mut out = std::io::stdout().lock();
+        diff_overlay(&upper, &lower, |change| {
+            writeln!(out, "{change}").context("failed to write to stdout")
+        })
+    }
+}
+
+// ─── check ──────────────────────────────────────────────────────────────────
+
+fn cmd_check(json: bool) -> Result<()> {
     #[cfg(not(target_os = "linux"))]
     {
+        let _ = json;
         bail!("craterun only runs on Linux");
     }
 
     #[cfg(target_os = "linux")]
     {
-        state::ensure_state_dir()?;
+        use crate::core::model::CheckStatus;
 
-        let result = crate::platform::linux::process::run_container(&config)
-            .context("failed to run container")?;
+        let results = crate::platform::linux::check::run_all();
 
-        println!("{}", result.container_id);
-        std::process::exit(result.exit_code);
+        if json {
+            let out = serde_json::to_string_pretty(&results)
+                .context("failed to serialize check results")?;
+            println!("{out}");
+        } else {
+            let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+            for result in &results {
+                let status = format!("[{:<4}]", result.status.to_string().to_uppercase());
+                let status = match result.status {
+                    _ if !color => status,
+                    CheckStatus::Pass => format!("\x1b[32m{status}\x1b[0m"),
+                    CheckStatus::Warn => format!("\x1b[33m{status}\x1b[0m"),
+                    CheckStatus::Fail => format!("\x1b[31m{status}\x1b[0m"),
+                };
+                println!("{status} {:<20} {}", result.name, result.detail);
+                if let Some(hint) = &result.hint {
+                    println!("       {:<20} hint: {hint}", "");
+                }
+            }
+        }
+
+        let failed = results.iter().filter(|r| r.status == CheckStatus::Fail).count();
+        if failed > 0 {
+            bail!("{failed} check(s) failed");
+        }
+        Ok(())
     }
 }
 
-// ─── ps ─────────────────────────────────────────────────────────────────────
+// ─── system info ────────────────────────────────────────────────────────────
 
-fn cmd_ps() -> Result<()> {
-    let ids = state::list_containers()?;
+fn cmd_system_info(format: OutputFormat) -> Result<()> {
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = format;
+        bail!("craterun only runs on Linux");
+    }
 
-    println!(
-        "{:<18} {:<8} {:<10} {:<24} {}",
-        "CONTAINER ID", "PID", "STATUS", "CREATED", "COMMAND"
-    );
+    #[cfg(target_os = "linux")]
+    {
+        let host = crate::platform::linux::check::host_info();
+        let state_dir = state::state_dir()?;
 
-    for id in ids {
-        let mut meta = match state::load_meta(&id) {
-            Ok(m) => m,
-            Err(_) => continue,
-        };
-        state::refresh_status(&mut meta)?;
+        // Statuses are refreshed in memory only: `system info` changes nothing.
+        let ids = state::list_containers()?;
+        let mut containers = ContainerCounts::default();
+        for meta in state::load_metas(&ids) {
+            let Ok(mut meta) = meta else {
+                containers.broken += 1;
+                continue;
+            };
+            state::update_status(&mut meta)?;
+            match meta.status {
+                ContainerStatus::Running => containers.running += 1,
+                ContainerStatus::Stopped => containers.stopped += 1,
+                ContainerStatus::Created => containers.created += 1,
+            }
+        }
 
-        let pid_str = if meta.pid > 0 {
-            meta.pid.to_string()
-        } else {
-            "-".to_string()
+        let bridge = network::load_network(network::DEFAULT_NETWORK)?;
+        let attached = ipam::holders(&bridge.name)?.len();
+        let info = SystemInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            build: if cfg!(debug_assertions) {
+                "debug"
+            } else {
+                "release"
+            },
+            target: format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS),
+            state_dir: &state_dir,
+            state_dir_free: crate::util::fs::free_space(&state_dir).ok(),
+            host: &host,
+            containers,
+            default_network: NetworkSummary::new(&bridge, attached),
         };
+        if format == OutputFormat::Json {
+            return output::print_json(&info);
+        }
 
-        let created = meta.created_at.format("%Y-%m-%d %H:%M:%S UTC");
-        let cmd_str = meta.cmd.join(" ");
-        let cmd_display = if cmd_str.len() > 40 {
-            format!("{}...", &cmd_str[..37])
-        } else {
-            cmd_str
+        let list = |items: &[String]| match items.is_empty() {
+            true => "none".to_string(),
+            false => items.join(" "),
         };
-
+        let yes_no = |yes: bool| if yes { "yes" } else { "no" };
+        println!(
+            "Version:          {} ({}, {})",
+            info.version, info.build, info.target
+        );
+        match info.state_dir_free {
+            Some(free) => println!(
+                "State directory:  {} ({} free)",
+                state_dir.display(),
+                units::format_bytes(free)
+            ),
+            None => println!(
+                "State directory:  {} (free space unknown)",
+                state_dir.display()
+            ),
+        }
+        let kernel = host.kernel.as_deref().unwrap_or("unknown");
+        println!("Kernel:           {kernel}");
+        println!("Cgroups:          {}", host.cgroup_mode);
+        println!("Controllers:      {}", list(&host.cgroup_controllers));
+        println!("Delegated:        {}", list(&host.cgroup_delegated));
+        println!("Cgroup parent:    {}", host.cgroup_parent.display());
+        println!("User namespaces:  {}", yes_no(host.user_namespaces));
+        println!("Overlayfs:        {}", yes_no(host.overlayfs));
+        let counts = &info.containers;
+        println!(
+            "Containers:       {} running, {} stopped, {} created, {} broken",
+            counts.running, counts.stopped, counts.created, counts.broken
+        );
         println!(
-            "{:<18} {:<8} {:<10} {:<24} {}",
-            &meta.id[..16.min(meta.id.len())],
-            pid_str,
-            meta.status,
-            created,
-            cmd_display
+            "Default network:  {} ({}, {}, gateway {}, {attached} attached)",
+            bridge.name,
+            bridge.bridge,
+            bridge.subnet,
+            bridge.gateway()
         );
+        Ok(())
     }
-
-    Ok(())
 }
 
-// ─── rm ─────────────────────────────────────────────────────────────────────
+// ─── update ─────────────────────────────────────────────────────────────────
+
+fn cmd_update(id_prefix: &str, limits: ResourceLimits, format: OutputFormat) -> Result<()> {
+    if limits.is_empty() {
+        bail!("no limits given; pass at least one of --memory, --memory-swappiness, --cpu, --cpus, --pids, --cpuset-cpus or --cpuset-mems");
+    }
 
-fn cmd_rm(id_prefix: &str, force: bool) -> Result<()> {
     let id = state::resolve_id(id_prefix)?;
+    // Held from loading to saving, so that an exit the container's monitor
+    // records meanwhile is not overwritten with the status loaded here.
+    let lock = state::lock_meta(&id)?;
     let mut meta = state::load_meta(&id)?;
-    state::refresh_status(&mut meta)?;
+    state::update_status(&mut meta)?;
 
+    // Only a running container has a cgroup to update.
+    #[cfg(target_os = "linux")]
     if meta.status == ContainerStatus::Running {
-        if !force {
-            bail!(
-                "container {id} is still running. Use --force to remove a running container."
-            );
+        use crate::platform::linux::cgroups;
+        cgroups::check_cpuset(&limits)?;
+        if let Some(warning) = cgroups::update_cgroup(&id, &limits)? {
+            eprintln!("warning: {warning}");
         }
-        // Kill the process first.
-        #[cfg(target_os = "linux")]
-        {
-            crate::platform::linux::process::kill_container(meta.pid)?;
+    }
+
+    meta.apply_limits(&limits);
+    state::save_meta(&meta)?;
+    drop(lock);
+
+    match format {
+        OutputFormat::Json => output::print_json(&ContainerResult {
+            id: &id,
+            result: "updated",
+        }),
+        OutputFormat::Text => {
+            println!("Updated container {id}");
+            Ok(())
         }
     }
+}
+
+// ─── import ─────────────────────────────────────────────────────────────────
+
+fn cmd_import(
+    tarball: &Path,
+    name: &str,
+    verify: Option<&str>,
+    format: OutputFormat,
+) -> Result<()> {
+    state::ensure_state_dir()?;
+    let image = image::import_image(tarball, name, verify)?;
+    if format == OutputFormat::Json {
+        return output::print_json(&ImageSummary::from(&image));
+    }
+    eprintln!("{}: {}", image.name, image.digest);
+    println!("{}", image.rootfs.display());
+    Ok(())
+}
+
+// ─── commit ─────────────────────────────────────────────────────────────────
+
+fn cmd_commit(id_prefix: &str, name: &str, format: OutputFormat) -> Result<()> {
+    let id = state::resolve_id(id_prefix)?;
+    let mut meta = state::load_meta(&id)?;
+    state::refresh_status(&mut meta)?;
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (name, format);
+        bail!("commit is only supported on Linux");
+    }
 
-    // Remove cgroup.
     #[cfg(target_os = "linux")]
     {
-        let _ = crate::platform::linux::cgroups::remove_cgroup(&id);
+        // A directory rootfs is the container's filesystem; an image rootfs
+        // only has the container's changes in its overlay while it runs.
+        let overlay = state::overlay_dir(&id)?;
+        let (upper, lower) = match meta.loop_device {
+            Some(_) if meta.status != ContainerStatus::Running => bail!(
+                "container {id} has exited, and its changes to its image were discarded with \
+                 its overlay; commit it while it is running"
+            ),
+            Some(_) => (Some(overlay.join("upper")), overlay.join("lower")),
+            None => (None, PathBuf::from(&meta.rootfs)),
+        };
+
+        let parent = image::image_of(Path::new(&meta.rootfs))?;
+        let mut history = parent
+            .as_ref()
+            .and_then(|parent| parent.provenance.as_ref())
+            .map(|provenance| provenance.history.clone())
+            .unwrap_or_default();
+        history.push(meta.cmd.clone());
+        let provenance = image::Provenance {
+            container: id.clone(),
+            parent_rootfs: meta.rootfs.clone(),
+            parent_image: parent.map(|parent| parent.name),
+            history,
+        };
+
+        state::ensure_state_dir()?;
+        let image = image::commit_image(name, provenance, |file| {
+            crate::platform::linux::commit::archive_layers(upper.as_deref(), &lower, file)
+        })?;
+        if format == OutputFormat::Json {
+            return output::print_json(&ImageSummary::from(&image));
+        }
+        eprintln!("{}: {}", image.name, image.digest);
+        println!("{}", image.rootfs.display());
+        Ok(())
     }
+}
 
-    // Remove state directory.
-    state::remove_container_dir(&id)?;
+// ─── pull ───────────────────────────────────────────────────────────────────
 
-    println!("Removed container {id}");
+fn cmd_pull(reference: &str, name: Option<&str>, format: OutputFormat) -> Result<()> {
+    let parsed: Reference = reference.parse()?;
+    let name = name.map_or_else(|| parsed.image_name(), str::to_string);
+    let progress = std::io::stderr().is_terminal();
+    state::ensure_state_dir()?;
+    let image = registry::pull(&parsed, &name, |i, count, layer| {
+        if progress {
+            print_layer_progress(i, count, layer);
+        }
+    })?;
+    if format == OutputFormat::Json {
+        return output::print_json(&ImageSummary::from(&image));
+    }
+    eprintln!("{}: {}", image.name, image.digest);
+    println!("{}", image.rootfs.display());
     Ok(())
 }
 
-// ─── logs ───────────────────────────────────────────────────────────────────
+// ─── images ─────────────────────────────────────────────────────────────────
 
-fn cmd_logs(id_prefix: &str) -> Result<()> {
-    let id = state::resolve_id(id_prefix)?;
+fn cmd_images(format: OutputFormat) -> Result<()> {
+    let images = image::list_images()?;
+    if format == OutputFormat::Json {
+        let images: Vec<_> = images.iter().map(ImageSummary::from).collect();
+        return output::print_json(&images);
+    }
+
+    println!(
+        "{:<24} {:<20} {:<10} {:<24} ROOTFS",
+        "NAME", "DIGEST", "SIZE", "CREATED"
+    );
+    for image in images {
+        // Short form like Docker's image IDs: the first 12 hex digits.
+        let digest = &image.digest[..19.min(image.digest.len())];
+        println!(
+            "{:<24} {:<20} {:<10} {:<24} {}",
+            image.name,
+            digest,
+            units::format_bytes(image.size),
+            image.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            image.rootfs.display()
+        );
+    }
+    Ok(())
+}
 
-    let stdout_path = state::log_path(&id, state::STDOUT_LOG)?;
-    let stderr_path = state::log_path(&id, state::STDERR_LOG)?;
+// ─── rmi ────────────────────────────────────────────────────────────────────
 
-    if stdout_path.exists() {
-        let contents =
-            fs::read_to_string(&stdout_path).context("failed to read stdout.log")?;
-        if !contents.is_empty() {
-            print!("{contents}");
+fn cmd_rmi(name: &str, format: OutputFormat) -> Result<()> {
+    image::remove_image(name)?;
+    match format {
+        OutputFormat::Json => output::print_json(&ImageResult {
+            name,
+            result: "removed",
+        }),
+        OutputFormat::Text => {
+            println!("Removed image {name}");
+            Ok(())
         }
     }
+}
+
+// ─── network ────────────────────────────────────────────────────────────────
+
+fn cmd_network_create(name: &str, subnet: Subnet, format: OutputFormat) -> Result<()> {
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (name, subnet, format);
+        bail!("networks are only supported on Linux");
+    }
 
-    if stderr_path.exists() {
-        let contents =
-            fs::read_to_string(&stderr_path).context("failed to read stderr.log")?;
-        if !contents.is_empty() {
-            eprint!("{contents}");
+    #[cfg(target_os = "linux")]
+    {
+        state::ensure_state_dir()?;
+        let created = create_bridged_network(name, subnet)?;
+        match format {
+            OutputFormat::Json => output::print_json(&NetworkSummary::new(&created, 0)),
+            OutputFormat::Text => {
+                println!("{name}");
+                Ok(())
+            }
         }
     }
+}
+
+fn cmd_network_ls(format: OutputFormat) -> Result<()> {
+    let networks = network::list_networks()?;
+    let mut attached = Vec::with_capacity(networks.len());
+    for network in &networks {
+        attached.push(ipam::holders(&network.name)?.len());
+    }
+    if format == OutputFormat::Json {
+        let networks: Vec<_> = networks
+            .iter()
+            .zip(&attached)
+            .map(|(network, &count)| NetworkSummary::new(network, count))
+            .collect();
+        return output::print_json(&networks);
+    }
 
+    println!(
+        "{:<24} {:<12} {:<18} {:<16} {:<10} CREATED",
+        "NAME", "BRIDGE", "SUBNET", "GATEWAY", "CONTAINERS"
+    );
+    for (network, count) in networks.iter().zip(attached) {
+        let created = match network.created_at {
+            Some(at) => at.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:<24} {:<12} {:<18} {:<16} {:<10} {created}",
+            network.name,
+            network.bridge,
+            network.subnet.to_string(),
+            network.gateway().to_string(),
+            count
+        );
+    }
     Ok(())
 }
 
-// ─── inspect ────────────────────────────────────────────────────────────────
+fn cmd_network_rm(name: &str, force: bool, format: OutputFormat) -> Result<()> {
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (name, force, format);
+        bail!("networks are only supported on Linux");
+    }
 
-fn cmd_inspect(id_prefix: &str) -> Result<()> {
-    let id = state::resolve_id(id_prefix)?;
-    let mut meta = state::load_meta(&id)?;
-    state::refresh_status(&mut meta)?;
+    #[cfg(target_os = "linux")]
+    {
+        remove_bridged_network(name, force)?;
+        match format {
+            OutputFormat::Json => output::print_json(&NetworkResult {
+                name,
+                result: "removed",
+            }),
+            OutputFormat::Text => {
+                println!("Removed network {name}");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Record the network `name` on `subnet` and create its host bridge.
+#[cfg(target_os = "linux")]
+fn create_bridged_network(name: &str, subnet: Subnet) -> Result<network::NetworkInfo> {
+    let created = network::create_network(name, subnet)?;
+    if let Err(e) = crate::platform::linux::network::ensure_bridge(&created) {
+        let _ = network::remove_network(name, true);
+        return Err(e.context(format!("failed to create bridge {}", created.bridge)));
+    }
+    Ok(created)
+}
+
+/// Remove the network `name` and its host bridge.
+#[cfg(target_os = "linux")]
+fn remove_bridged_network(name: &str, force: bool) -> Result<()> {
+    let removed = network::remove_network(name, force)?;
+    crate::platform::linux::network::remove_bridge(&removed)
+        .with_context(|| format!("failed to remove bridge {}", removed.bridge))
+}
+
+// ─── system df ──────────────────────────────────────────────────────────────
+
+fn cmd_system_df(
+    detailed: bool,
+    max_log_size: Option<&ByteSize>,
+    format: OutputFormat,
+) -> Result<()> {
+    let ids = state::list_containers()?;
+    let metas = state::load_metas(&ids);
+    let usages = state::disk_usages(&ids);
+
+    let mut containers = ContainersUsage::default();
+    let mut container_details = Vec::new();
+    let mut unreadable = 0;
+    // Canonical rootfs of each container, to find the images in use.
+    let mut rootfs_paths = Vec::new();
+    for ((id, meta), usage) in ids.iter().zip(metas).zip(usages) {
+        let usage = usage.with_context(|| format!("failed to measure container {id}"))?;
+        let (status, storage) = match meta {
+            Ok(mut meta) => {
+                state::refresh_status(&mut meta)?;
+                let rootfs = Path::new(&meta.rootfs);
+                rootfs_paths.push(fs::canonicalize(rootfs).unwrap_or_else(|_| rootfs.into()));
+                (Some(meta.status), meta.storage)
+            }
+            Err(_) => (None, None),
+        };
+        containers.count += 1;
+        containers.size += usage.total;
+        containers.logs += usage.logs;
+        containers.writable += usage.writable;
+        if status == Some(ContainerStatus::Running) {
+            containers.running += 1;
+        } else {
+            containers.reclaimable += usage.total;
+        }
+        unreadable += usage.unreadable;
+        if let Some(max) = max_log_size.filter(|max| usage.logs > max.bytes) {
+            eprintln!(
+                "warning: container {id} has {} of logs, more than --max-log-size {}; \
+                 `craterun logs --clear {id}` truncates them",
+                units::format_bytes(usage.logs),
+                max.original
+            );
+        }
+        container_details.push(ContainerUsage {
+            id,
+            status: status.map_or_else(|| "broken".to_string(), |s| s.to_string()),
+            size: usage.total,
+            logs: usage.logs,
+            writable: usage.writable,
+            storage_limit: storage.map(|limit| limit.size),
+        });
+    }
+
+    let images = image::list_images()?;
+    let mut images_usage = ImagesUsage::default();
+    let mut image_details = Vec::new();
+    for image in &images {
+        let rootfs = fs::canonicalize(&image.rootfs).unwrap_or_else(|_| image.rootfs.clone());
+        let users = rootfs_paths
+            .iter()
+            .filter(|path| path.starts_with(&rootfs))
+            .count();
+        images_usage.count += 1;
+        images_usage.size += image.size;
+        if users > 0 {
+            images_usage.in_use += 1;
+        } else {
+            images_usage.reclaimable += image.size;
+        }
+        image_details.push(ImageUsage {
+            name: &image.name,
+            containers: users,
+            size: image.size,
+        });
+    }
 
-    let json = serde_json::to_string_pretty(&meta)
-        .context("failed to serialize container metadata")?;
-    println!("{json}");
+    if unreadable > 0 {
+        eprintln!(
+            "warning: {unreadable} files or directories could not be read and are not counted"
+        );
+    }
+    let report = DiskUsageReport {
+        size: containers.size + images_usage.size,
+        reclaimable: containers.reclaimable + images_usage.reclaimable,
+        containers,
+        images: images_usage,
+        unreadable,
+        container_details: detailed.then_some(container_details),
+        image_details: detailed.then_some(image_details),
+    };
+    if format == OutputFormat::Json {
+        return output::print_json(&report);
+    }
+
+    let bytes = units::format_bytes;
+    println!(
+        "{:<18} {:<8} {:<8} {:<10} RECLAIMABLE",
+        "TYPE", "TOTAL", "ACTIVE", "SIZE"
+    );
+    let (containers, images) = (&report.containers, &report.images);
+    println!(
+        "{:<18} {:<8} {:<8} {:<10} {}",
+        "Containers",
+        containers.count,
+        containers.running,
+        bytes(containers.size),
+        bytes(containers.reclaimable)
+    );
+    println!("{:<36} {}", "  logs", bytes(containers.logs));
+    println!("{:<36} {}", "  writable layers", bytes(containers.writable));
+    println!(
+        "{:<18} {:<8} {:<8} {:<10} {}",
+        "Images",
+        images.count,
+        images.in_use,
+        bytes(images.size),
+        bytes(images.reclaimable)
+    );
+    println!(
+        "{:<36} {:<10} {}",
+        "Total",
+        bytes(report.size),
+        bytes(report.reclaimable)
+    );
 
+    if let Some(details) = &report.container_details {
+        println!();
+        println!(
+            "{:<18} {:<10} {:<10} {:<14} SIZE",
+            "CONTAINER", "STATUS", "LOGS", "WRITABLE"
+        );
+        for container in details {
+            let writable = match container.storage_limit {
+                Some(limit) => format!("{}/{}", bytes(container.writable), bytes(limit)),
+                None => bytes(container.writable),
+            };
+            println!(
+                "{:<18} {:<10} {:<10} {:<14} {}",
+                &container.id[..16.min(container.id.len())],
+                container.status,
+                bytes(container.logs),
+                writable,
+                bytes(container.size)
+            );
+        }
+    }
+    if let Some(details) = &report.image_details {
+        println!();
+        println!("{:<24} {:<12} SIZE", "IMAGE", "CONTAINERS");
+        for image in details {
+            println!(
+                "{:<24} {:<12} {}",
+                image.name,
+                image.containers,
+                bytes(image.size)
+            );
+        }
+    }
     Ok(())
 }
 
 // ─── exec ───────────────────────────────────────────────────────────────────
 
-fn cmd_exec(id_prefix: &str, cmd: &[String]) -> Result<()> {
+fn cmd_exec(
+    id_prefix: &str,
+    cmd: &[String],
+    detach: bool,
+    list: bool,
+    format: OutputFormat,
+) -> Result<()> {
     let id = state::resolve_id(id_prefix)?;
+    if list {
+        return cmd_exec_list(&id, format);
+    }
     let mut meta = state::load_meta(&id)?;
     state::refresh_status(&mut meta)?;
 
@@ -187,27 +2297,238 @@ fn cmd_exec(id_prefix: &str, cmd: &[String]) -> Result<()> {
 
     #[cfg(not(target_os = "linux"))]
     {
+        let _ = (cmd, detach, format);
         bail!("exec is only supported on Linux");
     }
 
     #[cfg(target_os = "linux")]
     {
-        exec_in_container(meta.pid, cmd)?;
+        if detach {
+            let session = exec_detached(&meta, cmd)?;
+            return match format {
+                OutputFormat::Json => output::print_json(&ExecSummary::new(&session)?),
+                OutputFormat::Text => {
+                    println!("{}", session.id);
+                    Ok(())
+                }
+            };
+        }
+        exec_in_container(&meta, cmd)?;
         Ok(())
     }
 }
 
-/// Enter the namespaces of a running container and exec a command.
+fn cmd_exec_list(id: &str, format: OutputFormat) -> Result<()> {
+    let sessions = exec::list_sessions(id)?;
+    if format == OutputFormat::Json {
+        let sessions = sessions
+            .iter()
+            .map(ExecSummary::new)
+            .collect::<Result<Vec<_>>>()?;
+        return output::print_json(&sessions);
+    }
+
+    println!(
+        "{:<14} {:<8} {:<14} {:<24} COMMAND",
+        "SESSION", "PID", "STATUS", "STARTED"
+    );
+    for session in sessions {
+        println!(
+            "{:<14} {:<8} {:<14} {:<24} {}",
+            session.id,
+            session.pid,
+            session.status_summary(),
+            session.started_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            session.cmd.join(" ")
+        );
+    }
+    Ok(())
+}
+
+/// Start `cmd` in a running container in the background, returning once it
+/// has started. A helper forked here enters the container's PID namespace,
+/// so that the command it forks in turn runs inside it, then stays behind to
+/// record the command's exit in its session.
 #[cfg(target_os = "linux")]
-fn exec_in_container(pid: u32, cmd: &[String]) -> Result<()> {
-    use std::ffi::CString;
+fn exec_detached(meta: &ContainerMeta, cmd: &[String]) -> Result<exec::ExecSession> {
+    use std::io::Read;
+
+    let mut session = exec::ExecSession::new(&meta.id, cmd);
+    session.save()?;
+    let log_path = session.log_path()?;
+    let log = fs::File::create(&log_path)
+        .with_context(|| format!("failed to create {}", log_path.display()))?;
+    let pid_ns = format!("/proc/{}/ns/pid", meta.pid);
+    let pid_ns =
+        fs::File::open(&pid_ns).with_context(|| format!("failed to open namespace {pid_ns}"))?;
+    let (started_read, started_write) =
+        nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC).context("failed to create pipe")?;
+
+    // SAFETY: the CLI is single-threaded, so the child may do anything.
+    match unsafe { nix::unistd::fork() }.context("failed to fork exec helper")? {
+        nix::unistd::ForkResult::Parent { .. } => {
+            drop(started_write);
+            // The helper reports the command's PID, and the pipe closes once
+            // the command has been exec'd or has failed to.
+            let mut pid = String::new();
+            fs::File::from(started_read)
+                .read_to_string(&mut pid)
+                .context("failed to wait for exec helper")?;
+            session.pid = pid.trim().parse().with_context(|| {
+                format!("failed to start exec session (see {})", log_path.display())
+            })?;
+            Ok(session)
+        }
+        nix::unistd::ForkResult::Child => {
+            drop(started_read);
+            let code = match exec_helper(meta, session, log, pid_ns, started_write.into()) {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("craterun exec: {e:#}");
+                    1
+                }
+            };
+            std::process::exit(code);
+        }
+    }
+}
+
+/// The helper of `exec_detached`: detach from the CLI, fork the command
+/// into the container's PID namespace, report its PID on `started` and
+/// record its exit.
+#[cfg(target_os = "linux")]
+fn exec_helper(
+    meta: &ContainerMeta,
+    mut session: exec::ExecSession,
+    log: fs::File,
+    pid_ns: fs::File,
+    mut started: fs::File,
+) -> Result<()> {
+    use crate::core::error::LaunchError;
+    use crate::core::model::EXIT_CODE_UNKNOWN;
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use std::os::unix::io::{AsFd, AsRawFd};
+
+    nix::unistd::setsid().context("failed to start a new session")?;
+    let null = fs::File::open("/dev/null").context("failed to open /dev/null")?;
+    nix::unistd::dup2(null.as_raw_fd(), 0).context("dup2 stdin")?;
+    nix::unistd::dup2(log.as_raw_fd(), 1).context("dup2 stdout")?;
+    nix::unistd::dup2(log.as_raw_fd(), 2).context("dup2 stderr")?;
+    nix::sched::setns(pid_ns.as_fd(), nix::sched::CloneFlags::CLONE_NEWPID)
+        .context("failed to setns into the container's pid namespace")?;
+
+    // SAFETY: the helper is single-threaded.
+    match unsafe { nix::unistd::fork() }.context("failed to fork exec command")? {
+        nix::unistd::ForkResult::Child => {
+            let started =
+                enter_container(meta).and_then(|()| start_in_container(meta, &session.cmd));
+            let err = match started {
+                Ok(()) => unreachable!("exec should have replaced this process"),
+                Err(e) => e,
+            };
+            eprintln!("craterun exec: {err:#}");
+            let code = err
+                .downcast_ref::<LaunchError>()
+                .map_or(1, LaunchError::exit_code);
+            std::process::exit(code);
+        }
+        nix::unistd::ForkResult::Parent { child } => {
+            session.pid = child.as_raw() as u32;
+            session.save()?;
+            write!(started, "{}", session.pid).context("failed to report exec PID")?;
+            drop(started);
+
+            let code = match waitpid(child, None).context("failed to wait for exec command")? {
+                WaitStatus::Exited(_, code) => code,
+                WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+                _ => EXIT_CODE_UNKNOWN,
+            };
+            session.exit_code = Some(code);
+            session.finished_at = Some(Utc::now());
+            session.save()
+        }
+    }
+}
+
+/// Run a command in a running container as the container's user, with its
+/// environment, exiting with its exit status. Joining the container's PID
+/// namespace only affects our children, so the command runs in a child,
+/// while we stay on the host to record its session.
+#[cfg(target_os = "linux")]
+fn exec_in_container(meta: &ContainerMeta, cmd: &[String]) -> Result<()> {
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use std::os::unix::io::AsFd;
 
     if cmd.is_empty() {
         bail!("no command specified for exec");
     }
+    let mut session = exec::ExecSession::new(&meta.id, cmd);
+    session.foreground = true;
+    session.save()?;
+    let pid_ns = format!("/proc/{}/ns/pid", meta.pid);
+    let pid_ns =
+        fs::File::open(&pid_ns).with_context(|| format!("failed to open namespace {pid_ns}"))?;
+    nix::sched::setns(pid_ns.as_fd(), nix::sched::CloneFlags::CLONE_NEWPID)
+        .context("failed to setns into the container's pid namespace")?;
+
+    // SAFETY: the CLI is single-threaded, so the child may do anything.
+    match unsafe { nix::unistd::fork() }.context("failed to fork exec command")? {
+        // A failure to start the command is reported like any other error.
+        nix::unistd::ForkResult::Child => {
+            enter_container(meta).and_then(|()| start_in_container(meta, cmd))
+        }
+        nix::unistd::ForkResult::Parent { child } => {
+            session.pid = child.as_raw() as u32;
+            session.save()?;
+            let code = match waitpid(child, None).context("failed to wait for exec command")? {
+                WaitStatus::Exited(_, code) => code,
+                WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+                _ => 1,
+            };
+            session.exit_code = Some(code);
+            session.finished_at = Some(Utc::now());
+            session.save()?;
+            std::process::exit(code);
+        }
+    }
+}
+
+/// Join the cgroup and namespaces of a running container, and make its root
+/// our root and working directory. The
+/// container's `oom_score_adj` is taken on first, while the host's /proc is
+/// still reachable.
+#[cfg(target_os = "linux")]
+fn enter_container(meta: &ContainerMeta) -> Result<()> {
+    use std::os::unix::io::{AsFd, AsRawFd};
+
+    if let Some(adj) = meta.oom_score_adj {
+        crate::platform::linux::process::set_oom_score_adj(adj)?;
+    }
+    let pid = meta.pid;
+
+    // The container's root as its init sees it, opened before joining its
+    // mount namespace: there, setns leaves us at the namespace's root
+    // mount, not necessarily the directory the container was pivoted into,
+    // and host paths like /proc/<pid> no longer resolve.
+    let root_path = format!("/proc/{pid}/root");
+    let root = fs::File::open(&root_path)
+        .with_context(|| format!("failed to open container root {root_path}"))?;
 
-    // Open the namespaces of the target process.
-    let ns_types = ["mnt", "pid", "uts", "ipc", "net"];
+    // Open the namespaces of the target process; its user namespace first,
+    // as the others belong to it.
+    let mut ns_types = vec!["mnt", "pid", "uts", "ipc", "net"];
+    if !meta.userns.is_host() {
+        ns_types.insert(0, "user");
+    }
+    // In the container's cgroup, exec'd commands share its limits and are
+    // killed with it. Joined before its cgroup namespace: outside the
+    // cgroup the namespace is rooted at, ours would show as a path climbing
+    // out of it.
+    let cgroup = crate::platform::linux::cgroups::cgroup_path(&meta.id);
+    crate::platform::linux::cgroups::add_process(&cgroup, std::process::id())?;
+    if !meta.cgroup_ns.is_host() {
+        ns_types.push("cgroup");
+    }
     let mut fds = Vec::new();
 
     for ns in &ns_types {
@@ -219,33 +2540,46 @@ fn exec_in_container(pid: u32, cmd: &[String]) -> Result<()> {
 
     // setns into each namespace.
     for (ns, file) in &fds {
-        use std::os::unix::io::AsFd;
         nix::sched::setns(file.as_fd(), nix::sched::CloneFlags::empty()).with_context(|| {
             format!("failed to setns into {ns} namespace of pid {pid}")
         })?;
     }
 
-    // chroot into the container's root.
-    let root_path = format!("/proc/{pid}/root");
-    nix::unistd::chroot(root_path.as_str())
-        .context("failed to chroot into container root")?;
+    // chroot into the container's root from inside it, so that no directory
+    // of ours is left outside it to climb out of.
+    nix::unistd::fchdir(root.as_raw_fd()).context("failed to chdir into container root")?;
+    nix::unistd::chroot(".").context("failed to chroot into container root")?;
     nix::unistd::chdir("/").context("chdir / after chroot")?;
+    Ok(())
+}
 
-    // exec
-    let program =
-        CString::new(cmd[0].as_str()).with_context(|| format!("invalid command: {}", cmd[0]))?;
-    let args: Vec<CString> = cmd
-        .iter()
-        .map(|a| CString::new(a.as_str()).context("invalid argument"))
-        .collect::<Result<_>>()?;
+/// Exec `cmd` in the container entered with [`enter_container`], with the
+/// container's limits, umask, keyring, user and environment. Only returns on failure.
+#[cfg(target_os = "linux")]
+fn start_in_container(meta: &ContainerMeta, cmd: &[String]) -> Result<()> {
+    use std::ffi::CString;
 
-    let env: Vec<CString> = vec![
-        CString::new("PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin").unwrap(),
-        CString::new("TERM=xterm").unwrap(),
-    ];
+    crate::platform::linux::process::apply_ulimits(&meta.ulimits)?;
+    if let Some(mask) = meta.umask {
+        crate::platform::linux::process::set_umask(mask);
+    }
+    if meta.keyring.is_private() {
+        crate::platform::linux::process::join_session_keyring()?;
+    }
+    if let Some(user) = &meta.user {
+        crate::platform::linux::process::switch_user(user)?;
+    }
 
-    nix::unistd::execve(&program, &args, &env)
-        .with_context(|| format!("execve '{}' failed", cmd[0]))?;
+    // Containers recorded before the environment was stored get the old
+    // minimal one.
+    let env = if meta.env.is_empty() {
+        vec![
+            CString::new(format!("PATH={DEFAULT_PATH}")).unwrap(),
+            CString::new("TERM=xterm").unwrap(),
+        ]
+    } else {
+        crate::platform::linux::process::env_cstrings(&meta.env)?
+    };
 
-    unreachable!()
+    crate::platform::linux::process::exec_command(cmd, None, &env)
 }
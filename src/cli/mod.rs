@@ -1,11 +1,64 @@
+pub mod api;
 pub mod commands;
+pub mod output;
+pub mod progress;
+pub mod project;
+pub mod spec;
 
-use clap::{Parser, Subcommand};
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::parser::ValueSource;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+
+use crate::core::model::{
+    CgroupNsMode, ContainerFilter, CpuLimit, DeviceMapping, Keyring, MacAddress, MountPropagation,
+    NamespaceMode, PidsLimit, PortMapping, ProcOptions, StorageDriver, Subnet, Sysctl, Timezone,
+    TmpfsMount, Ulimit, Umask, UserSpec, UsernsMode, RLIM_UNLIMITED,
+};
+use crate::core::registry::PullPolicy;
+use crate::core::{image, state, validate};
+use crate::util::signal;
+use crate::util::units::{self, ByteSize};
+
+use self::output::OutputFormat;
+use self::project::PROJECT_FILE;
 
 /// CrateRun — a minimal Linux container runtime.
 #[derive(Parser, Debug)]
 #[command(name = "craterun", version, about)]
 pub struct Cli {
+    /// Report failures on stderr as JSON (`{"error": ..., "context": [...]}`)
+    /// instead of text.
+    #[arg(long, global = true)]
+    pub json_errors: bool,
+
+    /// Output format of ps, inspect, logs, diff, check, images, import,
+    /// pull, network create, network ls, system df, system info, config
+    /// show, exec --detach and exec --list and the results of rm, prune,
+    /// rmi, stop, kill, update and network rm.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, global = true)]
+    pub format: OutputFormat,
+
+    /// Log what craterun does to stderr: -v for debug events (every mount,
+    /// cgroup write, namespace change and fork), -vv for trace.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Log level: off, error, warn, info, debug or trace. Overrides -v and
+    /// the CRATERUN_LOG environment variable.
+    #[arg(long, value_name = "LEVEL", global = true)]
+    pub log_level: Option<log::LevelFilter>,
+
+    /// Send the command to the `craterun api` server listening on this
+    /// socket, `unix:///PATH`, instead of carrying it out here. Only ps,
+    /// inspect, stop, rm and logs can be sent.
+    #[arg(long, value_name = "ADDRESS", value_parser = api::parse_host, global = true)]
+    pub host: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Command,
 }
@@ -13,79 +66,967 @@ pub struct Cli {
 #[derive(Subcommand, Debug)]
 pub enum Command {
     /// Create and run a new container.
-    Run {
-        /// Path to the root filesystem (e.g. an extracted Alpine minirootfs).
-        #[arg(long)]
-        rootfs: String,
-
-        /// Memory limit in bytes (e.g. 67108864 for 64 MiB). Passed to cgroup memory.max.
-        #[arg(long)]
-        memory: Option<u64>,
+    Run(Box<RunArgs>),
 
-        /// CPU bandwidth in the form `quota period` (microseconds), e.g. "100000 100000" for 100 %.
-        /// Passed to cgroup cpu.max.
+    /// List containers.
+    Ps {
+        /// After the listing, print the number of running and stopped
+        /// containers and the disk space their state directories use.
         #[arg(long)]
-        cpu: Option<String>,
+        summary: bool,
 
-        /// Maximum number of PIDs in the container.
+        /// Save the status of containers found to have exited since it was
+        /// last recorded. Without it `ps` only reads the state directory.
         #[arg(long)]
-        pids: Option<u64>,
+        sync: bool,
 
-        /// UID to map inside the container (host UID that becomes root inside). Optional.
-        #[arg(long)]
-        uid: Option<u32>,
+        /// Order of the rows: newest first, running before stopped (newest
+        /// first within each), or by ID. Broken containers come last.
+        #[arg(long, value_enum, default_value_t = PsSort::Created)]
+        sort: PsSort,
 
-        /// GID to map inside the container. Optional.
+        /// Reverse the order given by --sort.
         #[arg(long)]
-        gid: Option<u32>,
+        reverse: bool,
 
-        /// Hostname to set inside the container (default: "craterun").
-        #[arg(long, default_value = "craterun")]
-        hostname: String,
+        /// Only list the first N containers in that order.
+        #[arg(short = 'n', long, value_name = "N")]
+        last: Option<usize>,
 
-        /// The command (and arguments) to execute inside the container.
-        /// Everything after `--` is treated as the command.
-        #[arg(last = true, required = true)]
-        cmd: Vec<String>,
+        /// Show the exact creation time in CREATED instead of how long ago
+        /// it was.
+        #[arg(long)]
+        no_humanize: bool,
     },
 
-    /// List containers.
-    Ps,
-
-    /// Remove a stopped container.
+    /// Remove stopped containers, given by ID or selected with --filter.
     Rm {
-        /// Container ID (or unique prefix).
-        id: String,
+        /// Containers to remove, by ID (or unique prefix) or `latest`.
+        #[arg(value_name = "ID", required_unless_present_any = ["latest", "filter"])]
+        ids: Vec<String>,
 
-        /// Force-remove even if the container is still running.
+        /// Remove the most recently created container.
+        #[arg(short, long)]
+        latest: bool,
+
+        /// Remove the containers matching KEY=VALUE, with KEY `status`
+        /// (running, stopped or created) or `network`. Repeatable; a
+        /// container must match every filter.
+        #[arg(long, value_name = "KEY=VALUE")]
+        filter: Vec<ContainerFilter>,
+
+        /// Force-remove even if a container is still running, or broken
+        /// (its metadata cannot be loaded).
         #[arg(long)]
         force: bool,
     },
 
+    /// Remove all stopped containers.
+    Prune {
+        /// Remove the broken containers, whose metadata cannot be loaded,
+        /// instead, killing any processes left in their cgroups.
+        #[arg(long)]
+        broken: bool,
+    },
+
     /// Print the stdout/stderr logs of a container.
+    ///
+    /// By default the container's stdout goes to stdout and its stderr to
+    /// stderr.
     Logs {
-        /// Container ID (or unique prefix).
-        id: String,
+        #[command(flatten)]
+        container: ContainerRef,
+
+        /// Print only the container's stdout.
+        #[arg(long, conflicts_with = "stderr")]
+        stdout: bool,
+
+        /// Print only the container's stderr.
+        #[arg(long)]
+        stderr: bool,
+
+        /// Print both streams to stdout: all of stdout's lines, then all of
+        /// stderr's. The order they were written in is not kept.
+        #[arg(long, conflicts_with_all = ["stdout", "stderr"])]
+        combine: bool,
+
+        /// Print only the last N lines of each stream, or of the merged
+        /// one with --combine. Only the end of the log files is read.
+        #[arg(long, value_name = "N")]
+        tail: Option<usize>,
+
+        /// Truncate the logs instead of printing them. Only allowed for a
+        /// stopped container.
+        #[arg(long, conflicts_with_all = ["stdout", "stderr", "combine", "tail"])]
+        clear: bool,
     },
 
     /// Display detailed container metadata as JSON.
+    ///
+    /// With several containers the output is a JSON array of them.
     Inspect {
-        /// Container ID (or unique prefix).
-        id: String,
+        /// Container IDs (or unique prefixes), names, or `latest`.
+        #[arg(value_name = "ID", required_unless_present = "latest")]
+        ids: Vec<String>,
+
+        /// Act on the most recently created container. Cannot be combined with
+        /// an ID.
+        #[arg(short, long, conflicts_with = "ids")]
+        latest: bool,
+
+        /// Fail if any of several containers cannot be found or loaded,
+        /// instead of leaving it out with a warning.
+        #[arg(long)]
+        strict: bool,
+    },
+
+    /// List filesystem changes of a running image-backed container, as
+    /// `A` (added), `C` (changed) or `D` (deleted) followed by the path.
+    Diff {
+        #[command(flatten)]
+        container: ContainerRef,
+    },
+
+    /// Check that the host meets CrateRun's requirements, with a hint on
+    /// how to fix each one that it does not.
+    #[command(visible_alias = "doctor")]
+    Check {
+        /// Print the results as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Change the resource limits of a container.
+    ///
+    /// A running container's cgroup is updated in place; for a stopped
+    /// container only the recorded metadata changes.
+    Update {
+        #[command(flatten)]
+        container: ContainerRef,
+
+        #[command(flatten)]
+        limits: LimitArgs,
+    },
+
+    /// Import a rootfs tarball (plain or gzip-compressed) as a named image.
+    ///
+    /// Prints the path of the extracted rootfs, for use with `run --rootfs`.
+    Import {
+        /// The rootfs tarball, e.g. an Alpine minirootfs .tar.gz.
+        tarball: PathBuf,
+
+        /// Name of the new image.
+        name: String,
+
+        /// Expected SHA-256 digest of the tarball (64 hex digits, optionally
+        /// prefixed with `sha256:`). The import is refused on a mismatch.
+        #[arg(long, value_name = "SHA256", value_parser = parse_digest)]
+        verify: Option<String>,
+    },
+
+    /// Save a container's filesystem as a new image, as an overlay-backed
+    /// container sees it with its changes.
+    ///
+    /// Prints the path of the image's rootfs, for use with `run --rootfs`.
+    Commit {
+        /// The container, by ID, ID prefix or `latest`.
+        container: String,
+
+        /// Name of the new image.
+        name: String,
+    },
+
+    /// Pull an image from an OCI registry, e.g. docker.io/library/alpine:3.20
+    /// or alpine:3.20, as the image NAME:TAG.
+    ///
+    /// Prints the path of the image's rootfs, for use with `run --rootfs`.
+    Pull {
+        /// The image, as [REGISTRY/]REPOSITORY[:TAG]. The registry defaults
+        /// to docker.io, the tag to latest.
+        reference: String,
+
+        /// Keep the image under NAME instead of the last part of its
+        /// repository and its tag, e.g. alpine:3.20.
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// List imported, committed and pulled images.
+    Images,
+
+    /// Remove an imported image. Refused while a container uses it as its
+    /// rootfs.
+    Rmi {
+        /// Name of the image.
+        name: String,
+    },
+
+    /// Manage the networks `run --network` attaches containers to.
+    Network {
+        #[command(subcommand)]
+        command: NetworkCommand,
+    },
+
+    /// Report how craterun uses the host.
+    System {
+        #[command(subcommand)]
+        command: SystemCommand,
+    },
+
+    /// Check configuration files for `run --config`, and show containers'
+    /// configuration in their format.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Create and start the services of a project file in dependency order,
+    /// on a network of their own, then follow their logs.
+    ///
+    /// Services whose configuration is unchanged and that still run are
+    /// left alone; the others are recreated. Interrupting stops following
+    /// the logs, not the services.
+    Up {
+        /// The project file.
+        #[arg(short, long, value_name = "PATH", default_value = PROJECT_FILE)]
+        file: PathBuf,
+
+        /// Return once the services are started instead of following their
+        /// logs.
+        #[arg(short, long)]
+        detach: bool,
+    },
+
+    /// Stop and remove the containers of a project file's services, in
+    /// reverse dependency order, and then its network.
+    Down {
+        /// The project file.
+        #[arg(short, long, value_name = "PATH", default_value = PROJECT_FILE)]
+        file: PathBuf,
+
+        /// Seconds to wait for each service after its stop signal before
+        /// sending SIGKILL.
+        #[arg(short = 't', long, value_name = "SECONDS", default_value_t = 10)]
+        time: u64,
+    },
+
+    /// Serve a JSON-RPC control API on a Unix socket until killed, for
+    /// other programs and `--host` to run, list, inspect, stop, remove and
+    /// wait for containers and read their logs.
+    ///
+    /// Only the socket's owner can connect to it; change its permissions to
+    /// let others in.
+    Api {
+        /// The socket to listen on.
+        #[arg(long, value_name = "PATH", default_value = api::DEFAULT_SOCKET)]
+        socket: PathBuf,
+    },
+
+    /// Stop a running container: send its command the stop signal (SIGTERM
+    /// unless set with `run --stop-signal`), then SIGKILL if it is still
+    /// running after the timeout.
+    Stop {
+        #[command(flatten)]
+        containers: ContainerSelection,
+
+        /// Seconds to wait after the stop signal before sending SIGKILL.
+        #[arg(short = 't', long, value_name = "SECONDS", default_value_t = 10)]
+        time: u64,
+    },
+
+    /// Kill a running container with SIGKILL, without giving its command a
+    /// chance to stop.
+    Kill {
+        #[command(flatten)]
+        containers: ContainerSelection,
     },
 
     /// Execute a command inside a running container.
     Exec {
-        /// Container ID (or unique prefix).
-        id: String,
+        #[command(flatten)]
+        container: ContainerRef,
+
+        /// Run the command in the background and print its session ID. Its
+        /// output goes to a log in the container's state directory.
+        #[arg(short, long)]
+        detach: bool,
+
+        /// List the container's detached exec sessions instead of running a
+        /// command.
+        #[arg(long, conflicts_with_all = ["detach", "cmd"])]
+        list: bool,
 
         /// The command (and arguments) to execute.
-        #[arg(last = true, required = true)]
+        #[arg(last = true, required_unless_present = "list")]
         cmd: Vec<String>,
     },
 }
 
+/// The container a command acts on: an ID or unique prefix, or the most
+/// recently created container with `--latest` or the ID `latest`.
+#[derive(Args, Debug)]
+pub struct ContainerRef {
+    /// Container ID (or unique prefix), name, or `latest`.
+    #[arg(value_name = "ID", required_unless_present = "latest")]
+    id: Option<String>,
+
+    /// Act on the most recently created container. Cannot be combined with
+    /// an ID.
+    #[arg(short, long, conflicts_with = "id")]
+    latest: bool,
+}
+
+impl ContainerRef {
+    /// The reference to pass to [`state::resolve_id`].
+    pub fn as_str(&self) -> &str {
+        self.id.as_deref().unwrap_or(state::LATEST)
+    }
+}
+
+/// The containers `stop` and `kill` act on: one, given as for
+/// [`ContainerRef`], or every running container with `--all`.
+#[derive(Args, Debug)]
+pub struct ContainerSelection {
+    /// Container ID (or unique prefix), name, or `latest`.
+    #[arg(value_name = "ID", required_unless_present_any = ["latest", "all"])]
+    id: Option<String>,
+
+    /// Act on the most recently created container. Cannot be combined with
+    /// an ID.
+    #[arg(short, long, conflicts_with_all = ["id", "all"])]
+    latest: bool,
+
+    /// Act on every running container, several at a time, printing the
+    /// result for each. Fails if any of them failed.
+    #[arg(short, long, conflicts_with = "id")]
+    pub all: bool,
+
+    /// With --all, only act on the containers matching KEY=VALUE, as for
+    /// `rm --filter`. Repeatable; a container must match every filter.
+    // Not `requires = "all"`, which a flag's default of false satisfies.
+    #[arg(long, value_name = "KEY=VALUE", conflicts_with_all = ["id", "latest"])]
+    pub filter: Vec<ContainerFilter>,
+}
+
+impl ContainerSelection {
+    /// The reference to pass to [`state::resolve_id`], unless `--all`.
+    pub fn as_str(&self) -> Option<&str> {
+        (!self.all).then(|| self.id.as_deref().unwrap_or(state::LATEST))
+    }
+}
+
+/// Subcommands of `craterun network`.
+#[derive(Subcommand, Debug)]
+pub enum NetworkCommand {
+    /// Create a network: a host bridge, named craterun1, craterun2, ...,
+    /// whose containers get addresses from SUBNET.
+    Create {
+        /// Name of the new network.
+        name: String,
+
+        /// IPv4 subnet of the network in CIDR notation, e.g. 10.88.0.0/24.
+        /// The bridge takes its first address.
+        #[arg(long, value_name = "CIDR")]
+        subnet: Subnet,
+    },
+
+    /// List networks, starting with the built-in default network `bridge`.
+    Ls,
+
+    /// Remove a network and its host bridge. Refused while containers are
+    /// attached, unless forced.
+    Rm {
+        /// Name of the network.
+        name: String,
+
+        /// Remove the network even with containers attached; they lose their
+        /// connection to it.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Subcommands of `craterun system`.
+#[derive(Subcommand, Debug)]
+pub enum SystemCommand {
+    /// Show the disk space used by containers (logs, writable layers and
+    /// other state) and images, and how much of it can be reclaimed.
+    Df {
+        /// Also list the usage of each container and image.
+        #[arg(short, long)]
+        detailed: bool,
+
+        /// Warn about containers whose logs take more than SIZE, e.g. 100M.
+        #[arg(long, value_name = "SIZE")]
+        max_log_size: Option<ByteSize>,
+    },
+
+    /// Show the craterun build, its state directory and defaults, what the
+    /// host supports and how many containers there are; worth attaching
+    /// to bug reports.
+    Info,
+}
+
+/// Subcommands of `craterun config`.
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Check that a configuration file parses, with known keys and valid
+    /// values, without running anything.
+    Validate {
+        /// The file, TOML or, if it ends in .json, JSON.
+        path: PathBuf,
+    },
+
+    /// Print the effective configuration of a container as TOML, in the
+    /// format `run --config` reads.
+    Show {
+        #[command(flatten)]
+        container: ContainerRef,
+    },
+}
+
+/// Order of the containers listed by `ps`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PsSort {
+    /// By creation time, newest first.
+    #[default]
+    Created,
+    /// Running, then created, then stopped containers.
+    Status,
+    /// By container name, unnamed containers last.
+    Name,
+    /// By container ID.
+    Id,
+}
+
+/// Arguments of `craterun run`.
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// Read the container's configuration from PATH, a TOML file (JSON if
+    /// it ends in .json) with the keys `config show` prints. Flags given on
+    /// the command line override its values; --env is layered over its env.
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Give the container a unique NAME that commands accept in place of its
+    /// ID: lowercase letters, digits, '.', '_' and '-', not hex digits only.
+    #[arg(long, value_parser = parse_container_name)]
+    pub name: Option<String>,
+
+    /// Path to the root filesystem (e.g. an extracted Alpine minirootfs).
+    #[arg(long, required_unless_present_any = ["image", "config"])]
+    pub rootfs: Option<String>,
+
+    /// Run the image REF instead of a --rootfs: an imported, committed or
+    /// pulled image of that name, or [REGISTRY/]REPOSITORY[:TAG] in an OCI
+    /// registry, pulled as --pull says.
+    #[arg(long, value_name = "REF", conflicts_with = "rootfs")]
+    pub image: Option<String>,
+
+    /// When to pull the --image from its registry: `missing` (the default)
+    /// only if it has not been pulled yet, `always` to update it if its tag
+    /// has moved, `never` to fail instead.
+    // Not `requires = "image"`, which clap drops when --rootfs, which
+    // --image conflicts with, is given.
+    #[arg(
+        long,
+        value_name = "never|missing|always",
+        default_value = "missing",
+        conflicts_with = "rootfs"
+    )]
+    pub pull: PullPolicy,
+
+    #[command(flatten)]
+    pub limits: LimitArgs,
+
+    /// Process resource limit as TYPE=SOFT[:HARD], e.g. nofile=1024:4096 or
+    /// core=0. TYPE is one of nofile, nproc, core, stack, memlock; values may
+    /// be "unlimited". Repeatable.
+    #[arg(long = "ulimit", value_name = "TYPE=SOFT[:HARD]")]
+    pub ulimits: Vec<Ulimit>,
+
+    /// Disable core dumps of the container's processes, which land wherever
+    /// the host's core_pattern says and can fill its disks. Same as
+    /// --ulimit core=0.
+    #[arg(long, conflicts_with = "core_limit")]
+    pub no_core: bool,
+
+    /// Largest core dump of the container's processes, e.g. 64M, or
+    /// "unlimited". Same as --ulimit core=SIZE.
+    #[arg(long, value_name = "SIZE", value_parser = parse_core_limit)]
+    pub core_limit: Option<u64>,
+
+    /// OOM killer score adjustment of the container command, from -1000
+    /// (never kill) to 1000 (kill first).
+    #[arg(
+        long,
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i32).range(-1000..=1000)
+    )]
+    pub oom_score_adj: Option<i32>,
+
+    /// File mode creation mask of the container command and exec sessions,
+    /// in octal, e.g. 0027. Defaults to craterun's own.
+    #[arg(long, value_name = "OCTAL")]
+    pub umask: Option<Umask>,
+
+    /// Time zone of the container: a zone like Europe/Berlin from the
+    /// host's /usr/share/zoneinfo, or `host` for the host's own. Bound
+    /// read-only over the container's /etc/localtime, with TZ set to it.
+    #[arg(long, value_name = "ZONE|host")]
+    pub timezone: Option<Timezone>,
+
+    /// Pass a host device into the container, as
+    /// HOSTPATH[:CONTAINERPATH][:rwm], e.g. /dev/fuse. Repeatable.
+    #[arg(long = "device", value_name = "HOSTPATH[:CONTAINERPATH][:rwm]")]
+    pub devices: Vec<DeviceMapping>,
+
+    /// Mount a tmpfs inside the container, as PATH[:OPTIONS] with OPTIONS
+    /// from size=SIZE, mode=OCTAL, noexec, nosuid, nodev and ro, e.g.
+    /// /tmp:size=16m,noexec. The size defaults to the memory limit, or 64M.
+    /// Repeatable.
+    #[arg(long, value_name = "PATH[:OPTIONS]")]
+    pub tmpfs: Vec<TmpfsMount>,
+
+    /// Size of the container's /dev/shm, e.g. 1G, for programs that share
+    /// memory through it such as databases and browsers. Defaults to 64M.
+    #[arg(long, value_name = "SIZE")]
+    pub shm_size: Option<ByteSize>,
+
+    /// Cap the container's writable layer at SIZE, e.g. 1G, so that writes
+    /// past it fail inside the container instead of filling the host's
+    /// disk. Needs a squashfs or EROFS image rootfs: a directory rootfs is
+    /// changed in place.
+    #[arg(long, value_name = "SIZE")]
+    pub storage_size: Option<ByteSize>,
+
+    /// How --storage-size is enforced: `quota` (the default) sets a project
+    /// quota on the writable layer, which needs project quotas enabled on
+    /// the filesystem of craterun's state directory (xfs, or ext4 mounted
+    /// with prjquota); `loop` keeps it in an ext4 image of the size, made
+    /// with mkfs.ext4; `tmpfs` keeps it in a tmpfs of the size, in memory.
+    #[arg(
+        long,
+        value_name = "quota|loop|tmpfs",
+        default_value = "quota",
+        requires = "storage_size"
+    )]
+    pub storage_driver: StorageDriver,
+
+    /// Set a namespaced kernel parameter inside the container, as KEY=VALUE,
+    /// e.g. net.ipv4.ip_unprivileged_port_start=0. Repeatable.
+    #[arg(long = "sysctl", value_name = "KEY=VALUE")]
+    pub sysctls: Vec<Sysctl>,
+
+    /// Enter the rootfs with a mount move and `chroot` instead of
+    /// `pivot_root`, e.g. when the host root is a ramfs.
+    #[arg(long)]
+    pub no_pivot: bool,
+
+    /// Fall back to a mount move and `chroot`, as with --no-pivot, where
+    /// `pivot_root` is not possible, accepting that it isolates the
+    /// container less. Without it, `run` then fails.
+    #[arg(long, conflicts_with = "no_pivot")]
+    pub allow_chroot_fallback: bool,
+
+    /// Do not check that the command exists in the rootfs, and is
+    /// executable, before starting the container; a missing command is
+    /// then only reported by the container itself, with exit code 127.
+    #[arg(long)]
+    pub no_preflight: bool,
+
+    /// Connect craterun's stdin to the container command's, e.g. to pipe
+    /// data through it. Without it the command's stdin is /dev/null.
+    #[arg(short = 'i', long)]
+    pub attach_stdin: bool,
+
+    /// Mount propagation between the container and the host: `private`
+    /// (the default) isolates both ways, `slave` lets host mount events
+    /// reach the container, `shared` also lets the container's mounts
+    /// appear on the host.
+    #[arg(
+        long,
+        visible_alias = "rootfs-propagation",
+        value_name = "private|slave|shared",
+        default_value = "private"
+    )]
+    pub mount_propagation: MountPropagation,
+
+    /// Harden the container's /proc, as a comma-separated list of `ro`
+    /// (read-only, so /proc/sys cannot be written even by the container's
+    /// root) and `hidepid=2` (users only see their own processes).
+    #[arg(long, value_name = "ro,hidepid=2")]
+    pub proc_opts: Option<ProcOptions>,
+
+    /// Session keyring of the container's processes: `private` (the
+    /// default) gives them a fresh one, `host` keeps the caller's, for
+    /// workloads that need its keys.
+    #[arg(long, value_name = "private|host", default_value = "private")]
+    pub keyring: Keyring,
+
+    /// User namespace of the container: `host` (the default) or `keep-id`,
+    /// a new one in which the calling user's UID and GID are their own and
+    /// the other IDs come from the user's /etc/subuid and /etc/subgid
+    /// ranges. The command runs as the calling user unless --user is given.
+    #[arg(long, value_name = "host|keep-id", default_value = "host")]
+    pub userns: UsernsMode,
+
+    /// Cgroup namespace of the container: `private` (the default) roots
+    /// it at the container's cgroup, so the host's cgroup hierarchy does
+    /// not show in /proc/self/cgroup; `host` keeps the host's.
+    #[arg(long, value_name = "private|host", default_value = "private")]
+    pub cgroup_ns: CgroupNsMode,
+
+    /// UID to map inside the container (host UID that becomes root inside). Optional.
+    #[arg(long)]
+    pub uid: Option<u32>,
+
+    /// GID to map inside the container. Optional.
+    #[arg(long)]
+    pub gid: Option<u32>,
+
+    /// Hostname to set inside the container (default: "craterun"). Also
+    /// written to the container's /etc/hostname. Not allowed with a shared
+    /// UTS namespace.
+    #[arg(long, value_parser = parse_hostname)]
+    pub hostname: Option<String>,
+
+    /// NIS domain name to set inside the container.
+    #[arg(long, value_parser = parse_domainname)]
+    pub domainname: Option<String>,
+
+    /// Network namespace: `private` (or `none`) for a new one with only
+    /// loopback, `host` for the host's, `container:<id>` to share that of a
+    /// running container, `bridge` for a new one attached to the craterun0
+    /// host bridge, or `slirp` for a new one with user-mode networking
+    /// through slirp4netns.
+    #[arg(
+        long,
+        value_name = "private|host|container:<id>|bridge|slirp",
+        default_value = "private"
+    )]
+    pub net: NamespaceMode,
+
+    /// Attach the container to the bridge of the network NAME (see
+    /// `craterun network`) instead of the default one. Implies --net bridge.
+    #[arg(long, value_name = "NAME", conflicts_with = "net")]
+    pub network: Option<String>,
+
+    /// Address of the container on the bridge, instead of the lowest free
+    /// one. Needs --net bridge or --network.
+    #[arg(long, value_name = "IPV4")]
+    pub ip: Option<Ipv4Addr>,
+
+    /// MAC address of the container's bridge interface, e.g.
+    /// 02:42:ac:11:00:02, instead of one derived from its IP address. Needs
+    /// --net bridge or --network.
+    #[arg(long, value_name = "MAC")]
+    pub mac_address: Option<MacAddress>,
+
+    /// Forward a host port into the container, as
+    /// [HOSTADDR:]HOSTPORT:CONTAINERPORT[/tcp|udp], e.g. 8080:80. Needs
+    /// --net slirp. Repeatable.
+    #[arg(
+        short = 'p',
+        long,
+        value_name = "[HOSTADDR:]HOSTPORT:CONTAINERPORT[/PROTO]"
+    )]
+    pub publish: Vec<PortMapping>,
+
+    /// PID namespace: `private`, `host` or `container:<id>`.
+    #[arg(
+        long,
+        value_name = "private|host|container:<id>",
+        default_value = "private",
+        value_parser = parse_shared_namespace
+    )]
+    pub pid: NamespaceMode,
+
+    /// IPC namespace: `private`, `host` or `container:<id>`.
+    #[arg(
+        long,
+        value_name = "private|host|container:<id>",
+        default_value = "private",
+        value_parser = parse_shared_namespace
+    )]
+    pub ipc: NamespaceMode,
+
+    /// UTS (hostname) namespace: `private`, `host` or `container:<id>`.
+    #[arg(
+        long,
+        value_name = "private|host|container:<id>",
+        default_value = "private",
+        value_parser = parse_shared_namespace
+    )]
+    pub uts: NamespaceMode,
+
+    /// Run the command with this argv[0] instead of the command path, e.g. to
+    /// pick an applet of a busybox-style multi-call binary.
+    #[arg(long)]
+    pub argv0: Option<String>,
+
+    /// Run PATH as the container's PID 1, with the command as its
+    /// arguments, e.g. an init like tini or dumb-init that forwards signals
+    /// and reaps zombies. PATH is taken from the rootfs if it is there, else
+    /// from the host and bound read-only at /dev/init, so it should be a
+    /// static build.
+    #[arg(long, value_name = "PATH", conflicts_with = "argv0")]
+    pub init_path: Option<String>,
+
+    /// Run the command as USER[:GROUP], each a name from the rootfs's
+    /// /etc/passwd and /etc/group or a numeric ID, e.g. nobody or 1000:1000.
+    /// Also the default user of `exec`.
+    #[arg(long, value_name = "USER[:GROUP]")]
+    pub user: Option<UserSpec>,
+
+    /// Set an environment variable in the container, as KEY=VALUE. Overrides
+    /// every other source. Repeatable.
+    #[arg(long = "env", value_name = "KEY=VALUE")]
+    pub env: Vec<String>,
+
+    /// Read environment variables from a file of KEY=VALUE lines; blank
+    /// lines and lines starting with '#' are skipped. Repeatable.
+    #[arg(long = "env-file", value_name = "PATH")]
+    pub env_files: Vec<PathBuf>,
+
+    /// Copy a variable from the host environment into the container, if set.
+    /// Repeatable.
+    #[arg(long = "preserve-env", value_name = "VAR")]
+    pub preserve_env: Vec<String>,
+
+    /// Copy the whole host environment into the container, under --env and
+    /// --env-file, except variables describing the host session (HOME, PATH,
+    /// HOSTNAME, PWD, OLDPWD, SHLVL and _) or handing over its agents
+    /// (SSH_AUTH_SOCK, SSH_AGENT_PID, GPG_AGENT_INFO, DBUS_SESSION_BUS_ADDRESS,
+    /// XDG_RUNTIME_DIR and XAUTHORITY) unless they are named with
+    /// --preserve-env.
+    #[arg(long, visible_alias = "env-host")]
+    pub preserve_env_all: bool,
+
+    /// Check the container's health by running CMD inside it with
+    /// `/bin/sh -c`, like `exec`. Exit status 0 means healthy. `ps` shows the
+    /// result and `inspect` the details.
+    #[arg(long, value_name = "CMD")]
+    pub health_cmd: Option<String>,
+
+    /// Time between health checks (e.g. 30s, 1m); a check running longer
+    /// fails.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "30s",
+        value_parser = parse_interval,
+        requires = "health_cmd"
+    )]
+    pub health_interval: Duration,
+
+    /// Consecutive failed health checks after which the container is
+    /// unhealthy.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 3,
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "health_cmd"
+    )]
+    pub health_retries: u32,
+
+    /// Signal `stop` sends to the command before escalating to SIGKILL, by
+    /// name or number (e.g. QUIT, SIGUSR1, 3). Defaults to SIGTERM.
+    #[arg(long, value_name = "SIGNAL", value_parser = parse_signal)]
+    pub stop_signal: Option<String>,
+
+    /// Validate the configuration and print the resolved plan as JSON without
+    /// creating the container.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Do not show what setup step is in progress while the container starts.
+    /// The status is only shown when stderr is a terminal.
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// Write the container ID to this file once the container has started.
+    /// Fails if the file already exists.
+    #[arg(long)]
+    pub cidfile: Option<PathBuf>,
+
+    /// Write the host PID of the container to this file once it has started,
+    /// and remove it when the container exits. Fails if the file exists and
+    /// names a running process; a stale file is replaced.
+    #[arg(long)]
+    pub pidfile: Option<PathBuf>,
+
+    /// Read the command from a file instead, one argument per line, with
+    /// no quoting or escaping. `-` reads it from stdin.
+    #[arg(long, value_name = "PATH", conflicts_with = "cmd")]
+    pub cmd_file: Option<PathBuf>,
+
+    /// Separate the arguments of --cmd-file with NUL bytes instead of
+    /// newlines, e.g. for the output of `find -print0`.
+    #[arg(long, requires = "cmd_file", conflicts_with = "cmd")]
+    pub null: bool,
+
+    /// The command (and arguments) to execute inside the container.
+    /// Everything after `--` is treated as the command.
+    #[arg(last = true, required_unless_present_any = ["cmd_file", "config"])]
+    pub cmd: Vec<String>,
+
+    /// IDs of the arguments given on the command line rather than left at
+    /// their defaults, which a --config file does not override.
+    #[arg(skip)]
+    pub given: BTreeSet<String>,
+}
+
+/// Resource-limit flags shared by `run` and `update`.
+#[derive(Args, Debug)]
+pub struct LimitArgs {
+    /// Memory limit, in bytes or with a unit suffix (e.g. 64M, 1G). Passed to
+    /// cgroup memory.max.
+    #[arg(long)]
+    pub memory: Option<ByteSize>,
+
+    /// Swappiness of the container's memory. cgroup v2 has no per-cgroup
+    /// swappiness, so only 0 (never swap) is accepted, written to cgroup
+    /// memory.swap.max.
+    #[arg(long, value_name = "0..100", value_parser = parse_memory_swappiness)]
+    pub memory_swappiness: Option<u8>,
+
+    /// CPU bandwidth in the form `quota period` (microseconds), e.g. "100000 100000" for 100 %.
+    /// The quota may be `max`, and a lone `max` removes the limit. Passed to
+    /// cgroup cpu.max.
+    #[arg(long, conflicts_with = "cpus")]
+    pub cpu: Option<CpuLimit>,
+
+    /// Number of CPUs the container may use, e.g. 0.5 or 2.
+    #[arg(long, value_parser = parse_cpus)]
+    pub cpus: Option<CpuLimit>,
+
+    /// Maximum number of PIDs in the container, or "unlimited". `run`
+    /// defaults to 1024.
+    #[arg(long)]
+    pub pids: Option<PidsLimit>,
+
+    /// CPUs the container may run on, as a list like "0-3,7". Written to
+    /// cgroup cpuset.cpus.
+    #[arg(long, value_parser = parse_cpu_list)]
+    pub cpuset_cpus: Option<String>,
+
+    /// Memory (NUMA) nodes the container may allocate from, e.g. "0".
+    /// Written to cgroup cpuset.mems.
+    #[arg(long, value_parser = parse_cpu_list)]
+    pub cpuset_mems: Option<String>,
+}
+
+/// Parse a `--cpus` value into a CPU limit.
+fn parse_cpus(s: &str) -> Result<CpuLimit, String> {
+    let cpus: f64 = s
+        .parse()
+        .map_err(|_| format!("'{s}' is not a number"))?;
+    CpuLimit::from_cpus(cpus).map_err(|e| e.to_string())
+}
+
+/// Parse a `--memory-swappiness` value, rejecting the ones cgroup v2 cannot
+/// honor.
+fn parse_memory_swappiness(s: &str) -> Result<u8, String> {
+    match s.parse::<u8>() {
+        Ok(0) => Ok(0),
+        Ok(1..=100) => Err(
+            "cgroup v2 has no per-container swappiness, so only 0 (never swap, applied as \
+             memory.swap.max=0) can be honored; tune vm.swappiness on the host instead"
+                .to_string(),
+        ),
+        _ => Err(format!("'{s}' is not a number from 0 to 100")),
+    }
+}
+
+/// Validate a `--name`.
+fn parse_container_name(s: &str) -> Result<String, String> {
+    state::validate_container_name(s)
+        .map(|()| s.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Validate a `--hostname` as RFC 1123.
+fn parse_hostname(s: &str) -> Result<String, String> {
+    validate::validate_hostname(s)
+        .map(|()| s.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Validate a `--domainname` with the hostname rules.
+fn parse_domainname(s: &str) -> Result<String, String> {
+    validate::validate_domainname(s)
+        .map(|()| s.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Parse a non-zero duration like `30s`.
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    match units::parse_duration(s) {
+        Ok(d) if d.is_zero() => Err("must be greater than zero".to_string()),
+        Ok(d) => Ok(d),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parse the mode of a namespace other than the network's, for which
+/// `bridge` and `slirp` mean nothing.
+fn parse_shared_namespace(s: &str) -> Result<NamespaceMode, String> {
+    match s.parse() {
+        Ok(mode @ (NamespaceMode::Bridge | NamespaceMode::Slirp)) => {
+            Err(format!("{mode} is only available for --net"))
+        }
+        Ok(mode) => Ok(mode),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parse a signal name or number into the signal's name, e.g. `SIGQUIT`.
+fn parse_signal(s: &str) -> Result<String, String> {
+    signal::parse_signal(s)
+        .map(|signal| signal.as_str().to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Normalize a `--verify` digest to `sha256:<hex>`.
+fn parse_digest(s: &str) -> Result<String, String> {
+    image::parse_digest(s).map_err(|e| e.to_string())
+}
+
+/// Parse a `--core-limit`: a size in bytes or `unlimited`.
+fn parse_core_limit(s: &str) -> Result<u64, String> {
+    match s {
+        "unlimited" => Ok(RLIM_UNLIMITED),
+        _ => units::parse_bytes(s).map_err(|e| e.to_string()),
+    }
+}
+
+/// Validate a CPU/node list like `0-3,7`, keeping it as written.
+fn parse_cpu_list(s: &str) -> Result<String, String> {
+    validate::parse_cpu_list(s)
+        .map(|_| s.to_string())
+        .map_err(|e| e.to_string())
+}
+
 /// Parse CLI arguments. Called from `main`.
 pub fn parse() -> Cli {
-    Cli::parse()
+    try_parse_from(std::env::args_os()).unwrap_or_else(|e| e.exit())
+}
+
+/// Parse `args`, starting with the program name, recording in
+/// [`RunArgs::given`] which arguments of `run` were given.
+fn try_parse_from(
+    args: impl IntoIterator<Item = impl Into<OsString> + Clone>,
+) -> clap::error::Result<Cli> {
+    let matches = Cli::command().try_get_matches_from(args)?;
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    if let (Command::Run(args), Some(("run", run))) = (&mut cli.command, matches.subcommand()) {
+        args.given = run
+            .ids()
+            .filter(|id| run.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+            .map(|id| id.to_string())
+            .collect();
+    }
+    Ok(cli)
 }
@@ -0,0 +1,651 @@
+//! Machine-readable output of the read commands (`--format json`).
+//!
+//! Every JSON document craterun prints is one of the structs below, so the
+//! schema only changes when they do. Container IDs are always printed in
+//! full.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::core::exec::ExecSession;
+use crate::core::image::{ImageInfo, Provenance};
+use crate::core::model::{ContainerMeta, ContainerStatus, FsChange, HealthStatus, HostInfo};
+use crate::core::network::NetworkInfo;
+
+/// How commands print their results.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable tables and messages.
+    #[default]
+    Text,
+    /// JSON documents (JSON lines for `logs`).
+    Json,
+}
+
+/// One container as listed by `ps`.
+#[derive(Debug, Serialize)]
+pub struct ContainerSummary<'a> {
+    pub id: &'a str,
+    /// Host PID of the container's init, while it runs.
+    pub pid: Option<u32>,
+    pub status: ContainerStatus,
+    /// The `STATUS` column of `ps`, e.g. `"Up 3 minutes"`.
+    pub status_text: String,
+    pub exit_code: Option<i32>,
+    pub signal: Option<&'a str>,
+    pub health: Option<HealthStatus>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Whole seconds the container has been (or was) running.
+    pub duration_secs: Option<u64>,
+    /// Published ports while the container runs, e.g.
+    /// `"0.0.0.0:8080->80/tcp"`.
+    pub ports: Vec<String>,
+    pub command: &'a [String],
+}
+
+impl<'a> ContainerSummary<'a> {
+    /// Summarize `meta` as of `now`.
+    pub fn new(meta: &'a ContainerMeta, now: DateTime<Utc>) -> Self {
+        let running = meta.status == ContainerStatus::Running;
+        Self {
+            id: &meta.id,
+            pid: (meta.pid > 0).then_some(meta.pid),
+            status: meta.status,
+            status_text: meta.status_summary(now),
+            exit_code: if running { None } else { meta.exit_code },
+            signal: meta.signal.as_deref(),
+            health: meta.health.as_ref().map(|h| h.status),
+            created_at: meta.created_at,
+            started_at: meta.started_at,
+            finished_at: meta.finished_at,
+            duration_secs: meta.run_duration(now).map(|d| d.as_secs()),
+            ports: meta.port_summaries(),
+            command: &meta.cmd,
+        }
+    }
+}
+
+/// A container whose metadata cannot be loaded, as listed by `ps`.
+#[derive(Debug, Serialize)]
+pub struct BrokenContainer {
+    pub id: String,
+    /// Always `"broken"`.
+    pub status: &'static str,
+    /// Why the metadata could not be loaded.
+    pub error: String,
+}
+
+impl BrokenContainer {
+    pub fn new(id: &str, error: &anyhow::Error) -> Self {
+        Self {
+            id: id.to_string(),
+            status: "broken",
+            error: format!("{error:#}"),
+        }
+    }
+}
+
+/// One entry of `ps`: a container, or a broken one.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum PsEntry<'a> {
+    Container(ContainerSummary<'a>),
+    Broken(&'a BrokenContainer),
+}
+
+/// The output of `ps --summary`: the listing followed by its totals.
+#[derive(Debug, Serialize)]
+pub struct PsListing<'a> {
+    pub containers: Vec<PsEntry<'a>>,
+    pub summary: PsTotals,
+}
+
+/// The totals printed by `ps --summary`.
+#[derive(Debug, Serialize)]
+pub struct PsTotals {
+    pub containers: usize,
+    pub running: usize,
+    /// Containers not running, including any never started.
+    pub stopped: usize,
+    /// Containers whose metadata cannot be loaded.
+    pub broken: usize,
+    /// Bytes used by the containers' state directories: logs, overlay upper
+    /// directories and other records.
+    pub disk_usage: u64,
+}
+
+/// The full metadata of a container, as printed by `inspect`.
+#[derive(Debug, Serialize)]
+pub struct ContainerDetails<'a> {
+    #[serde(flatten)]
+    pub meta: &'a ContainerMeta,
+    /// How long the container has been (or was) running, e.g. `"3m12s"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<String>,
+    /// How the container's command ended, e.g. `"exited (42)"` or
+    /// `"killed (SIGKILL)"`. Unset while it runs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit: Option<String>,
+    /// How many `exec` sessions the container has had.
+    pub exec_sessions: usize,
+}
+
+/// One line of a container's logs, as printed by `logs`.
+#[derive(Debug, Serialize)]
+pub struct LogLine<'a> {
+    /// `"stdout"` or `"stderr"`.
+    pub stream: &'static str,
+    pub line: &'a str,
+}
+
+/// One filesystem change, as listed by `diff`.
+#[derive(Debug, Serialize)]
+pub struct ChangeLine<'a> {
+    /// `"A"` (added), `"C"` (changed) or `"D"` (deleted).
+    pub kind: String,
+    pub path: &'a str,
+}
+
+impl<'a> From<&'a FsChange> for ChangeLine<'a> {
+    fn from(change: &'a FsChange) -> Self {
+        Self {
+            kind: change.kind.to_string(),
+            path: &change.path,
+        }
+    }
+}
+
+/// One image, as listed by `images` or created by `import`.
+#[derive(Debug, Serialize)]
+pub struct ImageSummary<'a> {
+    pub name: &'a str,
+    pub digest: &'a str,
+    /// Size of the extracted rootfs, in bytes.
+    pub size: u64,
+    pub created_at: DateTime<Utc>,
+    pub rootfs: &'a Path,
+    /// Where an image made by `commit` came from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<&'a Provenance>,
+    /// The registry reference of an image made by `pull`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<&'a str>,
+}
+
+impl<'a> From<&'a ImageInfo> for ImageSummary<'a> {
+    fn from(image: &'a ImageInfo) -> Self {
+        Self {
+            name: &image.name,
+            digest: &image.digest,
+            size: image.size,
+            created_at: image.created_at,
+            rootfs: &image.rootfs,
+            provenance: image.provenance.as_ref(),
+            source: image.source.as_deref(),
+        }
+    }
+}
+
+/// The outcome of a command that changes a container (`rm`, `stop`, `kill`,
+/// `update`, `logs --clear`).
+#[derive(Debug, Serialize)]
+pub struct ContainerResult<'a> {
+    pub id: &'a str,
+    /// What was done: `"removed"`, `"stopped"`, `"killed"`, `"updated"` or
+    /// `"logs_cleared"`.
+    pub result: &'static str,
+}
+
+/// A container removed by `prune`.
+#[derive(Debug, Serialize)]
+pub struct PruneResult<'a> {
+    pub id: &'a str,
+    /// What was done: `"removed"`.
+    pub result: &'static str,
+    /// Bytes freed by removing the container's state directory.
+    pub reclaimed: u64,
+}
+
+/// The output of `system df`: disk space used by containers and images.
+#[derive(Debug, Serialize)]
+pub struct DiskUsageReport<'a> {
+    pub containers: ContainersUsage,
+    pub images: ImagesUsage,
+    /// Bytes used by containers and images together.
+    pub size: u64,
+    /// Bytes `prune` and `rmi` of the unused images would free.
+    pub reclaimable: u64,
+    /// Files and directories that could not be read, and are missing from
+    /// the sizes.
+    pub unreadable: u64,
+    /// Usage of each container, with `--detailed`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_details: Option<Vec<ContainerUsage<'a>>>,
+    /// Usage of each image, with `--detailed`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_details: Option<Vec<ImageUsage<'a>>>,
+}
+
+/// The containers' share of `system df`: their state directories.
+#[derive(Debug, Default, Serialize)]
+pub struct ContainersUsage {
+    pub count: usize,
+    pub running: usize,
+    pub size: u64,
+    /// Bytes of log files, included in `size`.
+    pub logs: u64,
+    /// Bytes of image containers' writable layers, included in `size`.
+    pub writable: u64,
+    /// Bytes used by containers that are not running.
+    pub reclaimable: u64,
+}
+
+/// The images' share of `system df`: their extracted rootfs.
+#[derive(Debug, Default, Serialize)]
+pub struct ImagesUsage {
+    pub count: usize,
+    /// Images that the rootfs of at least one container is in.
+    pub in_use: usize,
+    pub size: u64,
+    /// Bytes used by images no container uses.
+    pub reclaimable: u64,
+}
+
+/// One container as listed by `system df --detailed`.
+#[derive(Debug, Serialize)]
+pub struct ContainerUsage<'a> {
+    pub id: &'a str,
+    /// `"running"`, `"stopped"`, `"created"` or `"broken"`.
+    pub status: String,
+    pub size: u64,
+    pub logs: u64,
+    pub writable: u64,
+    /// `--storage-size` of the writable layer, if set.
+    pub storage_limit: Option<u64>,
+}
+
+/// One image as listed by `system df --detailed`.
+#[derive(Debug, Serialize)]
+pub struct ImageUsage<'a> {
+    pub name: &'a str,
+    /// How many containers use the image.
+    pub containers: usize,
+    pub size: u64,
+}
+
+/// The output of `system info`: how craterun was built, where it keeps its
+/// state, what the host offers it and its defaults.
+#[derive(Debug, Serialize)]
+pub struct SystemInfo<'a> {
+    pub version: &'static str,
+    /// `"release"` or `"debug"`.
+    pub build: &'static str,
+    /// Platform the binary was built for, e.g. `"x86_64-linux"`.
+    pub target: String,
+    pub state_dir: &'a Path,
+    /// Bytes free on the state directory's filesystem, if it could be read.
+    pub state_dir_free: Option<u64>,
+    #[serde(flatten)]
+    pub host: &'a HostInfo,
+    pub containers: ContainerCounts,
+    /// The network `--net bridge` attaches containers to.
+    pub default_network: NetworkSummary<'a>,
+}
+
+/// The containers of `system info`, by status.
+#[derive(Debug, Default, Serialize)]
+pub struct ContainerCounts {
+    pub running: usize,
+    pub stopped: usize,
+    pub created: usize,
+    /// Containers whose metadata cannot be loaded.
+    pub broken: usize,
+}
+
+/// One network, as listed by `network ls` or created by `network create`.
+#[derive(Debug, Serialize)]
+pub struct NetworkSummary<'a> {
+    pub name: &'a str,
+    pub bridge: &'a str,
+    pub subnet: String,
+    pub gateway: String,
+    /// Number of containers holding an address on the network.
+    pub containers: usize,
+    /// `null` for the built-in default network.
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl<'a> NetworkSummary<'a> {
+    /// Summarize `network`, which `containers` containers are attached to.
+    pub fn new(network: &'a NetworkInfo, containers: usize) -> Self {
+        Self {
+            name: &network.name,
+            bridge: &network.bridge,
+            subnet: network.subnet.to_string(),
+            gateway: network.gateway().to_string(),
+            containers,
+            created_at: network.created_at,
+        }
+    }
+}
+
+/// One exec session, as listed by `exec --list` or started by
+/// `exec --detach`.
+#[derive(Debug, Serialize)]
+pub struct ExecSummary<'a> {
+    pub id: &'a str,
+    pub pid: u32,
+    pub cmd: &'a [String],
+    /// `running`, `exited (N)` or `unknown`.
+    pub status: String,
+    pub exit_code: Option<i32>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// The file the command's stdout and stderr go to; `null` for a
+    /// session run in the foreground.
+    pub log: Option<PathBuf>,
+}
+
+impl<'a> ExecSummary<'a> {
+    pub fn new(session: &'a ExecSession) -> Result<Self> {
+        Ok(Self {
+            id: &session.id,
+            pid: session.pid,
+            cmd: &session.cmd,
+            status: session.status_summary(),
+            exit_code: session.exit_code,
+            started_at: session.started_at,
+            finished_at: session.finished_at,
+            log: (!session.foreground)
+                .then(|| session.log_path())
+                .transpose()?,
+        })
+    }
+}
+
+/// The outcome of `rmi`.
+#[derive(Debug, Serialize)]
+pub struct ImageResult<'a> {
+    pub name: &'a str,
+    /// What was done: `"removed"`.
+    pub result: &'static str,
+}
+
+/// The outcome of `network rm`.
+#[derive(Debug, Serialize)]
+pub struct NetworkResult<'a> {
+    pub name: &'a str,
+    /// What was done: `"removed"`.
+    pub result: &'static str,
+}
+
+/// Print `value` as pretty-printed JSON on stdout.
+pub fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    let json = serde_json::to_string_pretty(value).context("failed to serialize output")?;
+    println!("{json}");
+    Ok(())
+}
+
+/// Print `value` as a single line of JSON on stdout.
+pub fn print_json_line<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    let json = serde_json::to_string(value).context("failed to serialize output")?;
+    println!("{json}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::{CgroupMode, FsChangeKind};
+    use std::path::PathBuf;
+
+    fn meta() -> ContainerMeta {
+        let json = r#"{"schema_version":1,"id":"0123456789abcdef",
+            "rootfs":"/tmp/rootfs","cmd":["/bin/sh","-c","exit 3"],"pid":0,"exit_code":3,
+            "created_at":"2024-01-01T00:00:00Z","started_at":"2024-01-01T00:00:01Z",
+            "finished_at":"2024-01-01T00:01:01Z","status":"stopped","hostname":"h",
+            "memory_limit":null,"cpu_limit":null,"pids_limit":1024}"#;
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn now() -> DateTime<Utc> {
+        "2024-01-01T02:00:00Z".parse().unwrap()
+    }
+
+    // The tests below pin the JSON schema: a change to any of them is a
+    // change to the output dashboards and scripts parse.
+
+    #[test]
+    fn container_summary_schema() {
+        let meta = meta();
+        let json = serde_json::to_string(&ContainerSummary::new(&meta, now())).unwrap();
+        assert_eq!(
+            json,
+            r#"{"id":"0123456789abcdef","pid":null,"status":"stopped","#.to_owned()
+                + r#""status_text":"Exited (3) 2 hours ago","exit_code":3,"signal":null,"#
+                + r#""health":null,"created_at":"2024-01-01T00:00:00Z","#
+                + r#""started_at":"2024-01-01T00:00:01Z","#
+                + r#""finished_at":"2024-01-01T00:01:01Z","duration_secs":60,"ports":[],"#
+                + r#""command":["/bin/sh","-c","exit 3"]}"#
+        );
+    }
+
+    #[test]
+    fn ps_listing_schema() {
+        let listing = PsListing {
+            containers: Vec::new(),
+            summary: PsTotals {
+                containers: 3,
+                running: 1,
+                stopped: 1,
+                broken: 1,
+                disk_usage: 4096,
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&listing).unwrap(),
+            r#"{"containers":[],"summary":{"containers":3,"running":1,"stopped":1,"#.to_owned()
+                + r#""broken":1,"disk_usage":4096}}"#
+        );
+    }
+
+    #[test]
+    fn container_details_schema() {
+        let meta = meta();
+        let details = ContainerDetails {
+            meta: &meta,
+            duration: Some("1m0s".into()),
+            exit: meta.exit().map(|exit| exit.to_string()),
+            exec_sessions: 2,
+        };
+        let json = serde_json::to_string(&details).unwrap();
+        assert_eq!(
+            json,
+            r#"{"schema_version":1,"id":"0123456789abcdef","#.to_owned()
+                + r#""rootfs":"/tmp/rootfs","cmd":["/bin/sh","-c","exit 3"],"pid":0,"#
+                + r#""exit_code":3,"created_at":"2024-01-01T00:00:00Z","#
+                + r#""started_at":"2024-01-01T00:00:01Z","#
+                + r#""finished_at":"2024-01-01T00:01:01Z","status":"stopped","#
+                + r#""hostname":"h","memory_limit":null,"cpu_limit":null,"#
+                + r#""pids_limit":1024,"duration":"1m0s","exit":"exited (3)","#
+                + r#""exec_sessions":2}"#
+        );
+    }
+
+    #[test]
+    fn line_change_and_result_schemas() {
+        let line = LogLine {
+            stream: "stderr",
+            line: "oops",
+        };
+        assert_eq!(
+            serde_json::to_string(&line).unwrap(),
+            r#"{"stream":"stderr","line":"oops"}"#
+        );
+
+        let change = FsChange {
+            kind: FsChangeKind::Deleted,
+            path: "/etc/motd".into(),
+        };
+        assert_eq!(
+            serde_json::to_string(&ChangeLine::from(&change)).unwrap(),
+            r#"{"kind":"D","path":"/etc/motd"}"#
+        );
+
+        let removed = ContainerResult {
+            id: "0123456789abcdef",
+            result: "removed",
+        };
+        assert_eq!(
+            serde_json::to_string(&removed).unwrap(),
+            r#"{"id":"0123456789abcdef","result":"removed"}"#
+        );
+        let removed = ImageResult {
+            name: "alpine",
+            result: "removed",
+        };
+        assert_eq!(
+            serde_json::to_string(&removed).unwrap(),
+            r#"{"name":"alpine","result":"removed"}"#
+        );
+        let pruned = PruneResult {
+            id: "0123456789abcdef",
+            result: "removed",
+            reclaimed: 4096,
+        };
+        assert_eq!(
+            serde_json::to_string(&pruned).unwrap(),
+            r#"{"id":"0123456789abcdef","result":"removed","reclaimed":4096}"#
+        );
+    }
+
+    #[test]
+    fn disk_usage_report_schema() {
+        let report = DiskUsageReport {
+            containers: ContainersUsage {
+                count: 2,
+                running: 1,
+                size: 300,
+                logs: 200,
+                writable: 50,
+                reclaimable: 100,
+            },
+            images: ImagesUsage {
+                count: 1,
+                in_use: 1,
+                size: 4096,
+                reclaimable: 0,
+            },
+            size: 4396,
+            reclaimable: 100,
+            unreadable: 0,
+            container_details: None,
+            image_details: Some(vec![ImageUsage {
+                name: "alpine",
+                containers: 2,
+                size: 4096,
+            }]),
+        };
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"containers":{"count":2,"running":1,"size":300,"logs":200,"writable":50,"#
+                .to_owned()
+                + r#""reclaimable":100},"images":{"count":1,"in_use":1,"size":4096,"#
+                + r#""reclaimable":0},"size":4396,"reclaimable":100,"unreadable":0,"#
+                + r#""image_details":[{"name":"alpine","containers":2,"size":4096}]}"#
+        );
+    }
+
+    #[test]
+    fn system_info_schema() {
+        let host = HostInfo {
+            kernel: Some("6.8.0-45-generic".into()),
+            cgroup_mode: CgroupMode::V2,
+            cgroup_controllers: vec!["cpu".into(), "memory".into(), "pids".into()],
+            cgroup_delegated: vec!["memory".into(), "pids".into()],
+            cgroup_parent: "/sys/fs/cgroup/craterun".into(),
+            user_namespaces: true,
+            overlayfs: false,
+        };
+        let network = NetworkInfo {
+            name: "bridge".into(),
+            bridge: "craterun0".into(),
+            subnet: "10.77.0.0/24".parse().unwrap(),
+            created_at: None,
+        };
+        let info = SystemInfo {
+            version: "0.1.0",
+            build: "release",
+            target: "x86_64-linux".into(),
+            state_dir: Path::new("/var/lib/craterun"),
+            state_dir_free: Some(1 << 30),
+            host: &host,
+            containers: ContainerCounts {
+                running: 1,
+                stopped: 2,
+                created: 0,
+                broken: 1,
+            },
+            default_network: NetworkSummary::new(&network, 1),
+        };
+        assert_eq!(
+            serde_json::to_string(&info).unwrap(),
+            r#"{"version":"0.1.0","build":"release","target":"x86_64-linux","#.to_owned()
+                + r#""state_dir":"/var/lib/craterun","state_dir_free":1073741824,"#
+                + r#""kernel":"6.8.0-45-generic","cgroup_mode":"v2","#
+                + r#""cgroup_controllers":["cpu","memory","pids"],"#
+                + r#""cgroup_delegated":["memory","pids"],"#
+                + r#""cgroup_parent":"/sys/fs/cgroup/craterun","user_namespaces":true,"#
+                + r#""overlayfs":false,"containers":{"running":1,"stopped":2,"created":0,"#
+                + r#""broken":1},"default_network":{"name":"bridge","bridge":"craterun0","#
+                + r#""subnet":"10.77.0.0/24","gateway":"10.77.0.1","containers":1,"#
+                + r#""created_at":null}}"#
+        );
+    }
+
+    #[test]
+    fn network_summary_schema() {
+        let network = NetworkInfo {
+            name: "web".into(),
+            bridge: "craterun1".into(),
+            subnet: "10.88.0.0/24".parse().unwrap(),
+            created_at: Some(now()),
+        };
+        let json = serde_json::to_string(&NetworkSummary::new(&network, 2)).unwrap();
+        assert_eq!(
+            json,
+            r#"{"name":"web","bridge":"craterun1","subnet":"10.88.0.0/24","#.to_owned()
+                + r#""gateway":"10.88.0.1","containers":2,"#
+                + r#""created_at":"2024-01-01T02:00:00Z"}"#
+        );
+    }
+
+    #[test]
+    fn image_summary_schema() {
+        let image = ImageInfo {
+            name: "alpine".into(),
+            digest: format!("sha256:{}", "ab".repeat(32)),
+            size: 4096,
+            created_at: now(),
+            rootfs: PathBuf::from("/var/lib/craterun/images/alpine/rootfs"),
+            provenance: None,
+            source: Some("docker.io/library/alpine:latest".into()),
+        };
+        let json = serde_json::to_string(&ImageSummary::from(&image)).unwrap();
+        assert_eq!(
+            json,
+            format!(
+                r#"{{"name":"alpine","digest":"sha256:{}","size":4096,"#,
+                "ab".repeat(32)
+            ) + r#""created_at":"2024-01-01T02:00:00Z","#
+                + r#""rootfs":"/var/lib/craterun/images/alpine/rootfs","#
+                + r#""source":"docker.io/library/alpine:latest"}"#
+        );
+    }
+}
@@ -0,0 +1,65 @@
+//! The status line `run` shows on stderr while a container is set up, so a
+//! slow setup does not look like a hang.
+
+use std::io::{IsTerminal, Write};
+
+/// Frames of the spinner in front of the status.
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// A single line on stderr, redrawn in place, naming the setup step being
+/// waited for. It draws nothing unless stderr is a terminal.
+pub struct StatusLine {
+    enabled: bool,
+    status: &'static str,
+    frame: usize,
+    drawn: bool,
+}
+
+impl StatusLine {
+    /// A status line, unless `quiet` is set or stderr is not a terminal.
+    /// Debug logging also goes to stderr and would break up the line, so it
+    /// disables the status line too.
+    pub fn new(quiet: bool) -> Self {
+        let logging = log::max_level() >= log::LevelFilter::Debug;
+        Self {
+            enabled: !quiet && !logging && std::io::stderr().is_terminal(),
+            status: "",
+            frame: 0,
+            drawn: false,
+        }
+    }
+
+    /// Show `status`, or advance the spinner of the current one if `None`.
+    pub fn update(&mut self, status: Option<&'static str>) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(status) = status {
+            self.status = status;
+        }
+        if self.status.is_empty() {
+            return;
+        }
+        self.frame = (self.frame + 1) % SPINNER.len();
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[K{} {}...", SPINNER[self.frame], self.status);
+        let _ = stderr.flush();
+        self.drawn = true;
+    }
+
+    /// Erase the line, leaving the cursor where it started.
+    pub fn clear(&mut self) {
+        if self.drawn {
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[K");
+            let _ = stderr.flush();
+            self.drawn = false;
+        }
+    }
+}
+
+impl Drop for StatusLine {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
@@ -0,0 +1,328 @@
+//! Project files read by `up` and `down`: named services, each configured
+//! like a `run --config` file, that run together on a network of their own.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use sha2::{Digest, Sha256};
+
+use super::spec::RunSpec;
+use crate::core::model::Subnet;
+use crate::core::{network, state};
+
+/// The project file `up` and `down` read unless told otherwise.
+pub const PROJECT_FILE: &str = "craterun.yaml";
+/// Key of a service's table listing the services it starts after.
+const DEPENDS_ON: &str = "depends_on";
+
+/// A project file as written.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProjectFile {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    subnet: Option<String>,
+    services: Mapping,
+}
+
+/// The services of a project file, in the order they start.
+#[derive(Debug)]
+pub struct Project {
+    /// Name of the project, which is also the name of its network: the
+    /// file's `name`, else the name of its directory.
+    pub name: String,
+    /// Subnet of the project's network; a free one is picked if unset.
+    pub subnet: Option<Subnet>,
+    /// Each service after those it depends on, otherwise in file order.
+    pub services: Vec<Service>,
+    /// The file's directory, which relative paths are relative to.
+    pub dir: PathBuf,
+}
+
+/// A service of a project: the configuration of its container.
+#[derive(Debug)]
+pub struct Service {
+    pub name: String,
+    pub depends_on: Vec<String>,
+    pub spec: RunSpec,
+    /// Hash of `spec`, recorded with the container so that `up` can tell
+    /// when the service has changed.
+    pub config_hash: String,
+}
+
+impl Project {
+    /// Read the project file at `path`. Errors name the service and key
+    /// whose value is wrong.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        Self::parse(&text, &dir).with_context(|| format!("invalid {}", path.display()))
+    }
+
+    fn parse(text: &str, dir: &Path) -> Result<Self> {
+        let file: ProjectFile = serde_yaml::from_str(text)?;
+        let name = match file.name {
+            Some(name) => name,
+            None => {
+                let dir = match dir.is_absolute() {
+                    true => dir.to_path_buf(),
+                    false => dir
+                        .canonicalize()
+                        .with_context(|| format!("failed to resolve {}", dir.display()))?,
+                };
+                default_project_name(&dir)
+                    .context("cannot name the project after its directory; set `name`")?
+            }
+        };
+        network::validate_network_name(&name).context("invalid project name")?;
+        let subnet = file
+            .subnet
+            .map(|subnet| subnet.parse().map_err(|e| anyhow::anyhow!("`subnet`: {e}")))
+            .transpose()?;
+
+        let mut services = Vec::new();
+        for (key, value) in file.services {
+            let Value::String(service) = key else {
+                bail!("service names must be strings");
+            };
+            let parsed = parse_service(&name, &service, value);
+            services.push(parsed.with_context(|| format!("service '{service}'"))?);
+        }
+        if services.is_empty() {
+            bail!("no services defined");
+        }
+        Ok(Project {
+            name,
+            subnet,
+            services: start_order(services)?,
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Name of the container of `service`: `<project>_<service>`.
+    pub fn container_name(&self, service: &Service) -> String {
+        format!("{}_{}", self.name, service.name)
+    }
+}
+
+/// Parse the table of service `name` of `project`.
+fn parse_service(project: &str, name: &str, value: Value) -> Result<Service> {
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c));
+    if !valid {
+        bail!("invalid name: expected lowercase letters, digits, '.', '_' or '-'");
+    }
+    state::validate_container_name(&format!("{project}_{name}"))?;
+
+    let mut table = match value {
+        Value::Mapping(table) => table,
+        Value::Null => Mapping::new(),
+        _ => bail!("expected a table of settings"),
+    };
+    let depends_on = match table.remove(DEPENDS_ON) {
+        Some(value) => serde_yaml::from_value(value).context("`depends_on`")?,
+        None => Vec::new(),
+    };
+    let spec = RunSpec::parse(Value::Mapping(table))?;
+    if spec.cmd.is_empty() {
+        bail!("no `cmd` to run");
+    }
+    Ok(Service {
+        name: name.to_string(),
+        depends_on,
+        config_hash: config_hash(&spec)?,
+        spec,
+    })
+}
+
+/// The project name of a file in `dir`: the directory's name, lowercased,
+/// without the characters network names do not allow.
+fn default_project_name(dir: &Path) -> Option<String> {
+    let name: String = dir
+        .file_name()?
+        .to_str()?
+        .to_ascii_lowercase()
+        .chars()
+        .filter(|&c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c))
+        .collect();
+    let name = name.trim_start_matches(['.', '_', '-']);
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Hex SHA-256 of `spec` as JSON.
+fn config_hash(spec: &RunSpec) -> Result<String> {
+    let json = serde_json::to_vec(spec).context("failed to serialize the configuration")?;
+    Ok(Sha256::digest(json)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Order `services` so that each comes after those it depends on, keeping
+/// the file's order otherwise.
+fn start_order(mut services: Vec<Service>) -> Result<Vec<Service>> {
+    for service in &services {
+        for dependency in &service.depends_on {
+            if dependency == &service.name {
+                bail!("service '{}' depends on itself", service.name);
+            }
+            if !services.iter().any(|s| &s.name == dependency) {
+                bail!(
+                    "service '{}' depends on '{dependency}', which is not defined",
+                    service.name
+                );
+            }
+        }
+    }
+
+    let mut ordered: Vec<Service> = Vec::with_capacity(services.len());
+    while !services.is_empty() {
+        let ready = services.iter().position(|service| {
+            service
+                .depends_on
+                .iter()
+                .all(|dependency| ordered.iter().any(|s| &s.name == dependency))
+        });
+        let Some(ready) = ready else {
+            let names: Vec<&str> = services.iter().map(|s| s.name.as_str()).collect();
+            bail!("dependency cycle among services {}", names.join(", "));
+        };
+        ordered.push(services.remove(ready));
+    }
+    Ok(ordered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Result<Project> {
+        Project::parse(yaml, Path::new("/srv/shop"))
+    }
+
+    fn names(project: &Project) -> Vec<&str> {
+        project.services.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn services_start_after_their_dependencies() {
+        let project = parse(
+            "name: shop
+services:
+  web:
+    rootfs: ./web
+    cmd: [/bin/httpd, -f]
+    depends_on: [api]
+  api:
+    rootfs: ./api
+    cmd: [/bin/api]
+    depends_on: [db]
+  db:
+    rootfs: /srv/db
+    cmd: [/bin/db]
+    limits:
+      memory: 64M
+  worker:
+    rootfs: ./api
+    cmd: [/bin/worker]
+",
+        )
+        .unwrap();
+        assert_eq!(project.name, "shop");
+        assert_eq!(names(&project), ["db", "api", "web", "worker"]);
+        assert_eq!(project.container_name(&project.services[0]), "shop_db");
+        assert_eq!(project.services[2].depends_on, ["api"]);
+        assert_eq!(
+            project.services[0]
+                .spec
+                .limits
+                .memory
+                .as_ref()
+                .unwrap()
+                .bytes,
+            64 << 20
+        );
+        assert_eq!(project.services[0].config_hash.len(), 64);
+    }
+
+    #[test]
+    fn config_hash_follows_the_configuration() {
+        let hash = |cmd: &str| {
+            let yaml = format!("services:\n  web:\n    rootfs: ./web\n    cmd: [{cmd}]\n");
+            parse(&yaml).unwrap().services[0].config_hash.clone()
+        };
+        assert_eq!(hash("/bin/httpd"), hash("/bin/httpd"));
+        assert_ne!(hash("/bin/httpd"), hash("/bin/nginx"));
+    }
+
+    #[test]
+    fn project_name_defaults_to_the_directory() {
+        assert_eq!(
+            default_project_name(Path::new("/home/me/My Shop!")).as_deref(),
+            Some("myshop")
+        );
+        assert_eq!(
+            default_project_name(Path::new("/srv/.app")).as_deref(),
+            Some("app")
+        );
+        assert_eq!(default_project_name(Path::new("/")), None);
+    }
+
+    #[test]
+    fn invalid_projects() {
+        let service = "    rootfs: ./web\n    cmd: [/bin/sh]\n";
+        for (yaml, error) in [
+            ("services: {}\n".to_string(), "no services defined"),
+            (
+                format!("name: Shop\nservices:\n  web:\n{service}"),
+                "invalid project name",
+            ),
+            (
+                format!("name: shop\nport: 80\nservices:\n  web:\n{service}"),
+                "unknown field `port`",
+            ),
+            (
+                format!("name: shop\nservices:\n  Web:\n{service}"),
+                "service 'Web'",
+            ),
+            (
+                "name: shop\nservices:\n  web:\n    rootfs: ./web\n".to_string(),
+                "no `cmd` to run",
+            ),
+            (
+                format!(
+                    "name: shop\nservices:\n  web:\n{service}    limits:\n      memory: lots\n"
+                ),
+                "`limits.memory`",
+            ),
+            (
+                format!("name: shop\nservices:\n  web:\n{service}    depends_on: [db]\n"),
+                "depends on 'db', which is not defined",
+            ),
+            (
+                format!("name: shop\nservices:\n  web:\n{service}    depends_on: [web]\n"),
+                "depends on itself",
+            ),
+            (
+                format!(
+                    "name: shop\nservices:\n  a:\n{service}    depends_on: [b]\n  \
+                     b:\n{service}    depends_on: [a]\n  c:\n{service}"
+                ),
+                "dependency cycle among services a, b",
+            ),
+        ] {
+            let err = format!("{:#}", parse(&yaml).unwrap_err());
+            assert!(err.contains(error), "{yaml}: {err}");
+        }
+    }
+}
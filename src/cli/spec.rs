@@ -0,0 +1,926 @@
+//! Container configuration files, read by `run --config` and printed by
+//! `config show`.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+
+use super::RunArgs;
+use crate::core::model::{
+    CgroupNsMode, ContainerMeta, CpuLimit, DeviceMapping, Keyring, MacAddress, MountPropagation,
+    NamespaceMode, PidsLimit, PortMapping, ProcOptions, StorageDriver, Sysctl, Timezone,
+    TmpfsMount, Ulimit, Umask, UserSpec, UsernsMode,
+};
+use crate::core::registry::PullPolicy;
+use crate::util::units::ByteSize;
+
+/// The configuration of a container as written in a file: the settings of
+/// `run`'s flags, with keys named like those of
+/// [`ContainerConfig`](crate::core::model::ContainerConfig). Values are
+/// written as for the flags, e.g. `ulimits = ["nofile=1024:4096"]`; sizes
+/// and counts may also be numbers.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunSpec {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rootfs: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub pull: Option<PullPolicy>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cmd: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub argv0: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub init_path: Option<String>,
+    #[serde(deserialize_with = "hostname", skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(
+        deserialize_with = "domainname",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub domainname: Option<String>,
+    #[serde(skip_serializing_if = "is_default")]
+    pub limits: LimitSpec,
+    #[serde(with = "parsed_list", skip_serializing_if = "Vec::is_empty")]
+    pub ulimits: Vec<Ulimit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oom_score_adj: Option<i32>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub umask: Option<Umask>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<Timezone>,
+    #[serde(with = "parsed_list", skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<DeviceMapping>,
+    #[serde(with = "parsed_list", skip_serializing_if = "Vec::is_empty")]
+    pub tmpfs: Vec<TmpfsMount>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub shm_size: Option<ByteSize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage: Option<StorageSpec>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub mount_propagation: Option<MountPropagation>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub proc_opts: Option<ProcOptions>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub keyring: Option<Keyring>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub userns: Option<UsernsMode>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub cgroup_ns: Option<CgroupNsMode>,
+    #[serde(with = "parsed_list", skip_serializing_if = "Vec::is_empty")]
+    pub sysctls: Vec<Sysctl>,
+    /// `KEY=VALUE` assignments, under the command line's `--env`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    /// Files read as with `--env-file`, before the command line's.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub env_files: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub preserve_env: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub env_host: bool,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub user: Option<UserSpec>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub net: Option<NamespaceMode>,
+    #[serde(
+        serialize_with = "parsed::serialize",
+        deserialize_with = "shared_namespace",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub pid_ns: Option<NamespaceMode>,
+    #[serde(
+        serialize_with = "parsed::serialize",
+        deserialize_with = "shared_namespace",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ipc: Option<NamespaceMode>,
+    #[serde(
+        serialize_with = "parsed::serialize",
+        deserialize_with = "shared_namespace",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub uts: Option<NamespaceMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub ip: Option<Ipv4Addr>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub mac_address: Option<MacAddress>,
+    #[serde(with = "parsed_list", skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<PortMapping>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<HealthSpec>,
+    #[serde(deserialize_with = "signal", skip_serializing_if = "Option::is_none")]
+    pub stop_signal: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub no_pivot: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub allow_chroot_fallback: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub no_preflight: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub attach_stdin: bool,
+}
+
+/// The `[limits]` table: `--memory`, `--cpus` and the other flags shared
+/// with `update`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitSpec {
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub memory: Option<ByteSize>,
+    #[serde(
+        deserialize_with = "memory_swappiness",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub memory_swappiness: Option<u8>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<CpuLimit>,
+    /// Only read: `config show` writes the limit as `cpu`.
+    #[serde(deserialize_with = "cpus", skip_serializing)]
+    pub cpus: Option<CpuLimit>,
+    #[serde(with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub pids: Option<PidsLimit>,
+    #[serde(deserialize_with = "cpu_list", skip_serializing_if = "Option::is_none")]
+    pub cpuset_cpus: Option<String>,
+    #[serde(deserialize_with = "cpu_list", skip_serializing_if = "Option::is_none")]
+    pub cpuset_mems: Option<String>,
+}
+
+/// The `[storage]` table: `--storage-size` and `--storage-driver`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StorageSpec {
+    #[serde(with = "parsed_required")]
+    pub size: ByteSize,
+    #[serde(default, with = "parsed", skip_serializing_if = "Option::is_none")]
+    pub driver: Option<StorageDriver>,
+}
+
+/// The `[health]` table: `--health-cmd` and the flags tuning it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthSpec {
+    pub cmd: String,
+    #[serde(
+        default,
+        serialize_with = "serialize_interval",
+        deserialize_with = "interval",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub interval: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+}
+
+impl RunSpec {
+    /// Read the file at `path`: JSON if its name ends in `.json`, else
+    /// TOML. Errors name the key whose value is wrong.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let parsed = if path.extension().is_some_and(|ext| ext == "json") {
+            Self::parse(&mut serde_json::Deserializer::from_str(&text))
+        } else {
+            Self::parse(toml::Deserializer::new(&text))
+        };
+        parsed.with_context(|| format!("invalid {}", path.display()))
+    }
+
+    /// Read a configuration from `deserializer` and check it. Errors name
+    /// the key whose value is wrong.
+    pub(super) fn parse<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self> {
+        let spec: Self = serde_path_to_error::deserialize(deserializer)
+            .map_err(|e| at_key(e.path(), e.inner()))?;
+        spec.check()?;
+        Ok(spec)
+    }
+
+    /// Reject settings that the command line would refuse to combine.
+    fn check(&self) -> Result<()> {
+        let limits = &self.limits;
+        let conflicts = [
+            (
+                self.rootfs.is_some() && self.image.is_some(),
+                "rootfs",
+                "image",
+            ),
+            (
+                limits.cpu.is_some() && limits.cpus.is_some(),
+                "limits.cpu",
+                "limits.cpus",
+            ),
+            (
+                self.argv0.is_some() && self.init_path.is_some(),
+                "argv0",
+                "init_path",
+            ),
+            (
+                self.net.is_some() && self.network.is_some(),
+                "net",
+                "network",
+            ),
+            (
+                self.no_pivot && self.allow_chroot_fallback,
+                "no_pivot",
+                "allow_chroot_fallback",
+            ),
+        ];
+        if let Some((_, a, b)) = conflicts.iter().find(|(both, ..)| *both) {
+            bail!("`{a}` and `{b}` cannot both be set");
+        }
+        if self.pull.is_some() && self.rootfs.is_some() {
+            bail!("`pull` only applies to an `image`, not a `rootfs`");
+        }
+        Ok(())
+    }
+
+    /// Fill in the arguments of `run` that were not given on the command
+    /// line with the file's values. Lists given on the command line replace
+    /// the file's, except for the environment, where both are combined and
+    /// the command line's `--env` wins. Relative paths in the file are
+    /// relative to `dir`, the file's directory.
+    pub fn apply(self, args: &mut RunArgs, dir: &Path) -> Result<()> {
+        let given = std::mem::take(&mut args.given);
+        let is_given = |id: &str| given.contains(id);
+        let path = |p: PathBuf| if p.is_relative() { dir.join(p) } else { p };
+
+        // Arguments that conflict on the command line are taken together.
+        if !is_given("rootfs") && !is_given("image") {
+            args.rootfs = self
+                .rootfs
+                .map(|rootfs| path(rootfs.into()).display().to_string());
+            args.image = self.image;
+        }
+        if args.image.is_some() {
+            fill(&mut args.pull, is_given("pull"), self.pull);
+        }
+        if !is_given("cmd") && !is_given("cmd_file") {
+            args.cmd = self.cmd;
+        }
+        if !is_given("net") && !is_given("network") {
+            fill(&mut args.net, false, self.net);
+            args.network = self.network;
+        }
+        if !is_given("cpu") && !is_given("cpus") {
+            args.limits.cpu = self.limits.cpu;
+            args.limits.cpus = self.limits.cpus;
+        }
+
+        let limits = self.limits;
+        fill(
+            &mut args.limits.memory,
+            is_given("memory"),
+            limits.memory.map(Some),
+        );
+        fill(
+            &mut args.limits.memory_swappiness,
+            is_given("memory_swappiness"),
+            limits.memory_swappiness.map(Some),
+        );
+        fill(
+            &mut args.limits.pids,
+            is_given("pids"),
+            limits.pids.map(Some),
+        );
+        fill(
+            &mut args.limits.cpuset_cpus,
+            is_given("cpuset_cpus"),
+            limits.cpuset_cpus.map(Some),
+        );
+        fill(
+            &mut args.limits.cpuset_mems,
+            is_given("cpuset_mems"),
+            limits.cpuset_mems.map(Some),
+        );
+
+        // --no-core and --core-limit stand for a `core` ulimit.
+        let mut ulimits = self.ulimits;
+        if is_given("no_core") || is_given("core_limit") {
+            ulimits.retain(|u| u.kind != crate::core::model::UlimitKind::Core);
+        }
+        fill(&mut args.ulimits, is_given("ulimits"), non_empty(ulimits));
+        fill(
+            &mut args.devices,
+            is_given("devices"),
+            non_empty(self.devices),
+        );
+        fill(&mut args.tmpfs, is_given("tmpfs"), non_empty(self.tmpfs));
+        fill(
+            &mut args.shm_size,
+            is_given("shm_size"),
+            self.shm_size.map(Some),
+        );
+        fill(
+            &mut args.sysctls,
+            is_given("sysctls"),
+            non_empty(self.sysctls),
+        );
+        fill(
+            &mut args.publish,
+            is_given("publish"),
+            non_empty(self.ports),
+        );
+
+        args.env = [self.env, std::mem::take(&mut args.env)].concat();
+        args.env_files = self
+            .env_files
+            .into_iter()
+            .map(path)
+            .chain(std::mem::take(&mut args.env_files))
+            .collect();
+        let mut preserve: BTreeSet<String> = self.preserve_env.into_iter().collect();
+        args.preserve_env.retain(|var| preserve.insert(var.clone()));
+        args.preserve_env = preserve.into_iter().collect();
+        args.preserve_env_all |= self.env_host;
+
+        if let Some(storage) = self.storage {
+            fill(
+                &mut args.storage_size,
+                is_given("storage_size"),
+                Some(Some(storage.size)),
+            );
+            fill(
+                &mut args.storage_driver,
+                is_given("storage_driver"),
+                storage.driver,
+            );
+        }
+        if let Some(health) = self.health {
+            fill(
+                &mut args.health_cmd,
+                is_given("health_cmd"),
+                Some(Some(health.cmd)),
+            );
+            fill(
+                &mut args.health_interval,
+                is_given("health_interval"),
+                health.interval,
+            );
+            fill(
+                &mut args.health_retries,
+                is_given("health_retries"),
+                health.retries,
+            );
+        }
+
+        fill(&mut args.argv0, is_given("argv0"), self.argv0.map(Some));
+        fill(
+            &mut args.init_path,
+            is_given("init_path"),
+            self.init_path.map(Some),
+        );
+        fill(
+            &mut args.hostname,
+            is_given("hostname"),
+            self.hostname.map(Some),
+        );
+        fill(
+            &mut args.domainname,
+            is_given("domainname"),
+            self.domainname.map(Some),
+        );
+        fill(
+            &mut args.oom_score_adj,
+            is_given("oom_score_adj"),
+            self.oom_score_adj.map(Some),
+        );
+        fill(&mut args.umask, is_given("umask"), self.umask.map(Some));
+        fill(
+            &mut args.timezone,
+            is_given("timezone"),
+            self.timezone.map(Some),
+        );
+        fill(
+            &mut args.mount_propagation,
+            is_given("mount_propagation"),
+            self.mount_propagation,
+        );
+        fill(
+            &mut args.proc_opts,
+            is_given("proc_opts"),
+            self.proc_opts.map(Some),
+        );
+        fill(&mut args.keyring, is_given("keyring"), self.keyring);
+        fill(&mut args.userns, is_given("userns"), self.userns);
+        fill(&mut args.cgroup_ns, is_given("cgroup_ns"), self.cgroup_ns);
+        fill(&mut args.user, is_given("user"), self.user.map(Some));
+        fill(&mut args.pid, is_given("pid"), self.pid_ns);
+        fill(&mut args.ipc, is_given("ipc"), self.ipc);
+        fill(&mut args.uts, is_given("uts"), self.uts);
+        fill(&mut args.ip, is_given("ip"), self.ip.map(Some));
+        fill(
+            &mut args.mac_address,
+            is_given("mac_address"),
+            self.mac_address.map(Some),
+        );
+        fill(
+            &mut args.stop_signal,
+            is_given("stop_signal"),
+            self.stop_signal.map(Some),
+        );
+        args.no_pivot |= self.no_pivot;
+        args.allow_chroot_fallback |= self.allow_chroot_fallback;
+        args.no_preflight |= self.no_preflight;
+        args.attach_stdin |= self.attach_stdin;
+
+        // Conflicts between the file and the command line.
+        if args.argv0.is_some() && args.init_path.is_some() {
+            bail!("argv0 and init_path cannot both be set");
+        }
+        if args.no_pivot && args.allow_chroot_fallback {
+            bail!("no_pivot and allow_chroot_fallback cannot both be set");
+        }
+        args.given = given;
+        Ok(())
+    }
+
+    /// The configuration of the container `meta` describes, as `run`
+    /// applied it: with its resolved environment and user, and its limits as
+    /// last changed by `update`.
+    pub fn from_meta(meta: &ContainerMeta) -> Self {
+        let memory = meta.memory_limit.map(|bytes| ByteSize {
+            bytes,
+            original: meta
+                .memory_limit_spec
+                .clone()
+                .unwrap_or_else(|| bytes.to_string()),
+        });
+        let bridged = meta.network.is_some();
+        Self {
+            rootfs: Some(meta.rootfs.clone()),
+            cmd: meta.cmd.clone(),
+            argv0: meta.argv0.clone(),
+            init_path: meta.init_path.clone(),
+            // A shared UTS namespace has the hostname of its owner.
+            hostname: meta.uts.is_new().then(|| meta.hostname.clone()),
+            domainname: meta.domainname.clone(),
+            limits: LimitSpec {
+                memory,
+                memory_swappiness: meta.memory_swappiness,
+                cpu: meta.cpu_limit.as_deref().and_then(|cpu| cpu.parse().ok()),
+                cpus: None,
+                pids: Some(PidsLimit {
+                    max: meta.pids_limit,
+                }),
+                cpuset_cpus: meta.cpuset_cpus.clone(),
+                cpuset_mems: meta.cpuset_mems.clone(),
+            },
+            ulimits: meta.ulimits.clone(),
+            oom_score_adj: meta.oom_score_adj,
+            umask: meta.umask,
+            timezone: meta.timezone.clone(),
+            devices: meta.devices.clone(),
+            tmpfs: meta.tmpfs.clone(),
+            shm_size: meta.shm_size.map(|bytes| ByteSize {
+                bytes,
+                original: bytes.to_string(),
+            }),
+            storage: meta.storage.map(|storage| StorageSpec {
+                size: ByteSize {
+                    bytes: storage.size,
+                    original: storage.size.to_string(),
+                },
+                driver: Some(storage.driver),
+            }),
+            mount_propagation: Some(meta.mount_propagation).filter(|p| !p.is_private()),
+            proc_opts: Some(meta.proc_opts).filter(|opts| !opts.is_default()),
+            keyring: Some(meta.keyring).filter(|keyring| !keyring.is_private()),
+            userns: Some(meta.userns).filter(|userns| !userns.is_host()),
+            cgroup_ns: Some(meta.cgroup_ns).filter(|ns| *ns != CgroupNsMode::Private),
+            sysctls: meta.sysctls.clone(),
+            env: meta.env.clone(),
+            user: meta.user.as_ref().map(|user| UserSpec {
+                user: user.uid.to_string(),
+                group: Some(user.gid.to_string()),
+            }),
+            net: Some(meta.net.clone()).filter(|net| !net.is_private() && !bridged),
+            pid_ns: Some(meta.pid_ns.clone()).filter(|ns| !ns.is_private()),
+            ipc: Some(meta.ipc.clone()).filter(|ns| !ns.is_private()),
+            uts: Some(meta.uts.clone()).filter(|ns| !ns.is_private()),
+            network: meta.network.clone(),
+            ip: meta.ip_address,
+            mac_address: meta.mac_address,
+            ports: meta.ports.clone(),
+            health: meta.health.as_ref().map(|health| HealthSpec {
+                cmd: health.cmd.clone(),
+                interval: Some(Duration::from_millis(health.interval_ms)),
+                retries: Some(health.retries),
+            }),
+            stop_signal: meta.stop_signal.clone(),
+            no_pivot: meta.no_pivot,
+            allow_chroot_fallback: meta.allow_chroot_fallback,
+            no_preflight: meta.no_preflight,
+            attach_stdin: meta.attach_stdin,
+            ..Self::default()
+        }
+    }
+
+    /// The configuration as a TOML document.
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).context("failed to serialize the configuration as TOML")
+    }
+}
+
+/// Set `arg` to `value` unless it was given on the command line.
+fn fill<T>(arg: &mut T, given: bool, value: Option<T>) {
+    if let (false, Some(value)) = (given, value) {
+        *arg = value;
+    }
+}
+
+/// A list of the file's, unless it is empty.
+fn non_empty<T>(list: Vec<T>) -> Option<Vec<T>> {
+    (!list.is_empty()).then_some(list)
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// Prefix a parse error with the key it is about, e.g. `limits.memory`.
+fn at_key(path: &serde_path_to_error::Path, error: &dyn fmt::Display) -> anyhow::Error {
+    let key = path.to_string();
+    if key == "." {
+        anyhow::anyhow!("{error}")
+    } else {
+        anyhow::anyhow!("`{key}`: {error}")
+    }
+}
+
+/// A value given as a string, or as a number or boolean that stands for
+/// its spelling.
+struct Scalar(String);
+
+impl<'de> Deserialize<'de> for Scalar {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ScalarVisitor;
+
+        impl Visitor<'_> for ScalarVisitor {
+            type Value = Scalar;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a string or a number")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Scalar, E> {
+                Ok(Scalar(v.to_string()))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Scalar, E> {
+                Ok(Scalar(v.to_string()))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Scalar, E> {
+                Ok(Scalar(v.to_string()))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Scalar, E> {
+                Ok(Scalar(v.to_string()))
+            }
+        }
+
+        deserializer.deserialize_any(ScalarVisitor)
+    }
+}
+
+/// Deserialize a value with one of the value parsers of `run`'s flags.
+fn parse_with<'de, D, T>(
+    deserializer: D,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Scalar(s) = Scalar::deserialize(deserializer)?;
+    parse(&s).map(Some).map_err(de::Error::custom)
+}
+
+/// Serde functions for values written as their [`FromStr`] and
+/// [`Display`](fmt::Display) spelling.
+mod parsed {
+    use super::*;
+
+    pub fn serialize<S: Serializer, T: fmt::Display>(
+        value: &Option<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.collect_str(value),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        parse_with(deserializer, |s| s.parse().map_err(|e| format!("{e:#}")))
+    }
+}
+
+/// [`parsed`] for a value that has to be given.
+mod parsed_required {
+    use super::*;
+
+    pub fn serialize<S: Serializer, T: fmt::Display>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        parsed::deserialize(deserializer).map(|value| value.expect("parsed values are Some"))
+    }
+}
+
+/// [`parsed`] for a list of values.
+mod parsed_list {
+    use super::*;
+
+    pub fn serialize<S: Serializer, T: fmt::Display>(
+        values: &[T],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(values.iter().map(ToString::to_string))
+    }
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let values = Vec::<Element<T>>::deserialize(deserializer)?;
+        Ok(values.into_iter().map(|Element(value)| value).collect())
+    }
+
+    /// An element of a list, parsed on its own so that errors carry its
+    /// index.
+    struct Element<T>(T);
+
+    impl<'de, T> Deserialize<'de> for Element<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            parsed_required::deserialize(deserializer).map(Element)
+        }
+    }
+}
+
+fn hostname<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    parse_with(deserializer, super::parse_hostname)
+}
+
+fn domainname<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    parse_with(deserializer, super::parse_domainname)
+}
+
+fn memory_swappiness<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u8>, D::Error> {
+    parse_with(deserializer, super::parse_memory_swappiness)
+}
+
+fn cpus<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<CpuLimit>, D::Error> {
+    parse_with(deserializer, super::parse_cpus)
+}
+
+fn cpu_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    parse_with(deserializer, super::parse_cpu_list)
+}
+
+fn shared_namespace<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<NamespaceMode>, D::Error> {
+    parse_with(deserializer, super::parse_shared_namespace)
+}
+
+fn signal<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    parse_with(deserializer, super::parse_signal)
+}
+
+fn interval<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    parse_with(deserializer, super::parse_interval)
+}
+
+/// Write an interval as `--health-interval` takes it, e.g. `30s`.
+fn serialize_interval<S: Serializer>(
+    interval: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match interval.map(|d| d.as_millis()) {
+        Some(ms) if ms % 1000 == 0 => serializer.collect_str(&format_args!("{}s", ms / 1000)),
+        Some(ms) => serializer.collect_str(&format_args!("{ms}ms")),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{try_parse_from, Command};
+
+    /// Parse `run ARGS` and apply `toml` to it.
+    fn run_args(args: &str, toml: &str) -> Result<RunArgs> {
+        let argv = ["craterun", "run"]
+            .into_iter()
+            .chain(args.split_whitespace());
+        let Command::Run(mut args) = try_parse_from(argv).unwrap().command else {
+            unreachable!()
+        };
+        let spec: RunSpec = toml::from_str(toml)?;
+        spec.check()?;
+        spec.apply(&mut args, Path::new("/etc/app"))?;
+        Ok(*args)
+    }
+
+    const SPEC: &str = r#"
+        rootfs = "rootfs"
+        cmd = ["/bin/sh", "-c", "sleep 1"]
+        hostname = "web"
+        net = "host"
+        ulimits = ["nofile=1024:4096", "core=0"]
+        env = ["MODE=file", "ONLY_FILE=1"]
+        env_files = ["app.env"]
+        mount_propagation = "slave"
+        shm_size = "256M"
+        no_preflight = true
+
+        [limits]
+        memory = "64M"
+        cpus = 0.5
+        pids = 100
+
+        [health]
+        cmd = "true"
+        interval = "5s"
+    "#;
+
+    #[test]
+    fn file_values_fill_in_missing_flags() {
+        let args = run_args("--config app.toml", SPEC).unwrap();
+        assert_eq!(args.rootfs.as_deref(), Some("/etc/app/rootfs"));
+        assert_eq!(args.cmd, ["/bin/sh", "-c", "sleep 1"]);
+        assert_eq!(args.hostname.as_deref(), Some("web"));
+        assert_eq!(args.net, NamespaceMode::Host);
+        assert_eq!(args.ulimits.len(), 2);
+        assert_eq!(args.env_files, [PathBuf::from("/etc/app/app.env")]);
+        assert_eq!(args.mount_propagation, MountPropagation::Slave);
+        assert!(args.no_preflight);
+        assert_eq!(args.limits.memory.unwrap().bytes, 64 << 20);
+        assert_eq!(args.shm_size.unwrap().bytes, 256 << 20);
+        assert_eq!(args.limits.cpus.unwrap().to_string(), "50000 100000");
+        assert_eq!(args.limits.pids.unwrap().max, Some(100));
+        assert_eq!(args.health_cmd.as_deref(), Some("true"));
+        assert_eq!(args.health_interval, Duration::from_secs(5));
+        // Not in the file: the flag's default.
+        assert_eq!(args.health_retries, 3);
+        assert_eq!(args.keyring, Keyring::Private);
+    }
+
+    #[test]
+    fn flags_override_file_values() {
+        let args = run_args(
+            "--config app.toml --rootfs /srv/alpine --hostname api --net private --memory 1G \
+             --cpu max --no-core --env MODE=cli --mount-propagation private --keyring host \
+             -- /bin/true",
+            SPEC,
+        )
+        .unwrap();
+        assert_eq!(args.rootfs.as_deref(), Some("/srv/alpine"));
+        assert_eq!(args.cmd, ["/bin/true"]);
+        assert_eq!(args.hostname.as_deref(), Some("api"));
+        // Given explicitly, even though it is the default.
+        assert_eq!(args.net, NamespaceMode::Private);
+        assert_eq!(args.mount_propagation, MountPropagation::Private);
+        assert_eq!(args.limits.memory.unwrap().bytes, 1 << 30);
+        assert_eq!(args.limits.cpu.unwrap().quota_us, None);
+        assert!(args.limits.cpus.is_none(), "--cpu replaces the file's cpus");
+        // --no-core replaces the file's core ulimit, but not the others.
+        assert_eq!(args.ulimits.len(), 1);
+        assert!(args.no_core);
+        // The environment is layered: --env last, so it wins.
+        assert_eq!(args.env, ["MODE=file", "ONLY_FILE=1", "MODE=cli"]);
+        assert_eq!(args.keyring, Keyring::Host);
+        // Values the command line did not touch still come from the file.
+        assert_eq!(args.limits.pids.unwrap().max, Some(100));
+        assert!(args.no_preflight);
+    }
+
+    #[test]
+    fn flags_replace_file_lists_and_conflicting_settings() {
+        let args = run_args(
+            "--config app.toml --image alpine --ulimit nproc=64 --network backend \
+             --cmd-file /dev/null",
+            SPEC,
+        )
+        .unwrap();
+        assert_eq!(args.rootfs, None);
+        assert_eq!(args.image.as_deref(), Some("alpine"));
+        assert_eq!(args.ulimits, ["nproc=64".parse::<Ulimit>().unwrap()]);
+        assert_eq!(
+            args.net,
+            NamespaceMode::Private,
+            "--network drops the file's net"
+        );
+        assert_eq!(args.network.as_deref(), Some("backend"));
+        assert!(args.cmd.is_empty(), "--cmd-file replaces the file's cmd");
+
+        let err =
+            run_args("--config app.toml --argv0 sh", "init_path = \"/sbin/tini\"").unwrap_err();
+        assert_eq!(err.to_string(), "argv0 and init_path cannot both be set");
+    }
+
+    #[test]
+    fn errors_name_the_key() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cases = [
+            (
+                "app.toml",
+                "[limits]\nmemory = \"lots\"\n",
+                "`limits.memory`: ",
+            ),
+            ("app.toml", "hostnme = \"web\"\n", "unknown field `hostnme`"),
+            ("app.toml", "pid_ns = \"slirp\"\n", "`pid_ns`: "),
+            (
+                "app.toml",
+                "ulimits = [\"nofile=1\", \"bogus=1\"]\n",
+                "`ulimits[1]`: ",
+            ),
+            (
+                "app.toml",
+                "[storage]\ndriver = \"loop\"\n",
+                "missing field `size`",
+            ),
+            (
+                "app.toml",
+                "rootfs = \"/a\"\nimage = \"alpine\"\n",
+                "`rootfs` and `image`",
+            ),
+            (
+                "app.json",
+                r#"{"health": {"cmd": "true", "retries": "x"}}"#,
+                "`health.retries`: ",
+            ),
+        ];
+        for (name, text, expected) in cases {
+            let path = tmp.path().join(name);
+            fs::write(&path, text).unwrap();
+            let err = format!("{:#}", RunSpec::load(&path).unwrap_err());
+            assert!(err.contains(expected), "{text}: {err}");
+        }
+    }
+
+    #[test]
+    fn shown_configuration_reads_back() {
+        let mut meta = crate::core::state::tests::sample_meta("5eedc0f1");
+        meta.memory_limit = Some(64 << 20);
+        meta.memory_limit_spec = Some("64M".into());
+        meta.cpu_limit = Some("50000 100000".into());
+        meta.ulimits = vec!["nofile=1024:4096".parse().unwrap()];
+        meta.tmpfs = vec!["/run:size=1m,noexec".parse().unwrap()];
+        meta.shm_size = Some(1 << 30);
+        meta.uts = NamespaceMode::Host;
+        meta.network = Some("backend".into());
+        meta.net = NamespaceMode::Bridge;
+        meta.stop_signal = Some("SIGQUIT".into());
+
+        let spec = RunSpec::from_meta(&meta);
+        assert_eq!(
+            spec.hostname, None,
+            "the host's UTS namespace has its hostname"
+        );
+        assert_eq!(spec.net, None, "network implies the bridge");
+        let text = spec.to_toml().unwrap();
+        assert!(text.contains("memory = \"64M\"\n"), "{text}");
+        assert!(
+            text.contains("tmpfs = [\"/run:size=1048576,noexec\"]\n"),
+            "{text}"
+        );
+        assert!(text.contains("shm_size = \"1073741824\"\n"), "{text}");
+
+        let back: RunSpec = toml::from_str(&text).unwrap();
+        assert_eq!(back, spec);
+        back.check().unwrap();
+    }
+}
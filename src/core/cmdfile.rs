@@ -0,0 +1,73 @@
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+/// The path `--cmd-file` takes to mean stdin.
+pub const STDIN_PATH: &str = "-";
+
+/// Read a container command from `path`, or stdin for [`STDIN_PATH`]: one
+/// argument per line, or per NUL-terminated string if `null` is set.
+pub fn read_cmd_file(path: &Path, null: bool) -> Result<Vec<String>> {
+    let mut data = Vec::new();
+    if path.as_os_str() == STDIN_PATH {
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .context("failed to read the command from stdin")?;
+    } else {
+        data = std::fs::read(path)
+            .with_context(|| format!("failed to read command file {}", path.display()))?;
+    }
+    parse_cmd(&data, null).with_context(|| format!("invalid command file {}", path.display()))
+}
+
+/// Split `data` into arguments at each newline, or each NUL if `null` is
+/// set. A terminator after the last argument is optional, and empty
+/// arguments in between are kept.
+pub fn parse_cmd(data: &[u8], null: bool) -> Result<Vec<String>> {
+    let separator = if null { b'\0' } else { b'\n' };
+    let data = data.strip_suffix(&[separator]).unwrap_or(data);
+    if data.is_empty() {
+        bail!("no command given");
+    }
+    data.split(|&b| b == separator)
+        .enumerate()
+        .map(|(i, arg)| {
+            if arg.contains(&b'\0') {
+                bail!(
+                    "argument {} contains a NUL byte; use --null for NUL-separated arguments",
+                    i + 1
+                );
+            }
+            String::from_utf8(arg.to_vec())
+                .with_context(|| format!("argument {} is not valid UTF-8", i + 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newline_separated() {
+        let cmd = parse_cmd(b"/bin/sh\n-c\necho 'a b' \"$HOME\"\n", false).unwrap();
+        assert_eq!(cmd, ["/bin/sh", "-c", "echo 'a b' \"$HOME\""]);
+        // The final newline is optional; empty arguments are kept.
+        assert_eq!(
+            parse_cmd(b"printf\n\nx", false).unwrap(),
+            ["printf", "", "x"]
+        );
+        assert!(parse_cmd(b"", false).is_err());
+        assert!(parse_cmd(b"\n", false).is_err());
+        assert!(parse_cmd(b"a\0b\n", false).is_err());
+    }
+
+    #[test]
+    fn null_separated() {
+        let cmd = parse_cmd(b"/bin/sh\0-c\0echo a\necho b\0", true).unwrap();
+        assert_eq!(cmd, ["/bin/sh", "-c", "echo a\necho b"]);
+        assert!(parse_cmd(b"\0", true).is_err());
+        assert!(parse_cmd(b"\xff\0", true).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// How much of a binary is read to find its interpreter. `PT_INTERP` and the
+/// program headers sit at the start of the file.
+const HEADER_READ_LEN: u64 = 64 * 1024;
+/// Program header type of the interpreter path.
+const PT_INTERP: u32 = 3;
+
+/// Read the interpreter a file needs to be exec'd: the `PT_INTERP` loader of
+/// a dynamically linked ELF binary, or the `#!` program of a script. `None`
+/// for static binaries and anything else.
+pub fn read_interpreter(path: &Path) -> Result<Option<String>> {
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|f| f.take(HEADER_READ_LEN).read_to_end(&mut data))
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(elf_interpreter(&data).or_else(|| script_interpreter(&data)))
+}
+
+/// The `#!` interpreter of a script, without its arguments.
+pub fn script_interpreter(data: &[u8]) -> Option<String> {
+    let line = data.strip_prefix(b"#!")?.split(|&b| b == b'\n').next()?;
+    let line = String::from_utf8_lossy(line);
+    line.split_whitespace().next().map(str::to_string)
+}
+
+/// The `PT_INTERP` path of an ELF binary.
+pub fn elf_interpreter(data: &[u8]) -> Option<String> {
+    if !data.starts_with(b"\x7fELF") {
+        return None;
+    }
+    let elf = Elf {
+        data,
+        is_64: *data.get(4)? == 2,
+        little_endian: *data.get(5)? == 1,
+    };
+
+    let (phoff, phentsize, phnum) = if elf.is_64 {
+        (elf.u64(0x20)?, elf.u16(0x36)?, elf.u16(0x38)?)
+    } else {
+        (elf.u32(0x1c)? as u64, elf.u16(0x2a)?, elf.u16(0x2c)?)
+    };
+
+    (0..phnum as u64).find_map(|i| {
+        let header = usize::try_from(phoff + i * phentsize as u64).ok()?;
+        if elf.u32(header)? != PT_INTERP {
+            return None;
+        }
+        let (offset, size) = if elf.is_64 {
+            (elf.u64(header + 0x08)?, elf.u64(header + 0x20)?)
+        } else {
+            (elf.u32(header + 0x04)? as u64, elf.u32(header + 0x10)? as u64)
+        };
+        let start = usize::try_from(offset).ok()?;
+        let bytes = data.get(start..start.checked_add(usize::try_from(size).ok()?)?)?;
+        let path = bytes.split(|&b| b == 0).next()?;
+        Some(String::from_utf8_lossy(path).into_owned())
+    })
+}
+
+/// Bounds-checked field access into an ELF file of either class and byte
+/// order.
+struct Elf<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    little_endian: bool,
+}
+
+impl Elf<'_> {
+    fn bytes<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        self.data.get(offset..offset.checked_add(N)?)?.try_into().ok()
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let b = self.bytes(offset)?;
+        Some(if self.little_endian { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let b = self.bytes(offset)?;
+        Some(if self.little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) })
+    }
+
+    fn u64(&self, offset: usize) -> Option<u64> {
+        let b = self.bytes(offset)?;
+        Some(if self.little_endian { u64::from_le_bytes(b) } else { u64::from_be_bytes(b) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal 64-bit little-endian ELF with a `PT_LOAD` header followed by
+    /// a `PT_INTERP` header pointing at `interp` (if given).
+    fn elf64(interp: Option<&str>) -> Vec<u8> {
+        let mut data = vec![0u8; 0x40];
+        data[..4].copy_from_slice(b"\x7fELF");
+        data[4] = 2; // ELFCLASS64
+        data[5] = 1; // ELFDATA2LSB
+        data[0x20..0x28].copy_from_slice(&0x40u64.to_le_bytes()); // e_phoff
+        data[0x36..0x38].copy_from_slice(&56u16.to_le_bytes()); // e_phentsize
+        data[0x38..0x3a].copy_from_slice(&2u16.to_le_bytes()); // e_phnum
+
+        let interp_offset = 0x40 + 2 * 56;
+        for p_type in [1u32, if interp.is_some() { PT_INTERP } else { 1 }] {
+            let mut header = vec![0u8; 56];
+            header[..4].copy_from_slice(&p_type.to_le_bytes());
+            if p_type == PT_INTERP {
+                let size = interp.unwrap().len() as u64 + 1;
+                header[0x08..0x10].copy_from_slice(&(interp_offset as u64).to_le_bytes());
+                header[0x20..0x28].copy_from_slice(&size.to_le_bytes());
+            }
+            data.extend(header);
+        }
+        if let Some(interp) = interp {
+            data.extend(interp.as_bytes());
+            data.push(0);
+        }
+        data
+    }
+
+    /// The same as `elf64`, as a 32-bit big-endian binary.
+    fn elf32_be(interp: &str) -> Vec<u8> {
+        let mut data = vec![0u8; 0x34];
+        data[..4].copy_from_slice(b"\x7fELF");
+        data[4] = 1; // ELFCLASS32
+        data[5] = 2; // ELFDATA2MSB
+        data[0x1c..0x20].copy_from_slice(&0x34u32.to_be_bytes());
+        data[0x2a..0x2c].copy_from_slice(&32u16.to_be_bytes());
+        data[0x2c..0x2e].copy_from_slice(&1u16.to_be_bytes());
+
+        let mut header = vec![0u8; 32];
+        header[..4].copy_from_slice(&PT_INTERP.to_be_bytes());
+        header[0x04..0x08].copy_from_slice(&(0x34u32 + 32).to_be_bytes());
+        header[0x10..0x14].copy_from_slice(&(interp.len() as u32 + 1).to_be_bytes());
+        data.extend(header);
+        data.extend(interp.as_bytes());
+        data.push(0);
+        data
+    }
+
+    #[test]
+    fn reads_elf_interpreter() {
+        let dynamic = elf64(Some("/lib/ld-musl-x86_64.so.1"));
+        assert_eq!(elf_interpreter(&dynamic).as_deref(), Some("/lib/ld-musl-x86_64.so.1"));
+        assert_eq!(elf_interpreter(&elf64(None)), None);
+
+        let be = elf32_be("/lib/ld.so.1");
+        assert_eq!(elf_interpreter(&be).as_deref(), Some("/lib/ld.so.1"));
+    }
+
+    #[test]
+    fn truncated_or_foreign_files_have_no_interpreter() {
+        let dynamic = elf64(Some("/lib/ld-linux-x86-64.so.2"));
+        for len in [0, 4, 0x30, 0x40 + 56, dynamic.len() - 4] {
+            assert_eq!(elf_interpreter(&dynamic[..len]), None, "truncated at {len}");
+        }
+        assert_eq!(elf_interpreter(b"MZ\x90\x00"), None);
+        assert_eq!(script_interpreter(b"plain text"), None);
+    }
+
+    #[test]
+    fn reads_script_interpreter() {
+        assert_eq!(script_interpreter(b"#!/bin/sh\necho hi").as_deref(), Some("/bin/sh"));
+        assert_eq!(
+            script_interpreter(b"#! /usr/bin/env python3 -u\n").as_deref(),
+            Some("/usr/bin/env")
+        );
+    }
+
+    #[test]
+    fn reads_interpreter_from_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let binary = tmp.path().join("app");
+        std::fs::write(&binary, elf64(Some("/lib/ld-musl-x86_64.so.1"))).unwrap();
+        let script = tmp.path().join("run.sh");
+        std::fs::write(&script, "#!/bin/bash\n").unwrap();
+
+        assert_eq!(
+            read_interpreter(&binary).unwrap().as_deref(),
+            Some("/lib/ld-musl-x86_64.so.1")
+        );
+        assert_eq!(read_interpreter(&script).unwrap().as_deref(), Some("/bin/bash"));
+    }
+}
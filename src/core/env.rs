@@ -0,0 +1,292 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use super::model::{ContainerUser, DEFAULT_PATH};
+
+/// Host variables `--preserve-env-all` does not pass through, because they
+/// describe the host session rather than the container, or point at agents
+/// and sockets of that session that would hand its credentials over.
+/// Naming one in `--preserve-env` still passes it.
+const PRESERVE_ALL_DENYLIST: [&str; 13] = [
+    "HOME",
+    "PATH",
+    "HOSTNAME",
+    "PWD",
+    "OLDPWD",
+    "SHLVL",
+    "_",
+    "SSH_AUTH_SOCK",
+    "SSH_AGENT_PID",
+    "GPG_AGENT_INFO",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "XDG_RUNTIME_DIR",
+    "XAUTHORITY",
+];
+
+/// Where the container's environment comes from, besides the defaults.
+#[derive(Debug, Clone, Default)]
+pub struct EnvSources {
+    /// Host variables to copy (`--preserve-env`).
+    pub preserve: Vec<String>,
+    /// Copy every host variable outside the denylist (`--preserve-env-all`,
+    /// or `--env-host`).
+    pub preserve_all: bool,
+    /// Files of `KEY=VALUE` lines (`--env-file`).
+    pub env_files: Vec<PathBuf>,
+    /// `KEY=VALUE` assignments (`--env`).
+    pub env: Vec<String>,
+}
+
+/// The environment every container starts with. With a `--user`, `HOME` and
+/// `USER` come from its passwd entry; a user without one gets `HOME=/`.
+pub fn default_env(hostname: &str, user: Option<&ContainerUser>) -> Vec<(String, String)> {
+    let home = user.map_or("/root", |u| u.home.as_deref().unwrap_or("/"));
+    let mut env = vec![
+        ("PATH", DEFAULT_PATH),
+        ("HOSTNAME", hostname),
+        ("TERM", "xterm"),
+        ("HOME", home),
+    ];
+    if let Some(name) = user.and_then(|u| u.name.as_deref()) {
+        env.push(("USER", name));
+    }
+    env.into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// Resolve the container's environment as `KEY=VALUE` strings. Later sources
+/// win: defaults, then preserved host variables, then env files in order,
+/// then `--env` in order. A variable keeps the position it was first set at.
+pub fn resolve_env(
+    defaults: Vec<(String, String)>,
+    host: impl IntoIterator<Item = (String, String)>,
+    sources: &EnvSources,
+) -> Result<Vec<String>> {
+    let mut env = defaults;
+
+    for (key, value) in host {
+        let named = sources.preserve.contains(&key);
+        let all = sources.preserve_all && !PRESERVE_ALL_DENYLIST.contains(&key.as_str());
+        if named || all {
+            set(&mut env, &key, &value);
+        }
+    }
+    for file in &sources.env_files {
+        for (key, value) in read_env_file(file)? {
+            set(&mut env, &key, &value);
+        }
+    }
+    for assignment in &sources.env {
+        let (key, value) = parse_assignment(assignment)
+            .with_context(|| format!("invalid --env '{assignment}'"))?;
+        set(&mut env, key, value);
+    }
+
+    Ok(env.into_iter().map(|(k, v)| format!("{k}={v}")).collect())
+}
+
+/// Layer an environment resolved by [`resolve_env`] over `defaults`, once
+/// the defaults are known (they depend on the container's user).
+pub fn with_defaults(defaults: Vec<(String, String)>, env: &[String]) -> Vec<String> {
+    let mut merged = defaults;
+    for var in env {
+        let (key, value) = var.split_once('=').unwrap_or((var, ""));
+        set(&mut merged, key, value);
+    }
+    merged
+        .into_iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect()
+}
+
+/// Set `key` in `env`, keeping its position if already present.
+fn set(env: &mut Vec<(String, String)>, key: &str, value: &str) {
+    match env.iter_mut().find(|(k, _)| k == key) {
+        Some(entry) => entry.1 = value.to_string(),
+        None => env.push((key.to_string(), value.to_string())),
+    }
+}
+
+/// Read an env file: one `KEY=VALUE` per line; blank lines and lines
+/// starting with `#` are ignored.
+fn read_env_file(path: &Path) -> Result<Vec<(String, String)>> {
+    let data = fs::read_to_string(path)
+        .with_context(|| format!("failed to read env file {}", path.display()))?;
+    data.lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim_start()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            let (key, value) = parse_assignment(line)
+                .with_context(|| format!("{}:{}: invalid line", path.display(), i + 1))?;
+            Ok((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Split `KEY=VALUE`, checking that the key is a valid variable name.
+fn parse_assignment(s: &str) -> Result<(&str, &str)> {
+    let Some((key, value)) = s.split_once('=') else {
+        bail!("expected KEY=VALUE");
+    };
+    validate_env_name(key)?;
+    if value.contains('\0') {
+        bail!("value of {key} contains a NUL byte");
+    }
+    Ok((key, value))
+}
+
+/// Check that `name` is usable as an environment variable name.
+pub fn validate_env_name(name: &str) -> Result<()> {
+    let valid = name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        bail!("invalid environment variable name '{name}'");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host() -> Vec<(String, String)> {
+        [
+            ("http_proxy", "http://proxy:3128"),
+            ("SSH_AUTH_SOCK", "/tmp/agent.sock"),
+            ("HOME", "/home/me"),
+            ("PATH", "/home/me/bin:/usr/bin"),
+            ("EDITOR", "vi"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn defaults_only() {
+        let env = resolve_env(default_env("web", None), host(), &EnvSources::default()).unwrap();
+        assert_eq!(
+            env,
+            [
+                &format!("PATH={DEFAULT_PATH}"),
+                "HOSTNAME=web",
+                "TERM=xterm",
+                "HOME=/root"
+            ]
+        );
+    }
+
+    #[test]
+    fn preserve_named_and_all() {
+        let named = EnvSources {
+            preserve: vec!["http_proxy".into(), "HOME".into(), "MISSING".into()],
+            ..Default::default()
+        };
+        let env = resolve_env(default_env("h", None), host(), &named).unwrap();
+        assert!(env.contains(&"http_proxy=http://proxy:3128".into()));
+        assert!(env.contains(&"HOME=/home/me".into()));
+        assert!(!env
+            .iter()
+            .any(|v| v.starts_with("EDITOR=") || v.starts_with("MISSING=")));
+
+        // --preserve-env-all skips the denylist unless a variable is named.
+        let all = EnvSources {
+            preserve: vec!["HOME".into()],
+            preserve_all: true,
+            ..Default::default()
+        };
+        let env = resolve_env(default_env("h", None), host(), &all).unwrap();
+        assert!(env.contains(&"EDITOR=vi".into()));
+        assert!(!env.iter().any(|v| v.starts_with("SSH_AUTH_SOCK=")));
+        assert!(env.contains(&"HOME=/home/me".into()));
+        assert!(env.contains(&format!("PATH={DEFAULT_PATH}")));
+    }
+
+    #[test]
+    fn precedence_defaults_host_file_flag() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("app.env");
+        fs::write(
+            &file,
+            "# proxy for the app\n\nhttp_proxy=http://file:1\nLEVEL=file\nTERM=dumb\n",
+        )
+        .unwrap();
+
+        let sources = EnvSources {
+            preserve: vec!["http_proxy".into()],
+            env_files: vec![file],
+            env: vec!["LEVEL=flag".into(), "EMPTY=".into()],
+            ..Default::default()
+        };
+        let env = resolve_env(default_env("h", None), host(), &sources).unwrap();
+        assert_eq!(
+            env,
+            [
+                &format!("PATH={DEFAULT_PATH}"),
+                "HOSTNAME=h",
+                "TERM=dumb",
+                "HOME=/root",
+                "http_proxy=http://file:1",
+                "LEVEL=flag",
+                "EMPTY=",
+            ]
+        );
+    }
+
+    #[test]
+    fn user_defaults_under_explicit_env() {
+        let user = ContainerUser {
+            uid: 1000,
+            gid: 1000,
+            groups: vec![1000],
+            name: Some("app".into()),
+            home: Some("/home/app".into()),
+        };
+        let sources = EnvSources {
+            env: vec!["TERM=dumb".into()],
+            ..Default::default()
+        };
+        let explicit = resolve_env(Vec::new(), host(), &sources).unwrap();
+        let env = with_defaults(default_env("h", Some(&user)), &explicit);
+        assert_eq!(&env[2..], ["TERM=dumb", "HOME=/home/app", "USER=app"]);
+
+        let env = with_defaults(default_env("h", Some(&user)), &["HOME=/srv".into()]);
+        assert!(env.contains(&"HOME=/srv".into()), "{env:?}");
+
+        let anon = ContainerUser {
+            name: None,
+            home: None,
+            ..user
+        };
+        let env = with_defaults(default_env("h", Some(&anon)), &[]);
+        assert_eq!(&env[3..], ["HOME=/"]);
+    }
+
+    #[test]
+    fn rejects_invalid_assignments() {
+        for bad in ["NOVALUE", "=x", "1X=y", "A-B=c", "A B=c"] {
+            let sources = EnvSources {
+                env: vec![bad.into()],
+                ..Default::default()
+            };
+            assert!(
+                resolve_env(Vec::new(), Vec::new(), &sources).is_err(),
+                "{bad}"
+            );
+        }
+
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("bad.env");
+        fs::write(&file, "OK=1\nnot an assignment\n").unwrap();
+        let sources = EnvSources {
+            env_files: vec![file],
+            ..Default::default()
+        };
+        let err = resolve_env(Vec::new(), Vec::new(), &sources).unwrap_err();
+        assert!(format!("{err:#}").contains("bad.env:2"), "{err:#}");
+    }
+}
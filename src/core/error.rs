@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+/// Failure to exec the container command, reported with the shell's exit-code
+/// conventions so callers can tell it apart from runtime setup failures.
+#[derive(Debug, Clone, Error, Serialize, Deserialize)]
+pub enum LaunchError {
+    /// `execve` failed with `ENOENT`.
+    #[error("command not found: {0}")]
+    NotFound(String),
+    /// `execve` failed with `EACCES`.
+    #[error("command not executable (permission denied): {0}")]
+    NotExecutable(String),
+    /// `execve` failed with `ENOENT` because the command exists but its ELF
+    /// loader or `#!` interpreter does not.
+    #[error("interpreter {interpreter} of {command} not found in rootfs")]
+    InterpreterNotFound { command: String, interpreter: String },
+}
+
+impl LaunchError {
+    /// The exit code `craterun` exits with: 127 for not found (including a
+    /// missing interpreter), 126 for not executable.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::NotFound(_) | Self::InterpreterNotFound { .. } => 127,
+            Self::NotExecutable(_) => 126,
+        }
+    }
+}
+
+/// Metadata written by a newer craterun, whose schema this one does not
+/// know. Such a container is not broken, so it is never pruned.
+#[derive(Debug, Clone, Error)]
+#[error(
+    "container {id} was created by a newer craterun (metadata schema version {version}, this \
+     one supports up to {supported}); upgrade craterun to manage it"
+)]
+pub struct NewerSchemaError {
+    pub id: String,
+    pub version: u32,
+    pub supported: u32,
+}
+
+/// A [`LaunchError`] found by checking the rootfs before the container is
+/// started, described in more detail than the container itself could, e.g.
+/// with the commands named most like a missing one.
+#[derive(Debug, Error)]
+#[error("{message}")]
+pub struct PreflightError {
+    pub error: LaunchError,
+    pub message: String,
+}
+
+/// The code `craterun` exits with for `e`: that of the [`LaunchError`] it
+/// is or predicts, else 1.
+pub fn exit_code(e: &anyhow::Error) -> i32 {
+    e.downcast_ref::<LaunchError>()
+        .or_else(|| e.downcast_ref::<PreflightError>().map(|p| &p.error))
+        .map_or(1, LaunchError::exit_code)
+}
+
+/// Render an error for `--json-errors` as
+/// `{"error": "...", "context": [...]}`: the outermost message, then the
+/// messages of the error chain below it, outermost first.
+pub fn error_json(e: &anyhow::Error) -> serde_json::Value {
+    let mut chain = e.chain().map(ToString::to_string);
+    let error = chain.next().unwrap_or_default();
+    json!({ "error": error, "context": chain.collect::<Vec<_>>() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn error_json_walks_chain() {
+        let e = Err::<(), _>(LaunchError::NotFound("/bin/bash".into()))
+            .context("container init failed")
+            .context("failed to run container")
+            .unwrap_err();
+        assert_eq!(
+            error_json(&e),
+            json!({
+                "error": "failed to run container",
+                "context": ["container init failed", "command not found: /bin/bash"],
+            })
+        );
+
+        let e = anyhow::anyhow!("no container found with ID prefix 'x'");
+        assert_eq!(error_json(&e)["context"], json!([]));
+    }
+
+    #[test]
+    fn exit_codes_follow_the_shell() {
+        let e = Err::<(), _>(LaunchError::NotExecutable("/bin/sh".into()))
+            .context("container init failed")
+            .unwrap_err();
+        assert_eq!(exit_code(&e), 126);
+        let e = anyhow::Error::new(PreflightError {
+            error: LaunchError::NotFound("/bin/hs".into()),
+            message: "command '/bin/hs' not found".into(),
+        });
+        assert_eq!(exit_code(&e), 127);
+        assert_eq!(exit_code(&anyhow::anyhow!("no such container")), 1);
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{id, state};
+use crate::util;
+
+/// Name of the per-container directory holding the records of exec sessions
+/// and the logs of `exec --detach` ones.
+const EXECS_DIR: &str = "execs";
+/// Length of an exec session ID in hex characters.
+const SESSION_ID_LEN: usize = 12;
+
+/// A command started in a container with `exec`, recorded as
+/// `execs/<id>.json`. The output of one started with `--detach` goes to
+/// `execs/<id>.log`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecSession {
+    pub id: String,
+    pub container_id: String,
+    pub cmd: Vec<String>,
+    /// Host PID of the command; 0 until it has been started.
+    pub pid: u32,
+    pub started_at: DateTime<Utc>,
+    /// Set once the command has exited: its exit code, or 128 + the signal
+    /// number if a signal killed it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Whether the command ran attached to the terminal of `exec`, with no
+    /// log file.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub foreground: bool,
+}
+
+impl ExecSession {
+    /// A new session running `cmd` in container `container_id`.
+    pub fn new(container_id: &str, cmd: &[String]) -> Self {
+        Self {
+            id: id::generate_id()[..SESSION_ID_LEN].to_string(),
+            container_id: container_id.to_string(),
+            cmd: cmd.to_vec(),
+            pid: 0,
+            started_at: Utc::now(),
+            exit_code: None,
+            finished_at: None,
+            foreground: false,
+        }
+    }
+
+    /// `running`, `exited (N)`, or `unknown` if the command is gone without
+    /// its exit having been recorded.
+    pub fn status_summary(&self) -> String {
+        match self.exit_code {
+            Some(code) => format!("exited ({code})"),
+            None if state::pid_alive(self.pid) => "running".to_string(),
+            None => "unknown".to_string(),
+        }
+    }
+
+    /// The file the command's stdout and stderr go to, unless it runs in
+    /// the foreground.
+    pub fn log_path(&self) -> Result<PathBuf> {
+        Ok(execs_dir(&self.container_id)?.join(format!("{}.log", self.id)))
+    }
+
+    /// Write the session's record, creating the container's `execs`
+    /// directory if needed. Nothing is written once the container has been
+    /// removed, which kills its sessions.
+    pub fn save(&self) -> Result<()> {
+        if !state::container_dir(&self.container_id)?.is_dir() {
+            return Ok(());
+        }
+        let dir = execs_dir(&self.container_id)?;
+        util::fs::ensure_dir(&dir)?;
+        let json =
+            serde_json::to_string_pretty(self).context("failed to serialize exec session")?;
+        util::fs::write_file(&dir.join(format!("{}.json", self.id)), &json)
+    }
+}
+
+/// Return the directory of container `id`'s exec sessions.
+fn execs_dir(id: &str) -> Result<PathBuf> {
+    Ok(state::container_dir(id)?.join(EXECS_DIR))
+}
+
+/// List the exec sessions of container `id`, oldest first.
+pub fn list_sessions(id: &str) -> Result<Vec<ExecSession>> {
+    let dir = execs_dir(id)?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+    };
+
+    let mut sessions = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let data = util::fs::read_to_string(&path)?;
+            let session: ExecSession = serde_json::from_str(&data)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            sessions.push(session);
+        }
+    }
+    sessions.sort_by_key(|s| s.started_at);
+    Ok(sessions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_status() {
+        let mut session = ExecSession::new("0123456789abcdef", &["sleep".into(), "1".into()]);
+        assert_eq!(session.id.len(), SESSION_ID_LEN);
+        assert_eq!(session.status_summary(), "unknown");
+        session.pid = std::process::id();
+        assert_eq!(session.status_summary(), "running");
+        session.exit_code = Some(3);
+        assert_eq!(session.status_summary(), "exited (3)");
+
+        let json = serde_json::to_string(&session).unwrap();
+        let back: ExecSession = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.exit_code, Some(3));
+        assert_eq!(back.cmd, session.cmd);
+        assert!(!json.contains("foreground"), "{json}");
+    }
+
+    #[test]
+    fn foreground_sessions_round_trip() {
+        let mut session = ExecSession::new("0123456789abcdef", &["true".into()]);
+        session.foreground = true;
+        let json = serde_json::to_string(&session).unwrap();
+        let back: ExecSession = serde_json::from_str(&json).unwrap();
+        assert!(back.foreground);
+    }
+}
@@ -1,7 +1,7 @@
 use rand::Rng;
 
 /// Length of a container ID in hex characters.
-const ID_LEN: usize = 16;
+pub const ID_LEN: usize = 16;
 
 /// Generate a random hex container ID (16 hex chars = 8 random bytes).
 pub fn generate_id() -> String {
@@ -0,0 +1,626 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::state;
+use crate::util;
+
+/// Directory under the state directory holding imported images.
+const IMAGES_DIR: &str = "images";
+/// Name of the per-image metadata file.
+const META_FILE: &str = "meta.json";
+/// Name of the per-image directory holding the extracted rootfs.
+const ROOTFS_DIR: &str = "rootfs";
+/// Longest accepted image name, tag included.
+const MAX_NAME_LEN: usize = 128;
+/// Prefix of the whiteout files that delete a path of lower image layers.
+const WHITEOUT_PREFIX: &str = ".wh.";
+/// Whiteout file that hides the lower layers' contents of its directory.
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Image metadata persisted as `images/<name>/meta.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ImageMeta {
+    name: String,
+    /// `sha256:<hex>` of the imported tarball.
+    digest: String,
+    /// Size of the extracted rootfs on disk, in bytes.
+    size: u64,
+    created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provenance: Option<Provenance>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+}
+
+/// Where an image made by `commit` came from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// ID of the committed container.
+    pub container: String,
+    /// The container's `--rootfs`.
+    pub parent_rootfs: String,
+    /// The image that rootfs belongs to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_image: Option<String>,
+    /// The commands of the containers committed to build the image, oldest
+    /// first: the parent image's history, then the container's command.
+    pub history: Vec<Vec<String>>,
+}
+
+/// An imported or committed image as listed by `craterun images`.
+#[derive(Debug, Clone)]
+pub struct ImageInfo {
+    pub name: String,
+    /// `sha256:<hex>` of the imported tarball, the archive of a committed
+    /// container, or the manifest of a pulled image.
+    pub digest: String,
+    /// Size of the extracted rootfs on disk, in bytes.
+    pub size: u64,
+    pub created_at: DateTime<Utc>,
+    /// The extracted rootfs, usable as `run --rootfs`.
+    pub rootfs: PathBuf,
+    /// Set for an image made by `commit`.
+    pub provenance: Option<Provenance>,
+    /// The registry reference of an image made by `pull`, e.g.
+    /// `docker.io/library/alpine:3.20`.
+    pub source: Option<String>,
+}
+
+/// Return the directory holding imported images.
+pub fn images_dir() -> Result<PathBuf> {
+    Ok(state::state_dir()?.join(IMAGES_DIR))
+}
+
+/// Return the directory of the image `name`.
+pub fn image_dir(name: &str) -> Result<PathBuf> {
+    Ok(images_dir()?.join(name))
+}
+
+/// Check that `name` is usable as an image name: lowercase letters,
+/// digits, `.`, `_` and `-`, starting with a letter or digit, optionally
+/// followed by a `:TAG` of letters, digits, `.`, `_` and `-` that does not
+/// start with `.` or `-`, like the `alpine:3.20` of a pulled image. At most
+/// 128 characters in all.
+pub fn validate_image_name(name: &str) -> Result<()> {
+    let (base, tag) = match name.split_once(':') {
+        Some((base, tag)) => (base, Some(tag)),
+        None => (name, None),
+    };
+    let valid = name.len() <= MAX_NAME_LEN
+        && base.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && base
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c))
+        && tag.is_none_or(|tag| {
+            tag.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+                && tag
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+        });
+    if !valid {
+        bail!(
+            "invalid image name '{name}': expected up to {MAX_NAME_LEN} lowercase letters, \
+             digits, '.', '_' or '-', starting with a letter or digit, and optionally a \
+             :TAG"
+        );
+    }
+    Ok(())
+}
+
+/// Normalize a `--verify` value to `sha256:<hex>`. Accepts 64 hex digits,
+/// with or without the `sha256:` prefix.
+pub fn parse_digest(s: &str) -> Result<String> {
+    let hex = s.strip_prefix("sha256:").unwrap_or(s);
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("invalid --verify '{s}': expected a SHA-256 digest of 64 hex digits");
+    }
+    Ok(format!("sha256:{}", hex.to_ascii_lowercase()))
+}
+
+/// Hash `reader` with SHA-256, returning `sha256:<hex>`.
+pub fn sha256_digest(mut reader: impl Read) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher).context("failed to hash image")?;
+    let hex: String = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    Ok(format!("sha256:{hex}"))
+}
+
+/// Import the rootfs tarball at `tarball` (plain or gzip-compressed) as the
+/// image `name` and return it.
+///
+/// The tarball is hashed before anything is extracted; if `expected` is
+/// given, a mismatching digest is refused.
+pub fn import_image(tarball: &Path, name: &str, expected: Option<&str>) -> Result<ImageInfo> {
+    check_new_image(name)?;
+    let mut file =
+        File::open(tarball).with_context(|| format!("failed to open {}", tarball.display()))?;
+    let digest = sha256_digest(BufReader::new(&mut file))?;
+    if let Some(expected) = expected {
+        if digest != expected {
+            bail!(
+                "digest mismatch for {}: expected {expected}, got {digest}",
+                tarball.display()
+            );
+        }
+    }
+    file.rewind()
+        .with_context(|| format!("failed to read {}", tarball.display()))?;
+    install_image(name, digest, None, None, |rootfs| {
+        extract(file, rootfs).with_context(|| format!("failed to extract {}", tarball.display()))
+    })
+}
+
+/// Make the image `name`, pulled from the registry reference `source` with
+/// manifest digest `digest`, and return it. `fill` writes the rootfs into
+/// the directory it is given, next to the images. An image of the same name
+/// is replaced, but only once the new one is complete, and not at all while
+/// a container uses it.
+pub fn install_pulled(
+    name: &str,
+    source: &str,
+    digest: String,
+    fill: impl FnOnce(&Path) -> Result<()>,
+) -> Result<ImageInfo> {
+    validate_image_name(name)?;
+    util::fs::ensure_dir(&images_dir()?)?;
+    install_image(name, digest, None, Some(source.to_string()), fill)
+}
+
+/// Make the image `name` from a container's filesystem, which `archive`
+/// writes as a tar archive, and return it. The archive is kept next to the
+/// images while it is hashed and extracted, like an imported tarball.
+pub fn commit_image(
+    name: &str,
+    provenance: Provenance,
+    archive: impl FnOnce(&mut File) -> Result<()>,
+) -> Result<ImageInfo> {
+    check_new_image(name)?;
+    util::fs::ensure_dir(&images_dir()?)?;
+    let path = images_dir()?.join(format!(".{name}.tar"));
+    let result = (|| {
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        archive(&mut file)?;
+        file.rewind()?;
+        let digest = sha256_digest(BufReader::new(&mut file))?;
+        file.rewind()?;
+        install_image(name, digest, Some(provenance), None, |rootfs| {
+            extract(file, rootfs).with_context(|| format!("failed to extract {}", path.display()))
+        })
+    })();
+    let _ = fs::remove_file(&path);
+    result
+}
+
+/// Check that `name` is a valid name no image has yet.
+fn check_new_image(name: &str) -> Result<()> {
+    validate_image_name(name)?;
+    if image_dir(name)?.exists() {
+        bail!("image '{name}' already exists");
+    }
+    Ok(())
+}
+
+/// Have `fill` write the rootfs of the image `name` with digest `digest`
+/// and return the image. The rootfs is written next to the images and only
+/// moved into place once complete, replacing an existing image of the name.
+fn install_image(
+    name: &str,
+    digest: String,
+    provenance: Option<Provenance>,
+    source: Option<String>,
+    fill: impl FnOnce(&Path) -> Result<()>,
+) -> Result<ImageInfo> {
+    let dir = image_dir(name)?;
+    let staging = images_dir()?.join(format!(".{name}.importing"));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)
+            .with_context(|| format!("failed to remove {}", staging.display()))?;
+    }
+    let result = util::fs::ensure_dir(&staging.join(ROOTFS_DIR))
+        .and_then(|()| fill(&staging.join(ROOTFS_DIR)))
+        .and_then(|()| {
+            let meta = ImageMeta {
+                name: name.to_string(),
+                digest,
+                size: disk_usage(&staging.join(ROOTFS_DIR))?,
+                created_at: Utc::now(),
+                provenance,
+                source,
+            };
+            let json = serde_json::to_string_pretty(&meta)?;
+            util::fs::write_file(&staging.join(META_FILE), &json)
+        })
+        .and_then(|()| {
+            if dir.join(META_FILE).exists() {
+                remove_image(name)?;
+            }
+            fs::rename(&staging, &dir)
+                .with_context(|| format!("failed to move image into {}", dir.display()))
+        });
+    if let Err(e) = result {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+    load_image(name)
+}
+
+/// Unpack a plain or gzip-compressed tar archive into `dest`, keeping
+/// permissions and ownership. Entries that would escape `dest` are skipped.
+fn extract(file: File, dest: &Path) -> Result<()> {
+    util::fs::ensure_dir(dest)?;
+    open_archive(file)?.unpack(dest)?;
+    Ok(())
+}
+
+/// Apply the image layer `file`, a plain or gzip-compressed tar archive, to
+/// `rootfs`, which holds the layers below it: first delete what its
+/// whiteout files name, `.wh.NAME` for NAME and `.wh..wh..opq` for all of
+/// its directory's contents, then unpack the rest over `rootfs`, replacing
+/// a directory by a file or the other way around.
+pub fn apply_layer(mut file: File, rootfs: &Path) -> Result<()> {
+    for entry in open_archive(file.try_clone()?)?.entries()? {
+        let entry = entry?;
+        let path = entry.path()?;
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Some(whited_out) = name.strip_prefix(WHITEOUT_PREFIX) else {
+            continue;
+        };
+        let Some(dir) = path.parent().and_then(|dir| path_in(rootfs, dir)) else {
+            continue;
+        };
+        if name == OPAQUE_WHITEOUT {
+            if dir.is_dir() && !dir.is_symlink() {
+                for child in fs::read_dir(&dir)? {
+                    remove_path(&child?.path())?;
+                }
+            }
+        } else if !matches!(whited_out, "" | "." | "..") {
+            remove_path(&dir.join(whited_out))?;
+        }
+    }
+
+    file.rewind()?;
+    for entry in open_archive(file)?.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(WHITEOUT_PREFIX))
+        {
+            continue;
+        }
+        if let Some(dest) = path_in(rootfs, &path) {
+            let is_dir = entry.header().entry_type().is_dir();
+            if let Ok(meta) = fs::symlink_metadata(&dest) {
+                if meta.is_dir() != is_dir {
+                    remove_path(&dest)?;
+                }
+            }
+        }
+        entry
+            .unpack_in(rootfs)
+            .with_context(|| format!("failed to unpack {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// `rel`, a path inside an image layer, under `rootfs`, or `None` if it
+/// would leave `rootfs`, through `..` or a symlink of a lower layer.
+fn path_in(rootfs: &Path, rel: &Path) -> Option<PathBuf> {
+    let mut path = rootfs.to_path_buf();
+    for component in rel.components() {
+        if path.is_symlink() {
+            return None;
+        }
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(path)
+}
+
+/// Remove the file, symlink or directory tree at `path`, if there is one.
+fn remove_path(path: &Path) -> Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => Err(e),
+    };
+    result.with_context(|| format!("failed to remove {}", path.display()))
+}
+
+/// A tar archive reading `file`, decompressing it if it is gzip-compressed,
+/// that unpacks with permissions and ownership.
+fn open_archive(file: File) -> Result<tar::Archive<Box<dyn Read>>> {
+    let mut reader = BufReader::new(file);
+    let gzip = {
+        let mut magic = [0u8; 2];
+        let n = reader.read(&mut magic)?;
+        reader.seek_relative(-(n as i64))?;
+        magic == [0x1f, 0x8b]
+    };
+    let reader: Box<dyn Read> = if gzip {
+        Box::new(flate2::read::GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_ownerships(true);
+    archive.set_unpack_xattrs(true);
+    Ok(archive)
+}
+
+/// Load the image `name`.
+pub fn load_image(name: &str) -> Result<ImageInfo> {
+    validate_image_name(name)?;
+    let dir = image_dir(name)?;
+    let path = dir.join(META_FILE);
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => bail!("no image named '{name}'"),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let meta: ImageMeta = serde_json::from_str(&data)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    Ok(ImageInfo {
+        name: meta.name,
+        digest: meta.digest,
+        size: meta.size,
+        created_at: meta.created_at,
+        rootfs: dir.join(ROOTFS_DIR),
+        provenance: meta.provenance,
+        source: meta.source,
+    })
+}
+
+/// The image `name`, or `None` if there is none of that name.
+pub fn find_image(name: &str) -> Result<Option<ImageInfo>> {
+    validate_image_name(name)?;
+    if !image_dir(name)?.join(META_FILE).exists() {
+        return Ok(None);
+    }
+    load_image(name).map(Some)
+}
+
+/// Remove the image `name`. Refused while any container, running or not,
+/// still has the image's rootfs as its `--rootfs`.
+pub fn remove_image(name: &str) -> Result<()> {
+    let image = load_image(name)?;
+    let users = containers_using(&image)?;
+    if !users.is_empty() {
+        bail!(
+            "image '{name}' is used by container(s) {}; remove them first",
+            users.join(", ")
+        );
+    }
+
+    // Drop the metadata first so a partial removal no longer lists the image.
+    let dir = image_dir(name)?;
+    fs::remove_file(dir.join(META_FILE))
+        .with_context(|| format!("failed to remove image '{name}'"))?;
+    fs::remove_dir_all(&dir).with_context(|| format!("failed to remove {}", dir.display()))
+}
+
+/// IDs of the containers whose rootfs is `image`'s rootfs or lies inside it.
+pub fn containers_using(image: &ImageInfo) -> Result<Vec<String>> {
+    let rootfs = fs::canonicalize(&image.rootfs).unwrap_or_else(|_| image.rootfs.clone());
+    let mut ids = Vec::new();
+    for id in state::list_containers()? {
+        let Ok(meta) = state::load_meta(&id) else {
+            continue;
+        };
+        let path = Path::new(&meta.rootfs);
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if path.starts_with(&rootfs) {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+/// The image whose rootfs is `rootfs` or contains it, if any.
+pub fn image_of(rootfs: &Path) -> Result<Option<ImageInfo>> {
+    let rootfs = fs::canonicalize(rootfs).unwrap_or_else(|_| rootfs.to_path_buf());
+    Ok(list_images()?.into_iter().find(|image| {
+        let image_rootfs = fs::canonicalize(&image.rootfs).unwrap_or_else(|_| image.rootfs.clone());
+        rootfs.starts_with(image_rootfs)
+    }))
+}
+
+/// List imported images, sorted by name. Unfinished imports are skipped.
+pub fn list_images() -> Result<Vec<ImageInfo>> {
+    let dir = images_dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if let Some(name) = entry.file_name().to_str() {
+            if validate_image_name(name).is_ok() && entry.path().join(META_FILE).is_file() {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort();
+    names.iter().map(|name| load_image(name)).collect()
+}
+
+/// Total size of the files under `path`, without following symlinks. Hard
+/// links are counted once per link.
+fn disk_usage(path: &Path) -> Result<u64> {
+    let meta =
+        fs::symlink_metadata(path).with_context(|| format!("failed to stat {}", path.display()))?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let mut total = 0;
+    for entry in fs::read_dir(path).with_context(|| format!("failed to read {}", path.display()))? {
+        total += disk_usage(&entry?.path())?;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::state::tests::with_tmp_home;
+
+    /// A gzip-compressed tarball with `bin/sh` and `etc/os-release`.
+    fn tarball(dir: &Path) -> PathBuf {
+        let path = dir.join("rootfs.tar.gz");
+        let gz = flate2::write::GzEncoder::new(
+            File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(gz);
+        for (name, data, mode) in [
+            ("bin/sh", &b"#!fake"[..], 0o755),
+            ("etc/os-release", b"ID=test\n", 0o644),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(mode);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn digests() {
+        assert_eq!(
+            sha256_digest(&b"abc"[..]).unwrap(),
+            "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let upper = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        assert_eq!(
+            parse_digest(upper).unwrap(),
+            parse_digest(&format!("sha256:{}", upper.to_lowercase())).unwrap()
+        );
+        for bad in ["", "sha256:", "abc", &"g".repeat(64), &"a".repeat(65)] {
+            assert!(parse_digest(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn image_names() {
+        for good in ["alpine", "alpine-3.20", "a_b", "0", "a:3.20", "a:V1-x"] {
+            assert!(validate_image_name(good).is_ok(), "{good}");
+        }
+        for bad in [
+            "",
+            "Alpine",
+            ".hidden",
+            "-x",
+            "a/b",
+            "..",
+            &"a".repeat(129),
+            "alpine:",
+            ":3.20",
+            "alpine:-x",
+            "a:b:c",
+        ] {
+            assert!(validate_image_name(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn import_verifies_and_lists() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+        let tarball = tarball(tmp.path());
+        let digest = sha256_digest(File::open(&tarball).unwrap()).unwrap();
+        // As root the state directory is shared, so use a unique name.
+        let name = format!("test-{}", crate::core::id::generate_id());
+        let listed = || list_images().unwrap().iter().any(|i| i.name == name);
+
+        let wrong = format!("sha256:{}", "0".repeat(64));
+        let err = import_image(&tarball, &name, Some(&wrong)).unwrap_err();
+        assert!(err.to_string().contains("digest mismatch"), "{err}");
+        assert!(!listed());
+
+        let image = import_image(&tarball, &name, Some(&digest)).unwrap();
+        assert_eq!(image.digest, digest);
+        assert_eq!(image.size, 6 + 8);
+        assert!(image.rootfs.join("bin/sh").is_file());
+        assert!(import_image(&tarball, &name, None).is_err());
+        assert!(listed());
+
+        let loaded = load_image(&name).unwrap();
+        assert_eq!(loaded.created_at, image.created_at);
+
+        remove_image(&name).unwrap();
+        assert!(!listed());
+        assert!(!image_dir(&name).unwrap().exists());
+        assert!(remove_image(&name).is_err());
+    }
+
+    #[test]
+    fn failed_commits_leave_nothing_behind() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+        let name = format!("test-{}", crate::core::id::generate_id());
+        let provenance = Provenance {
+            container: "0123456789abcdef".into(),
+            parent_rootfs: "/tmp/rootfs".into(),
+            parent_image: None,
+            history: vec![vec!["/bin/sh".into()]],
+        };
+        let err = commit_image(&name, provenance, |_| bail!("disk full")).unwrap_err();
+        assert!(err.to_string().contains("disk full"), "{err}");
+        assert!(!image_dir(&name).unwrap().exists());
+        assert!(!images_dir().unwrap().join(format!(".{name}.tar")).exists());
+    }
+
+    #[test]
+    fn remove_refuses_images_in_use() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+        let tarball = tarball(tmp.path());
+        let name = format!("test-{}", crate::core::id::generate_id());
+        let image = import_image(&tarball, &name, None).unwrap();
+
+        let mut meta = state::tests::sample_meta(&crate::core::id::generate_id());
+        meta.rootfs = image.rootfs.join("../rootfs").display().to_string();
+        state::save_meta(&meta).unwrap();
+        assert_eq!(containers_using(&image).unwrap(), [meta.id.clone()]);
+        let err = remove_image(&name).unwrap_err();
+        assert!(err.to_string().contains(&meta.id), "{err}");
+        assert!(load_image(&name).is_ok());
+
+        state::remove_container_dir(&meta.id).unwrap();
+        remove_image(&name).unwrap();
+    }
+}
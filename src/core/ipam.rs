@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, Write};
+use std::net::Ipv4Addr;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::model::MacAddress;
+use super::network::{self, NetworkInfo};
+use super::state;
+use crate::util;
+
+/// Name of the file in a network's directory recording which container
+/// holds which of its addresses.
+const IPAM_FILE: &str = "ipam.json";
+
+/// Bridge addresses held by containers. An address stays held until its
+/// container is removed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Allocations {
+    /// Owning container ID by address.
+    addresses: BTreeMap<Ipv4Addr, String>,
+}
+
+/// Check that `ip` can be given to a container on `network`: inside its
+/// subnet and neither its network, broadcast nor gateway address.
+pub fn check_address(network: &NetworkInfo, ip: Ipv4Addr) -> Result<()> {
+    let subnet = network.subnet;
+    if !subnet.contains(ip) {
+        bail!(
+            "--ip {ip} is outside the subnet {subnet} of network '{}'",
+            network.name
+        );
+    }
+    if ip == subnet.addr || ip == subnet.broadcast() {
+        bail!("--ip {ip} is the network or broadcast address of {subnet}");
+    }
+    if ip == network.gateway() {
+        bail!(
+            "--ip {ip} is the address of the bridge of network '{}'",
+            network.name
+        );
+    }
+    Ok(())
+}
+
+/// The MAC address given to a container at `ip` without `--mac-address`:
+/// locally administered, ending in the IP address.
+pub fn mac_address(ip: Ipv4Addr) -> MacAddress {
+    let [a, b, c, d] = ip.octets();
+    MacAddress([0x02, 0x42, a, b, c, d])
+}
+
+/// Reserve an address on `network` for container `id`: `requested` if
+/// given, else the lowest free one. Addresses of containers that no longer
+/// exist are reclaimed first.
+pub fn allocate(network: &NetworkInfo, id: &str, requested: Option<Ipv4Addr>) -> Result<Ipv4Addr> {
+    update(&network.name, |allocations| {
+        allocations
+            .addresses
+            .retain(|_, owner| owner == id || exists(owner));
+        let ip = pick(network, &allocations.addresses, requested)?;
+        allocations.addresses.insert(ip, id.to_string());
+        Ok(ip)
+    })
+}
+
+/// Release the address container `id` holds on the network `name`, if any.
+/// Nothing is held on a network that has since been removed.
+pub fn release(name: &str, id: &str) -> Result<()> {
+    if !network::network_dir(name)?.exists() {
+        return Ok(());
+    }
+    update(name, |allocations| {
+        allocations.addresses.retain(|_, owner| owner != id);
+        Ok(())
+    })
+}
+
+/// IDs of the existing containers holding an address on the network `name`.
+pub fn holders(name: &str) -> Result<Vec<String>> {
+    let path = network::network_dir(name)?.join(IPAM_FILE);
+    let data = match std::fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let allocations = parse(&data, &path)?;
+    Ok(allocations
+        .addresses
+        .into_values()
+        .filter(|owner| exists(owner))
+        .collect())
+}
+
+/// Whether container `id` still has a state directory.
+fn exists(id: &str) -> bool {
+    state::container_dir(id).is_ok_and(|dir| dir.exists())
+}
+
+/// Pick `requested` if it is free, else the lowest free address of `network`.
+fn pick(
+    network: &NetworkInfo,
+    taken: &BTreeMap<Ipv4Addr, String>,
+    requested: Option<Ipv4Addr>,
+) -> Result<Ipv4Addr> {
+    if let Some(ip) = requested {
+        check_address(network, ip)?;
+        if let Some(owner) = taken.get(&ip) {
+            bail!("--ip {ip} is already allocated to container {owner}");
+        }
+        return Ok(ip);
+    }
+    let gateway = network.gateway();
+    network
+        .subnet
+        .hosts()
+        .find(|ip| *ip != gateway && !taken.contains_key(ip))
+        .with_context(|| {
+            format!(
+                "no free address left on network '{}' ({})",
+                network.name, network.subnet
+            )
+        })
+}
+
+/// Parse the allocations file at `path`, empty when just created.
+fn parse(data: &str, path: &std::path::Path) -> Result<Allocations> {
+    if data.trim().is_empty() {
+        return Ok(Allocations::default());
+    }
+    serde_json::from_str(data).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// Apply `change` to the allocations file of the network `name` while
+/// holding a lock on it, so concurrent `run`s never hand out the same
+/// address.
+fn update<T>(name: &str, change: impl FnOnce(&mut Allocations) -> Result<T>) -> Result<T> {
+    let dir = network::network_dir(name)?;
+    util::fs::ensure_dir(&dir)?;
+    let path = dir.join(IPAM_FILE);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    // Released when the file is closed.
+    file.lock()
+        .with_context(|| format!("failed to lock {}", path.display()))?;
+
+    let mut data = String::new();
+    file.read_to_string(&mut data)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let mut allocations = parse(&data, &path)?;
+
+    let result = change(&mut allocations)?;
+    let json =
+        serde_json::to_string_pretty(&allocations).context("failed to serialize allocations")?;
+    file.set_len(0)
+        .and_then(|()| file.rewind())
+        .and_then(|()| file.write_all(json.as_bytes()))
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Ipv4Addr {
+        s.parse().unwrap()
+    }
+
+    fn bridge() -> NetworkInfo {
+        network::load_network(network::DEFAULT_NETWORK).unwrap()
+    }
+
+    #[test]
+    fn checks_requested_addresses() {
+        check_address(&bridge(), ip("10.77.0.42")).unwrap();
+        check_address(&bridge(), ip("10.77.0.254")).unwrap();
+        for (bad, expected) in [
+            ("10.78.0.42", "outside the subnet 10.77.0.0/24"),
+            ("10.77.0.0", "network or broadcast"),
+            ("10.77.0.255", "network or broadcast"),
+            ("10.77.0.1", "address of the bridge"),
+        ] {
+            let err = check_address(&bridge(), ip(bad)).unwrap_err();
+            assert!(err.to_string().contains(expected), "{bad}: {err}");
+        }
+    }
+
+    #[test]
+    fn picks_requested_or_lowest_free_address() {
+        let mut taken = BTreeMap::new();
+        assert_eq!(pick(&bridge(), &taken, None).unwrap(), ip("10.77.0.2"));
+        taken.insert(ip("10.77.0.2"), "a".to_string());
+        taken.insert(ip("10.77.0.4"), "b".to_string());
+        assert_eq!(pick(&bridge(), &taken, None).unwrap(), ip("10.77.0.3"));
+        assert_eq!(
+            pick(&bridge(), &taken, Some(ip("10.77.0.42"))).unwrap(),
+            ip("10.77.0.42")
+        );
+
+        let err = pick(&bridge(), &taken, Some(ip("10.77.0.4"))).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--ip 10.77.0.4 is already allocated to container b"
+        );
+
+        let full: BTreeMap<_, _> = (2..=254)
+            .map(|host| (Ipv4Addr::new(10, 77, 0, host), "c".to_string()))
+            .collect();
+        assert!(pick(&bridge(), &full, None).is_err());
+    }
+
+    #[test]
+    fn derives_mac_from_address() {
+        assert_eq!(
+            mac_address(ip("10.77.0.42")).to_string(),
+            "02:42:0a:4d:00:2a"
+        );
+    }
+
+    #[test]
+    fn allocations_round_trip() {
+        let mut allocations = Allocations::default();
+        allocations
+            .addresses
+            .insert(ip("10.77.0.2"), "0123456789abcdef".into());
+        let json = serde_json::to_string(&allocations).unwrap();
+        assert_eq!(json, r#"{"addresses":{"10.77.0.2":"0123456789abcdef"}}"#);
+        let back: Allocations = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.addresses, allocations.addresses);
+    }
+}
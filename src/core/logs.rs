@@ -0,0 +1,212 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use super::state::{self, STDERR_LOG, STDOUT_LOG};
+use crate::util;
+
+/// Which parts of a container's logs `read_logs` returns, and how.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogOptions {
+    /// Only return stdout.
+    pub stdout_only: bool,
+    /// Only return stderr.
+    pub stderr_only: bool,
+    /// Return stdout's lines followed by stderr's, as one stream.
+    pub combine: bool,
+    /// Only return the last N lines of each stream, or of the merged one.
+    /// Every line returned then ends with a newline.
+    pub tail: Option<usize>,
+}
+
+/// Log contents as returned by `read_logs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogView {
+    /// Streams kept apart. A stream that was not requested is `None`.
+    Separate {
+        stdout: Option<String>,
+        stderr: Option<String>,
+    },
+    /// Both streams as a single one: stdout's lines, then stderr's.
+    Combined(String),
+}
+
+/// Read the logs of container `id`.
+pub fn read_logs(id: &str, opts: LogOptions) -> Result<LogView> {
+    read_log_files(
+        &state::log_path(id, STDOUT_LOG)?,
+        &state::log_path(id, STDERR_LOG)?,
+        opts,
+    )
+}
+
+fn read_log_files(stdout_path: &Path, stderr_path: &Path, opts: LogOptions) -> Result<LogView> {
+    let read = |path: &Path| -> Result<String> {
+        if !path.exists() {
+            return Ok(String::new());
+        }
+        match opts.tail {
+            Some(n) => Ok(util::fs::tail_lines(path, n)?
+                .into_iter()
+                .map(|line| line + "\n")
+                .collect()),
+            None => fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display())),
+        }
+    };
+
+    let stdout = (!opts.stderr_only).then(|| read(stdout_path)).transpose()?;
+    let stderr = (!opts.stdout_only).then(|| read(stderr_path)).transpose()?;
+
+    if !opts.combine {
+        return Ok(LogView::Separate { stdout, stderr });
+    }
+
+    // The log files carry no per-line timestamps, so there is nothing to
+    // interleave by: stdout's lines come first, then stderr's.
+    let lines: Vec<&str> = [&stdout, &stderr]
+        .into_iter()
+        .flatten()
+        .flat_map(|text| text.lines())
+        .collect();
+    let skip = opts.tail.map_or(0, |n| lines.len().saturating_sub(n));
+    let mut combined = String::new();
+    for line in &lines[skip..] {
+        combined.push_str(line);
+        combined.push('\n');
+    }
+    Ok(LogView::Combined(combined))
+}
+
+/// Truncate the stdout and stderr logs of container `id` to zero length.
+pub fn clear_logs(id: &str) -> Result<()> {
+    clear_log_files(&[
+        state::log_path(id, STDOUT_LOG)?,
+        state::log_path(id, STDERR_LOG)?,
+    ])
+}
+
+fn clear_log_files(paths: &[PathBuf]) -> Result<()> {
+    for path in paths.iter().filter(|p| p.exists()) {
+        fs::File::create(path).with_context(|| format!("failed to truncate {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_in(dir: &Path, opts: LogOptions) -> Result<LogView> {
+        read_log_files(&dir.join(STDOUT_LOG), &dir.join(STDERR_LOG), opts)
+    }
+
+    fn log_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(STDOUT_LOG), "out 1\nout 2").unwrap();
+        fs::write(dir.path().join(STDERR_LOG), "err 1\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn separate_and_single_streams() {
+        let dir = log_dir();
+        assert_eq!(
+            read_in(dir.path(), LogOptions::default()).unwrap(),
+            LogView::Separate {
+                stdout: Some("out 1\nout 2".into()),
+                stderr: Some("err 1\n".into()),
+            }
+        );
+
+        let stderr_only = LogOptions {
+            stderr_only: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            read_in(dir.path(), stderr_only).unwrap(),
+            LogView::Separate {
+                stdout: None,
+                stderr: Some("err 1\n".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn combined_stream_ends_every_line() {
+        let dir = log_dir();
+        let combine = LogOptions {
+            combine: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            read_in(dir.path(), combine).unwrap(),
+            LogView::Combined("out 1\nout 2\nerr 1\n".into())
+        );
+    }
+
+    #[test]
+    fn combined_stream_puts_stdout_first() {
+        // Nothing records which stream was written first: stderr follows.
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(STDERR_LOG), "err 1\nerr 2\n").unwrap();
+        fs::write(dir.path().join(STDOUT_LOG), "out 1\n").unwrap();
+        let combine = LogOptions {
+            combine: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            read_in(dir.path(), combine).unwrap(),
+            LogView::Combined("out 1\nerr 1\nerr 2\n".into())
+        );
+    }
+
+    #[test]
+    fn tail_of_each_stream_and_of_the_merged_one() {
+        let dir = log_dir();
+        let tail = LogOptions {
+            tail: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            read_in(dir.path(), tail).unwrap(),
+            LogView::Separate {
+                stdout: Some("out 2\n".into()),
+                stderr: Some("err 1\n".into()),
+            }
+        );
+
+        let combined_tail = LogOptions {
+            combine: true,
+            tail: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            read_in(dir.path(), combined_tail).unwrap(),
+            LogView::Combined("out 2\nerr 1\n".into())
+        );
+    }
+
+    #[test]
+    fn missing_log_files_read_as_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            read_in(dir.path(), LogOptions::default()).unwrap(),
+            LogView::Separate {
+                stdout: Some(String::new()),
+                stderr: Some(String::new()),
+            }
+        );
+    }
+
+    #[test]
+    fn clear_truncates_existing_logs_only() {
+        let dir = log_dir();
+        fs::remove_file(dir.path().join(STDERR_LOG)).unwrap();
+        clear_log_files(&[dir.path().join(STDOUT_LOG), dir.path().join(STDERR_LOG)]).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.path().join(STDOUT_LOG)).unwrap(), "");
+        assert!(!dir.path().join(STDERR_LOG).exists());
+    }
+}
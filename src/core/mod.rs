@@ -1,3 +1,16 @@
+pub mod cmdfile;
+pub mod elf;
+pub mod env;
+pub mod error;
+pub mod exec;
 pub mod id;
+pub mod image;
+pub mod ipam;
+pub mod logs;
 pub mod model;
+pub mod network;
+pub mod registry;
 pub mod state;
+pub mod user;
+pub mod userns;
+pub mod validate;
@@ -1,6 +1,25 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+
+use super::userns::IdMappings;
+
+/// `PATH` of the container command and `exec` sessions. Bare command names
+/// are looked up in it.
+pub const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Hostname of a container with its own UTS namespace and no `--hostname`.
+pub const DEFAULT_HOSTNAME: &str = "craterun";
+
+/// Exit code recorded for a container that stopped without craterun observing
+/// how (e.g. its monitor was killed) and without an exit-code file.
+pub const EXIT_CODE_UNKNOWN: i32 = -1;
 
 /// Status of a container in the CrateRun runtime.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -24,11 +43,180 @@ impl fmt::Display for ContainerStatus {
     }
 }
 
+/// How a container's command ended: on its own with an exit code, or
+/// killed by a signal, which its recorded exit code (128 + the signal
+/// number) cannot tell apart from exiting with that code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContainerExit {
+    Exited(i32),
+    Killed {
+        /// Name of the signal, e.g. `SIGKILL`.
+        signal: String,
+        core_dumped: bool,
+    },
+}
+
+impl fmt::Display for ContainerExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exited(code) => write!(f, "exited ({code})"),
+            Self::Killed {
+                signal,
+                core_dumped: false,
+            } => write!(f, "killed ({signal})"),
+            Self::Killed {
+                signal,
+                core_dumped: true,
+            } => write!(f, "killed ({signal}, core dumped)"),
+        }
+    }
+}
+
+/// A condition selecting containers by their metadata, written as
+/// `status=STATUS` or `network=NAME` (`rm --filter`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContainerFilter {
+    Status(ContainerStatus),
+    Network(String),
+}
+
+impl ContainerFilter {
+    /// Whether `meta`, with its status refreshed, matches the filter.
+    pub fn matches(&self, meta: &ContainerMeta) -> bool {
+        match self {
+            Self::Status(status) => meta.status == *status,
+            Self::Network(name) => meta.network.as_ref() == Some(name),
+        }
+    }
+}
+
+impl FromStr for ContainerFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((key, value)) = s.split_once('=') else {
+            bail!("invalid filter '{s}': expected 'KEY=VALUE'");
+        };
+        match key {
+            "status" => {
+                let status = [
+                    ContainerStatus::Running,
+                    ContainerStatus::Stopped,
+                    ContainerStatus::Created,
+                ]
+                .into_iter()
+                .find(|status| status.to_string() == value);
+                match status {
+                    Some(status) => Ok(Self::Status(status)),
+                    None => bail!(
+                        "invalid status '{value}': expected 'running', 'stopped' or 'created'"
+                    ),
+                }
+            }
+            "network" if !value.is_empty() => Ok(Self::Network(value.to_string())),
+            "label" => bail!("containers have no labels to filter on"),
+            _ => bail!("invalid filter '{s}': expected status=STATUS or network=NAME"),
+        }
+    }
+}
+
+/// Outcome of a single host check performed by `craterun check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    /// The requirement is met.
+    Pass,
+    /// Optional functionality is unavailable; containers can still run.
+    Warn,
+    /// The requirement is not met; `run` will fail.
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pass => write!(f, "pass"),
+            Self::Warn => write!(f, "warn"),
+            Self::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+/// Result of probing one host requirement.
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    /// Short identifier of the check (e.g. `"cgroup-v2"`).
+    pub name: String,
+    pub status: CheckStatus,
+    /// What was found.
+    pub detail: String,
+    /// How to fix it, for non-passing checks.
+    pub hint: Option<String>,
+}
+
+/// Which cgroup hierarchy the host has mounted at `/sys/fs/cgroup`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CgroupMode {
+    /// cgroups v2 alone, the only hierarchy craterun supports.
+    V2,
+    /// cgroups v1 with the controllers, and v2 beside it without any.
+    Hybrid,
+    V1,
+    /// No cgroup hierarchy at all.
+    Unmounted,
+}
+
+impl fmt::Display for CgroupMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V2 => write!(f, "v2"),
+            Self::Hybrid => write!(f, "hybrid"),
+            Self::V1 => write!(f, "v1"),
+            Self::Unmounted => write!(f, "unmounted"),
+        }
+    }
+}
+
+/// What the host offers craterun, as reported by `craterun system info`.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostInfo {
+    /// Kernel release, e.g. `6.8.0-45-generic`, if it could be read.
+    pub kernel: Option<String>,
+    pub cgroup_mode: CgroupMode,
+    /// Controllers of the root cgroup.
+    pub cgroup_controllers: Vec<String>,
+    /// Controllers handed down to container cgroups.
+    pub cgroup_delegated: Vec<String>,
+    /// The cgroup container cgroups are created in.
+    pub cgroup_parent: PathBuf,
+    /// Whether unprivileged user namespaces are allowed.
+    pub user_namespaces: bool,
+    pub overlayfs: bool,
+}
+
+/// Current version of the persisted `ContainerMeta` schema. Bump it when a
+/// change needs a migration step in `state::load_meta`.
+pub const META_SCHEMA_VERSION: u32 = 1;
+
 /// Persisted metadata for a single container.
+///
+/// Fields added after the first release must be `#[serde(default)]` so that
+/// metadata written by older versions keeps loading.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContainerMeta {
+    /// Version of the schema this metadata was written with. Absent (0) in
+    /// files written before versioning was introduced.
+    #[serde(default)]
+    pub schema_version: u32,
     /// Unique hex container ID.
     pub id: String,
+    /// Unique name the container can be referred to by (`--name`), if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The `up` project service the container runs, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<ServiceLabel>,
     /// Absolute path to the root filesystem.
     pub rootfs: String,
     /// The command (and arguments) the container was started with.
@@ -39,65 +227,2569 @@ pub struct ContainerMeta {
     pub exit_code: Option<i32>,
     /// When the container was created.
     pub created_at: DateTime<Utc>,
+    /// When the container process was confirmed running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    /// When the container process was reaped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
     /// Current status.
     pub status: ContainerStatus,
     /// Hostname set inside the container.
     pub hostname: String,
     /// Memory limit in bytes, if set.
     pub memory_limit: Option<u64>,
+    /// The memory limit as the user wrote it (e.g. `"64M"`), for display.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_limit_spec: Option<String>,
+    /// `--memory-swappiness`, if set. Only 0 is accepted, applied as a
+    /// `memory.swap.max` of 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_swappiness: Option<u8>,
     /// CPU limit string for cpu.max, if set.
     pub cpu_limit: Option<String>,
     /// PID limit, if set.
     pub pids_limit: Option<u64>,
+    /// How many times a fork failed because `pids_limit` was reached
+    /// (`max` in the cgroup's `pids.events`).
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub pids_limit_hits: u64,
+    /// CPUs the container is pinned to (`cpuset.cpus`), if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpuset_cpus: Option<String>,
+    /// Memory nodes the container is pinned to (`cpuset.mems`), if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpuset_mems: Option<String>,
+    /// Process resource limits applied to the container command and `exec`
+    /// sessions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ulimits: Vec<Ulimit>,
+    /// OOM killer score adjustment of the container command, if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oom_score_adj: Option<i32>,
+    /// umask of the container command and `exec` sessions, if set; else
+    /// they inherit craterun's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub umask: Option<Umask>,
+    /// Time zone of the container, if set; else it has the rootfs's own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<Timezone>,
+    /// Host devices passed through into the container's `/dev`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<DeviceMapping>,
+    /// tmpfs mounts created inside the container.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tmpfs: Vec<TmpfsMount>,
+    /// Size of the container's `/dev/shm` in bytes, if set with
+    /// `--shm-size`; else it is [`SHM_DEFAULT_SIZE`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shm_size: Option<u64>,
+    /// Mount propagation between the container and the host.
+    #[serde(default, skip_serializing_if = "MountPropagation::is_private")]
+    pub mount_propagation: MountPropagation,
+    /// Hardening options of the container's `/proc`.
+    #[serde(default, skip_serializing_if = "ProcOptions::is_default")]
+    pub proc_opts: ProcOptions,
+    /// Session keyring of the container's processes.
+    #[serde(default, skip_serializing_if = "Keyring::is_private")]
+    pub keyring: Keyring,
+    /// The container's user namespace, with its ID maps unless it is the
+    /// host's.
+    #[serde(default, skip_serializing_if = "UsernsMode::is_host")]
+    pub userns: UsernsMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_mappings: Option<IdMappings>,
+    /// The container's cgroup namespace. Containers created before it was
+    /// recorded share the host's.
+    #[serde(
+        default = "CgroupNsMode::host",
+        skip_serializing_if = "CgroupNsMode::is_host"
+    )]
+    pub cgroup_ns: CgroupNsMode,
+    /// Loop device the rootfs image is attached to, for image-backed
+    /// containers. Detached when the container's filesystem is torn down.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loop_device: Option<String>,
+    /// Size limit of the writable layer of an image-backed container, if
+    /// set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<StorageLimit>,
+    /// Namespaced kernel parameters set inside the container.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sysctls: Vec<Sysctl>,
+    /// Resolved environment of the container command, as `KEY=VALUE`. Also
+    /// used for `exec` sessions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    /// Whether the environment includes the whole host environment
+    /// (`--env-host` or `--preserve-env-all`).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub env_host: bool,
+    /// User the container command runs as; also the default for `exec`.
+    /// Root if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<ContainerUser>,
+    /// NIS domain name set inside the container, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domainname: Option<String>,
+    /// Network namespace; a joined container is recorded by its full ID, as
+    /// for the other namespaces below.
+    #[serde(default, skip_serializing_if = "NamespaceMode::is_private")]
+    pub net: NamespaceMode,
+    /// PID namespace.
+    #[serde(default, skip_serializing_if = "NamespaceMode::is_private")]
+    pub pid_ns: NamespaceMode,
+    /// IPC namespace.
+    #[serde(default, skip_serializing_if = "NamespaceMode::is_private")]
+    pub ipc: NamespaceMode,
+    /// UTS namespace.
+    #[serde(default, skip_serializing_if = "NamespaceMode::is_private")]
+    pub uts: NamespaceMode,
+    /// Network whose bridge the container is attached to (`--net bridge`
+    /// or `--network` only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    /// Address of the container on its network. Reserved until the
+    /// container is removed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_address: Option<Ipv4Addr>,
+    /// MAC address of the container's bridge interface.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac_address: Option<MacAddress>,
+    /// Host ports forwarded into the container (`--net slirp` only).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<PortMapping>,
+    /// PID of the `slirp4netns` helper of a `--net slirp` container while it
+    /// runs, so that a helper left behind by a crashed monitor can be killed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slirp_pid: Option<u32>,
+    /// Signal `stop` sends before escalating to SIGKILL (e.g. `"SIGQUIT"`);
+    /// SIGTERM if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_signal: Option<String>,
+    /// Init run as PID 1 with `cmd` as its arguments (`--init-path`), if
+    /// any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init_path: Option<String>,
+    /// argv[0] the command was started with instead of `cmd[0]`
+    /// (`--argv0`), if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub argv0: Option<String>,
+    /// Whether the container entered its rootfs with `chroot` (`--no-pivot`).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_pivot: bool,
+    /// Whether `chroot` was allowed where `pivot_root` is not possible.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_chroot_fallback: bool,
+    /// Whether the check of the command in the rootfs was skipped.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_preflight: bool,
+    /// Whether the command was given craterun's stdin.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub attach_stdin: bool,
+    /// Name of the signal that killed the container (e.g. `"SIGKILL"`), if
+    /// any. `exit_code` is then 128 + the signal number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<String>,
+    /// Whether the signal that killed the container dumped core.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub core_dumped: bool,
+    /// Health check results, if the container has a `--health-cmd`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<HealthState>,
+    /// The craterun arguments the container was created with, e.g.
+    /// `["run", "--memory", "64M", "--rootfs", "/srv/alpine", "--", "/bin/sh"]`.
+    /// Empty for containers created before it was recorded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub invocation: Vec<String>,
 }
 
-/// Configuration for launching a new container. Constructed from CLI arguments.
-#[derive(Debug, Clone)]
-pub struct ContainerConfig {
-    pub rootfs: String,
-    pub cmd: Vec<String>,
-    pub hostname: String,
-    pub memory: Option<u64>,
-    pub cpu: Option<String>,
-    pub pids: Option<u64>,
-    pub uid: Option<u32>,
-    pub gid: Option<u32>,
+fn is_zero(n: &u64) -> bool {
+    *n == 0
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Filesystem image formats accepted as `--rootfs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Squashfs,
+    Erofs,
+}
 
-    #[test]
-    fn status_display() {
-        assert_eq!(ContainerStatus::Running.to_string(), "running");
-        assert_eq!(ContainerStatus::Stopped.to_string(), "stopped");
-        assert_eq!(ContainerStatus::Created.to_string(), "created");
+impl ImageFormat {
+    /// Filesystem type passed to `mount(2)`.
+    pub fn fstype(self) -> &'static str {
+        match self {
+            Self::Squashfs => "squashfs",
+            Self::Erofs => "erofs",
+        }
+    }
+}
+
+/// Mount propagation of the container's mount tree (`--mount-propagation`):
+/// whether mount events cross between it and the host's.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MountPropagation {
+    /// No events cross in either direction.
+    #[default]
+    Private,
+    /// Host mount events reach the container, but not the other way round.
+    Slave,
+    /// Mount events cross in both directions.
+    Shared,
+}
+
+impl MountPropagation {
+    pub fn is_private(&self) -> bool {
+        *self == Self::Private
+    }
+}
+
+impl FromStr for MountPropagation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "private" => Ok(Self::Private),
+            "slave" => Ok(Self::Slave),
+            "shared" => Ok(Self::Shared),
+            _ => bail!("invalid mount propagation '{s}': expected 'private', 'slave' or 'shared'"),
+        }
+    }
+}
+
+impl fmt::Display for MountPropagation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Private => write!(f, "private"),
+            Self::Slave => write!(f, "slave"),
+            Self::Shared => write!(f, "shared"),
+        }
+    }
+}
+
+/// The session keyring of the container's processes (`--keyring`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Keyring {
+    /// A fresh, empty session keyring, so the container cannot read or
+    /// alter the keys of the session it was started from.
+    #[default]
+    Private,
+    /// The session keyring of the process that started the container.
+    Host,
+}
+
+impl Keyring {
+    pub fn is_private(&self) -> bool {
+        *self == Self::Private
+    }
+}
+
+impl FromStr for Keyring {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "private" => Ok(Self::Private),
+            "host" => Ok(Self::Host),
+            _ => bail!("invalid keyring '{s}': expected 'private' or 'host'"),
+        }
+    }
+}
+
+impl fmt::Display for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Private => write!(f, "private"),
+            Self::Host => write!(f, "host"),
+        }
+    }
+}
+
+/// The container's user namespace (`--userns`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UsernsMode {
+    /// The host's: container IDs are host IDs.
+    #[default]
+    Host,
+    /// A new one mapping the calling user's UID and GID to themselves and
+    /// the rest of the container's IDs to the user's subordinate ranges.
+    KeepId,
+}
+
+impl UsernsMode {
+    pub fn is_host(&self) -> bool {
+        *self == Self::Host
+    }
+}
+
+impl FromStr for UsernsMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "host" => Ok(Self::Host),
+            "keep-id" => Ok(Self::KeepId),
+            _ => bail!("invalid user namespace '{s}': expected 'host' or 'keep-id'"),
+        }
+    }
+}
+
+impl fmt::Display for UsernsMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Host => write!(f, "host"),
+            Self::KeepId => write!(f, "keep-id"),
+        }
+    }
+}
+
+/// The container's cgroup namespace (`--cgroup-ns`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CgroupNsMode {
+    /// A new one rooted at the container's cgroup, so `/proc/self/cgroup`
+    /// shows `/` instead of the host's cgroup hierarchy.
+    #[default]
+    Private,
+    /// The host's.
+    Host,
+}
+
+impl CgroupNsMode {
+    pub fn host() -> Self {
+        Self::Host
+    }
+
+    pub fn is_host(&self) -> bool {
+        *self == Self::Host
+    }
+}
+
+impl FromStr for CgroupNsMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "private" => Ok(Self::Private),
+            "host" => Ok(Self::Host),
+            _ => bail!("invalid cgroup namespace '{s}': expected 'private' or 'host'"),
+        }
+    }
+}
+
+impl fmt::Display for CgroupNsMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Private => write!(f, "private"),
+            Self::Host => write!(f, "host"),
+        }
+    }
+}
+
+/// How the size of a container's writable layer is capped
+/// (`--storage-driver`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageDriver {
+    /// A project quota on the overlay directory, which needs project quotas
+    /// enabled on its filesystem (xfs, or ext4 mounted with `prjquota`).
+    #[default]
+    Quota,
+    /// An ext4 filesystem of the size in a sparse image file, mounted from a
+    /// loop device.
+    Loop,
+    /// A tmpfs of the size, held in memory.
+    Tmpfs,
+}
+
+impl FromStr for StorageDriver {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "quota" => Ok(Self::Quota),
+            "loop" => Ok(Self::Loop),
+            "tmpfs" => Ok(Self::Tmpfs),
+            _ => bail!("invalid storage driver '{s}': expected 'quota', 'loop' or 'tmpfs'"),
+        }
+    }
+}
+
+impl fmt::Display for StorageDriver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Quota => write!(f, "quota"),
+            Self::Loop => write!(f, "loop"),
+            Self::Tmpfs => write!(f, "tmpfs"),
+        }
+    }
+}
+
+/// The size limit of a container's writable layer (`--storage-size`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageLimit {
+    /// Size in bytes.
+    pub size: u64,
+    pub driver: StorageDriver,
+}
+
+/// Hardening options of the container's `/proc` (`--proc-opts`), written as
+/// a comma-separated list of `ro` and `hidepid=2`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcOptions {
+    /// `/proc` is remounted read-only once the container is set up, so not
+    /// even its root can write `/proc/sys`.
+    pub read_only: bool,
+    /// Processes are hidden from users other than their owner, except
+    /// members of group 0.
+    pub hidepid: bool,
+}
+
+impl ProcOptions {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The proc data string passed to `mount(2)`, if any.
+    pub fn data(&self) -> Option<&'static str> {
+        self.hidepid.then_some("hidepid=invisible,gid=0")
+    }
+}
+
+impl FromStr for ProcOptions {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut options = Self::default();
+        for option in s.split(',').filter(|o| !o.is_empty()) {
+            match option {
+                "ro" => options.read_only = true,
+                "hidepid=2" | "hidepid=invisible" => options.hidepid = true,
+                _ => bail!("invalid --proc-opts option '{option}': expected 'ro' or 'hidepid=2'"),
+            }
+        }
+        Ok(options)
+    }
+}
+
+impl fmt::Display for ProcOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let options: Vec<&str> = [(self.read_only, "ro"), (self.hidepid, "hidepid=2")]
+            .into_iter()
+            .filter_map(|(set, option)| set.then_some(option))
+            .collect();
+        write!(f, "{}", options.join(","))
+    }
+}
+
+/// Default CFS period in microseconds, used by `--cpus`.
+pub const CPU_PERIOD_DEFAULT_US: u64 = 100_000;
+/// Smallest quota and period the kernel accepts for `cpu.max`, in microseconds.
+const CPU_MIN_US: u64 = 1_000;
+/// Largest period the kernel accepts for `cpu.max`, in microseconds.
+const CPU_PERIOD_MAX_US: u64 = 1_000_000;
+
+/// CPU bandwidth limit, written to the cgroup's `cpu.max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuLimit {
+    /// Runtime allowed per period, or `None` for unlimited (`max`).
+    pub quota_us: Option<u64>,
+    pub period_us: u64,
+}
+
+impl CpuLimit {
+    /// Build a limit from a fractional number of CPUs (e.g. `0.5`), using the
+    /// default period.
+    pub fn from_cpus(cpus: f64) -> Result<Self> {
+        if !cpus.is_finite() || cpus <= 0.0 {
+            bail!("invalid --cpus '{cpus}': expected a positive number");
+        }
+        let quota = (cpus * CPU_PERIOD_DEFAULT_US as f64).round();
+        if quota < CPU_MIN_US as f64 || quota > u64::MAX as f64 {
+            bail!("invalid --cpus '{cpus}': must be at least 0.01");
+        }
+        Ok(Self {
+            quota_us: Some(quota as u64),
+            period_us: CPU_PERIOD_DEFAULT_US,
+        })
+    }
+}
+
+impl FromStr for CpuLimit {
+    type Err = anyhow::Error;
+
+    /// Parse a raw `cpu.max` value: `<quota> <period>` where quota is a number
+    /// of microseconds or `max`, or a lone `max` for no limit with the default
+    /// period.
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let (quota, period) = match parts.as_slice() {
+            ["max"] => {
+                return Ok(Self {
+                    quota_us: None,
+                    period_us: CPU_PERIOD_DEFAULT_US,
+                })
+            }
+            [quota, period] => (*quota, *period),
+            _ => bail!(
+                "invalid --cpu '{s}': expected '<quota> <period>' in microseconds or 'max', \
+                 e.g. '50000 100000' for half a CPU"
+            ),
+        };
+
+        let quota_us = match quota {
+            "max" => None,
+            _ => match quota.parse::<u64>() {
+                Ok(q) if q >= CPU_MIN_US => Some(q),
+                _ => bail!(
+                    "invalid --cpu quota '{quota}': expected 'max' or an integer >= {CPU_MIN_US}"
+                ),
+            },
+        };
+        let period_us = match period.parse::<u64>() {
+            Ok(p) if (CPU_MIN_US..=CPU_PERIOD_MAX_US).contains(&p) => p,
+            _ => bail!(
+                "invalid --cpu period '{period}': expected an integer between \
+                 {CPU_MIN_US} and {CPU_PERIOD_MAX_US}"
+            ),
+        };
+        Ok(Self {
+            quota_us,
+            period_us,
+        })
+    }
+}
+
+impl fmt::Display for CpuLimit {
+    /// Format as a `cpu.max` value.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.quota_us {
+            Some(quota) => write!(f, "{quota} {}", self.period_us),
+            None => write!(f, "max {}", self.period_us),
+        }
+    }
+}
+
+impl Serialize for CpuLimit {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// PID limit, written to the cgroup's `pids.max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PidsLimit {
+    /// Maximum number of processes, or `None` for unlimited (`max`).
+    pub max: Option<u64>,
+}
+
+impl PidsLimit {
+    /// Limit applied by `run` unless `--pids` is given, so that a fork bomb
+    /// cannot exhaust the host's PIDs.
+    pub const DEFAULT: Self = Self { max: Some(1024) };
+}
+
+impl FromStr for PidsLimit {
+    type Err = anyhow::Error;
+
+    /// Parse a positive number of processes, or `unlimited`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "unlimited" | "max" => Ok(Self { max: None }),
+            _ => match s.parse::<u64>() {
+                Ok(n) if n > 0 => Ok(Self { max: Some(n) }),
+                _ => bail!("invalid --pids '{s}': expected a positive integer or 'unlimited'"),
+            },
+        }
+    }
+}
+
+impl fmt::Display for PidsLimit {
+    /// Format as a `pids.max` value.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.max {
+            Some(max) => write!(f, "{max}"),
+            None => f.write_str("max"),
+        }
+    }
+}
+
+impl Serialize for PidsLimit {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Soft or hard `--ulimit` value meaning "no limit" (`RLIM_INFINITY`).
+pub const RLIM_UNLIMITED: u64 = u64::MAX;
+
+/// A process resource that can be limited with `--ulimit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UlimitKind {
+    /// Open file descriptors (`RLIMIT_NOFILE`).
+    Nofile,
+    /// Processes of the container's user (`RLIMIT_NPROC`).
+    Nproc,
+    /// Core dump size in bytes (`RLIMIT_CORE`).
+    Core,
+    /// Stack size in bytes (`RLIMIT_STACK`).
+    Stack,
+    /// Locked memory in bytes (`RLIMIT_MEMLOCK`).
+    Memlock,
+}
+
+impl UlimitKind {
+    const ALL: [Self; 5] = [
+        Self::Nofile,
+        Self::Nproc,
+        Self::Core,
+        Self::Stack,
+        Self::Memlock,
+    ];
+
+    /// Name used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Nofile => "nofile",
+            Self::Nproc => "nproc",
+            Self::Core => "core",
+            Self::Stack => "stack",
+            Self::Memlock => "memlock",
+        }
+    }
+}
+
+/// A process resource limit, written as `TYPE=SOFT[:HARD]` (e.g.
+/// `nofile=1024:4096`). A missing hard limit equals the soft limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ulimit {
+    pub kind: UlimitKind,
+    pub soft: u64,
+    pub hard: u64,
+}
+
+impl FromStr for Ulimit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((name, values)) = s.split_once('=') else {
+            bail!("invalid --ulimit '{s}': expected 'TYPE=SOFT[:HARD]'");
+        };
+        let Some(kind) = UlimitKind::ALL.into_iter().find(|k| k.name() == name) else {
+            let names: Vec<&str> = UlimitKind::ALL.iter().map(|k| k.name()).collect();
+            bail!("invalid --ulimit type '{name}': expected one of {}", names.join(", "));
+        };
+
+        let parse = |v: &str| match v {
+            "unlimited" => Ok(RLIM_UNLIMITED),
+            _ => v.parse::<u64>().map_err(|_| {
+                anyhow::anyhow!("invalid --ulimit value '{v}': expected an integer or 'unlimited'")
+            }),
+        };
+        let (soft, hard) = match values.split_once(':') {
+            Some((soft, hard)) => (parse(soft)?, parse(hard)?),
+            None => (parse(values)?, parse(values)?),
+        };
+        if soft > hard {
+            bail!("invalid --ulimit '{s}': soft limit exceeds hard limit");
+        }
+        Ok(Self { kind, soft, hard })
+    }
+}
+
+impl fmt::Display for Ulimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |v: u64| match v {
+            RLIM_UNLIMITED => "unlimited".to_string(),
+            _ => v.to_string(),
+        };
+        write!(f, "{}={}:{}", self.kind.name(), value(self.soft), value(self.hard))
+    }
+}
+
+impl Serialize for Ulimit {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Ulimit {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// File mode creation mask of the container command (`--umask`), written in
+/// octal, e.g. `0027`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Umask(pub u32);
+
+impl FromStr for Umask {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match u32::from_str_radix(s, 8) {
+            Ok(mask) if mask <= 0o777 && !s.starts_with('+') => Ok(Self(mask)),
+            _ => bail!("invalid umask '{s}': expected an octal value from 0000 to 0777"),
+        }
+    }
+}
+
+impl fmt::Display for Umask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04o}", self.0)
+    }
+}
+
+impl Serialize for Umask {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Umask {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The host's time zone database, where `--timezone` zones are looked up.
+pub const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+
+/// Time zone of the container (`--timezone`): `host` for the host's own, or
+/// the name of a zone in the host's time zone database, e.g. `Europe/Berlin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Timezone {
+    Host,
+    Zone(String),
+}
+
+impl Timezone {
+    /// The host file bind-mounted over the container's `/etc/localtime`.
+    pub fn host_file(&self) -> PathBuf {
+        match self {
+            Self::Host => PathBuf::from("/etc/localtime"),
+            Self::Zone(zone) => Path::new(ZONEINFO_DIR).join(zone),
+        }
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "host" {
+            return Ok(Self::Host);
+        }
+        let valid = !s.is_empty()
+            && s.split('/')
+                .all(|part| !part.is_empty() && part != "." && part != "..")
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_+-/".contains(c));
+        if !valid {
+            bail!("invalid time zone '{s}': expected 'host' or a zone like Europe/Berlin");
+        }
+        Ok(Self::Zone(s.to_string()))
+    }
+}
+
+impl fmt::Display for Timezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Host => write!(f, "host"),
+            Self::Zone(zone) => write!(f, "{zone}"),
+        }
+    }
+}
+
+impl Serialize for Timezone {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timezone {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A host device passed into the container, written as
+/// `HOSTPATH[:CONTAINERPATH][:PERMISSIONS]` (e.g. `/dev/fuse` or
+/// `/dev/ttyUSB0:/dev/ttyS0:rw`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceMapping {
+    pub host_path: String,
+    /// Path of the node inside the container; defaults to `host_path`.
+    pub container_path: String,
+    /// Any of `r`, `w` and `m` (mknod), as for cgroup device rules.
+    pub permissions: String,
+}
+
+impl FromStr for DeviceMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let is_permissions =
+            |p: &str| !p.is_empty() && p.chars().all(|c| matches!(c, 'r' | 'w' | 'm'));
+        let parts: Vec<&str> = s.split(':').collect();
+        let (host, container, permissions) = match parts.as_slice() {
+            [host] => (*host, *host, "rwm"),
+            [host, perms] if is_permissions(perms) => (*host, *host, *perms),
+            [host, container] => (*host, *container, "rwm"),
+            [host, container, perms] => (*host, *container, *perms),
+            _ => bail!("invalid --device '{s}': expected 'HOSTPATH[:CONTAINERPATH][:rwm]'"),
+        };
+
+        if !host.starts_with('/') {
+            bail!("invalid --device '{s}': host path '{host}' must be absolute");
+        }
+        if !container.starts_with("/dev/") || container.split('/').any(|c| c == "..") {
+            bail!("invalid --device '{s}': container path '{container}' must be under /dev");
+        }
+        if !is_permissions(permissions) {
+            bail!(
+                "invalid --device '{s}': permissions '{permissions}' must be a combination \
+                 of r, w and m"
+            );
+        }
+        Ok(Self {
+            host_path: host.to_string(),
+            container_path: container.to_string(),
+            permissions: permissions.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for DeviceMapping {
+    /// Format as `HOSTPATH[:CONTAINERPATH][:rwm]`, leaving out the defaults.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.host_path)?;
+        if self.container_path != self.host_path {
+            write!(f, ":{}", self.container_path)?;
+        }
+        if self.permissions != "rwm" {
+            write!(f, ":{}", self.permissions)?;
+        }
+        Ok(())
+    }
+}
+
+/// An Ethernet (MAC) address, written as six colon-separated pairs of hex
+/// digits, e.g. `02:42:ac:11:00:02`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl FromStr for MacAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let mut bytes = [0u8; 6];
+        if parts.len() != bytes.len() {
+            bail!("invalid MAC address '{s}': expected six hex pairs like 02:42:ac:11:00:02");
+        }
+        for (byte, part) in bytes.iter_mut().zip(&parts) {
+            *byte = match u8::from_str_radix(part, 16) {
+                Ok(b) if part.len() == 2 => b,
+                _ => bail!("invalid MAC address '{s}': '{part}' is not a hex pair"),
+            };
+        }
+        if bytes[0] & 1 != 0 {
+            bail!("invalid MAC address '{s}': a multicast address cannot be assigned");
+        }
+        if bytes == [0; 6] {
+            bail!("invalid MAC address '{s}': must not be all zeros");
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+impl Serialize for MacAddress {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MacAddress {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// An IPv4 subnet in CIDR notation, e.g. `10.88.0.0/24`. The host bits of
+/// the address are always zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet {
+    pub addr: Ipv4Addr,
+    pub prefix_len: u8,
+}
+
+impl Subnet {
+    /// The bits of an address that select the subnet.
+    fn mask(&self) -> u32 {
+        u32::MAX << (32 - self.prefix_len)
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        u32::from(ip) & self.mask() == u32::from(self.addr)
+    }
+
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.addr) | !self.mask())
+    }
+
+    /// The first host address, taken by the network's bridge.
+    pub fn gateway(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.addr) + 1)
+    }
+
+    /// Every address between the network and broadcast addresses.
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        (u32::from(self.addr) + 1..u32::from(self.broadcast())).map(Ipv4Addr::from)
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((addr, prefix_len)) = s.split_once('/') else {
+            bail!("invalid subnet '{s}': expected ADDRESS/PREFIX like 10.88.0.0/24");
+        };
+        let Ok(addr) = addr.parse::<Ipv4Addr>() else {
+            bail!("invalid subnet '{s}': '{addr}' is not an IPv4 address");
+        };
+        let prefix_len: u8 = match prefix_len.parse() {
+            Ok(len @ 8..=30) => len,
+            _ => bail!("invalid subnet '{s}': the prefix length must be between 8 and 30"),
+        };
+        let subnet = Self { addr, prefix_len };
+        if u32::from(addr) & !subnet.mask() != 0 {
+            let network = Ipv4Addr::from(u32::from(addr) & subnet.mask());
+            bail!("invalid subnet '{s}': host bits are set (did you mean {network}/{prefix_len}?)");
+        }
+        Ok(subnet)
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl Serialize for Subnet {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Subnet {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Transport protocol of a published port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortProtocol {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for PortProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp => write!(f, "tcp"),
+            Self::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+/// A host port forwarded into the container (`--publish`), written as
+/// `[HOSTADDR:]HOSTPORT:CONTAINERPORT[/tcp|udp]` (e.g. `8080:80` or
+/// `127.0.0.1:5353:53/udp`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortMapping {
+    /// Host address to listen on; all addresses by default.
+    pub host_addr: IpAddr,
+    pub host_port: u16,
+    pub container_port: u16,
+    pub protocol: PortProtocol,
+}
+
+impl FromStr for PortMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (ports, protocol) = match s.rsplit_once('/') {
+            Some((ports, "tcp")) => (ports, PortProtocol::Tcp),
+            Some((ports, "udp")) => (ports, PortProtocol::Udp),
+            Some((_, protocol)) => {
+                bail!("invalid --publish '{s}': protocol '{protocol}' must be tcp or udp")
+            }
+            None => (s, PortProtocol::Tcp),
+        };
+        // The address may be IPv6, so the ports are split off the end.
+        let Some((rest, container_port)) = ports.rsplit_once(':') else {
+            bail!("invalid --publish '{s}': expected '[HOSTADDR:]HOSTPORT:CONTAINERPORT'");
+        };
+        let (host_addr, host_port) = match rest.rsplit_once(':') {
+            Some((addr, port)) => {
+                let addr = addr.trim_start_matches('[').trim_end_matches(']');
+                let addr = addr.parse().map_err(|_| {
+                    anyhow::anyhow!("invalid --publish '{s}': bad host address '{addr}'")
+                })?;
+                (addr, port)
+            }
+            None => (IpAddr::V4(Ipv4Addr::UNSPECIFIED), rest),
+        };
+        let port = |p: &str| match p.parse::<u16>() {
+            Ok(port) if port > 0 => Ok(port),
+            _ => bail!("invalid --publish '{s}': port '{p}' must be between 1 and 65535"),
+        };
+        Ok(Self {
+            host_addr,
+            host_port: port(host_port)?,
+            container_port: port(container_port)?,
+            protocol,
+        })
+    }
+}
+
+impl PortMapping {
+    /// The host address, bracketed if it is IPv6.
+    fn host_addr_str(&self) -> String {
+        match self.host_addr {
+            IpAddr::V6(addr) => format!("[{addr}]"),
+            IpAddr::V4(addr) => addr.to_string(),
+        }
+    }
+
+    /// The mapping as listed by `ps`, e.g. `0.0.0.0:8080->80/tcp`.
+    pub fn summary(&self) -> String {
+        format!(
+            "{}:{}->{}/{}",
+            self.host_addr_str(),
+            self.host_port,
+            self.container_port,
+            self.protocol
+        )
+    }
+}
+
+impl fmt::Display for PortMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}/{}",
+            self.host_addr_str(),
+            self.host_port,
+            self.container_port,
+            self.protocol
+        )
+    }
+}
+
+/// Sysctls that are scoped to a namespace the container owns (IPC, UTS or
+/// network), and so can be set without affecting the host. Entries ending in
+/// `.` match every key with that prefix.
+const NAMESPACED_SYSCTLS: [&str; 11] = [
+    "kernel.domainname",
+    "kernel.msgmax",
+    "kernel.msgmnb",
+    "kernel.msgmni",
+    "kernel.sem",
+    "kernel.shmall",
+    "kernel.shmmax",
+    "kernel.shmmni",
+    "kernel.shm_rmid_forced",
+    "fs.mqueue.",
+    "net.",
+];
+
+/// A kernel parameter set inside the container, written as `KEY=VALUE`
+/// (e.g. `net.ipv4.ip_unprivileged_port_start=0`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sysctl {
+    pub key: String,
+    pub value: String,
+}
+
+impl Sysctl {
+    /// The file under `/proc/sys` that holds this parameter.
+    pub fn proc_path(&self) -> String {
+        format!("/proc/sys/{}", self.key.replace('.', "/"))
+    }
+
+    /// The namespace this parameter is scoped to.
+    pub fn namespace(&self) -> NamespaceKind {
+        if self.key.starts_with("net.") {
+            NamespaceKind::Net
+        } else if self.key == "kernel.domainname" {
+            NamespaceKind::Uts
+        } else {
+            NamespaceKind::Ipc
+        }
+    }
+}
+
+impl FromStr for Sysctl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((key, value)) = s.split_once('=') else {
+            bail!("invalid --sysctl '{s}': expected 'KEY=VALUE'");
+        };
+        let is_key_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+        if key.split('.').any(|part| part.is_empty() || !part.chars().all(is_key_char)) {
+            bail!("invalid --sysctl key '{key}'");
+        }
+        let namespaced = NAMESPACED_SYSCTLS.iter().any(|allowed| {
+            if allowed.ends_with('.') {
+                key.starts_with(allowed)
+            } else {
+                key == *allowed
+            }
+        });
+        if !namespaced {
+            bail!(
+                "--sysctl '{key}' is not namespaced and would change the host; allowed are \
+                 net.*, fs.mqueue.*, kernel.domainname and the kernel.msg*/sem/shm* IPC \
+                 parameters"
+            );
+        }
+        if value.is_empty() || value.contains('\n') {
+            bail!("invalid --sysctl value for '{key}': '{value}'");
+        }
+        Ok(Self {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Sysctl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+impl Serialize for Sysctl {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Sysctl {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The user the container command runs as, written as `USER[:GROUP]` where
+/// each part is a numeric ID or a name from the rootfs's `/etc/passwd` and
+/// `/etc/group` (e.g. `1000:1000` or `nobody:nogroup`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserSpec {
+    pub user: String,
+    pub group: Option<String>,
+}
+
+impl FromStr for UserSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+        let valid = |part: &str| !part.is_empty() && !part.contains([':', '\n', '/']);
+        if !valid(user) || !group.is_none_or(valid) {
+            bail!("invalid --user '{s}': expected 'USER[:GROUP]' (names or numeric IDs)");
+        }
+        Ok(Self {
+            user: user.to_string(),
+            group: group.map(str::to_string),
+        })
+    }
+}
+
+impl fmt::Display for UserSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.group {
+            Some(group) => write!(f, "{}:{group}", self.user),
+            None => write!(f, "{}", self.user),
+        }
+    }
+}
+
+impl Serialize for UserSpec {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for UserSpec {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A `--user` resolved against the rootfs: the IDs the container command
+/// runs with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerUser {
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary groups, from the rootfs's `/etc/group`.
+    #[serde(default)]
+    pub groups: Vec<u32>,
+    /// User name, if the UID has a passwd entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Home directory from the passwd entry, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub home: Option<String>,
+}
+
+/// A namespace a container can share instead of getting its own, selected
+/// with `--net`, `--pid`, `--ipc` and `--uts`. The mount namespace is always
+/// the container's own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NamespaceKind {
+    Net,
+    Pid,
+    Ipc,
+    Uts,
+}
+
+impl NamespaceKind {
+    pub const ALL: [Self; 4] = [Self::Net, Self::Pid, Self::Ipc, Self::Uts];
+
+    /// Short name, as found under `/proc/<pid>/ns/`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Net => "net",
+            Self::Pid => "pid",
+            Self::Ipc => "ipc",
+            Self::Uts => "uts",
+        }
+    }
+}
+
+impl fmt::Display for NamespaceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Which namespace of a kind a container uses, written as `private`, `host`,
+/// `container:<id>` or, for the network, `bridge` or `slirp`. `none` is accepted for
+/// `private`, as `--net none` predates the other modes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum NamespaceMode {
+    /// A new namespace of the container's own; for the network, an empty one
+    /// with only loopback.
+    #[default]
+    Private,
+    /// The host's namespace.
+    Host,
+    /// Join the namespace of another running container. Holds the
+    /// container's ID, which may be abbreviated until it is resolved.
+    Container(String),
+    /// A new network namespace attached to the host bridge by a veth pair.
+    Bridge,
+    /// A new network namespace connected to the host's network by a
+    /// `slirp4netns` helper (user-mode networking).
+    Slirp,
+}
+
+impl NamespaceMode {
+    pub fn is_private(&self) -> bool {
+        *self == Self::Private
+    }
+
+    /// Whether the container gets a new namespace of its own, which it can
+    /// change without affecting anyone else.
+    pub fn is_new(&self) -> bool {
+        matches!(self, Self::Private | Self::Bridge | Self::Slirp)
+    }
+}
+
+impl FromStr for NamespaceMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            None if s == "private" || s == "none" => Ok(Self::Private),
+            None if s == "host" => Ok(Self::Host),
+            None if s == "bridge" => Ok(Self::Bridge),
+            None if s == "slirp" => Ok(Self::Slirp),
+            Some(("container", id)) if !id.is_empty() && !id.contains('/') => {
+                Ok(Self::Container(id.to_string()))
+            }
+            _ => bail!(
+                "invalid namespace mode '{s}': expected 'private', 'host', 'container:<id>', \
+                 'bridge' or 'slirp'"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for NamespaceMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Private => write!(f, "private"),
+            Self::Host => write!(f, "host"),
+            Self::Container(id) => write!(f, "container:{id}"),
+            Self::Bridge => write!(f, "bridge"),
+            Self::Slirp => write!(f, "slirp"),
+        }
+    }
+}
+
+impl Serialize for NamespaceMode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for NamespaceMode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A `--health-cmd` check, run periodically inside a running container.
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthCheck {
+    /// Shell command, run with `/bin/sh -c`. Exit status 0 means healthy.
+    pub cmd: String,
+    /// Time between checks; also the time a check may take.
+    pub interval: std::time::Duration,
+    /// Consecutive failures after which the container is unhealthy.
+    pub retries: u32,
+}
+
+/// Health of a container with a health check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// No check has passed yet, and fewer than `retries` have failed.
+    Starting,
+    /// The last check passed.
+    Healthy,
+    /// The last `retries` checks failed.
+    Unhealthy,
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Starting => write!(f, "starting"),
+            Self::Healthy => write!(f, "healthy"),
+            Self::Unhealthy => write!(f, "unhealthy"),
+        }
+    }
+}
+
+/// Longest check output kept in [`HealthState::last_output`], in bytes.
+pub const HEALTH_OUTPUT_MAX: usize = 4096;
+
+/// Health check results of a container, as recorded in its metadata.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthState {
+    /// The checked command.
+    pub cmd: String,
+    /// Time between checks, in milliseconds.
+    #[serde(
+        default = "HealthState::default_interval_ms",
+        skip_serializing_if = "HealthState::is_default_interval_ms"
+    )]
+    pub interval_ms: u64,
+    /// Consecutive failures after which the container is unhealthy.
+    #[serde(
+        default = "HealthState::default_retries",
+        skip_serializing_if = "HealthState::is_default_retries"
+    )]
+    pub retries: u32,
+    pub status: HealthStatus,
+    /// Consecutive failed checks.
+    pub failing_streak: u32,
+    /// Exit code of the last check; `None` before the first one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_exit_code: Option<i32>,
+    /// Combined stdout and stderr of the last check, truncated.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub last_output: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_checked_at: Option<DateTime<Utc>>,
+}
+
+impl HealthState {
+    /// The state of a container whose checks have not run yet.
+    pub fn new(check: &HealthCheck) -> Self {
+        Self {
+            cmd: check.cmd.clone(),
+            interval_ms: check.interval.as_millis().try_into().unwrap_or(u64::MAX),
+            retries: check.retries,
+            status: HealthStatus::Starting,
+            failing_streak: 0,
+            last_exit_code: None,
+            last_output: String::new(),
+            last_checked_at: None,
+        }
+    }
+
+    /// The default `--health-interval`, also that of containers created
+    /// before it was recorded.
+    fn default_interval_ms() -> u64 {
+        30_000
+    }
+
+    fn is_default_interval_ms(interval_ms: &u64) -> bool {
+        *interval_ms == Self::default_interval_ms()
+    }
+
+    /// The default `--health-retries`, also that of containers created
+    /// before it was recorded.
+    fn default_retries() -> u32 {
+        3
+    }
+
+    fn is_default_retries(retries: &u32) -> bool {
+        *retries == Self::default_retries()
+    }
+
+    /// Record the result of a check. A passing check makes the container
+    /// healthy; `retries` failing ones in a row make it unhealthy.
+    pub fn record(&mut self, exit_code: i32, output: &str, retries: u32, now: DateTime<Utc>) {
+        if exit_code == 0 {
+            self.status = HealthStatus::Healthy;
+            self.failing_streak = 0;
+        } else {
+            self.failing_streak += 1;
+            if self.failing_streak >= retries {
+                self.status = HealthStatus::Unhealthy;
+            }
+        }
+        let mut end = output.len().min(HEALTH_OUTPUT_MAX);
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.last_exit_code = Some(exit_code);
+        self.last_output = output[..end].to_string();
+        self.last_checked_at = Some(now);
+    }
+}
+
+/// Size of a `--tmpfs` mount without `size=` when no memory limit is set.
+pub const TMPFS_DEFAULT_SIZE: u64 = 64 << 20;
+
+/// Size of the container's `/dev/shm` without `--shm-size`.
+pub const SHM_DEFAULT_SIZE: u64 = 64 << 20;
+
+/// A tmpfs mounted inside the container, written as `PATH[:OPTIONS]` where
+/// OPTIONS is a comma-separated list of `size=SIZE`, `mode=OCTAL`, `noexec`,
+/// `nosuid`, `nodev` and `ro` (e.g. `/tmp:size=16m,noexec`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TmpfsMount {
+    /// Absolute mount point inside the container.
+    pub path: String,
+    /// Size in bytes; defaults to the memory limit, or
+    /// [`TMPFS_DEFAULT_SIZE`] without one.
+    pub size: Option<u64>,
+    /// Permission bits of the tmpfs root.
+    pub mode: Option<u32>,
+    /// Mount flags given in OPTIONS, e.g. `noexec`.
+    pub flags: Vec<String>,
+}
+
+impl TmpfsMount {
+    /// Flags accepted in OPTIONS.
+    pub const FLAGS: [&'static str; 4] = ["noexec", "nosuid", "nodev", "ro"];
+
+    /// The tmpfs data string (`size=...[,mode=...]`) passed to `mount(2)`.
+    pub fn data(&self, default_size: u64) -> String {
+        let mut data = format!("size={}", self.size.unwrap_or(default_size));
+        if let Some(mode) = self.mode {
+            data.push_str(&format!(",mode={mode:o}"));
+        }
+        data
+    }
+}
+
+impl FromStr for TmpfsMount {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (path, options) = s.split_once(':').unwrap_or((s, ""));
+        if !path.starts_with('/') || path == "/" || path.split('/').any(|c| c == "..") {
+            bail!("invalid --tmpfs '{s}': path '{path}' must be absolute and not '/'");
+        }
+
+        let mut mount = Self {
+            path: path.to_string(),
+            size: None,
+            mode: None,
+            flags: Vec::new(),
+        };
+        for option in options.split(',').filter(|o| !o.is_empty()) {
+            match option.split_once('=') {
+                Some(("size", size)) => {
+                    let bytes = crate::util::units::parse_bytes(size)?;
+                    if bytes == 0 {
+                        bail!("invalid --tmpfs '{s}': size must be greater than zero");
+                    }
+                    mount.size = Some(bytes);
+                }
+                Some(("mode", mode)) => match u32::from_str_radix(mode, 8) {
+                    Ok(mode) if mode <= 0o7777 => mount.mode = Some(mode),
+                    _ => bail!("invalid --tmpfs '{s}': mode '{mode}' must be octal, e.g. 1777"),
+                },
+                None if Self::FLAGS.contains(&option) => mount.flags.push(option.to_string()),
+                _ => bail!(
+                    "invalid --tmpfs option '{option}': expected size=, mode= or one of {}",
+                    Self::FLAGS.join(", ")
+                ),
+            }
+        }
+        Ok(mount)
+    }
+}
+
+impl fmt::Display for TmpfsMount {
+    /// Format as `PATH[:OPTIONS]`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut options = Vec::new();
+        if let Some(size) = self.size {
+            options.push(format!("size={size}"));
+        }
+        if let Some(mode) = self.mode {
+            options.push(format!("mode={mode:o}"));
+        }
+        options.extend(self.flags.iter().cloned());
+        f.write_str(&self.path)?;
+        if !options.is_empty() {
+            write!(f, ":{}", options.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl ContainerMeta {
+    /// The namespace of `kind` the container uses.
+    pub fn namespace(&self, kind: NamespaceKind) -> &NamespaceMode {
+        match kind {
+            NamespaceKind::Net => &self.net,
+            NamespaceKind::Pid => &self.pid_ns,
+            NamespaceKind::Ipc => &self.ipc,
+            NamespaceKind::Uts => &self.uts,
+        }
+    }
+
+    /// Overwrite the recorded limits with every limit set in `limits`.
+    pub fn apply_limits(&mut self, limits: &ResourceLimits) {
+        if let Some(memory) = limits.memory {
+            self.memory_limit = Some(memory);
+            self.memory_limit_spec = limits.memory_spec.clone();
+        }
+        if let Some(swappiness) = limits.memory_swappiness {
+            self.memory_swappiness = Some(swappiness);
+        }
+        if let Some(cpu) = limits.cpu {
+            self.cpu_limit = Some(cpu.to_string());
+        }
+        if let Some(pids) = limits.pids {
+            self.pids_limit = pids.max;
+        }
+        if let Some(cpus) = &limits.cpuset_cpus {
+            self.cpuset_cpus = Some(cpus.clone());
+        }
+        if let Some(mems) = &limits.cpuset_mems {
+            self.cpuset_mems = Some(mems.clone());
+        }
+    }
+
+    /// How long the container has been (or was) running: up to `now` while
+    /// running, up to `finished_at` once stopped. `None` if it never started
+    /// or the end time is unknown.
+    pub fn run_duration(&self, now: DateTime<Utc>) -> Option<std::time::Duration> {
+        let started = self.started_at?;
+        let end = match (self.finished_at, self.status) {
+            (Some(finished), _) => finished,
+            (None, ContainerStatus::Running) => now,
+            (None, _) => return None,
+        };
+        (end - started).to_std().ok()
+    }
+
+    /// The published ports as listed by `ps`, only while the container runs:
+    /// they are forwarded by a helper that exits with it.
+    pub fn port_summaries(&self) -> Vec<String> {
+        if self.status != ContainerStatus::Running {
+            return Vec::new();
+        }
+        self.ports.iter().map(PortMapping::summary).collect()
+    }
+
+    /// How the container's command ended, once stopped. `None` while it
+    /// runs, or if it stopped without craterun learning how.
+    pub fn exit(&self) -> Option<ContainerExit> {
+        if self.status != ContainerStatus::Stopped {
+            return None;
+        }
+        match (&self.signal, self.exit_code) {
+            (Some(signal), _) => Some(ContainerExit::Killed {
+                signal: signal.clone(),
+                core_dumped: self.core_dumped,
+            }),
+            (None, Some(EXIT_CODE_UNKNOWN) | None) => None,
+            (None, Some(code)) => Some(ContainerExit::Exited(code)),
+        }
+    }
+
+    /// Human-readable status for `ps`, e.g. `Up 3 minutes`,
+    /// `Exited (0) 2 hours ago` or `Killed (SIGSEGV) 5 minutes ago`. Times
+    /// that were not recorded are left out.
+    pub fn status_summary(&self, now: DateTime<Utc>) -> String {
+        let since = |t: DateTime<Utc>| {
+            crate::util::units::format_duration_words((now - t).to_std().unwrap_or_default())
+        };
+        match self.status {
+            ContainerStatus::Running => {
+                let mut summary = match self.started_at {
+                    Some(started) => format!("Up {}", since(started)),
+                    None => "Up".to_string(),
+                };
+                if let Some(health) = &self.health {
+                    summary.push_str(&format!(" ({})", health.status));
+                }
+                summary
+            }
+            ContainerStatus::Stopped => {
+                let mut summary = match (&self.signal, self.exit_code) {
+                    (Some(signal), _) => format!("Killed ({signal})"),
+                    (None, Some(code)) => format!("Exited ({code})"),
+                    (None, None) => "Exited".to_string(),
+                };
+                if let Some(finished) = self.finished_at {
+                    summary.push_str(&format!(" {} ago", since(finished)));
+                }
+                summary
+            }
+            ContainerStatus::Created => "Created".to_string(),
+        }
+    }
+}
+
+/// cgroup resource limits. Unset fields are left at the kernel default on
+/// `run`, and left unchanged by `update`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResourceLimits {
+    pub memory: Option<u64>,
+    /// `memory` as the user wrote it, if given.
+    pub memory_spec: Option<String>,
+    pub memory_swappiness: Option<u8>,
+    pub cpu: Option<CpuLimit>,
+    pub pids: Option<PidsLimit>,
+    pub cpuset_cpus: Option<String>,
+    pub cpuset_mems: Option<String>,
+}
+
+impl ResourceLimits {
+    /// Whether no limit is set at all.
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_none()
+            && self.memory_swappiness.is_none()
+            && self.cpu.is_none()
+            && self.pids.is_none()
+            && self.cpuset_cpus.is_none()
+            && self.cpuset_mems.is_none()
+    }
+}
+
+/// The service of an `up` project a container was created for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceLabel {
+    pub project: String,
+    pub service: String,
+    /// Hash of the service's configuration in the project file, so that `up`
+    /// can tell whether the container is out of date.
+    pub config_hash: String,
+}
+
+/// Configuration for launching a new container. Constructed from CLI arguments.
+#[derive(Debug, Clone, Serialize)]
+pub struct ContainerConfig {
+    /// Unique name to refer to the container by, if set.
+    pub name: Option<String>,
+    /// The `up` project service the container runs, if any.
+    pub service: Option<ServiceLabel>,
+    pub rootfs: String,
+    pub cmd: Vec<String>,
+    /// argv[0] passed to the program instead of `cmd[0]`, if set.
+    pub argv0: Option<String>,
+    /// Binary run as PID 1 with `cmd` as its arguments (`--init-path`): a
+    /// file of the rootfs, or else of the host.
+    pub init_path: Option<String>,
+    pub hostname: String,
+    pub domainname: Option<String>,
+    pub limits: ResourceLimits,
+    pub ulimits: Vec<Ulimit>,
+    pub oom_score_adj: Option<i32>,
+    pub umask: Option<Umask>,
+    pub timezone: Option<Timezone>,
+    pub devices: Vec<DeviceMapping>,
+    pub tmpfs: Vec<TmpfsMount>,
+    /// Size of `/dev/shm` in bytes (`--shm-size`).
+    pub shm_size: Option<u64>,
+    /// Size limit of the writable layer; needs an image rootfs.
+    pub storage: Option<StorageLimit>,
+    pub mount_propagation: MountPropagation,
+    pub proc_opts: ProcOptions,
+    pub keyring: Keyring,
+    pub userns: UsernsMode,
+    pub cgroup_ns: CgroupNsMode,
+    pub sysctls: Vec<Sysctl>,
+    /// Environment from the host, env files and `--env`, as `KEY=VALUE`.
+    /// The defaults are layered under it once the user is resolved.
+    pub env: Vec<String>,
+    /// Whether `env` includes the whole host environment (`--env-host`).
+    pub env_host: bool,
+    /// User to run the command as, resolved against the rootfs.
+    pub user: Option<UserSpec>,
+    pub net: NamespaceMode,
+    /// Named apart from a container's PID.
+    pub pid_ns: NamespaceMode,
+    pub ipc: NamespaceMode,
+    pub uts: NamespaceMode,
+    /// Network to attach to with `--net bridge`; the default network if
+    /// unset.
+    pub network: Option<String>,
+    /// Bridge address requested with `--ip`; allocated automatically if
+    /// unset.
+    pub ip: Option<Ipv4Addr>,
+    /// MAC address requested with `--mac-address`; derived from the IP
+    /// address if unset.
+    pub mac_address: Option<MacAddress>,
+    /// Host ports forwarded into the container; needs `--net slirp`.
+    pub ports: Vec<PortMapping>,
+    pub health: Option<HealthCheck>,
+    /// Name of the signal `stop` sends, if not SIGTERM.
+    pub stop_signal: Option<String>,
+    /// Switch into the rootfs with `chroot` instead of `pivot_root`.
+    pub no_pivot: bool,
+    /// Switch with `chroot` where `pivot_root` is not possible, instead of
+    /// failing.
+    pub allow_chroot_fallback: bool,
+    /// Skip checking that the command exists in the rootfs before forking.
+    pub no_preflight: bool,
+    /// Give the command craterun's stdin instead of `/dev/null`.
+    pub attach_stdin: bool,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// The craterun command line that created the container, without the
+    /// program path.
+    pub invocation: Vec<String>,
+}
+
+impl ContainerConfig {
+    /// Size of `--tmpfs` mounts without an explicit `size=`: the memory
+    /// limit, since tmpfs pages are charged to the container's cgroup.
+    pub fn tmpfs_default_size(&self) -> u64 {
+        self.limits.memory.unwrap_or(TMPFS_DEFAULT_SIZE)
+    }
+
+    /// Size of the container's `/dev/shm`.
+    pub fn dev_shm_size(&self) -> u64 {
+        self.shm_size.unwrap_or(SHM_DEFAULT_SIZE)
+    }
+
+    /// The mode selected for namespaces of `kind`.
+    pub fn namespace(&self, kind: NamespaceKind) -> &NamespaceMode {
+        match kind {
+            NamespaceKind::Net => &self.net,
+            NamespaceKind::Pid => &self.pid_ns,
+            NamespaceKind::Ipc => &self.ipc,
+            NamespaceKind::Uts => &self.uts,
+        }
+    }
+}
+
+/// Everything `run` would do for a given config, without doing it.
+/// Printed as JSON by `run --dry-run`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunPlan {
+    /// The config as resolved from the CLI.
+    pub config: ContainerConfig,
+    /// Canonical absolute path of the rootfs.
+    pub rootfs: String,
+    /// Namespaces that will be unshared (e.g. `"pid"`, `"net"`).
+    pub namespaces: Vec<String>,
+    /// Mounts performed inside the container, in order.
+    pub mounts: Vec<MountPlan>,
+    /// The cgroup that will be created.
+    pub cgroup: CgroupPlan,
+    /// The resolved `--user`. An image rootfs is only looked into once
+    /// mounted, so for images this is always unset.
+    pub user: Option<ContainerUser>,
+    /// The ID maps of the container's user namespace, for `--userns keep-id`.
+    pub id_mappings: Option<IdMappings>,
+    /// Namespaces joined instead of unshared, with the full IDs of the
+    /// containers they belong to.
+    pub joined: Vec<JoinedNamespace>,
+    /// The command's full environment.
+    pub env: Vec<String>,
+}
+
+/// A namespace of another container that a new container joins.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JoinedNamespace {
+    pub kind: NamespaceKind,
+    /// Full ID of the container the namespace belongs to.
+    pub container: String,
+}
+
+/// A single mount performed during container setup.
+#[derive(Debug, Clone, Serialize)]
+pub struct MountPlan {
+    pub source: String,
+    pub target: String,
+    pub fstype: Option<String>,
+    pub options: Option<String>,
+}
+
+/// The cgroup a container will be placed in and the control files written.
+#[derive(Debug, Clone, Serialize)]
+pub struct CgroupPlan {
+    pub path: String,
+    pub limits: BTreeMap<String, String>,
+}
+
+/// How a path in a container's filesystem differs from its rootfs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FsChangeKind {
+    Added,
+    Changed,
+    Deleted,
+}
+
+impl fmt::Display for FsChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added => write!(f, "A"),
+            Self::Changed => write!(f, "C"),
+            Self::Deleted => write!(f, "D"),
+        }
+    }
+}
+
+/// A single filesystem change reported by `craterun diff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsChange {
+    pub kind: FsChangeKind,
+    /// Absolute path inside the container.
+    pub path: String,
+}
+
+impl fmt::Display for FsChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind, self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::userns::IdMap;
+
+    #[test]
+    fn status_display() {
+        assert_eq!(ContainerStatus::Running.to_string(), "running");
+        assert_eq!(ContainerStatus::Stopped.to_string(), "stopped");
+        assert_eq!(ContainerStatus::Created.to_string(), "created");
+    }
+
+    #[test]
+    fn cpu_limit_parsing() {
+        let limit: CpuLimit = "100000 100000".parse().unwrap();
+        assert_eq!(limit.quota_us, Some(100000));
+        assert_eq!(limit.to_string(), "100000 100000");
+        assert_eq!("max 100000".parse::<CpuLimit>().unwrap().quota_us, None);
+        let max: CpuLimit = "max".parse().unwrap();
+        assert_eq!(max.to_string(), "max 100000");
+        assert_eq!(" 50000\t100000 ".parse::<CpuLimit>().unwrap().to_string(), "50000 100000");
+
+        for bad in [
+            "",
+            "100000",
+            "0 100000",
+            "abc 100000",
+            "100000 0",
+            "-1 100000",
+            "max max",
+            "50000 2000000",
+            "1 2 3",
+        ] {
+            assert!(bad.parse::<CpuLimit>().is_err(), "{bad} should be rejected");
+        }
+        let err = "100000".parse::<CpuLimit>().unwrap_err().to_string();
+        assert!(err.contains("e.g. '50000 100000'"), "{err}");
+    }
+
+    #[test]
+    fn pids_limit_parsing() {
+        assert_eq!("100".parse::<PidsLimit>().unwrap().max, Some(100));
+        let unlimited: PidsLimit = "unlimited".parse().unwrap();
+        assert_eq!(unlimited.max, None);
+        assert_eq!(unlimited.to_string(), "max");
+        assert_eq!(PidsLimit::DEFAULT.to_string(), "1024");
+        assert!("0".parse::<PidsLimit>().is_err());
+        assert!("-1".parse::<PidsLimit>().is_err());
+        assert!("lots".parse::<PidsLimit>().is_err());
+    }
+
+    #[test]
+    fn cpu_limit_from_cpus() {
+        assert_eq!(CpuLimit::from_cpus(0.5).unwrap().to_string(), "50000 100000");
+        assert_eq!(CpuLimit::from_cpus(2.0).unwrap().to_string(), "200000 100000");
+        assert!(CpuLimit::from_cpus(0.0).is_err());
+        assert!(CpuLimit::from_cpus(-1.0).is_err());
+        assert!(CpuLimit::from_cpus(0.001).is_err());
+        assert!(CpuLimit::from_cpus(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn umask_parsing() {
+        for (s, expected) in [("0027", 0o027), ("22", 0o022), ("0777", 0o777), ("0", 0)] {
+            assert_eq!(s.parse::<Umask>().unwrap(), Umask(expected), "{s}");
+        }
+        assert_eq!(Umask(0o22).to_string(), "0022");
+        for bad in ["", "0800", "1000", "-1", "+22", "abc"] {
+            assert!(bad.parse::<Umask>().is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn timezone_parsing() {
+        assert_eq!("host".parse::<Timezone>().unwrap(), Timezone::Host);
+        assert_eq!(Timezone::Host.host_file(), Path::new("/etc/localtime"));
+        let zone: Timezone = "America/Argentina/Buenos_Aires".parse().unwrap();
+        assert_eq!(
+            zone.host_file(),
+            Path::new("/usr/share/zoneinfo/America/Argentina/Buenos_Aires")
+        );
+        let zone: Timezone = "Etc/GMT+3".parse().unwrap();
+        assert_eq!(zone.to_string(), "Etc/GMT+3");
+        for bad in ["", "/etc/passwd", "Europe/../x", "Europe//Berlin", "UTC "] {
+            assert!(bad.parse::<Timezone>().is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn ulimit_parsing() {
+        let ulimit: Ulimit = "nofile=1024:4096".parse().unwrap();
+        assert_eq!(ulimit.kind, UlimitKind::Nofile);
+        assert_eq!((ulimit.soft, ulimit.hard), (1024, 4096));
+        assert_eq!(ulimit.to_string(), "nofile=1024:4096");
+
+        let core: Ulimit = "core=0".parse().unwrap();
+        assert_eq!((core.soft, core.hard), (0, 0));
+        let stack: Ulimit = "stack=8388608:unlimited".parse().unwrap();
+        assert_eq!(stack.hard, RLIM_UNLIMITED);
+        assert_eq!(stack.to_string(), "stack=8388608:unlimited");
+
+        for bad in ["nofile", "nofile=", "fsize=1", "nofile=2:1", "nofile=x", "nofile=1:2:3"] {
+            assert!(bad.parse::<Ulimit>().is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn device_mapping_parsing() {
+        let dev: DeviceMapping = "/dev/fuse".parse().unwrap();
+        assert_eq!(dev.container_path, "/dev/fuse");
+        assert_eq!(dev.permissions, "rwm");
+
+        let dev: DeviceMapping = "/dev/kvm:rw".parse().unwrap();
+        assert_eq!(dev.container_path, "/dev/kvm");
+        assert_eq!(dev.permissions, "rw");
+
+        let dev: DeviceMapping = "/dev/ttyUSB0:/dev/ttyS0:r".parse().unwrap();
+        assert_eq!(dev.host_path, "/dev/ttyUSB0");
+        assert_eq!(dev.container_path, "/dev/ttyS0");
+        assert_eq!(dev.permissions, "r");
+        assert_eq!(dev.to_string(), "/dev/ttyUSB0:/dev/ttyS0:r");
+        let dev: DeviceMapping = "/dev/fuse".parse().unwrap();
+        assert_eq!(dev.to_string(), "/dev/fuse");
+
+        for bad in [
+            "dev/fuse",
+            "/dev/fuse:/tmp/fuse",
+            "/dev/fuse:/dev/../etc/x",
+            "/dev/a:/dev/b:x",
+            "/a:/dev/b:r:w",
+        ] {
+            assert!(bad.parse::<DeviceMapping>().is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn mac_address_parsing() {
+        let mac: MacAddress = "02:42:AC:11:00:02".parse().unwrap();
+        assert_eq!(mac, MacAddress([0x02, 0x42, 0xac, 0x11, 0x00, 0x02]));
+        assert_eq!(mac.to_string(), "02:42:ac:11:00:02");
+        assert_eq!(serde_json::to_string(&mac).unwrap(), r#""02:42:ac:11:00:02""#);
+
+        for bad in [
+            "",
+            "02:42:ac:11:00",
+            "02:42:ac:11:00:02:03",
+            "02:42:ac:11:00:2",
+            "02:42:ac:11:00:zz",
+            "02-42-ac-11-00-02",
+            "01:00:5e:00:00:01",
+            "00:00:00:00:00:00",
+        ] {
+            assert!(
+                bad.parse::<MacAddress>().is_err(),
+                "{bad} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn userns_mode_parsing() {
+        for (s, expected) in [("host", UsernsMode::Host), ("keep-id", UsernsMode::KeepId)] {
+            let mode: UsernsMode = s.parse().unwrap();
+            assert_eq!(mode, expected);
+            assert_eq!(mode.to_string(), s);
+        }
+        for bad in ["", "keep_id", "auto"] {
+            assert!(
+                bad.parse::<UsernsMode>().is_err(),
+                "{bad} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn keyring_parsing() {
+        for (s, expected) in [("private", Keyring::Private), ("host", Keyring::Host)] {
+            let keyring: Keyring = s.parse().unwrap();
+            assert_eq!(keyring, expected);
+            assert_eq!(keyring.to_string(), s);
+        }
+        for bad in ["", "session", "Host"] {
+            assert!(bad.parse::<Keyring>().is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn cgroup_ns_parsing() {
+        for (s, expected) in [
+            ("private", CgroupNsMode::Private),
+            ("host", CgroupNsMode::Host),
+        ] {
+            let mode: CgroupNsMode = s.parse().unwrap();
+            assert_eq!(mode, expected);
+            assert_eq!(mode.to_string(), s);
+        }
+        assert!("none".parse::<CgroupNsMode>().is_err());
+    }
+
+    #[test]
+    fn container_filter_parsing() {
+        assert_eq!(
+            "status=stopped".parse::<ContainerFilter>().unwrap(),
+            ContainerFilter::Status(ContainerStatus::Stopped)
+        );
+        assert_eq!(
+            "network=web".parse::<ContainerFilter>().unwrap(),
+            ContainerFilter::Network("web".into())
+        );
+        for bad in [
+            "status",
+            "status=exited",
+            "network=",
+            "name=web",
+            "label=a=b",
+        ] {
+            assert!(bad.parse::<ContainerFilter>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn proc_options_parsing() {
+        let options: ProcOptions = "".parse().unwrap();
+        assert!(options.is_default());
+        assert_eq!(options.data(), None);
+
+        let options: ProcOptions = "ro,hidepid=2".parse().unwrap();
+        assert!(options.read_only && options.hidepid);
+        assert_eq!(options.to_string(), "ro,hidepid=2");
+        assert_eq!(options.data(), Some("hidepid=invisible,gid=0"));
+        let options: ProcOptions = "hidepid=invisible".parse().unwrap();
+        assert_eq!(options.to_string(), "hidepid=2");
+
+        for bad in ["rw", "RO", "hidepid=1", "gid=0"] {
+            assert!(
+                bad.parse::<ProcOptions>().is_err(),
+                "{bad} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn mount_propagation_parsing() {
+        for (s, expected) in [
+            ("private", MountPropagation::Private),
+            ("slave", MountPropagation::Slave),
+            ("shared", MountPropagation::Shared),
+        ] {
+            let propagation: MountPropagation = s.parse().unwrap();
+            assert_eq!(propagation, expected);
+            assert_eq!(propagation.to_string(), s);
+        }
+        for bad in ["", "rslave", "Shared", "unbindable"] {
+            assert!(
+                bad.parse::<MountPropagation>().is_err(),
+                "{bad} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn subnet_parsing() {
+        let subnet: Subnet = "10.88.0.0/24".parse().unwrap();
+        assert_eq!(subnet.to_string(), "10.88.0.0/24");
+        assert_eq!(subnet.gateway(), Ipv4Addr::new(10, 88, 0, 1));
+        assert_eq!(subnet.broadcast(), Ipv4Addr::new(10, 88, 0, 255));
+        assert!(subnet.contains(Ipv4Addr::new(10, 88, 0, 42)));
+        assert!(!subnet.contains(Ipv4Addr::new(10, 88, 1, 42)));
+        assert_eq!(subnet.hosts().count(), 254);
+        assert_eq!(serde_json::to_string(&subnet).unwrap(), r#""10.88.0.0/24""#);
+
+        let err = "10.88.0.1/24".parse::<Subnet>().unwrap_err();
+        assert!(err.to_string().contains("did you mean 10.88.0.0/24"), "{err}");
+        for bad in ["", "10.88.0.0", "10.88.0.0/", "10.88.0.0/7", "10.88.0.0/31", "fd00::/64"] {
+            assert!(bad.parse::<Subnet>().is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn port_mapping_parsing() {
+        let port: PortMapping = "8080:80".parse().unwrap();
+        assert_eq!(port.host_addr, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!((port.host_port, port.container_port), (8080, 80));
+        assert_eq!(port.protocol, PortProtocol::Tcp);
+        assert_eq!(port.to_string(), "0.0.0.0:8080:80/tcp");
+
+        let port: PortMapping = "127.0.0.1:5353:53/udp".parse().unwrap();
+        assert_eq!(port.host_addr, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(port.protocol, PortProtocol::Udp);
+
+        let port: PortMapping = "[::1]:8443:443".parse().unwrap();
+        assert_eq!(port.host_addr, "::1".parse::<IpAddr>().unwrap());
+        assert_eq!(port.to_string(), "[::1]:8443:443/tcp");
+        assert_eq!(port.summary(), "[::1]:8443->443/tcp");
+
+        for bad in ["80", "0:80", "8080:65536", "8080:80/sctp", "host:8080:80", ":80"] {
+            assert!(bad.parse::<PortMapping>().is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn tmpfs_mount_parsing() {
+        let tmpfs: TmpfsMount = "/tmp".parse().unwrap();
+        assert_eq!(tmpfs.path, "/tmp");
+        assert_eq!(tmpfs.data(TMPFS_DEFAULT_SIZE), "size=67108864");
+
+        let tmpfs: TmpfsMount = "/run:size=1m,mode=1777,noexec,nosuid".parse().unwrap();
+        assert_eq!(tmpfs.size, Some(1 << 20));
+        assert_eq!(tmpfs.mode, Some(0o1777));
+        assert_eq!(tmpfs.flags, ["noexec", "nosuid"]);
+        assert_eq!(tmpfs.data(TMPFS_DEFAULT_SIZE), "size=1048576,mode=1777");
+        assert_eq!(
+            tmpfs.to_string(),
+            "/run:size=1048576,mode=1777,noexec,nosuid"
+        );
+
+        for bad in [
+            "tmp",
+            "/",
+            "/tmp/../etc",
+            "/tmp:size=0",
+            "/tmp:size=lots",
+            "/tmp:mode=999",
+            "/tmp:exec",
+        ] {
+            assert!(bad.parse::<TmpfsMount>().is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn health_state_transitions() {
+        let check = HealthCheck {
+            cmd: "curl -f localhost".into(),
+            interval: std::time::Duration::from_secs(30),
+            retries: 2,
+        };
+        let now = Utc::now();
+        let mut health = HealthState::new(&check);
+        assert_eq!(health.status, HealthStatus::Starting);
+
+        health.record(1, "refused", check.retries, now);
+        assert_eq!((health.status, health.failing_streak), (HealthStatus::Starting, 1));
+        health.record(0, "ok", check.retries, now);
+        assert_eq!((health.status, health.failing_streak), (HealthStatus::Healthy, 0));
+        health.record(7, "", check.retries, now);
+        assert_eq!(health.status, HealthStatus::Healthy);
+        health.record(7, "", check.retries, now);
+        assert_eq!((health.status, health.failing_streak), (HealthStatus::Unhealthy, 2));
+        assert_eq!(health.last_exit_code, Some(7));
+
+        // Output is truncated on a character boundary.
+        health.record(0, &"é".repeat(HEALTH_OUTPUT_MAX), check.retries, now);
+        assert_eq!(health.last_output.len(), HEALTH_OUTPUT_MAX);
+        assert_eq!(health.status, HealthStatus::Healthy);
+        assert_eq!((health.interval_ms, health.retries), (30_000, 2));
+
+        // Metadata written before the interval and retries were recorded
+        // gets the defaults of `run`.
+        let old: HealthState =
+            serde_json::from_str(r#"{"cmd":"true","status":"healthy","failing_streak":0}"#)
+                .unwrap();
+        assert_eq!((old.interval_ms, old.retries), (30_000, 3));
+    }
+
+    #[test]
+    fn namespace_mode_parsing() {
+        assert_eq!(
+            "none".parse::<NamespaceMode>().unwrap(),
+            NamespaceMode::Private
+        );
+        assert_eq!(
+            "private".parse::<NamespaceMode>().unwrap(),
+            NamespaceMode::Private
+        );
+        assert_eq!(
+            "host".parse::<NamespaceMode>().unwrap(),
+            NamespaceMode::Host
+        );
+        let net: NamespaceMode = "container:0123abcd".parse().unwrap();
+        assert_eq!(net, NamespaceMode::Container("0123abcd".into()));
+        assert_eq!(net.to_string(), "container:0123abcd");
+        assert_eq!(
+            "bridge".parse::<NamespaceMode>().unwrap(),
+            NamespaceMode::Bridge
+        );
+        let slirp: NamespaceMode = "slirp".parse().unwrap();
+        assert!(slirp.is_new() && !slirp.is_private());
+        assert_eq!(slirp.to_string(), "slirp");
+
+        for bad in ["", "macvlan", "container:", "container:../x", "none:x", "host:x"] {
+            assert!(
+                bad.parse::<NamespaceMode>().is_err(),
+                "{bad} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn sysctl_namespaces() {
+        let kind = |s: &str| s.parse::<Sysctl>().unwrap().namespace();
+        assert_eq!(kind("net.ipv4.ip_forward=1"), NamespaceKind::Net);
+        assert_eq!(kind("kernel.shmmax=1024"), NamespaceKind::Ipc);
+        assert_eq!(kind("fs.mqueue.msg_max=16"), NamespaceKind::Ipc);
+        assert_eq!(kind("kernel.domainname=example"), NamespaceKind::Uts);
+    }
+
+    #[test]
+    fn sysctl_parsing() {
+        let sysctl: Sysctl = "net.ipv4.ip_unprivileged_port_start=0".parse().unwrap();
+        assert_eq!(sysctl.key, "net.ipv4.ip_unprivileged_port_start");
+        assert_eq!(sysctl.value, "0");
+        assert_eq!(sysctl.proc_path(), "/proc/sys/net/ipv4/ip_unprivileged_port_start");
+        assert_eq!(sysctl.to_string(), "net.ipv4.ip_unprivileged_port_start=0");
+
+        let sem: Sysctl = "kernel.sem=250 32000 100 128".parse().unwrap();
+        assert_eq!(sem.value, "250 32000 100 128");
+        assert!("fs.mqueue.msg_max=20".parse::<Sysctl>().is_ok());
+
+        for bad in [
+            "net.ipv4.ip_forward",
+            "=1",
+            "net..ipv4=1",
+            "net/../../kernel/x=1",
+            "kernel.hostname=x",
+            "kernel.shmmaxx=1",
+            "vm.swappiness=10",
+            "fs.file-max=1",
+            "net.core.somaxconn=",
+        ] {
+            assert!(bad.parse::<Sysctl>().is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn run_duration() {
+        let start = Utc::now();
+        let json = format!(
+            r#"{{"id":"a","rootfs":"/","cmd":[],"pid":0,"exit_code":0,"created_at":"{start:?}",
+                "status":"stopped","hostname":"h","memory_limit":null,"cpu_limit":null,
+                "pids_limit":null}}"#
+        );
+        let mut meta: ContainerMeta = serde_json::from_str(&json).unwrap();
+        assert_eq!(meta.run_duration(start), None);
+
+        meta.started_at = Some(start);
+        meta.status = ContainerStatus::Running;
+        let now = start + chrono::Duration::seconds(90);
+        assert_eq!(meta.run_duration(now), Some(std::time::Duration::from_secs(90)));
+
+        meta.status = ContainerStatus::Stopped;
+        meta.finished_at = Some(start + chrono::Duration::seconds(5));
+        assert_eq!(meta.run_duration(now), Some(std::time::Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn status_summary() {
+        // Metadata from before the start/finish times were recorded.
+        let json = r#"{"id":"a","rootfs":"/","cmd":[],"pid":0,"exit_code":0,
+            "created_at":"2024-01-01T00:00:00Z","status":"stopped","hostname":"h",
+            "memory_limit":null,"cpu_limit":null,"pids_limit":null}"#;
+        let mut meta: ContainerMeta = serde_json::from_str(json).unwrap();
+        let start = Utc::now();
+        assert_eq!(meta.status_summary(start), "Exited (0)");
+
+        meta.started_at = Some(start);
+        meta.status = ContainerStatus::Running;
+        let now = start + chrono::Duration::minutes(3);
+        assert_eq!(meta.status_summary(now), "Up 3 minutes");
+        let check = HealthCheck {
+            cmd: "true".into(),
+            interval: std::time::Duration::from_secs(30),
+            retries: 3,
+        };
+        meta.health = Some(HealthState::new(&check));
+        assert_eq!(meta.status_summary(now), "Up 3 minutes (starting)");
+
+        meta.status = ContainerStatus::Stopped;
+        meta.exit_code = Some(137);
+        meta.finished_at = Some(start + chrono::Duration::seconds(5));
+        let now = start + chrono::Duration::hours(2);
+        assert_eq!(meta.status_summary(now), "Exited (137) 2 hours ago");
+
+        meta.signal = Some("SIGKILL".into());
+        assert_eq!(meta.status_summary(now), "Killed (SIGKILL) 2 hours ago");
+    }
+
+    #[test]
+    fn exit_tells_signals_from_exit_codes() {
+        let json = r#"{"id":"a","rootfs":"/","cmd":[],"pid":1,"exit_code":null,
+            "created_at":"2024-01-01T00:00:00Z","status":"running","hostname":"h",
+            "memory_limit":null,"cpu_limit":null,"pids_limit":null}"#;
+        let mut meta: ContainerMeta = serde_json::from_str(json).unwrap();
+        assert_eq!(meta.exit(), None);
+
+        meta.status = ContainerStatus::Stopped;
+        meta.exit_code = Some(EXIT_CODE_UNKNOWN);
+        assert_eq!(meta.exit(), None);
+        meta.exit_code = Some(137);
+        assert_eq!(meta.exit(), Some(ContainerExit::Exited(137)));
+        assert_eq!(meta.exit().unwrap().to_string(), "exited (137)");
+
+        meta.signal = Some("SIGKILL".into());
+        assert_eq!(meta.exit().unwrap().to_string(), "killed (SIGKILL)");
+        meta.signal = Some("SIGSEGV".into());
+        meta.core_dumped = true;
+        assert_eq!(
+            meta.exit().unwrap().to_string(),
+            "killed (SIGSEGV, core dumped)"
+        );
+    }
+
+    #[test]
+    fn apply_limits_keeps_unset_limits() {
+        let json = r#"{"id":"a","rootfs":"/","cmd":[],"pid":0,"exit_code":0,
+            "created_at":"2024-01-01T00:00:00Z","status":"stopped","hostname":"h",
+            "memory_limit":1024,"cpu_limit":"50000 100000","pids_limit":100}"#;
+        let mut meta: ContainerMeta = serde_json::from_str(json).unwrap();
+
+        meta.apply_limits(&ResourceLimits {
+            memory: Some(268435456),
+            memory_spec: Some("256M".into()),
+            memory_swappiness: Some(0),
+            pids: Some(PidsLimit { max: Some(200) }),
+            ..Default::default()
+        });
+        assert_eq!(meta.memory_limit, Some(268435456));
+        assert_eq!(meta.memory_swappiness, Some(0));
+        assert_eq!(meta.memory_limit_spec.as_deref(), Some("256M"));
+        assert_eq!(meta.pids_limit, Some(200));
+        assert_eq!(meta.cpu_limit.as_deref(), Some("50000 100000"));
+
+        meta.apply_limits(&ResourceLimits {
+            pids: Some("unlimited".parse().unwrap()),
+            ..Default::default()
+        });
+        assert_eq!(meta.pids_limit, None);
+        assert_eq!(meta.memory_swappiness, Some(0));
     }
 
     #[test]
     fn meta_serialization_round_trip() {
         let meta = ContainerMeta {
+            schema_version: META_SCHEMA_VERSION,
             id: "abcdef0123456789".into(),
+            name: Some("shop_web".into()),
+            service: Some(ServiceLabel {
+                project: "shop".into(),
+                service: "web".into(),
+                config_hash: "9f86d081884c7d65".into(),
+            }),
             rootfs: "/tmp/rootfs".into(),
             cmd: vec!["/bin/sh".into(), "-c".into(), "echo hi".into()],
             pid: 12345,
             exit_code: None,
             created_at: Utc::now(),
+            started_at: Some(Utc::now()),
+            finished_at: None,
             status: ContainerStatus::Running,
             hostname: "craterun".into(),
             memory_limit: Some(67108864),
+            memory_limit_spec: Some("64M".into()),
+            memory_swappiness: Some(0),
             cpu_limit: None,
             pids_limit: Some(100),
+            pids_limit_hits: 7,
+            cpuset_cpus: Some("0-1".into()),
+            cpuset_mems: None,
+            ulimits: vec!["nofile=1024:4096".parse().unwrap()],
+            oom_score_adj: Some(500),
+            umask: Some(Umask(0o027)),
+            timezone: Some("Europe/Berlin".parse().unwrap()),
+            devices: vec!["/dev/fuse".parse().unwrap()],
+            tmpfs: vec!["/tmp:size=16m,noexec".parse().unwrap()],
+            shm_size: Some(1 << 30),
+            mount_propagation: MountPropagation::Slave,
+            proc_opts: "ro,hidepid=2".parse().unwrap(),
+            keyring: Keyring::Host,
+            userns: UsernsMode::KeepId,
+            id_mappings: Some(IdMappings {
+                uid_map: vec![IdMap {
+                    inside: 0,
+                    outside: 100000,
+                    count: 65536,
+                }],
+                gid_map: Vec::new(),
+            }),
+            cgroup_ns: CgroupNsMode::Private,
+            loop_device: Some("/dev/loop3".into()),
+            storage: Some(StorageLimit {
+                size: 1 << 30,
+                driver: StorageDriver::Loop,
+            }),
+            sysctls: vec!["net.ipv4.ip_unprivileged_port_start=0".parse().unwrap()],
+            env: vec!["PATH=/bin".into(), "LANG=C.UTF-8".into()],
+            env_host: true,
+            user: Some(ContainerUser {
+                uid: 65534,
+                gid: 65534,
+                groups: vec![65534],
+                name: Some("nobody".into()),
+                home: Some("/".into()),
+            }),
+            domainname: Some("example.internal".into()),
+            net: NamespaceMode::Container("0123456789abcdef".into()),
+            pid_ns: NamespaceMode::Host,
+            ipc: NamespaceMode::Private,
+            uts: NamespaceMode::Container("0123456789abcdef".into()),
+            network: Some("bridge".into()),
+            ip_address: Some(Ipv4Addr::new(10, 77, 0, 42)),
+            mac_address: Some("02:42:ac:11:00:02".parse().unwrap()),
+            ports: vec!["8080:80".parse().unwrap()],
+            slirp_pid: Some(4321),
+            stop_signal: Some("SIGQUIT".into()),
+            init_path: Some("/sbin/tini".into()),
+            argv0: Some("sh".into()),
+            no_pivot: true,
+            allow_chroot_fallback: false,
+            no_preflight: true,
+            attach_stdin: false,
+            signal: Some("SIGSEGV".into()),
+            core_dumped: true,
+            health: None,
+            invocation: vec!["run".into(), "--rootfs".into(), "/tmp/rootfs".into()],
         };
 
         let json = serde_json::to_string(&meta).expect("serialize");
         let back: ContainerMeta = serde_json::from_str(&json).expect("deserialize");
         assert_eq!(back.id, meta.id);
+        assert_eq!(back.name, meta.name);
+        assert_eq!(back.service, meta.service);
         assert_eq!(back.rootfs, meta.rootfs);
         assert_eq!(back.cmd, meta.cmd);
         assert_eq!(back.pid, meta.pid);
         assert_eq!(back.status, meta.status);
         assert_eq!(back.memory_limit, Some(67108864));
+        assert_eq!(back.memory_swappiness, Some(0));
+        assert_eq!(back.pids_limit_hits, 7);
+        assert_eq!(back.ulimits, meta.ulimits);
+        assert_eq!(back.umask, Some(Umask(0o027)));
+        assert_eq!(back.timezone, meta.timezone);
+        assert_eq!(back.devices, meta.devices);
+        assert_eq!(back.tmpfs, meta.tmpfs);
+        assert_eq!(back.shm_size, meta.shm_size);
+        assert_eq!(back.mount_propagation, MountPropagation::Slave);
+        assert_eq!(back.proc_opts, meta.proc_opts);
+        assert_eq!(back.keyring, Keyring::Host);
+        assert_eq!(back.userns, UsernsMode::KeepId);
+        assert_eq!(back.id_mappings, meta.id_mappings);
+        assert_eq!(back.cgroup_ns, CgroupNsMode::Private);
+        assert_eq!(back.loop_device, meta.loop_device);
+        assert_eq!(back.storage, meta.storage);
+        assert_eq!(back.sysctls, meta.sysctls);
+        assert_eq!(back.env, meta.env);
+        assert!(back.env_host);
+        assert_eq!(back.user, meta.user);
+        assert_eq!(back.domainname, meta.domainname);
+        assert_eq!(back.net, meta.net);
+        assert_eq!(back.pid_ns, meta.pid_ns);
+        assert_eq!(back.uts, meta.uts);
+        assert_eq!(back.network, meta.network);
+        assert_eq!(back.ip_address, meta.ip_address);
+        assert_eq!(back.mac_address, meta.mac_address);
+        assert_eq!(back.ports, meta.ports);
+        assert_eq!(back.slirp_pid, meta.slirp_pid);
+        assert_eq!(back.stop_signal, meta.stop_signal);
+        assert_eq!(back.argv0, meta.argv0);
+        assert!(back.no_pivot && back.no_preflight && !back.attach_stdin);
+        assert_eq!(back.signal, meta.signal);
+        assert!(back.core_dumped);
+        assert_eq!(back.invocation, meta.invocation);
     }
 }
@@ -0,0 +1,264 @@
+use std::fs;
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::ipam;
+use super::model::Subnet;
+use super::state;
+use crate::util;
+
+/// Directory under the state directory holding network definitions and
+/// their address allocations.
+const NETWORKS_DIR: &str = "networks";
+/// Name of the per-network definition file.
+const META_FILE: &str = "network.json";
+/// Longest accepted network name.
+const MAX_NAME_LEN: usize = 64;
+
+/// The network `--net bridge` attaches containers to. It is built in: it has
+/// no definition file and cannot be removed.
+pub const DEFAULT_NETWORK: &str = "bridge";
+/// Host bridge of the default network.
+const DEFAULT_BRIDGE: &str = "craterun0";
+/// Subnet of the default network.
+const DEFAULT_SUBNET: Subnet = Subnet {
+    addr: Ipv4Addr::new(10, 77, 0, 0),
+    prefix_len: 24,
+};
+/// Where [`free_subnet`] picks subnets from: the /24s of 10.78.0.0/16.
+const FREE_SUBNET_BASE: Ipv4Addr = Ipv4Addr::new(10, 78, 0, 0);
+/// Prefix of the host bridges of created networks, numbered from 1.
+const BRIDGE_PREFIX: &str = "craterun";
+
+/// A network containers can be attached to with `--network`: a Linux bridge
+/// on the host and the subnet its containers get addresses from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkInfo {
+    pub name: String,
+    /// Name of the host bridge, e.g. `craterun1`.
+    pub bridge: String,
+    pub subnet: Subnet,
+    /// `None` for the built-in default network.
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl NetworkInfo {
+    /// The bridge's own address: the containers' default gateway.
+    pub fn gateway(&self) -> Ipv4Addr {
+        self.subnet.gateway()
+    }
+}
+
+/// The built-in network used by `--net bridge`.
+fn default_network() -> NetworkInfo {
+    NetworkInfo {
+        name: DEFAULT_NETWORK.to_string(),
+        bridge: DEFAULT_BRIDGE.to_string(),
+        subnet: DEFAULT_SUBNET,
+        created_at: None,
+    }
+}
+
+/// Return the directory of the network `name`, which also holds its address
+/// allocations.
+pub fn network_dir(name: &str) -> Result<PathBuf> {
+    Ok(state::state_dir()?.join(NETWORKS_DIR).join(name))
+}
+
+/// Check that `name` is usable as a network name: 1-64 characters of
+/// lowercase letters, digits, `.`, `_` and `-`, starting with a letter or
+/// digit.
+pub fn validate_network_name(name: &str) -> Result<()> {
+    let valid = name.len() <= MAX_NAME_LEN
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c));
+    if !valid {
+        bail!(
+            "invalid network name '{name}': expected up to {MAX_NAME_LEN} lowercase letters, \
+             digits, '.', '_' or '-', starting with a letter or digit"
+        );
+    }
+    Ok(())
+}
+
+/// Record a new network `name` on `subnet` and return it. Its bridge is the
+/// lowest-numbered one no other network uses; creating it on the host is
+/// left to the caller.
+pub fn create_network(name: &str, subnet: Subnet) -> Result<NetworkInfo> {
+    validate_network_name(name)?;
+    let networks = list_networks()?;
+    if networks.iter().any(|n| n.name == name) {
+        bail!("network '{name}' already exists");
+    }
+    if let Some(other) = networks.iter().find(|n| n.subnet == subnet) {
+        bail!(
+            "subnet {subnet} is already used by network '{}'",
+            other.name
+        );
+    }
+
+    let network = NetworkInfo {
+        name: name.to_string(),
+        bridge: next_bridge_name(&networks),
+        subnet,
+        created_at: Some(Utc::now()),
+    };
+    let dir = network_dir(name)?;
+    util::fs::ensure_dir(&dir)?;
+    let json = serde_json::to_string_pretty(&network).context("failed to serialize network")?;
+    util::fs::write_file(&dir.join(META_FILE), &json)?;
+    Ok(network)
+}
+
+/// The first /24 of 10.78.0.0/16 that no network uses, for networks created
+/// without a subnet of their own.
+pub fn free_subnet() -> Result<Subnet> {
+    first_free_subnet(&list_networks()?).context("no free subnet left in 10.78.0.0/16")
+}
+
+/// The first /24 of 10.78.0.0/16 that none of `networks` uses.
+fn first_free_subnet(networks: &[NetworkInfo]) -> Option<Subnet> {
+    let [a, b, ..] = FREE_SUBNET_BASE.octets();
+    (0..=u8::MAX)
+        .map(|n| Subnet {
+            addr: Ipv4Addr::new(a, b, n, 0),
+            prefix_len: 24,
+        })
+        .find(|subnet| networks.iter().all(|n| n.subnet != *subnet))
+}
+
+/// The first `craterun<N>` bridge name, counting from 1, that none of
+/// `networks` uses.
+fn next_bridge_name(networks: &[NetworkInfo]) -> String {
+    (1..)
+        .map(|n| format!("{BRIDGE_PREFIX}{n}"))
+        .find(|bridge| networks.iter().all(|n| &n.bridge != bridge))
+        .expect("bridge numbers are unbounded")
+}
+
+/// Load the network `name`.
+pub fn load_network(name: &str) -> Result<NetworkInfo> {
+    if name == DEFAULT_NETWORK {
+        return Ok(default_network());
+    }
+    validate_network_name(name)?;
+    let path = network_dir(name)?.join(META_FILE);
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => bail!("no network named '{name}'"),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    serde_json::from_str(&data).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// List the networks, the default one first and the rest sorted by name.
+pub fn list_networks() -> Result<Vec<NetworkInfo>> {
+    let dir = state::state_dir()?.join(NETWORKS_DIR);
+    let mut networks = vec![default_network()];
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(networks),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if let Some(name) = entry.file_name().to_str() {
+            if validate_network_name(name).is_ok() && entry.path().join(META_FILE).is_file() {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort();
+    for name in names {
+        networks.push(load_network(&name)?);
+    }
+    Ok(networks)
+}
+
+/// Remove the definition and address allocations of the network `name`.
+/// Refused for the default network, and while containers are attached
+/// unless `force` is set. Removing its bridge is left to the caller.
+pub fn remove_network(name: &str, force: bool) -> Result<NetworkInfo> {
+    if name == DEFAULT_NETWORK {
+        bail!("the default network '{DEFAULT_NETWORK}' cannot be removed");
+    }
+    let network = load_network(name)?;
+    let attached = ipam::holders(name)?;
+    if !attached.is_empty() && !force {
+        bail!(
+            "network '{name}' has attached container(s) {}; remove them first or use --force",
+            attached.join(", ")
+        );
+    }
+
+    // Drop the definition first so a partial removal no longer lists it.
+    let dir = network_dir(name)?;
+    fs::remove_file(dir.join(META_FILE))
+        .with_context(|| format!("failed to remove network '{name}'"))?;
+    fs::remove_dir_all(&dir).with_context(|| format!("failed to remove {}", dir.display()))?;
+    Ok(network)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(name: &str, bridge: &str) -> NetworkInfo {
+        NetworkInfo {
+            name: name.into(),
+            bridge: bridge.into(),
+            subnet: "10.88.0.0/24".parse().unwrap(),
+            created_at: None,
+        }
+    }
+
+    #[test]
+    fn network_names() {
+        for good in ["web", "project-1", "a.b_c", "0"] {
+            assert!(validate_network_name(good).is_ok(), "{good}");
+        }
+        for bad in ["", "Web", ".hidden", "-x", "a/b", "..", &"a".repeat(65)] {
+            assert!(validate_network_name(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn bridge_names_fill_gaps() {
+        let mut networks = vec![default_network()];
+        assert_eq!(next_bridge_name(&networks), "craterun1");
+        networks.push(network("a", "craterun1"));
+        networks.push(network("b", "craterun3"));
+        assert_eq!(next_bridge_name(&networks), "craterun2");
+    }
+
+    #[test]
+    fn free_subnets_fill_gaps() {
+        let mut networks = vec![default_network()];
+        assert_eq!(first_free_subnet(&networks), "10.78.0.0/24".parse().ok());
+        for (name, subnet) in [("a", "10.78.0.0/24"), ("b", "10.78.2.0/24")] {
+            networks.push(NetworkInfo {
+                subnet: subnet.parse().unwrap(),
+                ..network(name, "craterun1")
+            });
+        }
+        assert_eq!(first_free_subnet(&networks), "10.78.1.0/24".parse().ok());
+    }
+
+    #[test]
+    fn default_network_is_built_in() {
+        let network = load_network(DEFAULT_NETWORK).unwrap();
+        assert_eq!(network.bridge, "craterun0");
+        assert_eq!(network.gateway(), Ipv4Addr::new(10, 77, 0, 1));
+        let err = remove_network(DEFAULT_NETWORK, true).unwrap_err();
+        assert!(err.to_string().contains("cannot be removed"), "{err}");
+    }
+}
@@ -0,0 +1,883 @@
+//! Pulling images from an OCI distribution registry (`craterun pull`,
+//! `run --image`): the manifest of a tag, the manifest of this host's
+//! platform if that is an index, then the layer blobs, applied in order.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::image::{self, ImageInfo};
+
+/// Registry of references that name none, like `alpine:3.20`.
+const DEFAULT_REGISTRY: &str = "docker.io";
+/// Host of the registry API of Docker Hub, which `docker.io` stands for.
+const DOCKER_HUB_HOST: &str = "registry-1.docker.io";
+/// Namespace of Docker Hub's official images, like `alpine`.
+const DOCKER_HUB_LIBRARY: &str = "library";
+/// Tag of references that name none.
+const DEFAULT_TAG: &str = "latest";
+/// Longest accepted tag.
+const MAX_TAG_LEN: usize = 128;
+/// Largest manifest or token response read, in bytes.
+const MAX_DOCUMENT_SIZE: u64 = 4 << 20;
+
+const OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+const OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+const DOCKER_MANIFEST_LIST: &str = "application/vnd.docker.distribution.manifest.list.v2+json";
+const DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+
+/// A reference to an image in a registry, `[REGISTRY/]REPOSITORY[:TAG]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// The registry, e.g. `docker.io` or `localhost:5000`.
+    pub registry: String,
+    /// The repository, with Docker Hub's `library/` namespace for official
+    /// images made explicit.
+    pub repository: String,
+    pub tag: String,
+}
+
+impl Reference {
+    /// The name the image is kept under: the last part of the repository
+    /// and the tag, e.g. `alpine:3.20`.
+    pub fn image_name(&self) -> String {
+        let base = self.repository.rsplit('/').next().unwrap_or_default();
+        format!("{base}:{}", self.tag)
+    }
+
+    /// The base URL of the registry API. A registry on the loopback
+    /// interface is spoken to over plain HTTP, like Docker does.
+    fn api_url(&self) -> String {
+        let host = match self.registry.as_str() {
+            DEFAULT_REGISTRY => DOCKER_HUB_HOST,
+            registry => registry,
+        };
+        let scheme = if is_loopback(host) { "http" } else { "https" };
+        format!("{scheme}://{host}/v2")
+    }
+}
+
+impl FromStr for Reference {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.contains('@') {
+            bail!("invalid image reference '{s}': pulling by digest is not supported, name a tag");
+        }
+        // Like Docker, the first part names a registry only if it looks
+        // like a host: `docker.io/x`, `localhost:5000/x`, but not `user/x`.
+        let (registry, rest) = match s.split_once('/') {
+            Some((first, rest)) if first.contains(['.', ':']) || first == "localhost" => {
+                (first, rest)
+            }
+            _ => (DEFAULT_REGISTRY, s),
+        };
+        let (repository, tag) = match rest.rsplit_once(':') {
+            Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+            _ => (rest, DEFAULT_TAG),
+        };
+
+        let valid_part = |part: &str| {
+            part.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+                && part.ends_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c))
+        };
+        if !repository.split('/').all(valid_part) {
+            bail!(
+                "invalid image reference '{s}': expected [REGISTRY/]REPOSITORY[:TAG], the \
+                 repository of lowercase letters, digits, '.', '_' and '-' parts joined by '/'"
+            );
+        }
+        let valid_tag = tag.len() <= MAX_TAG_LEN
+            && tag.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c));
+        if !valid_tag {
+            bail!(
+                "invalid tag '{tag}' in image reference '{s}': expected up to {MAX_TAG_LEN} \
+                 letters, digits, '.', '_' or '-', not starting with '.' or '-'"
+            );
+        }
+
+        let repository = if registry == DEFAULT_REGISTRY && !repository.contains('/') {
+            format!("{DOCKER_HUB_LIBRARY}/{repository}")
+        } else {
+            repository.to_string()
+        };
+        Ok(Self {
+            registry: registry.to_string(),
+            repository,
+            tag: tag.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}:{}", self.registry, self.repository, self.tag)
+    }
+}
+
+/// When `run --image` pulls its image (`--pull`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PullPolicy {
+    /// Never; the image must have been pulled or imported before.
+    Never,
+    /// Only if there is no image of the name yet.
+    #[default]
+    Missing,
+    /// Every time, updating the image if its tag has moved.
+    Always,
+}
+
+impl FromStr for PullPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "never" => Ok(Self::Never),
+            "missing" => Ok(Self::Missing),
+            "always" => Ok(Self::Always),
+            _ => bail!("invalid pull policy '{s}': expected 'never', 'missing' or 'always'"),
+        }
+    }
+}
+
+impl fmt::Display for PullPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Never => write!(f, "never"),
+            Self::Missing => write!(f, "missing"),
+            Self::Always => write!(f, "always"),
+        }
+    }
+}
+
+/// A layer blob as a manifest describes it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Layer {
+    pub media_type: String,
+    /// `sha256:<hex>` of the blob.
+    pub digest: String,
+    /// Size of the blob, in bytes.
+    pub size: u64,
+}
+
+/// An image index or manifest list, or an image manifest.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    schema_version: u32,
+    #[serde(default)]
+    media_type: Option<String>,
+    /// The manifests of an index, one per platform.
+    #[serde(default)]
+    manifests: Vec<PlatformManifest>,
+    /// The layers of an image manifest, lowest first.
+    #[serde(default)]
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlatformManifest {
+    digest: String,
+    #[serde(default)]
+    platform: Option<Platform>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Platform {
+    architecture: String,
+    os: String,
+}
+
+impl Manifest {
+    fn is_index(&self) -> bool {
+        matches!(
+            self.media_type.as_deref(),
+            Some(OCI_INDEX | DOCKER_MANIFEST_LIST)
+        ) || !self.manifests.is_empty()
+    }
+}
+
+/// Answer of a registry's token service.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    access_token: Option<String>,
+}
+
+/// Resolve `run --image REF` to an image, pulling it as `policy` says. An
+/// image imported or committed under the name `REF` is used as it is,
+/// unless `policy` is `always`.
+pub fn resolve_image(
+    reference: &str,
+    policy: PullPolicy,
+    progress: impl FnMut(usize, usize, &Layer),
+) -> Result<ImageInfo> {
+    if policy != PullPolicy::Always && image::validate_image_name(reference).is_ok() {
+        if let Some(image) = image::find_image(reference)? {
+            return Ok(image);
+        }
+    }
+    let parsed: Reference = reference.parse()?;
+    let name = parsed.image_name();
+    match policy {
+        PullPolicy::Never => image::find_image(&name)?.with_context(|| {
+            format!("image {parsed} has not been pulled, and --pull never forbids pulling it")
+        }),
+        PullPolicy::Missing => match image::find_image(&name)? {
+            Some(image) => Ok(image),
+            None => pull(&parsed, &name, progress),
+        },
+        PullPolicy::Always => pull(&parsed, &name, progress),
+    }
+}
+
+/// Pull `reference` as the image `name` and return it: fetch the manifest
+/// of its tag, then, if that is an index, the manifest of this host's
+/// platform, then download its layers, checking each against its digest,
+/// and apply them in order. `progress` is told of each layer before it is
+/// downloaded, with its number and the number of layers.
+///
+/// An image of the name pulled from the same manifest is kept as it is;
+/// otherwise it is replaced, unless a container uses it.
+pub fn pull(
+    reference: &Reference,
+    name: &str,
+    mut progress: impl FnMut(usize, usize, &Layer),
+) -> Result<ImageInfo> {
+    image::validate_image_name(name)?;
+    let source = reference.to_string();
+    let mut client = Client::new(reference);
+    let (mut manifest, digest) = client.manifest(&reference.tag, None)?;
+    if let Some(image) = image::find_image(name)? {
+        if image.digest == digest && image.source.as_deref() == Some(source.as_str()) {
+            return Ok(image);
+        }
+    }
+
+    if manifest.is_index() {
+        let arch = platform_arch();
+        let Some(platform) = manifest.manifests.iter().find(|m| {
+            m.platform
+                .as_ref()
+                .is_some_and(|p| p.os == "linux" && p.architecture == arch)
+        }) else {
+            let available: Vec<String> = manifest
+                .manifests
+                .iter()
+                .filter_map(|m| m.platform.as_ref())
+                .map(|p| format!("{}/{}", p.os, p.architecture))
+                .collect();
+            bail!(
+                "{source} has no image for linux/{arch}, only for {}",
+                available.join(", ")
+            );
+        };
+        let platform_digest = platform.digest.clone();
+        manifest = client.manifest(&platform_digest, Some(&platform_digest))?.0;
+    }
+    if manifest.is_index() || manifest.layers.is_empty() {
+        bail!("the manifest of {source} has no layers");
+    }
+    for layer in &manifest.layers {
+        if layer.media_type.contains("zstd") {
+            bail!(
+                "layer {} of {source} is zstd-compressed ({}), which is not supported",
+                layer.digest,
+                layer.media_type
+            );
+        }
+        if !layer.media_type.contains("tar") {
+            bail!(
+                "layer {} of {source} has the unsupported type {}",
+                layer.digest,
+                layer.media_type
+            );
+        }
+    }
+
+    image::install_pulled(name, &source, digest, |rootfs| {
+        let blob = rootfs.with_extension("layer");
+        let count = manifest.layers.len();
+        let result = manifest
+            .layers
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, layer)| {
+                progress(i + 1, count, layer);
+                client.download(layer, &blob)?;
+                let file = File::open(&blob)
+                    .with_context(|| format!("failed to open {}", blob.display()))?;
+                image::apply_layer(file, rootfs)
+                    .with_context(|| format!("failed to apply layer {} of {source}", layer.digest))
+            });
+        let _ = fs::remove_file(&blob);
+        result
+    })
+}
+
+/// A client of the registry API for one repository, with the bearer token
+/// the registry asked for, if any.
+struct Client {
+    agent: ureq::Agent,
+    base: String,
+    repository: String,
+    token: Option<String>,
+}
+
+impl Client {
+    /// A client for the repository of `reference`. HTTPS_PROXY, HTTP_PROXY
+    /// and ALL_PROXY are honored unless NO_PROXY names the registry.
+    fn new(reference: &Reference) -> Self {
+        let host = reference.registry.split(':').next().unwrap_or_default();
+        let exempt = std::env::var("NO_PROXY")
+            .or_else(|_| std::env::var("no_proxy"))
+            .is_ok_and(|list| no_proxy(host, &list));
+        let agent = ureq::AgentBuilder::new()
+            .user_agent(concat!("craterun/", env!("CARGO_PKG_VERSION")))
+            .try_proxy_from_env(!exempt)
+            .build();
+        Self {
+            agent,
+            base: reference.api_url(),
+            repository: reference.repository.clone(),
+            token: None,
+        }
+    }
+
+    /// Fetch the manifest `tag_or_digest` and its digest, checking that
+    /// against `expected` if given.
+    fn manifest(
+        &mut self,
+        tag_or_digest: &str,
+        expected: Option<&str>,
+    ) -> Result<(Manifest, String)> {
+        let accept = [
+            OCI_INDEX,
+            DOCKER_MANIFEST_LIST,
+            OCI_MANIFEST,
+            DOCKER_MANIFEST,
+        ]
+        .join(", ");
+        let response = self.get(&format!("manifests/{tag_or_digest}"), &accept)?;
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .take(MAX_DOCUMENT_SIZE)
+            .read_to_end(&mut body)
+            .context("failed to read manifest")?;
+        let digest = image::sha256_digest(&body[..])?;
+        if let Some(expected) = expected {
+            if digest != expected {
+                bail!("digest mismatch for manifest {expected}: got {digest}");
+            }
+        }
+        let manifest: Manifest =
+            serde_json::from_slice(&body).context("failed to parse manifest")?;
+        if manifest.schema_version != 2 {
+            bail!(
+                "manifest {tag_or_digest} has schema version {}, only 2 is supported",
+                manifest.schema_version
+            );
+        }
+        Ok((manifest, digest))
+    }
+
+    /// Download the blob of `layer` to `dest`, checking its size and
+    /// digest.
+    fn download(&mut self, layer: &Layer, dest: &Path) -> Result<()> {
+        image::parse_digest(&layer.digest)
+            .with_context(|| format!("unsupported layer digest '{}'", layer.digest))?;
+        let response = self.get(&format!("blobs/{}", layer.digest), "*/*")?;
+        let mut file =
+            File::create(dest).with_context(|| format!("failed to create {}", dest.display()))?;
+        let mut writer = HashingWriter {
+            inner: &mut file,
+            hasher: Sha256::new(),
+        };
+        let size = io::copy(
+            &mut response.into_reader().take(layer.size + 1),
+            &mut writer,
+        )
+        .with_context(|| format!("failed to download layer {}", layer.digest))?;
+        let hex: String = writer
+            .hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let digest = format!("sha256:{hex}");
+        if size != layer.size || digest != layer.digest {
+            bail!(
+                "layer {} is corrupt: got {size} bytes with digest {digest}, expected {} bytes",
+                layer.digest,
+                layer.size
+            );
+        }
+        Ok(())
+    }
+
+    /// GET `path` under the repository, fetching a token the first time the
+    /// registry answers 401 Unauthorized.
+    fn get(&mut self, path: &str, accept: &str) -> Result<ureq::Response> {
+        let url = format!("{}/{}/{path}", self.base, self.repository);
+        let result = match self.request(&url, accept).call() {
+            Err(ureq::Error::Status(401, response)) if self.token.is_none() => {
+                let challenge = response.header("www-authenticate").unwrap_or_default();
+                self.token = Some(self.fetch_token(challenge)?);
+                self.request(&url, accept).call()
+            }
+            result => result,
+        };
+        match result {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(401 | 403, _)) => bail!(
+                "the registry denied access to {}: it does not exist, or needs credentials, \
+                 which are not supported",
+                self.repository
+            ),
+            Err(ureq::Error::Status(404, _)) => {
+                bail!("{url} not found: no such repository, tag or blob")
+            }
+            Err(e) => Err(e).with_context(|| format!("failed to fetch {url}")),
+        }
+    }
+
+    fn request(&self, url: &str, accept: &str) -> ureq::Request {
+        let request = self.agent.get(url).set("Accept", accept);
+        match &self.token {
+            Some(token) => request.set("Authorization", &format!("Bearer {token}")),
+            None => request,
+        }
+    }
+
+    /// Fetch an anonymous pull token from the service `challenge`, a
+    /// WWW-Authenticate header, names.
+    fn fetch_token(&self, challenge: &str) -> Result<String> {
+        let Some(params) = parse_challenge(challenge) else {
+            bail!(
+                "the registry needs credentials for {}, which are not supported",
+                self.repository
+            );
+        };
+        let param = |key: &str| {
+            params
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.as_str())
+        };
+        let realm = param("realm").context("the registry's token challenge has no realm")?;
+        let scope = match param("scope") {
+            Some(scope) => scope.to_string(),
+            None => format!("repository:{}:pull", self.repository),
+        };
+        let mut request = self.agent.get(realm).query("scope", &scope);
+        if let Some(service) = param("service") {
+            request = request.query("service", service);
+        }
+        let mut body = Vec::new();
+        request
+            .call()
+            .with_context(|| format!("failed to fetch a token from {realm}"))?
+            .into_reader()
+            .take(MAX_DOCUMENT_SIZE)
+            .read_to_end(&mut body)
+            .context("failed to read token")?;
+        let response: TokenResponse =
+            serde_json::from_slice(&body).context("failed to parse token response")?;
+        response
+            .token
+            .or(response.access_token)
+            .with_context(|| format!("the token response of {realm} has no token"))
+    }
+}
+
+/// Writes through to `inner`, hashing what it writes.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The parameters of a `Bearer` WWW-Authenticate challenge, e.g.
+/// `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`,
+/// or `None` for another scheme.
+fn parse_challenge(header: &str) -> Option<Vec<(String, String)>> {
+    let (scheme, rest) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    let mut params = Vec::new();
+    let mut rest = rest.trim_start();
+    while let Some((key, after)) = rest.split_once('=') {
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => after.split_at(after.find(',').unwrap_or(after.len())),
+        };
+        params.push((key.trim().to_string(), value.to_string()));
+        rest = after.trim_start_matches([',', ' ']);
+    }
+    Some(params)
+}
+
+/// Whether `host` is `localhost` or a loopback address, with or without a
+/// port.
+fn is_loopback(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name == "localhost"
+        || name
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Whether `list`, a comma-separated NO_PROXY list of hosts, domain
+/// suffixes and `*`, exempts `host` from the proxy.
+fn no_proxy(host: &str, list: &str) -> bool {
+    list.split(',').any(|entry| {
+        let entry = entry.trim().trim_start_matches('.');
+        let entry = entry.split(':').next().unwrap_or_default();
+        entry == "*"
+            || (!entry.is_empty() && (host == entry || host.ends_with(&format!(".{entry}"))))
+    })
+}
+
+/// This host's architecture as OCI platforms name it.
+fn platform_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        "powerpc64" => "ppc64le",
+        "loongarch64" => "loong64",
+        arch => arch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::core::state::tests::with_tmp_home;
+
+    /// A tar archive of `files`, gzip-compressed if `gzip`. Each file is a
+    /// path and its contents, or `None` for a directory.
+    fn layer(files: &[(&str, Option<&str>)], gzip: bool) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            match data {
+                Some(data) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_size(data.len() as u64);
+                }
+                None => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_size(0);
+                }
+            }
+            header.set_mode(0o755);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            header.set_cksum();
+            let data = data.unwrap_or_default().as_bytes();
+            builder.append_data(&mut header, path, data).unwrap();
+        }
+        let tar = builder.into_inner().unwrap();
+        if !gzip {
+            return tar;
+        }
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&tar).unwrap();
+        gz.finish().unwrap()
+    }
+
+    fn digest(data: &[u8]) -> String {
+        image::sha256_digest(data).unwrap()
+    }
+
+    /// Serve `docs`, by path, over HTTP on a loopback port like a registry
+    /// that hands out anonymous tokens: a request without the token is
+    /// answered 401 with a challenge naming `/token`. Returns the port.
+    fn fake_registry(docs: Vec<(String, Vec<u8>)>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut lines = BufReader::new(&stream).lines().map_while(|l| l.ok());
+                let request = lines.next().unwrap_or_default();
+                let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+                let authorized = lines
+                    .take_while(|line| !line.is_empty())
+                    .any(|line| line == "Authorization: Bearer t0ken");
+                let path = path.split('?').next().unwrap_or_default();
+                let (status, extra, body) = if path == "/token" {
+                    ("200 OK", String::new(), br#"{"token":"t0ken"}"#.to_vec())
+                } else if !authorized {
+                    let challenge = format!(
+                        "WWW-Authenticate: Bearer realm=\"http://127.0.0.1:{port}/token\",\
+                         service=\"test\"\r\n"
+                    );
+                    ("401 Unauthorized", challenge, Vec::new())
+                } else {
+                    match docs.iter().find(|(p, _)| p == path) {
+                        Some((_, body)) => ("200 OK", String::new(), body.clone()),
+                        None => ("404 Not Found", String::new(), Vec::new()),
+                    }
+                };
+                let head = format!(
+                    "HTTP/1.1 {status}\r\n{extra}Content-Length: {}\r\n\
+                     Connection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(head.as_bytes());
+                let _ = stream.write_all(&body);
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn pull_applies_layers_in_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+
+        let lower = layer(
+            &[
+                ("bin/sh", Some("#!fake")),
+                ("etc/old", Some("old")),
+                ("etc/kept", Some("kept")),
+                ("data/a", Some("a")),
+                ("swap", None),
+            ],
+            true,
+        );
+        let upper = layer(
+            &[
+                ("etc/.wh.old", Some("")),
+                ("data/.wh..wh..opq", Some("")),
+                ("data/b", Some("b")),
+                ("swap", Some("now a file")),
+            ],
+            false,
+        );
+        let manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_MANIFEST,
+            "layers": [
+                {"mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+                 "digest": digest(&lower), "size": lower.len()},
+                {"mediaType": "application/vnd.oci.image.layer.v1.tar",
+                 "digest": digest(&upper), "size": upper.len()},
+            ],
+        })
+        .to_string()
+        .into_bytes();
+        let index = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_INDEX,
+            "manifests": [
+                {"mediaType": OCI_MANIFEST, "digest": format!("sha256:{}", "0".repeat(64)),
+                 "size": 1, "platform": {"os": "linux", "architecture": "none"}},
+                {"mediaType": OCI_MANIFEST, "digest": digest(&manifest),
+                 "size": manifest.len(), "platform": {"os": "linux", "architecture": platform_arch()}},
+            ],
+        })
+        .to_string()
+        .into_bytes();
+        // A manifest whose layer is not what its digest says.
+        let corrupt = serde_json::json!({
+            "schemaVersion": 2,
+            "layers": [
+                {"mediaType": "application/vnd.oci.image.layer.v1.tar",
+                 "digest": digest(b"something else"), "size": upper.len()},
+            ],
+        })
+        .to_string()
+        .into_bytes();
+        let repo = "/v2/test/app";
+        let port = fake_registry(vec![
+            (format!("{repo}/manifests/v1"), index.clone()),
+            (format!("{repo}/manifests/{}", digest(&manifest)), manifest),
+            (format!("{repo}/manifests/corrupt"), corrupt),
+            (format!("{repo}/blobs/{}", digest(&lower)), lower.clone()),
+            (format!("{repo}/blobs/{}", digest(&upper)), upper.clone()),
+            (format!("{repo}/blobs/{}", digest(b"something else")), upper),
+        ]);
+        // As root the state directory is shared, so use a unique name.
+        let name = format!("test-{}:v1", crate::core::id::generate_id());
+
+        let reference: Reference = format!("127.0.0.1:{port}/test/app:v1").parse().unwrap();
+        let mut layers = Vec::new();
+        let image = pull(&reference, &name, |i, count, layer| {
+            layers.push((i, count, layer.size));
+        })
+        .unwrap();
+        assert_eq!(layers, [(1, 2, lower.len() as u64), (2, 2, layers[1].2)]);
+        assert_eq!(image.digest, digest(&index));
+        assert_eq!(
+            image.source.as_deref(),
+            Some(reference.to_string().as_str())
+        );
+        let read = |path: &str| fs::read_to_string(image.rootfs.join(path)).ok();
+        assert_eq!(read("bin/sh").as_deref(), Some("#!fake"));
+        assert_eq!(read("etc/kept").as_deref(), Some("kept"));
+        assert_eq!(read("etc/old"), None);
+        assert_eq!(read("data/a"), None);
+        assert_eq!(read("data/b").as_deref(), Some("b"));
+        assert_eq!(read("swap").as_deref(), Some("now a file"));
+        assert!(!image.rootfs.join("etc/.wh.old").exists());
+        assert!(!image.rootfs.with_extension("layer").exists());
+
+        // The tag has not moved, so pulling again keeps the image.
+        let again = pull(&reference, &name, |_, _, _| panic!("pulled again")).unwrap();
+        assert_eq!(again.created_at, image.created_at);
+
+        let corrupt: Reference = format!("127.0.0.1:{port}/test/app:corrupt")
+            .parse()
+            .unwrap();
+        let err = pull(&corrupt, &name, |_, _, _| {}).unwrap_err();
+        assert!(format!("{err:#}").contains("is corrupt"), "{err:#}");
+        assert_eq!(image::load_image(&name).unwrap().digest, image.digest);
+
+        let missing: Reference = format!("127.0.0.1:{port}/test/app:v2").parse().unwrap();
+        let err = pull(&missing, &name, |_, _, _| {}).unwrap_err();
+        assert!(err.to_string().contains("not found"), "{err}");
+
+        image::remove_image(&name).unwrap();
+    }
+
+    #[test]
+    fn never_pulling_needs_the_image() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+        let err = resolve_image("localhost:1/no/such:image", PullPolicy::Never, |_, _, _| {})
+            .unwrap_err();
+        assert!(err.to_string().contains("--pull never"), "{err}");
+    }
+
+    #[test]
+    fn references() {
+        for (reference, full, name) in [
+            ("alpine", "docker.io/library/alpine:latest", "alpine:latest"),
+            (
+                "alpine:3.20",
+                "docker.io/library/alpine:3.20",
+                "alpine:3.20",
+            ),
+            (
+                "docker.io/library/alpine:3.20",
+                "docker.io/library/alpine:3.20",
+                "alpine:3.20",
+            ),
+            ("user/app:v1", "docker.io/user/app:v1", "app:v1"),
+            (
+                "ghcr.io/org/team/app",
+                "ghcr.io/org/team/app:latest",
+                "app:latest",
+            ),
+            (
+                "localhost:5000/app:1_0",
+                "localhost:5000/app:1_0",
+                "app:1_0",
+            ),
+        ] {
+            let parsed: Reference = reference.parse().unwrap();
+            assert_eq!(parsed.to_string(), full, "{reference}");
+            assert_eq!(parsed.image_name(), name, "{reference}");
+            assert!(image::validate_image_name(name).is_ok(), "{name}");
+        }
+        let parsed: Reference = "localhost:5000/app".parse().unwrap();
+        assert_eq!(parsed.registry, "localhost:5000");
+        assert_eq!(parsed.repository, "app");
+        for bad in [
+            "",
+            "Alpine",
+            "alpine:",
+            "alpine:-x",
+            "a//b",
+            "-a",
+            "alpine@sha256:00",
+        ] {
+            assert!(bad.parse::<Reference>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn api_urls() {
+        let url = |reference: &str| reference.parse::<Reference>().unwrap().api_url();
+        assert_eq!(url("alpine"), "https://registry-1.docker.io/v2");
+        assert_eq!(url("quay.io/x/y"), "https://quay.io/v2");
+        assert_eq!(url("localhost:5000/y"), "http://localhost:5000/v2");
+        assert_eq!(url("127.0.0.1:5000/y"), "http://127.0.0.1:5000/v2");
+        assert_eq!(url("[::1]:5000/y"), "http://[::1]:5000/v2");
+    }
+
+    #[test]
+    fn challenges() {
+        let params = parse_challenge(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/alpine:pull""#,
+        )
+        .unwrap();
+        assert_eq!(
+            params,
+            [
+                ("realm".into(), "https://auth.docker.io/token".into()),
+                ("service".into(), "registry.docker.io".into()),
+                ("scope".into(), "repository:library/alpine:pull".into()),
+            ]
+        );
+        assert_eq!(
+            parse_challenge("bearer realm=http://x/token, service=x").unwrap(),
+            [
+                ("realm".into(), "http://x/token".into()),
+                ("service".into(), "x".into())
+            ]
+        );
+        assert!(parse_challenge(r#"Basic realm="x""#).is_none());
+    }
+
+    #[test]
+    fn no_proxy_lists() {
+        assert!(no_proxy("quay.io", "localhost, quay.io"));
+        assert!(no_proxy("cdn.quay.io", ".quay.io"));
+        assert!(no_proxy("anything", "*"));
+        assert!(!no_proxy("notquay.io", "quay.io"));
+        assert!(!no_proxy("quay.io", ""));
+    }
+}
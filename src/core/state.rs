@@ -1,16 +1,60 @@
 use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
+use nix::fcntl::{Flock, FlockArg};
+use serde::Deserialize;
 
-use super::model::{ContainerMeta, ContainerStatus};
+use super::error::NewerSchemaError;
+use super::id;
+use super::model::{
+    ContainerFilter, ContainerMeta, ContainerStatus, NamespaceKind, NamespaceMode,
+    EXIT_CODE_UNKNOWN, META_SCHEMA_VERSION,
+};
+use crate::util;
 
 /// Name of the per-container metadata file.
 const META_FILE: &str = "metadata.json";
+/// Name of the per-container file locked by [`lock_meta`].
+const META_LOCK_FILE: &str = "metadata.lock";
+/// How long `run` may take to write a new container's metadata before its
+/// directory is considered broken.
+const SETUP_GRACE: Duration = Duration::from_secs(60);
+/// Most threads `load_metas` and `disk_usages` work with.
+const LOAD_THREADS: usize = 8;
+/// Container reference [`resolve_id`] resolves to the most recently
+/// created container. Not hex, so it cannot be mistaken for an ID prefix.
+pub const LATEST: &str = "latest";
+/// Longest accepted container name.
+const MAX_NAME_LEN: usize = 64;
 /// Name of the stdout log file.
 pub const STDOUT_LOG: &str = "stdout.log";
 /// Name of the stderr log file.
 pub const STDERR_LOG: &str = "stderr.log";
+/// Name of the per-container overlay directory (holding `upper/`, `work/`
+/// and `merged/`) for overlay-backed containers.
+const OVERLAY_DIR: &str = "overlay";
+/// Name of the per-container ext4 image holding the writable layer of a
+/// container with `--storage-driver loop`.
+const STORAGE_IMAGE: &str = "overlay.img";
+/// Name of the per-container directory the rootfs is bind-mounted onto
+/// before `pivot_root`.
+const ROOTFS_MOUNT_DIR: &str = "rootfs";
+/// Name of the per-container file bind-mounted over the rootfs's
+/// `/etc/hostname`.
+pub const HOSTNAME_FILE: &str = "hostname";
+/// Name of the file holding the container's exit code once it has exited.
+pub const EXIT_CODE_FILE: &str = "exit_code";
+/// Name of the file holding the signal that killed the container's init, if
+/// any, as written by [`signal_record`].
+const SIGNAL_FILE: &str = "signal";
+/// Name of the API socket of a `--net slirp` container's `slirp4netns`.
+const SLIRP_SOCKET: &str = "slirp.sock";
+/// Name of the file `slirp4netns` logs to.
+pub const SLIRP_LOG: &str = "slirp.log";
 
 /// Return the base state directory.
 ///
@@ -36,34 +80,69 @@ pub fn container_dir(id: &str) -> Result<PathBuf> {
 /// Ensure the base state directory exists.
 pub fn ensure_state_dir() -> Result<PathBuf> {
     let dir = state_dir()?;
-    fs::create_dir_all(&dir)
-        .with_context(|| format!("failed to create state directory {}", dir.display()))?;
+    util::fs::ensure_dir(&dir).context("failed to create state directory")?;
     Ok(dir)
 }
 
 /// Save container metadata to disk.
 pub fn save_meta(meta: &ContainerMeta) -> Result<()> {
-    let dir = container_dir(&meta.id)?;
-    fs::create_dir_all(&dir)
-        .with_context(|| format!("failed to create container directory {}", dir.display()))?;
-
-    let path = dir.join(META_FILE);
+    let path = container_dir(&meta.id)?.join(META_FILE);
     let json = serde_json::to_string_pretty(meta).context("failed to serialize metadata")?;
-    fs::write(&path, json)
-        .with_context(|| format!("failed to write metadata to {}", path.display()))?;
-    Ok(())
+    util::fs::write_file_atomic(&path, &json).context("failed to save container metadata")
+}
+
+/// Lock the metadata of container `id` until the returned guard is dropped,
+/// waiting for any other holder. Taken around loading, changing and saving
+/// the metadata where another process may change it at the same time, such
+/// as the container's monitor recording its exit.
+pub fn lock_meta(id: &str) -> Result<Flock<fs::File>> {
+    let path = container_dir(id)?.join(META_LOCK_FILE);
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    Flock::lock(file, FlockArg::LockExclusive)
+        .map_err(|(_, errno)| errno)
+        .with_context(|| format!("failed to lock {}", path.display()))
+}
+
+/// The version of a metadata document, read on its own first so that one
+/// written by a newer craterun is refused before it fails to parse.
+#[derive(Deserialize)]
+struct SchemaHeader {
+    #[serde(default)]
+    schema_version: u32,
 }
 
 /// Load container metadata from disk.
 pub fn load_meta(id: &str) -> Result<ContainerMeta> {
     let path = container_dir(id)?.join(META_FILE);
-    let data = fs::read_to_string(&path)
-        .with_context(|| format!("failed to read metadata from {}", path.display()))?;
-    let meta: ContainerMeta =
-        serde_json::from_str(&data).context("failed to parse container metadata")?;
+    let data = util::fs::read_to_string(&path)?;
+    let parse_error = || format!("failed to parse container metadata {}", path.display());
+    let header: SchemaHeader = serde_json::from_str(&data).with_context(parse_error)?;
+    if header.schema_version > META_SCHEMA_VERSION {
+        return Err(NewerSchemaError {
+            id: id.to_string(),
+            version: header.schema_version,
+            supported: META_SCHEMA_VERSION,
+        }
+        .into());
+    }
+    let mut meta: ContainerMeta = serde_json::from_str(&data).with_context(parse_error)?;
+    migrate_meta(&mut meta);
     Ok(meta)
 }
 
+/// Upgrade metadata written by an older craterun to the current schema, in
+/// memory. The upgraded form is persisted on the next `save_meta`.
+fn migrate_meta(meta: &mut ContainerMeta) {
+    // v0 -> v1: only `schema_version` was added; serde defaults cover it.
+    if meta.schema_version < META_SCHEMA_VERSION {
+        meta.schema_version = META_SCHEMA_VERSION;
+    }
+}
+
 /// List all container IDs in the state directory.
 pub fn list_containers() -> Result<Vec<String>> {
     let dir = match state_dir() {
@@ -79,22 +158,119 @@ pub fn list_containers() -> Result<Vec<String>> {
         fs::read_dir(&dir).with_context(|| format!("failed to read {}", dir.display()))?
     {
         let entry = entry?;
-        if entry.path().join(META_FILE).exists() {
-            if let Some(name) = entry.file_name().to_str() {
-                ids.push(name.to_string());
-            }
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        // A directory named like a container ID is one even without
+        // metadata: it is being set up, or broken.
+        let is_id = name.len() == id::ID_LEN && id::validate_id_prefix(&name);
+        if entry.path().join(META_FILE).exists() || (is_id && entry.path().is_dir()) {
+            ids.push(name);
         }
     }
     ids.sort();
     Ok(ids)
 }
 
-/// Resolve a potentially abbreviated container ID to a full ID.
+/// Load the metadata of each of `ids`, in order, spreading the work over up
+/// to [`LOAD_THREADS`] threads.
+pub fn load_metas(ids: &[String]) -> Vec<Result<ContainerMeta>> {
+    map_parallel(ids, load_meta)
+}
+
+/// Apply `f` to each of `ids`, in order, on up to [`LOAD_THREADS`] threads.
+fn map_parallel<T: Send>(ids: &[String], f: impl Fn(&str) -> T + Sync) -> Vec<T> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get().min(LOAD_THREADS));
+    let chunk_len = ids.len().div_ceil(threads).max(1);
+    let f = &f;
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = ids
+            .chunks(chunk_len)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(|id| f(id)).collect::<Vec<_>>()))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("state worker panicked"))
+            .collect()
+    })
+}
+
+/// Whether container `id` has no metadata yet because `run` is still
+/// setting it up, rather than because it is broken: its directory lacks
+/// the metadata file and is younger than [`SETUP_GRACE`].
+pub fn is_starting(id: &str) -> bool {
+    let Ok(dir) = container_dir(id) else {
+        return false;
+    };
+    if dir.join(META_FILE).exists() {
+        return false;
+    }
+    fs::metadata(&dir)
+        .and_then(|meta| meta.modified())
+        .is_ok_and(|modified| modified.elapsed().map_or(true, |age| age < SETUP_GRACE))
+}
+
+/// Check that `name` is usable as a container name: 1-64 characters of
+/// lowercase letters, digits, `.`, `_` and `-`, starting with a letter or
+/// digit. Names made of hex digits only would read as ID prefixes, and
+/// [`LATEST`] is taken, so both are refused.
+pub fn validate_container_name(name: &str) -> Result<()> {
+    let valid = name.len() <= MAX_NAME_LEN
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-".contains(c));
+    if !valid {
+        bail!(
+            "invalid container name '{name}': expected up to {MAX_NAME_LEN} lowercase letters, \
+             digits, '.', '_' or '-', starting with a letter or digit"
+        );
+    }
+    if name.chars().all(|c| c.is_ascii_hexdigit()) || name == LATEST {
+        bail!("invalid container name '{name}': it would read as a container ID or '{LATEST}'");
+    }
+    Ok(())
+}
+
+/// Return the ID of the container named `name`, if any, passing over those
+/// whose metadata cannot be loaded.
+pub fn find_named(name: &str) -> Result<Option<String>> {
+    let ids = list_containers()?;
+    Ok(load_metas(&ids)
+        .into_iter()
+        .filter_map(Result::ok)
+        .find(|meta| meta.name.as_deref() == Some(name))
+        .map(|meta| meta.id))
+}
+
+/// Resolve a potentially abbreviated container ID, a container name, or
+/// [`LATEST`], to a full ID.
 ///
-/// If `prefix` matches exactly one container, return that container's full ID.
-/// If multiple match, return an error listing the ambiguous matches.
+/// If `prefix` is a full container ID, return it even if other IDs share the
+/// prefix. Otherwise, if it matches exactly one container, return that
+/// container's full ID. If multiple match, return an error listing the
+/// ambiguous matches. Names cannot be mistaken for prefixes, see
+/// [`validate_container_name`].
 pub fn resolve_id(prefix: &str) -> Result<String> {
+    if prefix == LATEST {
+        return latest_id();
+    }
+    if !id::validate_id_prefix(prefix) {
+        if validate_container_name(prefix).is_ok() {
+            return find_named(prefix)?
+                .with_context(|| format!("no container found with name '{prefix}'"));
+        }
+        bail!(
+            "invalid container ID '{prefix}': expected 1-{} lowercase hex characters or a name",
+            id::ID_LEN
+        );
+    }
+
     let all = list_containers()?;
+    if all.iter().any(|id| id == prefix) {
+        return Ok(prefix.to_string());
+    }
+
     let matches: Vec<&String> = all.iter().filter(|id| id.starts_with(prefix)).collect();
 
     match matches.len() {
@@ -110,6 +286,18 @@ pub fn resolve_id(prefix: &str) -> Result<String> {
     }
 }
 
+/// Return the ID of the container with the newest `created_at`, passing
+/// over those whose metadata cannot be loaded.
+fn latest_id() -> Result<String> {
+    let ids = list_containers()?;
+    load_metas(&ids)
+        .into_iter()
+        .filter_map(Result::ok)
+        .max_by_key(|meta| meta.created_at)
+        .map(|meta| meta.id)
+        .context("no containers exist for 'latest' to refer to")
+}
+
 /// Remove the state directory for a container.
 pub fn remove_container_dir(id: &str) -> Result<()> {
     let dir = container_dir(id)?;
@@ -129,6 +317,135 @@ pub fn log_path(id: &str, name: &str) -> Result<PathBuf> {
     Ok(container_dir(id)?.join(name))
 }
 
+/// Return the overlay directory for a container. It only exists for
+/// overlay-backed containers.
+pub fn overlay_dir(id: &str) -> Result<PathBuf> {
+    Ok(container_dir(id)?.join(OVERLAY_DIR))
+}
+
+/// Return the ext4 image a `--storage-driver loop` container's writable
+/// layer is kept in, mounted at its overlay directory.
+pub fn storage_image(id: &str) -> Result<PathBuf> {
+    Ok(container_dir(id)?.join(STORAGE_IMAGE))
+}
+
+/// Return the container's own `/etc/hostname` file.
+pub fn hostname_file(id: &str) -> Result<PathBuf> {
+    Ok(container_dir(id)?.join(HOSTNAME_FILE))
+}
+
+/// Return the path of the API socket of a container's `slirp4netns`.
+pub fn slirp_socket(id: &str) -> Result<PathBuf> {
+    Ok(container_dir(id)?.join(SLIRP_SOCKET))
+}
+
+/// Return the staging mount point for a container's rootfs.
+pub fn rootfs_mount_dir(id: &str) -> Result<PathBuf> {
+    Ok(container_dir(id)?.join(ROOTFS_MOUNT_DIR))
+}
+
+/// Disk space used by a container's state directory, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// All files in the directory, including the ones below.
+    pub total: u64,
+    /// Log files: the command's stdout and stderr, exec session output and
+    /// the slirp4netns log.
+    pub logs: u64,
+    /// The overlay of an image container, holding its writable layer, or
+    /// the image it is kept in with `--storage-driver loop`.
+    pub writable: u64,
+    /// Entries that could not be read for lack of permission, and so are
+    /// not counted.
+    pub unreadable: u64,
+}
+
+/// Disk usage of container `id`'s state directory: its logs, overlay upper
+/// directory and other records. Mounts inside it, such as the staged rootfs
+/// or a mounted image, are not descended into; so a writable layer in a
+/// tmpfs is not counted, and one in an ext4 image is counted by the blocks
+/// the image has allocated.
+pub fn disk_usage(id: &str) -> Result<DiskUsage> {
+    let dir = container_dir(id)?;
+    let dev = fs::symlink_metadata(&dir)
+        .with_context(|| format!("failed to stat {}", dir.display()))?
+        .dev();
+    let walk = UsageWalk {
+        dev,
+        skip: dir.join(ROOTFS_MOUNT_DIR),
+        overlay: dir.join(OVERLAY_DIR),
+        image: dir.join(STORAGE_IMAGE),
+    };
+    let mut usage = DiskUsage::default();
+    walk.add(&dir, false, &mut usage)?;
+    Ok(usage)
+}
+
+/// The disk usage of each of `ids`, in order, spreading the work over up
+/// to [`LOAD_THREADS`] threads.
+pub fn disk_usages(ids: &[String]) -> Vec<Result<DiskUsage>> {
+    map_parallel(ids, disk_usage)
+}
+
+/// How [`disk_usage`] walks a container directory.
+struct UsageWalk {
+    /// Device of the directory; other filesystems mounted in it are skipped.
+    dev: u64,
+    /// The staged rootfs, which is skipped.
+    skip: PathBuf,
+    /// Everything under it counts as [`DiskUsage::writable`].
+    overlay: PathBuf,
+    /// A sparse file whose allocated blocks count as
+    /// [`DiskUsage::writable`].
+    image: PathBuf,
+}
+
+impl UsageWalk {
+    /// Add the sizes of the files under `dir` to `usage`. Entries that
+    /// vanish during the walk are ignored, and unreadable ones counted.
+    fn add(&self, dir: &Path, in_overlay: bool, usage: &mut DiskUsage) -> Result<()> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                usage.unreadable += 1;
+                return Ok(());
+            }
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let meta = match fs::symlink_metadata(&path) {
+                Ok(meta) => meta,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    usage.unreadable += 1;
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to stat {}", path.display()))
+                }
+            };
+            if meta.is_dir() {
+                if meta.dev() == self.dev && path != self.skip {
+                    self.add(&path, in_overlay || path == self.overlay, usage)?;
+                }
+            } else if path == self.image {
+                usage.total += meta.blocks() * 512;
+                usage.writable += meta.blocks() * 512;
+            } else if meta.is_file() {
+                usage.total += meta.len();
+                if in_overlay {
+                    usage.writable += meta.len();
+                } else if path.extension().is_some_and(|ext| ext == "log") {
+                    usage.logs += meta.len();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Check whether a PID is alive on the host.
 pub fn pid_alive(pid: u32) -> bool {
     if pid == 0 {
@@ -137,49 +454,222 @@ pub fn pid_alive(pid: u32) -> bool {
     Path::new(&format!("/proc/{pid}")).exists()
 }
 
+/// Record a container's exit code in its exit-code file.
+pub fn write_exit_code(id: &str, code: i32) -> Result<()> {
+    let path = container_dir(id)?.join(EXIT_CODE_FILE);
+    util::fs::write_file(&path, &code.to_string())
+}
+
+/// Return the path of a container's signal file.
+pub fn signal_file(id: &str) -> Result<PathBuf> {
+    Ok(container_dir(id)?.join(SIGNAL_FILE))
+}
+
+/// Format a terminating signal for the signal file: its name (e.g.
+/// `SIGSEGV`), followed by ` core` if a core was dumped.
+pub fn signal_record(signal: &str, core_dumped: bool) -> String {
+    if core_dumped {
+        format!("{signal} core\n")
+    } else {
+        format!("{signal}\n")
+    }
+}
+
+/// Read a container's signal file: the signal that killed its init and
+/// whether a core was dumped. `None` if the file is missing or empty.
+pub fn read_signal(id: &str) -> Result<Option<(String, bool)>> {
+    let path = signal_file(id)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let record = util::fs::read_to_string(&path)?;
+    let mut parts = record.split_whitespace();
+    Ok(parts
+        .next()
+        .map(|signal| (signal.to_string(), parts.next() == Some("core"))))
+}
+
+/// Read a container's exit-code file, if it exists and is well-formed.
+pub fn read_exit_code(id: &str) -> Result<Option<i32>> {
+    let path = container_dir(id)?.join(EXIT_CODE_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(util::fs::read_to_string(&path)?.trim().parse().ok())
+}
+
 /// Refresh the status field of metadata based on whether the PID is still alive.
-/// Returns `true` if the status was changed and saved.
+/// Returns `true` if the status was changed.
+///
+/// A container found dead may be having its exit recorded by its monitor
+/// at the same time, so `meta` is then replaced by the metadata saved under
+/// [`lock_meta`], updated and saved again unless the monitor got there
+/// first.
 pub fn refresh_status(meta: &mut ContainerMeta) -> Result<bool> {
+    if meta.status != ContainerStatus::Running || pid_alive(meta.pid) {
+        return Ok(false);
+    }
+    let _lock = lock_meta(&meta.id)?;
+    let mut saved = load_meta(&meta.id)?;
+    if update_status(&mut saved)? {
+        save_meta(&saved)?;
+    }
+    *meta = saved;
+    Ok(true)
+}
+
+/// Like [`refresh_status`], but only in memory. Returns `true` if the
+/// status was changed.
+///
+/// A container found dead here was not reaped by craterun, so its exit code
+/// is taken from the exit-code file if one was written, and is otherwise set
+/// to [`EXIT_CODE_UNKNOWN`].
+pub fn update_status(meta: &mut ContainerMeta) -> Result<bool> {
     if meta.status == ContainerStatus::Running && !pid_alive(meta.pid) {
         meta.status = ContainerStatus::Stopped;
-        save_meta(meta)?;
+        if meta.exit_code.is_none() {
+            meta.exit_code = Some(read_exit_code(&meta.id)?.unwrap_or(EXIT_CODE_UNKNOWN));
+        }
+        if meta.signal.is_none() {
+            if let Some((signal, core_dumped)) = read_signal(&meta.id)? {
+                meta.signal = Some(signal);
+                meta.core_dumped = core_dumped;
+            }
+        }
         return Ok(true);
     }
     Ok(false)
 }
 
+/// IDs of the containers matching every one of `filters`, by their
+/// refreshed status, sorted. Containers whose metadata cannot be loaded
+/// match no filter.
+pub fn filter_containers(filters: &[ContainerFilter]) -> Result<Vec<String>> {
+    let ids = list_containers()?;
+    let mut matches = Vec::new();
+    for mut meta in load_metas(&ids).into_iter().filter_map(Result::ok) {
+        refresh_status(&mut meta)?;
+        if filters.iter().all(|filter| filter.matches(&meta)) {
+            matches.push(meta.id);
+        }
+    }
+    Ok(matches)
+}
+
+/// Find the running containers that share a namespace of container `id`,
+/// with the kind of namespace each one shares.
+pub fn namespace_dependents(id: &str) -> Result<Vec<(String, NamespaceKind)>> {
+    let owner = NamespaceMode::Container(id.to_string());
+    let mut dependents = Vec::new();
+    for other in list_containers()? {
+        let Ok(mut meta) = load_meta(&other) else {
+            continue;
+        };
+        refresh_status(&mut meta)?;
+        if meta.status != ContainerStatus::Running {
+            continue;
+        }
+        for kind in NamespaceKind::ALL {
+            if *meta.namespace(kind) == owner {
+                dependents.push((other.clone(), kind));
+            }
+        }
+    }
+    Ok(dependents)
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
-    use crate::core::model::{ContainerMeta, ContainerStatus};
+    use crate::core::model::{
+        CgroupNsMode, ContainerMeta, ContainerStatus, MountPropagation, NamespaceMode,
+        META_SCHEMA_VERSION,
+    };
     use chrono::Utc;
     use std::env;
+    use std::sync::{Mutex, MutexGuard};
 
-    /// Helper: set HOME to a temp directory so state goes there.
-    fn with_tmp_home(dir: &Path) {
+    /// Serializes tests that point HOME at their own temp directory.
+    static HOME_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Helper: set HOME to a temp directory so state goes there. Hold the
+    /// returned guard for the duration of the test.
+    pub(crate) fn with_tmp_home(dir: &Path) -> MutexGuard<'static, ()> {
+        let guard = HOME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
         env::set_var("HOME", dir.to_str().unwrap());
+        guard
     }
 
-    fn sample_meta(id: &str) -> ContainerMeta {
+    pub(crate) fn sample_meta(id: &str) -> ContainerMeta {
         ContainerMeta {
+            schema_version: META_SCHEMA_VERSION,
             id: id.into(),
+            name: None,
+            service: None,
             rootfs: "/tmp/rootfs".into(),
             cmd: vec!["/bin/sh".into()],
             pid: 0,
             exit_code: None,
             created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
             status: ContainerStatus::Stopped,
             hostname: "craterun".into(),
             memory_limit: None,
+            memory_limit_spec: None,
+            memory_swappiness: None,
             cpu_limit: None,
             pids_limit: None,
+            pids_limit_hits: 0,
+            cpuset_cpus: None,
+            cpuset_mems: None,
+            ulimits: Vec::new(),
+            oom_score_adj: None,
+            umask: None,
+            timezone: None,
+            devices: Vec::new(),
+            tmpfs: Vec::new(),
+            shm_size: None,
+            mount_propagation: MountPropagation::Private,
+            proc_opts: Default::default(),
+            keyring: Default::default(),
+            userns: Default::default(),
+            id_mappings: None,
+            cgroup_ns: Default::default(),
+            loop_device: None,
+            storage: None,
+            sysctls: Vec::new(),
+            env: Vec::new(),
+            env_host: false,
+            user: None,
+            domainname: None,
+            net: NamespaceMode::Private,
+            pid_ns: NamespaceMode::Private,
+            ipc: NamespaceMode::Private,
+            uts: NamespaceMode::Private,
+            network: None,
+            ip_address: None,
+            mac_address: None,
+            ports: Vec::new(),
+            slirp_pid: None,
+            stop_signal: None,
+            init_path: None,
+            argv0: None,
+            no_pivot: false,
+            allow_chroot_fallback: false,
+            no_preflight: false,
+            attach_stdin: false,
+            signal: None,
+            core_dumped: false,
+            health: None,
+            invocation: Vec::new(),
         }
     }
 
     #[test]
     fn save_and_load_meta() {
         let tmp = tempfile::tempdir().unwrap();
-        with_tmp_home(tmp.path());
+        let _home = with_tmp_home(tmp.path());
 
         let meta = sample_meta("aabbccdd11223344");
         save_meta(&meta).unwrap();
@@ -191,9 +681,11 @@ mod tests {
     #[test]
     fn list_and_resolve_containers() {
         let tmp = tempfile::tempdir().unwrap();
-        with_tmp_home(tmp.path());
+        let _home = with_tmp_home(tmp.path());
 
-        save_meta(&sample_meta("aabbccdd11223344")).unwrap();
+        let mut newest = sample_meta("aabbccdd11223344");
+        newest.created_at += chrono::Duration::hours(1);
+        save_meta(&newest).unwrap();
         save_meta(&sample_meta("aabbccdd55667788")).unwrap();
         save_meta(&sample_meta("11223344aabbccdd")).unwrap();
 
@@ -211,14 +703,60 @@ mod tests {
         // Ambiguous prefix
         assert!(resolve_id("aabb").is_err());
 
+        // Names resolve to their container.
+        let mut named = sample_meta("5eed0000aaaa0001");
+        named.name = Some("shop_web".into());
+        save_meta(&named).unwrap();
+        assert_eq!(resolve_id("shop_web").unwrap(), "5eed0000aaaa0001");
+        let err = resolve_id("shop_db").unwrap_err().to_string();
+        assert!(err.contains("no container found with name"), "{err}");
+        remove_container_dir("5eed0000aaaa0001").unwrap();
+
+        // Syntactically invalid prefixes are rejected with a targeted error.
+        for bad in ["AABB", "../etc", "aabbccdd112233440"] {
+            let err = resolve_id(bad).unwrap_err().to_string();
+            assert!(err.contains("invalid container ID"), "{bad}: {err}");
+        }
+
         // No match
         assert!(resolve_id("ffff").is_err());
+
+        assert_eq!(resolve_id(LATEST).unwrap(), "aabbccdd11223344");
+    }
+
+    #[test]
+    fn container_names() {
+        for name in ["shop_web", "web-1", "0.web", "latest2"] {
+            assert!(validate_container_name(name).is_ok(), "{name}");
+        }
+        let bad = ["", "Web", "_web", "web/1", "beef", "c0ffee1234567890ab"];
+        for name in bad.into_iter().chain([LATEST]) {
+            assert!(validate_container_name(name).is_err(), "{name}");
+        }
+        assert!(validate_container_name(&"w".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn list_containers_without_metadata() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+
+        // Named like a container: listed, and starting until it is a
+        // minute old.
+        let id = "feedface00000000";
+        fs::create_dir_all(container_dir(id).unwrap()).unwrap();
+        let listed = list_containers().unwrap();
+        let starting = is_starting(id);
+        remove_container_dir(id).unwrap();
+        assert!(listed.contains(&id.to_string()));
+        assert!(starting);
+        assert!(!listed.iter().any(|id| id == "images" || id == "networks"));
     }
 
     #[test]
     fn remove_container() {
         let tmp = tempfile::tempdir().unwrap();
-        with_tmp_home(tmp.path());
+        let _home = with_tmp_home(tmp.path());
 
         save_meta(&sample_meta("deadbeef12345678")).unwrap();
         assert!(list_containers().unwrap().contains(&"deadbeef12345678".to_string()));
@@ -226,4 +764,205 @@ mod tests {
         remove_container_dir("deadbeef12345678").unwrap();
         assert!(!list_containers().unwrap().contains(&"deadbeef12345678".to_string()));
     }
+
+    #[test]
+    fn disk_usage_skips_the_staged_rootfs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+
+        let id = "deadbeef87654321";
+        save_meta(&sample_meta(id)).unwrap();
+        let dir = container_dir(id).unwrap();
+        let meta_size = fs::metadata(dir.join(META_FILE)).unwrap().len();
+        fs::write(dir.join(STDOUT_LOG), [0; 100]).unwrap();
+        fs::create_dir_all(dir.join("overlay/upper/etc")).unwrap();
+        fs::write(dir.join("overlay/upper/etc/hosts"), [0; 20]).unwrap();
+        fs::create_dir_all(dir.join(ROOTFS_MOUNT_DIR)).unwrap();
+        fs::write(dir.join(ROOTFS_MOUNT_DIR).join("big"), [0; 1000]).unwrap();
+
+        let usage = disk_usage(id);
+        remove_container_dir(id).unwrap();
+        let usage = usage.unwrap();
+        assert_eq!(usage.total, meta_size + 120);
+        assert_eq!((usage.logs, usage.writable), (100, 20));
+    }
+
+    #[test]
+    fn refresh_reads_exit_code_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+
+        let mut meta = sample_meta("feedface00000001");
+        meta.status = ContainerStatus::Running;
+        save_meta(&meta).unwrap();
+        write_exit_code(&meta.id, 3).unwrap();
+
+        assert!(refresh_status(&mut meta).unwrap());
+        assert_eq!(meta.status, ContainerStatus::Stopped);
+        assert_eq!(meta.exit_code, Some(3));
+        assert_eq!(load_meta(&meta.id).unwrap().exit_code, Some(3));
+
+        remove_container_dir(&meta.id).unwrap();
+    }
+
+    #[test]
+    fn refresh_reads_signal_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+
+        let mut meta = sample_meta("feedface00000003");
+        meta.status = ContainerStatus::Running;
+        save_meta(&meta).unwrap();
+        write_exit_code(&meta.id, 139).unwrap();
+        fs::write(signal_file(&meta.id).unwrap(), signal_record("SIGSEGV", true)).unwrap();
+
+        assert!(refresh_status(&mut meta).unwrap());
+        assert_eq!(meta.exit_code, Some(139));
+        assert_eq!(meta.signal.as_deref(), Some("SIGSEGV"));
+        assert!(meta.core_dumped);
+
+        // An empty file means the container was not killed by a signal.
+        fs::write(signal_file(&meta.id).unwrap(), "").unwrap();
+        assert_eq!(read_signal(&meta.id).unwrap(), None);
+
+        remove_container_dir(&meta.id).unwrap();
+    }
+
+    #[test]
+    fn finds_namespace_dependents() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+
+        let mut meta = sample_meta("feedface00000004");
+        meta.status = ContainerStatus::Running;
+        meta.pid = std::process::id();
+        meta.net = NamespaceMode::Container("feedface00000005".into());
+        meta.uts = NamespaceMode::Container("feedface00000005".into());
+        save_meta(&meta).unwrap();
+
+        let dependents = namespace_dependents("feedface00000005").unwrap();
+        assert_eq!(
+            dependents,
+            [
+                (meta.id.clone(), NamespaceKind::Net),
+                (meta.id.clone(), NamespaceKind::Uts)
+            ]
+        );
+        assert!(namespace_dependents("feedface00000006").unwrap().is_empty());
+
+        remove_container_dir(&meta.id).unwrap();
+    }
+
+    #[test]
+    fn refresh_without_exit_code_file_uses_sentinel() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+
+        let mut meta = sample_meta("feedface00000002");
+        meta.status = ContainerStatus::Running;
+        save_meta(&meta).unwrap();
+
+        assert!(refresh_status(&mut meta).unwrap());
+        assert_eq!(meta.status, ContainerStatus::Stopped);
+        assert_eq!(meta.exit_code, Some(EXIT_CODE_UNKNOWN));
+
+        remove_container_dir(&meta.id).unwrap();
+    }
+
+    #[test]
+    fn resolve_exact_match_amid_prefix_collision() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+
+        // A short (legacy/hand-made) ID that is also a prefix of another ID.
+        save_meta(&sample_meta("cafe")).unwrap();
+        save_meta(&sample_meta("cafebabe00000000")).unwrap();
+
+        assert_eq!(resolve_id("cafe").unwrap(), "cafe");
+        assert_eq!(resolve_id("cafeb").unwrap(), "cafebabe00000000");
+        assert!(resolve_id("caf").is_err());
+
+        remove_container_dir("cafe").unwrap();
+        remove_container_dir("cafebabe00000000").unwrap();
+    }
+
+    #[test]
+    fn load_v0_metadata_with_defaults() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+
+        // Metadata as written before `schema_version` existed.
+        let id = "0123456789abcdef";
+        let dir = container_dir(id).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(META_FILE),
+            r#"{
+                "id": "0123456789abcdef",
+                "rootfs": "/tmp/rootfs",
+                "cmd": ["/bin/sh"],
+                "pid": 0,
+                "exit_code": 0,
+                "created_at": "2025-01-01T00:00:00Z",
+                "status": "stopped",
+                "hostname": "craterun",
+                "memory_limit": null,
+                "cpu_limit": null,
+                "pids_limit": null
+            }"#,
+        )
+        .unwrap();
+
+        let meta = load_meta(id).unwrap();
+        assert_eq!(meta.schema_version, META_SCHEMA_VERSION);
+        assert_eq!(meta.status, ContainerStatus::Stopped);
+        assert_eq!(meta.exit_code, Some(0));
+
+        remove_container_dir(id).unwrap();
+    }
+
+    #[test]
+    fn load_v1_metadata_fixture() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+
+        let fixture = include_str!("../../tests/fixtures/metadata_v1.json");
+        let id = "0123456789abcdef";
+        let dir = container_dir(id).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(META_FILE), fixture).unwrap();
+
+        let meta = load_meta(id);
+        remove_container_dir(id).unwrap();
+        let meta = meta.unwrap();
+        assert_eq!(meta.signal.as_deref(), Some("SIGSEGV"));
+        assert_eq!(meta.ports.len(), 1);
+        // Containers of v1 before cgroup namespaces shared the host's.
+        assert_eq!(meta.cgroup_ns, CgroupNsMode::Host);
+
+        // Every field survives a load and save unchanged.
+        let saved = serde_json::to_value(&meta).unwrap();
+        let expected: serde_json::Value = serde_json::from_str(fixture).unwrap();
+        assert_eq!(saved, expected);
+    }
+
+    #[test]
+    fn refuse_metadata_from_newer_versions() {
+        let tmp = tempfile::tempdir().unwrap();
+        let _home = with_tmp_home(tmp.path());
+
+        let id = "0123456789abcdee";
+        let dir = container_dir(id).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        let version = META_SCHEMA_VERSION + 1;
+        fs::write(
+            dir.join(META_FILE),
+            format!(r#"{{"schema_version": {version}, "id": "{id}", "cgroup": {{}}}}"#),
+        )
+        .unwrap();
+
+        let err = load_meta(id).unwrap_err().to_string();
+        remove_container_dir(id).unwrap();
+        assert!(err.contains("created by a newer craterun"), "{err}");
+    }
 }
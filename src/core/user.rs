@@ -0,0 +1,204 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use super::model::{ContainerUser, UserSpec};
+use super::validate;
+
+/// An `/etc/passwd` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PasswdEntry {
+    name: String,
+    uid: u32,
+    gid: u32,
+    home: String,
+}
+
+/// An `/etc/group` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct GroupEntry {
+    name: String,
+    gid: u32,
+    members: Vec<String>,
+}
+
+/// Resolve `spec` against the `/etc/passwd` and `/etc/group` of `rootfs`.
+///
+/// The container's own NSS is not usable before exec, so the files are
+/// parsed here. A numeric user need not have a passwd entry; it then runs
+/// with GID 0 (or the given group) and no supplementary groups. Names must
+/// exist.
+pub fn resolve_user(rootfs: &Path, spec: &UserSpec) -> Result<ContainerUser> {
+    let passwd = read_db(rootfs, "/etc/passwd", parse_passwd)?;
+    let groups = read_db(rootfs, "/etc/group", parse_group)?;
+
+    let (uid, entry) = match spec.user.parse::<u32>() {
+        Ok(uid) => (uid, passwd.into_iter().find(|e| e.uid == uid)),
+        Err(_) => match passwd.into_iter().find(|e| e.name == spec.user) {
+            Some(entry) => (entry.uid, Some(entry)),
+            None => bail!(
+                "--user: no user '{}' in the rootfs's /etc/passwd",
+                spec.user
+            ),
+        },
+    };
+
+    let gid = match &spec.group {
+        Some(group) => match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => match groups.iter().find(|g| g.name == *group) {
+                Some(g) => g.gid,
+                None => bail!("--user: no group '{group}' in the rootfs's /etc/group"),
+            },
+        },
+        None => entry.as_ref().map_or(0, |e| e.gid),
+    };
+
+    let mut supplementary = vec![gid];
+    if let Some(entry) = &entry {
+        supplementary.extend(
+            groups
+                .iter()
+                .filter(|g| g.members.contains(&entry.name))
+                .map(|g| g.gid),
+        );
+    }
+    supplementary.sort_unstable();
+    supplementary.dedup();
+
+    Ok(ContainerUser {
+        uid,
+        gid,
+        groups: supplementary,
+        name: entry.as_ref().map(|e| e.name.clone()),
+        home: entry.map(|e| e.home),
+    })
+}
+
+/// Read and parse a database file of the rootfs; a missing file is empty.
+fn read_db<T>(rootfs: &Path, path: &str, parse: fn(&str) -> Vec<T>) -> Result<Vec<T>> {
+    let host_path = validate::resolve_in_rootfs(rootfs, Path::new(path))?;
+    match fs::read_to_string(&host_path) {
+        Ok(data) => Ok(parse(&data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("failed to read {path} in rootfs")),
+    }
+}
+
+/// Parse `name:password:uid:gid:gecos:home:shell` lines, skipping malformed
+/// ones.
+fn parse_passwd(data: &str) -> Vec<PasswdEntry> {
+    data.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 6 {
+                return None;
+            }
+            Some(PasswdEntry {
+                name: fields[0].to_string(),
+                uid: fields[2].parse().ok()?,
+                gid: fields[3].parse().ok()?,
+                home: fields[5].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parse `name:password:gid:member,member` lines, skipping malformed ones.
+fn parse_group(data: &str) -> Vec<GroupEntry> {
+    data.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 4 {
+                return None;
+            }
+            Some(GroupEntry {
+                name: fields[0].to_string(),
+                gid: fields[2].parse().ok()?,
+                members: fields[3]
+                    .split(',')
+                    .filter(|m| !m.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rootfs() -> tempfile::TempDir {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(tmp.path().join("etc")).unwrap();
+        fs::write(
+            tmp.path().join("etc/passwd"),
+            "root:x:0:0:root:/root:/bin/ash\n\
+             nobody:x:65534:65534:nobody:/:/sbin/nologin\n\
+             app:x:1000:1000::/home/app:/bin/sh\n\
+             broken line\n",
+        )
+        .unwrap();
+        fs::write(
+            tmp.path().join("etc/group"),
+            "root:x:0:root\nwheel:x:10:root,app\nnogroup:x:65534:\napp:x:1000:\naudio:x:18:app\n",
+        )
+        .unwrap();
+        tmp
+    }
+
+    fn resolve(rootfs: &Path, spec: &str) -> Result<ContainerUser> {
+        resolve_user(rootfs, &spec.parse().unwrap())
+    }
+
+    #[test]
+    fn resolves_names_and_supplementary_groups() {
+        let rootfs = rootfs();
+        let app = resolve(rootfs.path(), "app").unwrap();
+        assert_eq!((app.uid, app.gid), (1000, 1000));
+        assert_eq!(app.groups, [10, 18, 1000]);
+        assert_eq!(app.name.as_deref(), Some("app"));
+        assert_eq!(app.home.as_deref(), Some("/home/app"));
+
+        let nobody = resolve(rootfs.path(), "nobody:nogroup").unwrap();
+        assert_eq!((nobody.uid, nobody.gid), (65534, 65534));
+        assert_eq!(nobody.groups, [65534]);
+    }
+
+    #[test]
+    fn resolves_numeric_ids() {
+        let rootfs = rootfs();
+        let app = resolve(rootfs.path(), "1000:18").unwrap();
+        assert_eq!((app.uid, app.gid), (1000, 18));
+        assert_eq!(app.groups, [10, 18]);
+
+        // Unknown UIDs are allowed and get GID 0 unless one is given.
+        let anon = resolve(rootfs.path(), "4242").unwrap();
+        assert_eq!((anon.uid, anon.gid, anon.name), (4242, 0, None));
+        assert_eq!(resolve(rootfs.path(), "4242:4242").unwrap().groups, [4242]);
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        let rootfs = rootfs();
+        let err = resolve(rootfs.path(), "ghost").unwrap_err();
+        assert!(err.to_string().contains("no user 'ghost'"), "{err}");
+        let err = resolve(rootfs.path(), "app:ghosts").unwrap_err();
+        assert!(err.to_string().contains("no group 'ghosts'"), "{err}");
+
+        let empty = tempfile::tempdir().unwrap();
+        assert!(resolve(empty.path(), "app").is_err());
+        assert_eq!(resolve(empty.path(), "7:7").unwrap().uid, 7);
+    }
+
+    #[test]
+    fn parses_user_spec() {
+        for bad in ["", ":", "app:", ":app", "a:b:c", "../x"] {
+            assert!(bad.parse::<UserSpec>().is_err(), "{bad}");
+        }
+        let spec: UserSpec = "app:wheel".parse().unwrap();
+        assert_eq!(spec.to_string(), "app:wheel");
+    }
+}
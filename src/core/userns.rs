@@ -0,0 +1,256 @@
+use std::fmt;
+use std::fs;
+use std::io;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Subordinate UID ranges, as used by `newuidmap(1)`.
+pub const SUBUID_FILE: &str = "/etc/subuid";
+/// Subordinate GID ranges, as used by `newgidmap(1)`.
+pub const SUBGID_FILE: &str = "/etc/subgid";
+
+/// A range of subordinate IDs delegated to a user, from a
+/// `NAME_OR_ID:START:COUNT` line of `/etc/subuid` or `/etc/subgid`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubIdRange {
+    pub start: u32,
+    pub count: u32,
+}
+
+/// One line of a `uid_map` or `gid_map`: `count` IDs from `inside` in the
+/// container are `outside` onwards on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdMap {
+    pub inside: u32,
+    pub outside: u32,
+    pub count: u32,
+}
+
+impl fmt::Display for IdMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.inside, self.outside, self.count)
+    }
+}
+
+/// The UID and GID maps of a container's user namespace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdMappings {
+    pub uid_map: Vec<IdMap>,
+    pub gid_map: Vec<IdMap>,
+}
+
+/// The user running craterun, whose IDs `--userns keep-id` keeps: the one
+/// that invoked `sudo` if run through it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    pub uid: u32,
+    pub gid: u32,
+    /// Host user name, if the UID has a passwd entry.
+    pub name: Option<String>,
+}
+
+impl Caller {
+    pub fn current() -> Result<Self> {
+        let sudo_id = |var: &str| -> Result<Option<u32>> {
+            match std::env::var(var) {
+                Ok(v) => v
+                    .parse()
+                    .map(Some)
+                    .with_context(|| format!("invalid {var} '{v}'")),
+                Err(_) => Ok(None),
+            }
+        };
+        let (uid, gid) = match (sudo_id("SUDO_UID")?, sudo_id("SUDO_GID")?) {
+            (Some(uid), Some(gid)) => (uid, gid),
+            _ => (
+                nix::unistd::getuid().as_raw(),
+                nix::unistd::getgid().as_raw(),
+            ),
+        };
+        let name = nix::unistd::User::from_uid(uid.into())
+            .ok()
+            .flatten()
+            .map(|u| u.name);
+        Ok(Self { uid, gid, name })
+    }
+}
+
+/// The ranges in `contents`, a subordinate ID file, delegated to the user
+/// `name` or the numeric `id`, in file order.
+pub fn parse_subids(contents: &str, name: Option<&str>, id: u32) -> Vec<SubIdRange> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().split(':');
+            let owner = fields.next()?;
+            let start = fields.next()?.parse().ok()?;
+            let count = fields.next()?.parse().ok()?;
+            if fields.next().is_some() || count == 0 {
+                return None;
+            }
+            let matches = Some(owner) == name || owner.parse() == Ok(id);
+            matches.then_some(SubIdRange { start, count })
+        })
+        .collect()
+}
+
+/// The first range of `path` delegated to `name` or `id`.
+fn read_subid_range(path: &str, name: Option<&str>, id: u32) -> Result<SubIdRange> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {path}")),
+    };
+    let owner = name.map_or_else(|| id.to_string(), str::to_string);
+    parse_subids(&contents, name, id)
+        .into_iter()
+        .next()
+        .with_context(|| {
+            format!(
+                "--userns keep-id: no range for {owner} in {path}; add one, e.g. with \
+                 `usermod --add-subuids 100000-165535 --add-subgids 100000-165535 {owner}`"
+            )
+        })
+}
+
+/// Map `id` to itself and the IDs below and above it to `range`: `id`
+/// IDs from `range` for `0..id`, the rest from `id + 1` on.
+pub fn keep_id_map(id: u32, range: SubIdRange) -> Result<Vec<IdMap>> {
+    if range.count < id {
+        bail!(
+            "--userns keep-id: {} subordinate IDs cannot cover IDs 0 to {}; a range of at \
+             least {id} is needed",
+            range.count,
+            id.saturating_sub(1)
+        );
+    }
+    // IDs are 32 bits, inside the namespace and out.
+    let Some(above) = id.checked_add(1) else {
+        bail!("--userns keep-id: ID {id} is the largest ID, which cannot be mapped");
+    };
+    if range.start.checked_add(range.count).is_none() {
+        bail!(
+            "--userns keep-id: {} subordinate IDs from {} run past the largest ID",
+            range.count,
+            range.start
+        );
+    }
+    let mut map = Vec::new();
+    if id > 0 {
+        map.push(IdMap {
+            inside: 0,
+            outside: range.start,
+            count: id,
+        });
+    }
+    map.push(IdMap {
+        inside: id,
+        outside: id,
+        count: 1,
+    });
+    if range.count > id {
+        map.push(IdMap {
+            inside: above,
+            outside: range.start + id,
+            count: range.count - id,
+        });
+    }
+    Ok(map)
+}
+
+/// The `--userns keep-id` mappings for `caller`, from its ranges in
+/// [`SUBUID_FILE`] and [`SUBGID_FILE`].
+pub fn keep_id(caller: &Caller) -> Result<IdMappings> {
+    if caller.uid == 0 {
+        bail!(
+            "--userns keep-id keeps the IDs of the user running craterun, which is root; \
+             run it through sudo from that user"
+        );
+    }
+    let name = caller.name.as_deref();
+    let uids = read_subid_range(SUBUID_FILE, name, caller.uid)?;
+    let gids = read_subid_range(SUBGID_FILE, name, caller.uid)?;
+    Ok(IdMappings {
+        uid_map: keep_id_map(caller.uid, uids)?,
+        gid_map: keep_id_map(caller.gid, gids)?,
+    })
+}
+
+/// Render `map` in the format of `/proc/<pid>/uid_map`.
+pub fn map_file_contents(map: &[IdMap]) -> String {
+    map.iter().map(|line| format!("{line}\n")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subid_lines() {
+        let contents = "\
+alice:100000:65536
+1001:200000:65536
+bob:300000:0
+# comment
+alice:400000:10
+carol:bad:10
+";
+        assert_eq!(
+            parse_subids(contents, Some("alice"), 1000),
+            [
+                SubIdRange {
+                    start: 100000,
+                    count: 65536
+                },
+                SubIdRange {
+                    start: 400000,
+                    count: 10
+                },
+            ]
+        );
+        // Lines may name the user by UID.
+        assert_eq!(
+            parse_subids(contents, Some("dave"), 1001),
+            [SubIdRange {
+                start: 200000,
+                count: 65536
+            }]
+        );
+        assert!(parse_subids(contents, Some("bob"), 1002).is_empty());
+        assert!(parse_subids(contents, None, 1003).is_empty());
+    }
+
+    #[test]
+    fn keep_id_maps_the_caller_to_itself() {
+        let range = SubIdRange {
+            start: 100000,
+            count: 65536,
+        };
+        let map = keep_id_map(1000, range).unwrap();
+        assert_eq!(
+            map_file_contents(&map),
+            "0 100000 1000\n1000 1000 1\n1001 101000 64536\n"
+        );
+
+        // Exactly enough IDs below the caller leaves none above.
+        let small = SubIdRange {
+            start: 100000,
+            count: 1000,
+        };
+        assert_eq!(keep_id_map(1000, small).unwrap().len(), 2);
+        assert!(keep_id_map(1001, small).is_err());
+
+        // Mappings past the largest ID are refused rather than wrapped.
+        let high = SubIdRange {
+            start: u32::MAX - 10,
+            count: 65536,
+        };
+        assert!(keep_id_map(1000, high).is_err());
+        let all = SubIdRange {
+            start: 0,
+            count: u32::MAX,
+        };
+        assert!(keep_id_map(u32::MAX, all).is_err());
+    }
+}
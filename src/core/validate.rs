@@ -0,0 +1,635 @@
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use super::error::{LaunchError, PreflightError};
+use super::model::{
+    ContainerConfig, DeviceMapping, ImageFormat, MountPropagation, NamespaceMode, PortMapping,
+    Timezone, UsernsMode, DEFAULT_PATH,
+};
+use super::{elf, ipam, network};
+
+/// Maximum length of a hostname or domain name (see `HOST_NAME_MAX` in
+/// `limits.h`).
+const HOSTNAME_MAX: usize = 64;
+/// Maximum number of symlinks followed while resolving a path inside the rootfs.
+const MAX_SYMLINKS: usize = 40;
+/// Magic at the start of a squashfs image.
+const SQUASHFS_MAGIC: &[u8; 4] = b"hsqs";
+/// Offset and (little-endian) magic of an EROFS superblock.
+const EROFS_MAGIC_OFFSET: usize = 1024;
+const EROFS_MAGIC: u32 = 0xE0F5_E1E2;
+/// Directories searched for commands named like a missing one.
+const SUGGESTION_DIRS: [&str; 2] = ["/bin", "/usr/bin"];
+/// Maximum number of commands suggested for a missing one.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Validate a container configuration without touching the kernel.
+///
+/// Returns the canonical rootfs path on success. The command itself is not
+/// checked here; see [`validate_command`].
+pub fn validate_config(config: &ContainerConfig) -> Result<PathBuf> {
+    let rootfs = validate_rootfs(&config.rootfs)?;
+    validate_hostname(&config.hostname)?;
+    if let Some(domainname) = &config.domainname {
+        validate_domainname(domainname)?;
+    }
+    for device in &config.devices {
+        validate_device(device)?;
+    }
+    if let Some(timezone) = &config.timezone {
+        validate_timezone(timezone)?;
+    }
+    if let Some(storage) = &config.storage {
+        if image_format(&rootfs)?.is_none() {
+            bail!(
+                "--storage-size needs a squashfs or EROFS image rootfs: a directory rootfs is \
+                 changed in place, without a writable layer to limit"
+            );
+        }
+        if storage.size == 0 {
+            bail!("--storage-size must be more than 0");
+        }
+    }
+    if config.shm_size == Some(0) {
+        bail!("--shm-size must be more than 0");
+    }
+    // Changes to a shared namespace would leak out of the container.
+    for sysctl in &config.sysctls {
+        let kind = sysctl.namespace();
+        let mode = config.namespace(kind);
+        if !mode.is_new() {
+            bail!(
+                "--sysctl {}: the {kind} namespace is shared (--{kind} {mode})",
+                sysctl.key
+            );
+        }
+    }
+    if config.domainname.is_some() && !config.uts.is_new() {
+        bail!("--domainname cannot be set with --uts {}", config.uts);
+    }
+    if config.net != NamespaceMode::Bridge {
+        if config.ip.is_some() {
+            bail!("--ip needs --net bridge or --network");
+        }
+        if config.mac_address.is_some() {
+            bail!("--mac-address needs --net bridge or --network");
+        }
+    }
+    if config.net == NamespaceMode::Bridge {
+        let name = config
+            .network
+            .as_deref()
+            .unwrap_or(network::DEFAULT_NETWORK);
+        let network = network::load_network(name)?;
+        if let Some(ip) = config.ip {
+            ipam::check_address(&network, ip)?;
+        }
+    }
+    if !config.ports.is_empty() && config.net != NamespaceMode::Slirp {
+        bail!("--publish needs --net slirp");
+    }
+    for (i, port) in config.ports.iter().enumerate() {
+        let taken = |other: &PortMapping| {
+            (other.host_addr, other.host_port, other.protocol)
+                == (port.host_addr, port.host_port, port.protocol)
+        };
+        if config.ports[..i].iter().any(taken) {
+            bail!("--publish {port}: the host port is already published");
+        }
+    }
+    if config.no_pivot && config.mount_propagation == MountPropagation::Shared {
+        bail!("--mount-propagation shared cannot be used with --no-pivot");
+    }
+    // The container's root cannot create device nodes, nor configure the
+    // host's end of a veth pair, from inside its own user namespace.
+    if config.userns == UsernsMode::KeepId {
+        if !config.devices.is_empty() {
+            bail!("--device cannot be used with --userns keep-id");
+        }
+        if config.net == NamespaceMode::Bridge {
+            bail!("--userns keep-id cannot be used with --net bridge or --network");
+        }
+    }
+    if let Some(argv0) = &config.argv0 {
+        if argv0.is_empty() || argv0.contains('\0') {
+            bail!("invalid argv0: '{argv0}'");
+        }
+    }
+    if let Some(init) = &config.init_path {
+        if !init.starts_with('/') || init.contains('\0') {
+            bail!("invalid --init-path '{init}': expected an absolute path");
+        }
+    }
+    Ok(rootfs)
+}
+
+/// Validate that the rootfs path is safe and looks correct. It must be either
+/// a directory holding a filesystem root or a squashfs/EROFS image file.
+///
+/// Returns the canonicalized path.
+pub fn validate_rootfs(rootfs: &str) -> Result<PathBuf> {
+    if rootfs.is_empty() {
+        bail!("rootfs path must not be empty");
+    }
+
+    let path = Path::new(rootfs);
+
+    // Refuse dangerous paths.
+    let canon = if path.exists() {
+        fs::canonicalize(path)
+            .with_context(|| format!("cannot canonicalize rootfs path '{rootfs}'"))?
+    } else {
+        bail!("rootfs path '{rootfs}' does not exist");
+    };
+
+    if canon == Path::new("/") {
+        bail!("refusing to use '/' as rootfs — this would destroy the host");
+    }
+
+    if canon.is_file() {
+        if image_format(&canon)?.is_none() {
+            bail!(
+                "rootfs '{}' is a file but not a squashfs or EROFS image",
+                canon.display()
+            );
+        }
+        return Ok(canon);
+    }
+
+    // Check it looks like a filesystem root (has bin/ or usr/ or etc/).
+    let looks_like_root = canon.join("bin").is_dir()
+        || canon.join("usr").is_dir()
+        || canon.join("etc").is_dir();
+
+    if !looks_like_root {
+        bail!(
+            "rootfs '{}' does not look like a filesystem root (no bin/, usr/, or etc/ found). \
+             Please provide a path to an extracted rootfs (e.g. Alpine minirootfs).",
+            canon.display()
+        );
+    }
+
+    Ok(canon)
+}
+
+/// Detect the format of a rootfs image file from its magic. Returns `None`
+/// for directories and files in any other format.
+pub fn image_format(path: &Path) -> Result<Option<ImageFormat>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let mut header = Vec::with_capacity(EROFS_MAGIC_OFFSET + 4);
+    fs::File::open(path)
+        .and_then(|f| f.take(header.capacity() as u64).read_to_end(&mut header))
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+    if header.starts_with(SQUASHFS_MAGIC) {
+        return Ok(Some(ImageFormat::Squashfs));
+    }
+    let erofs = header.get(EROFS_MAGIC_OFFSET..EROFS_MAGIC_OFFSET + 4);
+    if erofs == Some(&EROFS_MAGIC.to_le_bytes()[..]) {
+        return Ok(Some(ImageFormat::Erofs));
+    }
+    Ok(None)
+}
+
+/// Check that the container command exists inside the rootfs and is
+/// executable, and that the interpreter it needs (ELF loader or `#!`
+/// program), if any, exists too. A missing command is reported with the
+/// commands named most like it. Failures are [`PreflightError`]s, exiting
+/// with the code the container would have.
+///
+/// A command containing a `/` is resolved against the container's `/`; a
+/// bare name is looked up in [`DEFAULT_PATH`], as [`find_command`] does.
+pub fn validate_command(rootfs: &Path, cmd: &[String]) -> Result<()> {
+    let Some(program) = cmd.first() else {
+        bail!("no command specified");
+    };
+    if program.is_empty() || program.contains('\0') {
+        bail!("invalid command: '{program}'");
+    }
+
+    let Some(found) = find_command(rootfs, program)? else {
+        let hint = match suggest_commands(rootfs, program)?.as_slice() {
+            [] => String::new(),
+            [one] => format!("; did you mean {one}?"),
+            many => format!("; did you mean one of {}?", many.join(", ")),
+        };
+        return Err(PreflightError {
+            error: LaunchError::NotFound(program.clone()),
+            message: format!(
+                "command '{program}' not found in rootfs {}{hint}",
+                rootfs.display()
+            ),
+        }
+        .into());
+    };
+    let resolved = resolve_in_rootfs(rootfs, Path::new(&found))?;
+    let mode = fs::metadata(&resolved)
+        .with_context(|| format!("failed to stat {}", resolved.display()))?
+        .permissions()
+        .mode();
+    if mode & 0o111 == 0 {
+        let mode = mode & 0o7777;
+        return Err(PreflightError {
+            error: LaunchError::NotExecutable(program.clone()),
+            message: format!("command '{program}' is not executable (mode {mode:o})"),
+        }
+        .into());
+    }
+    if let Some(interpreter) = elf::read_interpreter(&resolved)? {
+        if !resolve_in_rootfs(rootfs, Path::new(&interpreter))?.is_file() {
+            return Err(PreflightError {
+                message: format!("interpreter {interpreter} of '{program}' not found in rootfs"),
+                error: LaunchError::InterpreterNotFound {
+                    command: program.clone(),
+                    interpreter,
+                },
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Find `program` as `execvp(3)` would in the container with root `rootfs`:
+/// a path containing a `/` is used as is, a bare name is looked up in each
+/// directory of [`DEFAULT_PATH`]. Returns the path as seen inside the
+/// container, if it is a regular file.
+pub fn find_command(rootfs: &Path, program: &str) -> Result<Option<String>> {
+    let candidates: Vec<String> = if program.contains('/') {
+        vec![program.to_string()]
+    } else {
+        DEFAULT_PATH
+            .split(':')
+            .map(|dir| format!("{dir}/{program}"))
+            .collect()
+    };
+    for candidate in candidates {
+        if resolve_in_rootfs(rootfs, Path::new(&candidate))?.is_file() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// Commands in `/bin` and `/usr/bin` of the rootfs whose names are close to
+/// that of `program`: within a small edit distance, or starting with it.
+/// Returned closest first, at most [`MAX_SUGGESTIONS`], spelt like
+/// `program`: as a full path if it has a `/`, else as a bare name.
+fn suggest_commands(rootfs: &Path, program: &str) -> Result<Vec<String>> {
+    let name = program.rsplit('/').next().unwrap_or(program);
+    if name.is_empty() {
+        return Ok(Vec::new());
+    }
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut seen_dirs = Vec::new();
+    let mut matches = Vec::new();
+    for dir in SUGGESTION_DIRS {
+        // /bin is often a symlink to /usr/bin; list each directory once.
+        let resolved = resolve_in_rootfs(rootfs, Path::new(dir))?;
+        if seen_dirs.contains(&resolved) {
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(&resolved) else {
+            seen_dirs.push(resolved);
+            continue;
+        };
+        for entry in entries.flatten() {
+            let candidate = entry.file_name().to_string_lossy().into_owned();
+            let distance = edit_distance(name, &candidate);
+            if distance <= max_distance || candidate.starts_with(name) {
+                let spelt = if program.contains('/') {
+                    format!("{dir}/{candidate}")
+                } else {
+                    candidate
+                };
+                matches.push((distance, spelt));
+            }
+        }
+        seen_dirs.push(resolved);
+    }
+    matches.sort();
+    matches.dedup_by(|a, b| a.1 == b.1);
+    Ok(matches
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, spelt)| spelt)
+        .collect())
+}
+
+/// Edit distance between `a` and `b`, in characters, counting a swap of
+/// two adjacent characters, a common typo, as a single edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // d[i][j] is the distance between the first i characters of `a` and the
+    // first j of `b`.
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Check that the host has the file of a `--timezone`.
+pub fn validate_timezone(timezone: &Timezone) -> Result<()> {
+    let file = timezone.host_file();
+    if !file.is_file() {
+        match timezone {
+            Timezone::Host => bail!("--timezone host: the host has no {}", file.display()),
+            Timezone::Zone(zone) => bail!("unknown time zone '{zone}': no {}", file.display()),
+        }
+    }
+    Ok(())
+}
+
+/// Check that a `--device` host path is a character or block device.
+pub fn validate_device(device: &DeviceMapping) -> Result<()> {
+    let host = &device.host_path;
+    let meta = fs::metadata(host).with_context(|| format!("cannot access device '{host}'"))?;
+    let file_type = meta.file_type();
+    if !file_type.is_char_device() && !file_type.is_block_device() {
+        bail!("'{host}' is not a character or block device");
+    }
+    Ok(())
+}
+
+/// Validate a hostname according to RFC 1123.
+pub fn validate_hostname(name: &str) -> Result<()> {
+    validate_dns_name("hostname", name)
+}
+
+/// Validate a (NIS) domain name with the same rules as a hostname.
+pub fn validate_domainname(name: &str) -> Result<()> {
+    validate_dns_name("domainname", name)
+}
+
+/// Check `name` against RFC 1123: at most 64 characters in dot-separated
+/// labels of ASCII letters, digits and inner hyphens. `what` names the value
+/// in errors.
+fn validate_dns_name(what: &str, name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("{what} must not be empty");
+    }
+    if name.len() > HOSTNAME_MAX {
+        bail!("{what} '{name}' is longer than {HOSTNAME_MAX} characters");
+    }
+    for label in name.split('.') {
+        if label.is_empty() {
+            bail!("{what} '{name}' contains an empty label");
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            bail!("{what} '{name}' has a label starting or ending with '-'");
+        }
+        if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            bail!("{what} '{name}' may only contain ASCII letters, digits, '-' and '.'");
+        }
+    }
+    Ok(())
+}
+
+/// Parse a kernel CPU/memory-node list such as `0-3,7` into a sorted,
+/// de-duplicated list of indices.
+pub fn parse_cpu_list(list: &str) -> Result<Vec<u32>> {
+    let mut out = Vec::new();
+    for part in list.trim().split(',') {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (start, end),
+            None => (part, part),
+        };
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<u32>()
+                .with_context(|| format!("invalid CPU list '{list}': '{part}' is not a number or range"))
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start > end {
+            bail!("invalid CPU list '{list}': range '{part}' is reversed");
+        }
+        out.extend(start..=end);
+    }
+    out.sort_unstable();
+    out.dedup();
+    Ok(out)
+}
+
+/// Resolve `path` as the container would see it, following symlinks without
+/// ever escaping `rootfs`. Absolute symlink targets are re-rooted at `rootfs`.
+pub fn resolve_in_rootfs(rootfs: &Path, path: &Path) -> Result<PathBuf> {
+    let mut resolved = PathBuf::new();
+    let mut pending: Vec<PathBuf> = path
+        .components()
+        .rev()
+        .map(|c| PathBuf::from(c.as_os_str()))
+        .collect();
+    let mut links = 0;
+
+    while let Some(part) = pending.pop() {
+        match part.components().next() {
+            None | Some(Component::RootDir) | Some(Component::CurDir) => {}
+            Some(Component::ParentDir) => {
+                resolved.pop();
+            }
+            Some(Component::Prefix(_)) => bail!("unsupported path '{}'", path.display()),
+            Some(Component::Normal(name)) => {
+                let candidate = rootfs.join(&resolved).join(name);
+                match fs::read_link(&candidate) {
+                    Ok(target) => {
+                        links += 1;
+                        if links > MAX_SYMLINKS {
+                            bail!("too many levels of symlinks resolving '{}'", path.display());
+                        }
+                        if target.is_absolute() {
+                            resolved.clear();
+                        }
+                        pending.extend(
+                            target
+                                .components()
+                                .rev()
+                                .map(|c| PathBuf::from(c.as_os_str())),
+                        );
+                    }
+                    Err(_) => resolved.push(name),
+                }
+            }
+        }
+    }
+
+    Ok(rootfs.join(resolved))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn write_executable(path: &Path, contents: &str) {
+        fs::write(path, contents).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn hostname_validation() {
+        assert!(validate_hostname("craterun").is_ok());
+        assert!(validate_hostname("web-1.example").is_ok());
+        assert!(validate_hostname("").is_err());
+        assert!(validate_hostname("-bad").is_err());
+        assert!(validate_hostname("has space").is_err());
+        assert!(validate_hostname("a..b").is_err());
+        assert!(validate_hostname(&"a".repeat(65)).is_err());
+        assert!(validate_domainname("example.internal").is_ok());
+        let err = validate_domainname("bad_domain").unwrap_err();
+        assert!(err.to_string().starts_with("domainname"), "{err}");
+    }
+
+    #[test]
+    fn device_must_be_a_device_node() {
+        let device = |path: &str| DeviceMapping {
+            host_path: path.into(),
+            container_path: "/dev/x".into(),
+            permissions: "rwm".into(),
+        };
+        assert!(validate_device(&device("/dev/null")).is_ok());
+        assert!(validate_device(&device("/etc/passwd")).is_err());
+        assert!(validate_device(&device("/dev/does-not-exist")).is_err());
+    }
+
+    #[test]
+    fn detects_rootfs_images() {
+        let tmp = tempfile::tempdir().unwrap();
+        let squashfs = tmp.path().join("app.squashfs");
+        fs::write(&squashfs, b"hsqs\0\0\0\0").unwrap();
+        let mut erofs_image = vec![0u8; 2048];
+        erofs_image[1024..1028].copy_from_slice(&EROFS_MAGIC.to_le_bytes());
+        let erofs = tmp.path().join("app.erofs");
+        fs::write(&erofs, erofs_image).unwrap();
+        let other = tmp.path().join("app.tar");
+        fs::write(&other, b"not an image").unwrap();
+
+        assert_eq!(image_format(&squashfs).unwrap(), Some(ImageFormat::Squashfs));
+        assert_eq!(image_format(&erofs).unwrap(), Some(ImageFormat::Erofs));
+        assert_eq!(image_format(&other).unwrap(), None);
+        assert_eq!(image_format(tmp.path()).unwrap(), None);
+
+        assert!(validate_rootfs(squashfs.to_str().unwrap()).is_ok());
+        let err = validate_rootfs(other.to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("not a squashfs or EROFS image"), "{err}");
+    }
+
+    #[test]
+    fn cpu_list_parsing() {
+        assert_eq!(parse_cpu_list("0").unwrap(), vec![0]);
+        assert_eq!(parse_cpu_list("0-3,7").unwrap(), vec![0, 1, 2, 3, 7]);
+        assert_eq!(parse_cpu_list("3,1-2,2").unwrap(), vec![1, 2, 3]);
+        assert!(parse_cpu_list("").is_err());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("0,,1").is_err());
+        assert!(parse_cpu_list("a-b").is_err());
+    }
+
+    #[test]
+    fn resolves_absolute_symlinks_inside_rootfs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("bin")).unwrap();
+        write_executable(&root.join("bin/busybox"), "");
+        symlink("/bin/busybox", root.join("bin/sh")).unwrap();
+
+        let resolved = resolve_in_rootfs(root, Path::new("/bin/sh")).unwrap();
+        assert_eq!(resolved, root.join("bin/busybox"));
+        assert!(validate_command(root, &["/bin/sh".into()]).is_ok());
+        assert!(validate_command(root, &["/bin/bash".into()]).is_err());
+    }
+
+    #[test]
+    fn bare_commands_are_searched_in_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        fs::create_dir_all(root.join("bin")).unwrap();
+        write_executable(&root.join("bin/sh"), "");
+        write_executable(&root.join("usr/bin/env"), "");
+
+        assert_eq!(find_command(root, "sh").unwrap().as_deref(), Some("/bin/sh"));
+        assert_eq!(find_command(root, "env").unwrap().as_deref(), Some("/usr/bin/env"));
+        assert_eq!(find_command(root, "bash").unwrap(), None);
+        assert_eq!(find_command(root, "./sh").unwrap(), None);
+        assert!(validate_command(root, &["sh".into()]).is_ok());
+    }
+
+    #[test]
+    fn missing_interpreter_is_reported() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("bin")).unwrap();
+        write_executable(&root.join("bin/script"), "#!/bin/bash\necho hi\n");
+
+        let err = validate_command(root, &["/bin/script".into()]).unwrap_err();
+        assert!(err.to_string().contains("interpreter /bin/bash"), "{err}");
+
+        write_executable(&root.join("bin/bash"), "");
+        assert!(validate_command(root, &["/bin/script".into()]).is_ok());
+    }
+
+    #[test]
+    fn commands_must_be_executable() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("bin")).unwrap();
+        fs::write(root.join("bin/tool"), "").unwrap();
+
+        let err = validate_command(root, &["tool".into()]).unwrap_err();
+        assert!(err.to_string().contains("(mode 644)"), "{err}");
+    }
+
+    #[test]
+    fn missing_commands_suggest_close_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        symlink("usr/bin", root.join("bin")).unwrap();
+        for name in ["sh", "bash", "python3", "perl"] {
+            write_executable(&root.join("usr/bin").join(name), "");
+        }
+
+        let err = validate_command(root, &["/bin/hs".into()]).unwrap_err();
+        assert!(err.to_string().ends_with("did you mean /bin/sh?"), "{err}");
+        let err = validate_command(root, &["pyth".into()]).unwrap_err();
+        assert!(err.to_string().ends_with("did you mean python3?"), "{err}");
+        let err = validate_command(root, &["bsh".into()]).unwrap_err();
+        assert!(err.to_string().ends_with("one of bash, sh?"), "{err}");
+        let err = validate_command(root, &["ruby".into()]).unwrap_err();
+        assert!(!err.to_string().contains("did you mean"), "{err}");
+
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("hs", "sh"), 1);
+        assert_eq!(edit_distance("", "sh"), 2);
+    }
+
+    #[test]
+    fn resolution_does_not_escape_rootfs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let resolved = resolve_in_rootfs(root, Path::new("/../../etc/passwd")).unwrap();
+        assert_eq!(resolved, root.join("etc/passwd"));
+    }
+}
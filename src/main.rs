@@ -5,11 +5,20 @@ mod util;
 
 use std::process;
 
+use crate::core::error;
+
 fn main() {
     let args = cli::parse();
+    let json_errors = args.json_errors;
 
-    if let Err(e) = cli::commands::dispatch(args) {
-        eprintln!("craterun: {e:#}");
-        process::exit(1);
+    let result = util::logging::init(args.log_level, args.verbose)
+        .and_then(|()| cli::commands::dispatch(args));
+    if let Err(e) = result {
+        if json_errors {
+            eprintln!("{}", error::error_json(&e));
+        } else {
+            eprintln!("craterun: {e:#}");
+        }
+        process::exit(error::exit_code(&e));
     }
 }
@@ -1,28 +1,42 @@
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context, Result};
+use log::{debug, error};
+
+use crate::core::model::ResourceLimits;
+use crate::core::validate;
 
 /// The cgroup v2 unified mount point.
-const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
 /// CrateRun puts all its cgroups under this sub-hierarchy.
 const CRATERUN_PREFIX: &str = "craterun";
+/// How many times writing to `cgroup.procs` is tried before giving up.
+const ADD_PROCESS_ATTEMPTS: u32 = 5;
+/// Delay before the first retry; doubled after each one.
+const ADD_PROCESS_BACKOFF: Duration = Duration::from_millis(5);
+/// The swap limit `--memory-swappiness 0` is written to.
+const SWAP_MAX: &str = "memory.swap.max";
+/// How long `kill_cgroup` waits for the killed processes to exit.
+const KILL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Return the parent cgroup of all container cgroups
+/// (`/sys/fs/cgroup/craterun`), created by the first `run`.
+pub fn parent_cgroup() -> PathBuf {
+    Path::new(CGROUP_ROOT).join(CRATERUN_PREFIX)
+}
 
 /// Return the cgroup path for a specific container (e.g.
 /// `/sys/fs/cgroup/craterun/<container_id>`).
 pub fn cgroup_path(container_id: &str) -> PathBuf {
-    Path::new(CGROUP_ROOT)
-        .join(CRATERUN_PREFIX)
-        .join(container_id)
+    parent_cgroup().join(container_id)
 }
 
 /// Create a cgroup for the container and apply resource limits.
-pub fn setup_cgroup(
-    container_id: &str,
-    memory: Option<u64>,
-    cpu: Option<&str>,
-    pids: Option<u64>,
-) -> Result<PathBuf> {
+pub fn setup_cgroup(container_id: &str, limits: &ResourceLimits) -> Result<PathBuf> {
     let path = cgroup_path(container_id);
 
     // Ensure parent "craterun" cgroup exists
@@ -38,37 +52,224 @@ pub fn setup_cgroup(
         enable_controllers(parent)?;
     }
 
+    debug!("create cgroup {}", path.display());
     fs::create_dir_all(&path)
         .with_context(|| format!("failed to create cgroup {}", path.display()))?;
 
-    if let Some(mem) = memory {
-        write_cgroup_file(&path, "memory.max", &mem.to_string())
-            .context("failed to set memory.max")?;
+    set_limits(&path, limits)?;
+    Ok(path)
+}
+
+/// Rewrite the limits of an existing container cgroup. Limits that are not
+/// set in `limits` are left unchanged.
+///
+/// Returns a warning when the new memory limit is below the container's
+/// current usage; the kernel will reclaim memory or OOM-kill to comply.
+pub fn update_cgroup(container_id: &str, limits: &ResourceLimits) -> Result<Option<String>> {
+    let path = cgroup_path(container_id);
+    if !path.is_dir() {
+        bail!("cgroup {} does not exist", path.display());
     }
 
-    if let Some(cpu_max) = cpu {
-        write_cgroup_file(&path, "cpu.max", cpu_max).context("failed to set cpu.max")?;
+    let mut warning = None;
+    if let Some(memory) = limits.memory {
+        let current = fs::read_to_string(path.join("memory.current"))
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok());
+        if let Some(current) = current.filter(|&c| c > memory) {
+            warning = Some(format!(
+                "new memory limit {memory} bytes is below current usage of {current} bytes"
+            ));
+        }
     }
 
-    if let Some(max_pids) = pids {
-        write_cgroup_file(&path, "pids.max", &max_pids.to_string())
-            .context("failed to set pids.max")?;
+    set_limits(&path, limits)?;
+    Ok(warning)
+}
+
+/// Write the control files for `limits` into the cgroup at `path`.
+fn set_limits(path: &Path, limits: &ResourceLimits) -> Result<()> {
+    for (file, value) in cgroup_limits(limits) {
+        // The memory controller only has swap files with swap accounting.
+        if file == SWAP_MAX && !path.join(file).exists() {
+            bail!(
+                "--memory-swappiness needs {file}, which this kernel does not provide; swap \
+                 accounting is disabled (CONFIG_MEMCG_SWAP or the swapaccount=0 boot option)"
+            );
+        }
+        write_cgroup_file(path, file, &value).with_context(|| format!("failed to set {file}"))?;
     }
+    Ok(())
+}
 
-    Ok(path)
+/// Return the control files and values that `setup_cgroup` writes for
+/// `limits`.
+///
+/// cgroup v2 dropped v1's per-cgroup `memory.swappiness`. Its one value with
+/// a v2 equivalent is 0, never swapping the container's memory out, which is
+/// `memory.swap.max` 0; other values are rejected when parsed.
+pub fn cgroup_limits(limits: &ResourceLimits) -> Vec<(&'static str, String)> {
+    let mut files = Vec::new();
+    if let Some(mem) = limits.memory {
+        files.push(("memory.max", mem.to_string()));
+    }
+    if limits.memory_swappiness == Some(0) {
+        files.push((SWAP_MAX, "0".to_string()));
+    }
+    if let Some(cpu_max) = limits.cpu {
+        files.push(("cpu.max", cpu_max.to_string()));
+    }
+    if let Some(max_pids) = limits.pids {
+        files.push(("pids.max", max_pids.to_string()));
+    }
+    if let Some(cpus) = &limits.cpuset_cpus {
+        files.push(("cpuset.cpus", cpus.clone()));
+    }
+    if let Some(mems) = &limits.cpuset_mems {
+        files.push(("cpuset.mems", mems.clone()));
+    }
+    files
+}
+
+/// Check that cgroups v2 is mounted and that the controllers needed for the
+/// requested limits are available.
+pub fn check_available(limits: &ResourceLimits) -> Result<()> {
+    let controllers_file = Path::new(CGROUP_ROOT).join("cgroup.controllers");
+    let available = fs::read_to_string(&controllers_file).with_context(|| {
+        format!("cgroups v2 does not appear to be mounted at {CGROUP_ROOT}")
+    })?;
+    let available: Vec<&str> = available.split_whitespace().collect();
+
+    for (file, _) in cgroup_limits(limits) {
+        let controller = file.split('.').next().unwrap_or(file);
+        if !available.contains(&controller) {
+            bail!("cgroup controller '{controller}' is not available (needed for {file})");
+        }
+    }
+    Ok(())
+}
+
+/// Check that the CPUs and memory nodes requested with `--cpuset-cpus` and
+/// `--cpuset-mems` are online on this host.
+pub fn check_cpuset(limits: &ResourceLimits) -> Result<()> {
+    let requests = [
+        ("--cpuset-cpus", &limits.cpuset_cpus, "/sys/devices/system/cpu/online"),
+        ("--cpuset-mems", &limits.cpuset_mems, "/sys/devices/system/node/online"),
+    ];
+
+    for (flag, requested, online_file) in requests {
+        let Some(requested) = requested else {
+            continue;
+        };
+        // Hosts without NUMA support may lack the node list; let the kernel decide.
+        let Ok(online) = fs::read_to_string(online_file) else {
+            continue;
+        };
+        let online = validate::parse_cpu_list(&online)
+            .with_context(|| format!("failed to parse {online_file}"))?;
+        let missing: Vec<String> = validate::parse_cpu_list(requested)?
+            .into_iter()
+            .filter(|n| !online.contains(n))
+            .map(|n| n.to_string())
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "{flag} {requested}: {} not online on this host (online: {})",
+                missing.join(","),
+                fs::read_to_string(online_file).unwrap_or_default().trim()
+            );
+        }
+    }
+    Ok(())
 }
 
 /// Place a process into a cgroup by writing its PID to `cgroup.procs`.
+///
+/// Right after the cgroup is created the write can fail with `ENOENT` or
+/// `EBUSY` while the kernel is still setting it up or delegating
+/// controllers, so those errors are retried a few times with backoff.
 pub fn add_process(cgroup: &Path, pid: u32) -> Result<()> {
-    write_cgroup_file(cgroup, "cgroup.procs", &pid.to_string())
+    if !cgroup.exists() {
+        bail!("cgroup directory {} does not exist", cgroup.display());
+    }
+    let file = cgroup.join("cgroup.procs");
+    debug!("write '{pid}' to {}", file.display());
+    retry_transient(|| fs::write(&file, pid.to_string()))
+        .inspect_err(|e| error!("write '{pid}' to {} failed: {e:#}", file.display()))
         .with_context(|| format!("failed to add pid {pid} to cgroup {}", cgroup.display()))
 }
 
+/// Run `write`, retrying with a doubling delay while it fails with an error
+/// a cgroup that is still being set up can cause.
+fn retry_transient(mut write: impl FnMut() -> io::Result<()>) -> Result<()> {
+    let mut delay = ADD_PROCESS_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let e = match write() {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        let transient =
+            e.kind() == io::ErrorKind::NotFound || e.raw_os_error() == Some(libc::EBUSY);
+        if !transient {
+            return Err(e.into());
+        }
+        if attempt == ADD_PROCESS_ATTEMPTS {
+            return Err(e)
+                .with_context(|| format!("still failing after {ADD_PROCESS_ATTEMPTS} attempts"));
+        }
+        debug!("attempt {attempt} failed: {e}; retrying in {delay:?}");
+        thread::sleep(delay);
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// How many times a fork in the container's cgroup failed because `pids.max`
+/// was reached, from the `max` count in `pids.events`. 0 if unknown.
+pub fn pids_limit_hits(container_id: &str) -> u64 {
+    let events = fs::read_to_string(cgroup_path(container_id).join("pids.events"));
+    events
+        .ok()
+        .and_then(|events| parse_pids_events(&events))
+        .unwrap_or(0)
+}
+
+/// Parse the `max` count from the contents of a `pids.events` file.
+fn parse_pids_events(events: &str) -> Option<u64> {
+    events.lines().find_map(|line| {
+        let count = line.strip_prefix("max ")?;
+        count.trim().parse().ok()
+    })
+}
+
+/// SIGKILL every process in the container's cgroup through `cgroup.kill`,
+/// for a container whose PID is unknown, and wait up to [`KILL_TIMEOUT`]
+/// for them to be gone. A missing cgroup has nothing to kill.
+pub fn kill_cgroup(container_id: &str) -> Result<()> {
+    let path = cgroup_path(container_id);
+    if !path.exists() {
+        return Ok(());
+    }
+    debug!("kill cgroup {}", path.display());
+    fs::write(path.join("cgroup.kill"), "1")
+        .with_context(|| format!("failed to kill the processes of cgroup {}", path.display()))?;
+    let deadline = Instant::now() + KILL_TIMEOUT;
+    while Instant::now() < deadline {
+        match fs::read_to_string(path.join("cgroup.procs")) {
+            Ok(procs) if !procs.trim().is_empty() => thread::sleep(Duration::from_millis(10)),
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
 /// Remove the cgroup directory (must be empty of processes first).
 pub fn remove_cgroup(container_id: &str) -> Result<()> {
     let path = cgroup_path(container_id);
     if path.exists() {
         // The cgroup may still have zombie references; try to remove.
+        debug!("remove cgroup {}", path.display());
         fs::remove_dir(&path).with_context(|| {
             format!(
                 "failed to remove cgroup {}. Is the container still running?",
@@ -97,6 +298,7 @@ fn enable_controllers(path: &Path) -> Result<()> {
 
     if !enable_str.is_empty() {
         let subtree = path.join("cgroup.subtree_control");
+        debug!("write '{enable_str}' to {}", subtree.display());
         fs::write(&subtree, &enable_str).with_context(|| {
             format!(
                 "failed to enable controllers ({enable_str}) in {}",
@@ -114,7 +316,50 @@ fn write_cgroup_file(cgroup: &Path, filename: &str, value: &str) -> Result<()> {
     if !cgroup.exists() {
         bail!("cgroup directory {} does not exist", cgroup.display());
     }
+    debug!("write '{value}' to {}", file.display());
     fs::write(&file, value)
+        .inspect_err(|e| error!("write '{value}' to {} failed: {e}", file.display()))
         .with_context(|| format!("failed to write '{value}' to {}", file.display()))?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A writer that fails with `errno` for its first `failures` calls,
+    /// counting its calls in `calls`.
+    fn flaky(errno: i32, failures: u32, calls: &mut u32) -> impl FnMut() -> io::Result<()> + '_ {
+        move || {
+            *calls += 1;
+            if *calls <= failures {
+                Err(io::Error::from_raw_os_error(errno))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn add_process_retries_transient_errors() {
+        let mut calls = 0;
+        retry_transient(flaky(libc::ENOENT, 2, &mut calls)).unwrap();
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let err = retry_transient(flaky(libc::EBUSY, u32::MAX, &mut calls)).unwrap_err();
+        assert_eq!(calls, ADD_PROCESS_ATTEMPTS);
+        assert!(format!("{err:#}").contains("still failing after 5 attempts"), "{err:#}");
+
+        let mut calls = 0;
+        retry_transient(flaky(libc::EACCES, 1, &mut calls)).unwrap_err();
+        assert_eq!(calls, 1, "other errors are not retried");
+    }
+
+    #[test]
+    fn pids_events_max_count() {
+        assert_eq!(parse_pids_events("max 12\n"), Some(12));
+        assert_eq!(parse_pids_events("max 0\nmax.imposed 3\n"), Some(0));
+        assert_eq!(parse_pids_events(""), None);
+    }
+}
@@ -0,0 +1,450 @@
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{access, AccessFlags, ForkResult};
+
+use crate::core::model::{CgroupMode, CheckResult, CheckStatus, HostInfo};
+use crate::core::state;
+use crate::platform::linux::cgroups::{self, CGROUP_ROOT};
+use crate::platform::linux::namespaces::USERNS_CLONE_SYSCTL;
+
+/// Where systemd mounts cgroups v2, without controllers, on hosts with the
+/// hybrid hierarchy.
+const HYBRID_UNIFIED: &str = "/sys/fs/cgroup/unified";
+/// Minimum kernel version with a usable cgroup v2 cpu controller.
+const MIN_KERNEL: (u32, u32) = (4, 15);
+
+/// Run every host check and return the results in display order.
+pub fn run_all() -> Vec<CheckResult> {
+    vec![
+        check_kernel(),
+        check_cgroup_v2(),
+        check_controllers(),
+        check_cgroup_delegation(),
+        check_privileges(),
+        check_userns(),
+        check_overlayfs(),
+        check_proc_mount(),
+        check_state_dir(),
+    ]
+}
+
+/// Probe what the host offers, with the same probes as the checks.
+pub fn host_info() -> HostInfo {
+    let controllers = |path: &Path| {
+        fs::read_to_string(path)
+            .map(|list| list.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default()
+    };
+    let delegation_dir = delegation_dir();
+    HostInfo {
+        kernel: kernel_release().ok(),
+        cgroup_mode: cgroup_mode(),
+        cgroup_controllers: controllers(&Path::new(CGROUP_ROOT).join("cgroup.controllers")),
+        cgroup_delegated: controllers(&delegation_dir.join("cgroup.subtree_control")),
+        cgroup_parent: cgroups::parent_cgroup(),
+        user_namespaces: !userns_blocked(),
+        overlayfs: overlayfs_available(),
+    }
+}
+
+/// Cheap subset of checks run before every `run`. Fails with the diagnosis
+/// and remediation hint of the first failing check.
+pub fn preflight() -> Result<()> {
+    for result in [check_privileges(), check_cgroup_v2(), check_state_dir()] {
+        if result.status == CheckStatus::Fail {
+            match &result.hint {
+                Some(hint) => bail!("{}\nhint: {hint}", result.detail),
+                None => bail!("{}", result.detail),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn pass(name: &str, detail: impl Into<String>) -> CheckResult {
+    CheckResult {
+        name: name.to_string(),
+        status: CheckStatus::Pass,
+        detail: detail.into(),
+        hint: None,
+    }
+}
+
+fn fail(name: &str, status: CheckStatus, detail: impl Into<String>, hint: &str) -> CheckResult {
+    CheckResult {
+        name: name.to_string(),
+        status,
+        detail: detail.into(),
+        hint: Some(hint.to_string()),
+    }
+}
+
+fn is_root() -> bool {
+    nix::unistd::geteuid().is_root()
+}
+
+fn kernel_release() -> std::io::Result<String> {
+    fs::read_to_string("/proc/sys/kernel/osrelease").map(|release| release.trim().to_string())
+}
+
+fn check_kernel() -> CheckResult {
+    let release = match kernel_release() {
+        Ok(release) => release,
+        Err(e) => {
+            return fail(
+                "kernel",
+                CheckStatus::Fail,
+                format!("cannot read kernel release: {e}"),
+                "is /proc mounted?",
+            )
+        }
+    };
+
+    match parse_kernel_version(&release) {
+        Some(version) if version >= MIN_KERNEL => pass("kernel", format!("Linux {release}")),
+        Some(_) => fail(
+            "kernel",
+            CheckStatus::Fail,
+            format!("Linux {release} is older than {}.{}", MIN_KERNEL.0, MIN_KERNEL.1),
+            "upgrade to a kernel with full cgroup v2 support",
+        ),
+        None => fail(
+            "kernel",
+            CheckStatus::Warn,
+            format!("could not parse kernel release '{release}'"),
+            "CrateRun needs Linux 4.15 or newer",
+        ),
+    }
+}
+
+/// Parse the `major.minor` prefix of a kernel release string like `6.8.0-45-generic`.
+fn parse_kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Which cgroup hierarchy is mounted: v2 has `cgroup.controllers` at its
+/// root, v1 is mounted as filesystems of type `cgroup`.
+fn cgroup_mode() -> CgroupMode {
+    if Path::new(CGROUP_ROOT).join("cgroup.controllers").exists() {
+        CgroupMode::V2
+    } else if Path::new(HYBRID_UNIFIED)
+        .join("cgroup.controllers")
+        .exists()
+    {
+        CgroupMode::Hybrid
+    } else if fs::read_to_string("/proc/mounts").is_ok_and(|mounts| {
+        mounts
+            .lines()
+            .any(|line| line.split_whitespace().nth(2) == Some("cgroup"))
+    }) {
+        CgroupMode::V1
+    } else {
+        CgroupMode::Unmounted
+    }
+}
+
+fn check_cgroup_v2() -> CheckResult {
+    match cgroup_mode() {
+        CgroupMode::V2 => pass(
+            "cgroup-v2",
+            format!("unified hierarchy mounted at {CGROUP_ROOT}"),
+        ),
+        CgroupMode::Hybrid => fail(
+            "cgroup-v2",
+            CheckStatus::Fail,
+            format!(
+                "hybrid cgroup hierarchy: cgroups v2 is only mounted at {HYBRID_UNIFIED}, \
+                 with the controllers left to cgroups v1, which craterun does not support"
+            ),
+            "boot with systemd.unified_cgroup_hierarchy=1",
+        ),
+        CgroupMode::V1 | CgroupMode::Unmounted => fail(
+            "cgroup-v2",
+            CheckStatus::Fail,
+            format!("cgroups v2 is not mounted at {CGROUP_ROOT}"),
+            "boot with systemd.unified_cgroup_hierarchy=1 or mount cgroup2 at /sys/fs/cgroup",
+        ),
+    }
+}
+
+fn check_controllers() -> CheckResult {
+    let path = Path::new(CGROUP_ROOT).join("cgroup.controllers");
+    let Ok(available) = fs::read_to_string(&path) else {
+        return fail(
+            "cgroup-controllers",
+            CheckStatus::Fail,
+            "no cgroup v2 controllers found",
+            "mount cgroups v2 first (see cgroup-v2)",
+        );
+    };
+
+    let missing: Vec<&str> = ["memory", "cpu", "pids"]
+        .into_iter()
+        .filter(|c| !available.split_whitespace().any(|a| a == *c))
+        .collect();
+
+    if missing.is_empty() {
+        pass("cgroup-controllers", available.trim().to_string())
+    } else {
+        fail(
+            "cgroup-controllers",
+            CheckStatus::Warn,
+            format!("controllers not delegated: {}", missing.join(", ")),
+            "enable them in the parent's cgroup.subtree_control; the matching limits will fail",
+        )
+    }
+}
+
+/// The cgroup whose `cgroup.subtree_control` lists the controllers
+/// container cgroups get: craterun's parent cgroup, or until the first
+/// `run` creates it with every controller the root cgroup hands down, the
+/// root cgroup.
+fn delegation_dir() -> PathBuf {
+    let parent = cgroups::parent_cgroup();
+    if parent.exists() {
+        parent
+    } else {
+        Path::new(CGROUP_ROOT).to_path_buf()
+    }
+}
+
+/// Whether craterun can create container cgroups with the controllers it
+/// limits: they are created under its parent cgroup, which the first `run`
+/// creates with every controller the root cgroup hands down.
+fn check_cgroup_delegation() -> CheckResult {
+    const NAME: &str = "cgroup-delegation";
+    let parent = cgroups::parent_cgroup();
+    let dir = delegation_dir();
+    let subtree = dir.join("cgroup.subtree_control");
+    let Ok(delegated) = fs::read_to_string(&subtree) else {
+        return fail(
+            NAME,
+            CheckStatus::Fail,
+            format!("cannot read {}", subtree.display()),
+            "mount cgroups v2 first (see cgroup-v2)",
+        );
+    };
+    if access(&dir, AccessFlags::W_OK).is_err() {
+        return fail(
+            NAME,
+            CheckStatus::Fail,
+            format!("cannot create cgroups in {}", dir.display()),
+            "run craterun as root",
+        );
+    }
+
+    let missing: Vec<&str> = ["memory", "cpu", "pids"]
+        .into_iter()
+        .filter(|c| !delegated.split_whitespace().any(|d| d == *c))
+        .collect();
+    if missing.is_empty() {
+        pass(NAME, format!("{} can be created", parent.display()))
+    } else {
+        let enable: Vec<String> = missing.iter().map(|c| format!("+{c}")).collect();
+        fail(
+            NAME,
+            CheckStatus::Warn,
+            format!(
+                "{} does not hand {} down to child cgroups",
+                dir.display(),
+                missing.join(", ")
+            ),
+            &format!("echo '{}' > {}", enable.join(" "), subtree.display()),
+        )
+    }
+}
+
+fn check_privileges() -> CheckResult {
+    if is_root() {
+        return pass("privileges", "running as root");
+    }
+
+    let user = nix::unistd::User::from_uid(nix::unistd::getuid())
+        .ok()
+        .flatten()
+        .map(|u| u.name)
+        .unwrap_or_default();
+    let has_range = |file: &str| {
+        fs::read_to_string(file)
+            .map(|s| s.lines().any(|l| l.split(':').next() == Some(user.as_str())))
+            .unwrap_or(false)
+    };
+
+    if has_range("/etc/subuid") && has_range("/etc/subgid") {
+        fail(
+            "privileges",
+            CheckStatus::Warn,
+            format!("not root, but {user} has subuid/subgid ranges"),
+            "run with sudo; `run --userns keep-id` then keeps your own UID and GID \
+             inside the container, mapping the others to those ranges",
+        )
+    } else {
+        fail(
+            "privileges",
+            CheckStatus::Fail,
+            "not running as root and no subuid/subgid ranges configured",
+            "run craterun with sudo",
+        )
+    }
+}
+
+/// Whether the kernel blocks unprivileged user namespaces.
+fn userns_blocked() -> bool {
+    fs::read_to_string(USERNS_CLONE_SYSCTL).is_ok_and(|v| v.trim() == "0")
+}
+
+fn check_userns() -> CheckResult {
+    match userns_blocked() {
+        true => fail(
+            "user-namespaces",
+            if is_root() {
+                CheckStatus::Warn
+            } else {
+                CheckStatus::Fail
+            },
+            format!("{USERNS_CLONE_SYSCTL} is 0; unprivileged user namespaces are blocked"),
+            "sysctl -w kernel.unprivileged_userns_clone=1, or run as root",
+        ),
+        false => pass("user-namespaces", "unprivileged user namespaces allowed"),
+    }
+}
+
+fn overlayfs_available() -> bool {
+    fs::read_to_string("/proc/filesystems")
+        .map(|s| s.lines().any(|l| l.split_whitespace().last() == Some("overlay")))
+        .unwrap_or(false)
+}
+
+fn check_overlayfs() -> CheckResult {
+    if overlayfs_available() {
+        pass("overlayfs", "overlay filesystem available")
+    } else {
+        fail(
+            "overlayfs",
+            CheckStatus::Warn,
+            "overlay filesystem not listed in /proc/filesystems",
+            "modprobe overlay",
+        )
+    }
+}
+
+/// Whether a new `/proc` can be mounted, as every container does. The
+/// kernel refuses it where parts of the current `/proc` are hidden under
+/// other mounts, as inside most unprivileged containers.
+fn check_proc_mount() -> CheckResult {
+    const NAME: &str = "proc-mount";
+    if !is_root() {
+        return fail(
+            NAME,
+            CheckStatus::Warn,
+            "not checked: mounting /proc needs root",
+            "run craterun check as root",
+        );
+    }
+    match probe_proc_mount() {
+        Ok(()) => pass(NAME, "a new /proc can be mounted"),
+        Err(e) => fail(
+            NAME,
+            CheckStatus::Fail,
+            format!("cannot mount a new /proc: {e:#}"),
+            "run craterun on the host, or in a privileged container with nothing mounted \
+             over parts of /proc",
+        ),
+    }
+}
+
+/// Mount a `proc` filesystem in a child process with a mount namespace of
+/// its own, so that the mount is gone when the child exits.
+fn probe_proc_mount() -> Result<()> {
+    let target = std::env::temp_dir().join(format!("craterun-check-{}", std::process::id()));
+    fs::create_dir_all(&target)
+        .with_context(|| format!("failed to create {}", target.display()))?;
+    let result = fork_mount_proc(&target);
+    let _ = fs::remove_dir(&target);
+    result
+}
+
+fn fork_mount_proc(target: &Path) -> Result<()> {
+    // Allocate before forking; the child only makes raw syscalls.
+    let target = CString::new(target.as_os_str().as_bytes())?;
+    let root = c"/";
+    let proc = c"proc";
+
+    // SAFETY: the child only calls async-signal-safe functions before
+    // `_exit`.
+    match unsafe { nix::unistd::fork() }.context("fork failed")? {
+        ForkResult::Child => unsafe {
+            let mounted = libc::unshare(libc::CLONE_NEWNS) == 0
+                && libc::mount(
+                    std::ptr::null(),
+                    root.as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    std::ptr::null(),
+                ) == 0
+                && libc::mount(
+                    proc.as_ptr(),
+                    target.as_ptr(),
+                    proc.as_ptr(),
+                    0,
+                    std::ptr::null(),
+                ) == 0;
+            libc::_exit(if mounted { 0 } else { Errno::last_raw() })
+        },
+        ForkResult::Parent { child } => match waitpid(child, None)? {
+            WaitStatus::Exited(_, 0) => Ok(()),
+            WaitStatus::Exited(_, errno) => Err(Errno::from_raw(errno).into()),
+            status => bail!("probe process ended with {status:?}"),
+        },
+    }
+}
+
+fn check_state_dir() -> CheckResult {
+    let dir = match state::ensure_state_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            return fail(
+                "state-dir",
+                CheckStatus::Fail,
+                format!("{e:#}"),
+                "check that HOME is set and writable",
+            )
+        }
+    };
+
+    let probe = dir.join(".craterun-check");
+    match fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            pass("state-dir", format!("{} is writable", dir.display()))
+        }
+        Err(e) => fail(
+            "state-dir",
+            CheckStatus::Fail,
+            format!("{} is not writable: {e}", dir.display()),
+            "fix the permissions of the state directory",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_version_parsing() {
+        assert_eq!(parse_kernel_version("6.8.0-45-generic"), Some((6, 8)));
+        assert_eq!(parse_kernel_version("4.15.0"), Some((4, 15)));
+        assert_eq!(parse_kernel_version("5.10"), Some((5, 10)));
+        assert_eq!(parse_kernel_version("garbage"), None);
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use super::diff::{is_opaque, is_whiteout};
+
+/// Write the filesystem overlayfs would show for `upper` stacked on `lower`
+/// to `out` as a tar archive: a whiteout in `upper` hides the lower entry of
+/// its name, and an opaque directory all of the lower one's contents.
+/// Without `upper`, `lower` is archived as it is.
+///
+/// Sockets are left out, and hard links are archived as separate files.
+pub fn archive_layers(upper: Option<&Path>, lower: &Path, out: impl Write) -> Result<()> {
+    let mut builder = tar::Builder::new(out);
+    builder.follow_symlinks(false);
+    builder.mode(tar::HeaderMode::Complete);
+    append_dir(&mut builder, upper, Some(lower), Path::new(""))?;
+    builder
+        .into_inner()
+        .and_then(|mut out| out.flush())
+        .context("failed to finish archive")
+}
+
+/// Append the merged contents of the directories `upper` and `lower`, either
+/// of which may be missing, as `rel` in the archive.
+fn append_dir(
+    builder: &mut tar::Builder<impl Write>,
+    upper: Option<&Path>,
+    lower: Option<&Path>,
+    rel: &Path,
+) -> Result<()> {
+    // Upper entries replace lower ones of the same name.
+    let mut entries: BTreeMap<OsString, (PathBuf, bool)> = BTreeMap::new();
+    let lower = lower.filter(|_| !upper.is_some_and(is_opaque));
+    for (dir, is_upper) in [(lower, false), (upper, true)] {
+        let Some(dir) = dir else {
+            continue;
+        };
+        for entry in
+            fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?
+        {
+            let entry = entry?;
+            entries.insert(entry.file_name(), (entry.path(), is_upper));
+        }
+    }
+
+    for (name, (source, is_upper)) in entries {
+        let meta = fs::symlink_metadata(&source)
+            .with_context(|| format!("failed to stat {}", source.display()))?;
+        if (is_upper && is_whiteout(&meta)) || meta.file_type().is_socket() {
+            continue;
+        }
+        let path = rel.join(&name);
+        builder
+            .append_path_with_name(&source, &path)
+            .with_context(|| format!("failed to archive {}", source.display()))?;
+        if meta.is_dir() {
+            let (upper, lower) = match (is_upper, lower) {
+                (true, Some(lower)) => (Some(source.as_path()), Some(lower.join(&name))),
+                (true, None) => (Some(source.as_path()), None),
+                (false, _) => (None, Some(source.clone())),
+            };
+            // A directory in upper only merges with a directory below it.
+            let lower = lower.filter(|dir| dir.symlink_metadata().is_ok_and(|m| m.is_dir()));
+            append_dir(builder, upper, lower.as_deref(), &path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::stat::{mknod, Mode, SFlag};
+
+    /// The paths in `archive`, with a trailing `/` for directories, and the
+    /// contents of regular files.
+    fn listing(archive: &[u8]) -> Vec<(String, String)> {
+        let mut archive = tar::Archive::new(archive);
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut path = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut data = String::new();
+                if entry.header().entry_type().is_dir() {
+                    path.push('/');
+                } else {
+                    std::io::Read::read_to_string(&mut entry, &mut data).unwrap();
+                }
+                (path, data)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn archives_a_single_layer() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(tmp.path().join("etc")).unwrap();
+        fs::write(tmp.path().join("etc/hosts"), "127.0.0.1").unwrap();
+        std::os::unix::fs::symlink("/etc/hosts", tmp.path().join("hosts")).unwrap();
+
+        let mut archive = Vec::new();
+        archive_layers(None, tmp.path(), &mut archive).unwrap();
+        assert_eq!(
+            listing(&archive),
+            [
+                ("etc/".into(), "".into()),
+                ("etc/hosts".into(), "127.0.0.1".into()),
+                ("hosts".into(), "".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_upper_onto_lower() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (upper, lower) = (tmp.path().join("upper"), tmp.path().join("lower"));
+        fs::create_dir_all(lower.join("etc")).unwrap();
+        fs::write(lower.join("etc/hosts"), "old").unwrap();
+        fs::write(lower.join("etc/passwd"), "root").unwrap();
+        fs::create_dir_all(upper.join("etc")).unwrap();
+        fs::write(upper.join("etc/hosts"), "new").unwrap();
+        fs::create_dir_all(upper.join("srv")).unwrap();
+        fs::write(upper.join("srv/app"), "app").unwrap();
+
+        let mut archive = Vec::new();
+        archive_layers(Some(&upper), &lower, &mut archive).unwrap();
+        assert_eq!(
+            listing(&archive),
+            [
+                ("etc/".into(), "".into()),
+                ("etc/hosts".into(), "new".into()),
+                ("etc/passwd".into(), "root".into()),
+                ("srv/".into(), "".into()),
+                ("srv/app".into(), "app".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn whiteouts_and_opaque_dirs_hide_lower_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (upper, lower) = (tmp.path().join("upper"), tmp.path().join("lower"));
+        fs::create_dir_all(lower.join("var/cache")).unwrap();
+        fs::write(lower.join("var/cache/a"), "").unwrap();
+        fs::write(lower.join("var/old"), "").unwrap();
+        fs::create_dir_all(upper.join("var/cache")).unwrap();
+        fs::write(upper.join("var/cache/b"), "").unwrap();
+
+        // Whiteouts and trusted xattrs need privileges; skip when unavailable.
+        let whiteout = mknod(&upper.join("var/old"), SFlag::S_IFCHR, Mode::empty(), 0);
+        let opaque = xattr_set(&upper.join("var/cache"), "trusted.overlay.opaque");
+        if whiteout.is_err() || !opaque {
+            eprintln!("SKIP: cannot create overlay whiteouts here");
+            return;
+        }
+
+        let mut archive = Vec::new();
+        archive_layers(Some(&upper), &lower, &mut archive).unwrap();
+        let paths: Vec<_> = listing(&archive).into_iter().map(|(p, _)| p).collect();
+        assert_eq!(paths, ["var/", "var/cache/", "var/cache/b"]);
+    }
+
+    fn xattr_set(path: &Path, name: &str) -> bool {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let name = CString::new(name).unwrap();
+        // SAFETY: both strings are NUL-terminated and the value is a valid
+        // buffer of the length passed.
+        let rc =
+            unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), b"y".as_ptr().cast(), 1, 0) };
+        rc == 0
+    }
+}
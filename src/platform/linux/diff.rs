@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{CString, OsString};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::core::model::{FsChange, FsChangeKind};
+
+/// Extended attributes overlayfs uses to mark a directory as opaque. The
+/// `user.` variant is used by overlays mounted with `userxattr`.
+const OPAQUE_XATTRS: [&str; 2] = ["trusted.overlay.opaque", "user.overlay.opaque"];
+
+/// Compare an overlay upper directory against its lower directory (the
+/// container's rootfs) and pass each change to `on_change` as it is found.
+///
+/// Entries only in `upper` are Added, entries in both are Changed, and
+/// whiteouts are Deleted. The lower contents of an opaque directory are
+/// hidden, so lower entries without an upper counterpart are Deleted too.
+///
+/// Changes come in tree order, each directory right before its entries
+/// sorted by name, so only one directory listing per level of the tree is
+/// held at a time. Symlinks are reported but never followed.
+pub fn diff_overlay(
+    upper: &Path,
+    lower: &Path,
+    mut on_change: impl FnMut(FsChange) -> Result<()>,
+) -> Result<()> {
+    walk(upper, Some(lower), "", false, &mut on_change)
+}
+
+fn walk(
+    upper: &Path,
+    lower: Option<&Path>,
+    prefix: &str,
+    hidden: bool,
+    on_change: &mut impl FnMut(FsChange) -> Result<()>,
+) -> Result<()> {
+    // `None` marks a name that only exists in the lower directory.
+    let mut entries: BTreeMap<OsString, Option<fs::Metadata>> = BTreeMap::new();
+    for entry in
+        fs::read_dir(upper).with_context(|| format!("failed to read {}", upper.display()))?
+    {
+        let entry = entry?;
+        entries.insert(entry.file_name(), Some(entry.metadata()?));
+    }
+    // Everything below an opaque directory that only exists in the lower
+    // layer is masked by it.
+    if hidden {
+        for name in lower.map(lower_names).unwrap_or_default() {
+            entries.entry(name).or_insert(None);
+        }
+    }
+
+    for (name, meta) in entries {
+        let path = format!("{prefix}/{}", name.to_string_lossy());
+        let Some(meta) = meta.filter(|meta| !is_whiteout(meta)) else {
+            on_change(FsChange {
+                kind: FsChangeKind::Deleted,
+                path,
+            })?;
+            continue;
+        };
+
+        let lower_meta = lower.and_then(|lower| lower.join(&name).symlink_metadata().ok());
+        let kind = if !hidden && lower_meta.is_some() {
+            FsChangeKind::Changed
+        } else {
+            FsChangeKind::Added
+        };
+        on_change(FsChange {
+            kind,
+            path: path.clone(),
+        })?;
+
+        if meta.is_dir() {
+            let upper = upper.join(&name);
+            let opaque = hidden || is_opaque(&upper);
+            // Only a directory below merges with this one; a symlink in its
+            // place may point anywhere on the host.
+            let lower = lower
+                .filter(|_| lower_meta.is_some_and(|m| m.is_dir()))
+                .map(|lower| lower.join(&name));
+            walk(&upper, lower.as_deref(), &path, opaque, on_change)?;
+        }
+    }
+    Ok(())
+}
+
+/// Names in the lower directory; empty if it cannot be read.
+fn lower_names(lower: &Path) -> BTreeSet<OsString> {
+    fs::read_dir(lower)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// An overlay whiteout is a character device with device number 0:0.
+pub(crate) fn is_whiteout(meta: &fs::Metadata) -> bool {
+    meta.file_type().is_char_device() && meta.rdev() == 0
+}
+
+/// Whether `dir` carries an overlay opaque xattr set to `y`.
+pub(crate) fn is_opaque(dir: &Path) -> bool {
+    let Ok(path) = CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    OPAQUE_XATTRS.iter().any(|attr| {
+        let name = CString::new(*attr).unwrap();
+        let mut value = [0u8; 1];
+        // SAFETY: both strings are NUL-terminated and `value` is a valid
+        // buffer of the length passed.
+        let len = unsafe {
+            libc::lgetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        len == 1 && value[0] == b'y'
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::stat::{mknod, Mode, SFlag};
+
+    fn setxattr(path: &Path, name: &str, value: &[u8]) -> bool {
+        let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let name = CString::new(name).unwrap();
+        let rc = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        rc == 0
+    }
+
+    fn changes(upper: &Path, lower: &Path) -> Vec<String> {
+        let mut changes = Vec::new();
+        diff_overlay(upper, lower, |change| {
+            changes.push(change.to_string());
+            Ok(())
+        })
+        .unwrap();
+        changes
+    }
+
+    #[test]
+    fn classifies_added_and_changed_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (upper, lower) = (tmp.path().join("upper"), tmp.path().join("lower"));
+        fs::create_dir_all(lower.join("etc")).unwrap();
+        fs::write(lower.join("etc/hosts"), "old").unwrap();
+        fs::create_dir_all(upper.join("etc")).unwrap();
+        fs::write(upper.join("etc/hosts"), "new").unwrap();
+        fs::write(upper.join("etc/motd"), "hi").unwrap();
+        fs::create_dir_all(upper.join("data/sub")).unwrap();
+
+        assert_eq!(
+            changes(&upper, &lower),
+            [
+                "A /data",
+                "A /data/sub",
+                "C /etc",
+                "C /etc/hosts",
+                "A /etc/motd"
+            ]
+        );
+    }
+
+    #[test]
+    fn whiteouts_and_opaque_dirs_are_deletions() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (upper, lower) = (tmp.path().join("upper"), tmp.path().join("lower"));
+        fs::create_dir_all(lower.join("var/cache")).unwrap();
+        fs::write(lower.join("var/cache/a"), "").unwrap();
+        fs::write(lower.join("var/cache/b"), "").unwrap();
+        fs::write(lower.join("var/old"), "").unwrap();
+        fs::create_dir_all(upper.join("var/cache")).unwrap();
+        fs::write(upper.join("var/cache/b"), "").unwrap();
+
+        // Whiteouts and trusted xattrs need privileges; skip when unavailable.
+        let whiteout = mknod(&upper.join("var/old"), SFlag::S_IFCHR, Mode::empty(), 0);
+        if whiteout.is_err() || !setxattr(&upper.join("var/cache"), OPAQUE_XATTRS[0], b"y") {
+            eprintln!("SKIP: cannot create overlay whiteouts here");
+            return;
+        }
+
+        assert_eq!(
+            changes(&upper, &lower),
+            [
+                "C /var",
+                "C /var/cache",
+                "D /var/cache/a",
+                "A /var/cache/b",
+                "D /var/old"
+            ]
+        );
+    }
+
+    #[test]
+    fn lower_symlinks_are_not_followed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (upper, lower) = (tmp.path().join("upper"), tmp.path().join("lower"));
+        let outside = tmp.path().join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("hosts"), "").unwrap();
+        fs::create_dir_all(&lower).unwrap();
+        std::os::unix::fs::symlink(&outside, lower.join("etc")).unwrap();
+        // A directory in upper replacing a lower symlink does not merge with
+        // whatever the symlink points to.
+        fs::create_dir_all(upper.join("etc")).unwrap();
+        fs::write(upper.join("etc/hosts"), "").unwrap();
+        std::os::unix::fs::symlink("/etc", upper.join("etc-link")).unwrap();
+
+        assert_eq!(
+            changes(&upper, &lower),
+            ["C /etc", "A /etc/hosts", "A /etc-link"]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use crate::core::model::{HealthCheck, HealthState};
+use crate::core::state;
+
+/// How often a running check is polled for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs a container's health checks on a background thread of the monitor
+/// process until stopped.
+pub struct HealthMonitor {
+    stop: Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl HealthMonitor {
+    /// Start checking the container `id` every `check.interval`, recording
+    /// each result in its metadata. The first check runs one interval after
+    /// the start.
+    pub fn start(id: &str, check: &HealthCheck) -> Result<Self> {
+        let exe = std::env::current_exe().context("failed to locate the craterun binary")?;
+        let (stop, stopped) = mpsc::channel::<()>();
+        let id = id.to_string();
+        let check = check.clone();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(check.interval) {
+                let mut cmd = Command::new(&exe);
+                cmd.args(["exec", &id, "--", "/bin/sh", "-c", &check.cmd]);
+                let (exit_code, output) = run_check(cmd, check.interval);
+                if let Err(e) = record(&id, &check, exit_code, &output) {
+                    eprintln!("warning: failed to record health of {id}: {e:#}");
+                }
+            }
+        });
+        Ok(Self { stop, thread })
+    }
+
+    /// Stop checking, waiting for a running check to finish.
+    pub fn stop(self) {
+        drop(self.stop);
+        let _ = self.thread.join();
+    }
+}
+
+/// Run one check, killing it once `timeout` has passed. Returns its exit code
+/// (-1 if it could not be run, timed out or was killed by a signal) and its
+/// stdout followed by its stderr.
+fn run_check(mut cmd: Command, timeout: Duration) -> (i32, String) {
+    let mut child = match cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => return (-1, format!("failed to run health check: {e}")),
+    };
+
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if start.elapsed() >= timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => return (-1, format!("failed to wait for health check: {e}")),
+        }
+    };
+
+    let output = read_output(&mut child);
+    match status {
+        Some(status) => (status.code().unwrap_or(-1), output),
+        None => (
+            -1,
+            format!("health check timed out after {timeout:?}\n{output}"),
+        ),
+    }
+}
+
+/// Read what the exited `child` wrote to its stdout and stderr pipes.
+fn read_output(child: &mut Child) -> String {
+    let mut output = Vec::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        let _ = stdout.read_to_end(&mut output);
+    }
+    if let Some(mut stderr) = child.stderr.take() {
+        let _ = stderr.read_to_end(&mut output);
+    }
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// Record a check result in the metadata of the container `id`.
+fn record(id: &str, check: &HealthCheck, exit_code: i32, output: &str) -> Result<()> {
+    let _lock = state::lock_meta(id)?;
+    let mut meta = state::load_meta(id)?;
+    meta.health
+        .get_or_insert_with(|| HealthState::new(check))
+        .record(exit_code, output, check.retries, chrono::Utc::now());
+    state::save_meta(&meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args(["-c", script]);
+        cmd
+    }
+
+    #[test]
+    fn check_reports_exit_code_and_output() {
+        let (code, output) =
+            run_check(sh("echo out; echo err >&2; exit 3"), Duration::from_secs(5));
+        assert_eq!(code, 3);
+        assert_eq!(output, "out\nerr\n");
+    }
+
+    #[test]
+    fn check_times_out() {
+        let start = Instant::now();
+        let (code, output) = run_check(sh("exec sleep 5"), Duration::from_millis(200));
+        assert_eq!(code, -1);
+        assert!(output.contains("timed out"), "{output}");
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+}
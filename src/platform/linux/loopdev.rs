@@ -0,0 +1,141 @@
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use nix::errno::Errno;
+
+/// The loop control device, provided by the `loop` module.
+const LOOP_CONTROL: &str = "/dev/loop-control";
+
+// ioctl request numbers from <linux/loop.h>.
+const LOOP_SET_FD: libc::c_ulong = 0x4C00;
+const LOOP_CLR_FD: libc::c_ulong = 0x4C01;
+const LOOP_SET_STATUS64: libc::c_ulong = 0x4C04;
+const LOOP_CTL_GET_FREE: libc::c_ulong = 0x4C82;
+
+/// Detach the device automatically once its last user (the mount) is gone.
+const LO_FLAGS_AUTOCLEAR: u32 = 4;
+
+/// How often to retry when another process grabs the free device first.
+const ATTACH_ATTEMPTS: usize = 5;
+
+/// `struct loop_info64` from <linux/loop.h>.
+#[repr(C)]
+struct LoopInfo64 {
+    lo_device: u64,
+    lo_inode: u64,
+    lo_rdevice: u64,
+    lo_offset: u64,
+    lo_sizelimit: u64,
+    lo_number: u32,
+    lo_encrypt_type: u32,
+    lo_encrypt_key_size: u32,
+    lo_flags: u32,
+    lo_file_name: [u8; 64],
+    lo_crypt_name: [u8; 64],
+    lo_encrypt_key: [u8; 32],
+    lo_init: [u64; 2],
+}
+
+/// A loop device attached by [`attach`], held open until it is dropped.
+pub struct LoopDevice {
+    /// Path of the device, e.g. `/dev/loop3`.
+    pub path: PathBuf,
+    /// Auto-clear detaches the device as soon as it has no users, so it is
+    /// kept open until the filesystem on it is mounted.
+    _device: File,
+}
+
+/// Attach `image` to a free loop device, read-only unless `writable` is
+/// set.
+///
+/// The device is set to auto-clear, so once the returned [`LoopDevice`] is
+/// dropped, it is released when the filesystem mounted from it is unmounted
+/// even if [`detach`] is never called.
+pub fn attach(image: &Path, writable: bool) -> Result<LoopDevice> {
+    let control = File::open(LOOP_CONTROL).with_context(|| {
+        format!("loop devices are unavailable: cannot open {LOOP_CONTROL} (try `modprobe loop`)")
+    })?;
+    // The device is read-only if its backing file is.
+    let backing = OpenOptions::new()
+        .read(true)
+        .write(writable)
+        .open(image)
+        .with_context(|| format!("failed to open image {}", image.display()))?;
+
+    for _ in 0..ATTACH_ATTEMPTS {
+        // SAFETY: LOOP_CTL_GET_FREE takes no argument.
+        let number = Errno::result(unsafe { libc::ioctl(control.as_raw_fd(), LOOP_CTL_GET_FREE) })
+            .context("failed to find a free loop device")?;
+        let path = PathBuf::from(format!("/dev/loop{number}"));
+        let device = OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+
+        // SAFETY: LOOP_SET_FD takes the backing file descriptor by value.
+        match Errno::result(unsafe {
+            libc::ioctl(device.as_raw_fd(), LOOP_SET_FD, backing.as_raw_fd())
+        }) {
+            Ok(_) => {}
+            // Taken by someone else between GET_FREE and SET_FD.
+            Err(Errno::EBUSY) => continue,
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("failed to attach {} to {}", image.display(), path.display())
+                })
+            }
+        }
+
+        // SAFETY: all-zero is a valid `loop_info64`.
+        let mut info: LoopInfo64 = unsafe { std::mem::zeroed() };
+        info.lo_flags = LO_FLAGS_AUTOCLEAR;
+        // SAFETY: `info` is a valid `loop_info64` that outlives the call.
+        if let Err(e) = Errno::result(unsafe {
+            libc::ioctl(
+                device.as_raw_fd(),
+                LOOP_SET_STATUS64,
+                &info as *const LoopInfo64,
+            )
+        }) {
+            let _ = detach(&path);
+            return Err(e).with_context(|| format!("failed to configure {}", path.display()));
+        }
+        debug!("attached {} to {}", image.display(), path.display());
+        return Ok(LoopDevice {
+            path,
+            _device: device,
+        });
+    }
+    bail!("failed to attach {}: no free loop device", image.display())
+}
+
+/// Detach the loop device at `path`. A device that is already detached is
+/// not an error, nor is one still busy with a lazily unmounted filesystem:
+/// auto-clear releases it once that is gone.
+pub fn detach(path: &Path) -> Result<()> {
+    let device = match File::open(path) {
+        Ok(device) => device,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("failed to open {}", path.display())),
+    };
+    debug!("detach {}", path.display());
+    // SAFETY: LOOP_CLR_FD takes no argument.
+    match Errno::result(unsafe { libc::ioctl(device.as_raw_fd(), LOOP_CLR_FD) }) {
+        Ok(_) | Err(Errno::ENXIO) | Err(Errno::EBUSY) => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("failed to detach {}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loop_info_matches_kernel_layout() {
+        assert_eq!(std::mem::size_of::<LoopInfo64>(), 232);
+    }
+}
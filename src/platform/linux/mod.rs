@@ -1,4 +1,12 @@
 pub mod cgroups;
+pub mod check;
+pub mod commit;
+pub mod diff;
+pub mod health;
+pub mod loopdev;
 pub mod mounts;
 pub mod namespaces;
+pub mod network;
 pub mod process;
+pub mod slirp;
+pub mod storage;
@@ -1,86 +1,437 @@
+use std::fmt::Debug;
 use std::fs;
-use std::path::Path;
+use std::io::IsTerminal;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
-use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use anyhow::{bail, Context, Result};
+use log::{debug, error};
+use nix::errno::Errno;
+use nix::mount::{MntFlags, MsFlags};
+use nix::sys::stat::{mknod, Mode, SFlag};
+use nix::NixPath;
 
-/// Make the entire mount tree private so our changes do not leak to the host.
-pub fn make_mount_private() -> Result<()> {
+use crate::core::model::{
+    ContainerConfig, DeviceMapping, ImageFormat, MountPlan, MountPropagation, ProcOptions,
+    Timezone, TmpfsMount, UsernsMode,
+};
+use crate::core::{state, validate};
+
+/// The file a container's hostname is bind-mounted over.
+const ETC_HOSTNAME: &str = "/etc/hostname";
+/// The file a `--timezone` is bind-mounted over.
+const ETC_LOCALTIME: &str = "/etc/localtime";
+/// Mount flags of the container's `/proc`.
+const PROC_FLAGS: MsFlags = MsFlags::MS_NOSUID
+    .union(MsFlags::MS_NODEV)
+    .union(MsFlags::MS_NOEXEC);
+/// The file the terminal on stdin, if any, is bind-mounted over.
+const DEV_CONSOLE: &str = "/dev/console";
+/// Where a host `--init-path` is bind-mounted in the container.
+const DEV_INIT: &str = "/dev/init";
+/// The container's POSIX shared memory, a tmpfs of `--shm-size`.
+const DEV_SHM: &str = "/dev/shm";
+/// Mount flags of the container's `/dev/shm`.
+const SHM_FLAGS: MsFlags = MsFlags::MS_NOSUID
+    .union(MsFlags::MS_NODEV)
+    .union(MsFlags::MS_NOEXEC);
+
+/// Where the `--init-path` binary comes from.
+#[derive(Debug)]
+pub enum InitBinary {
+    /// A file of the rootfs, run where it is.
+    Rootfs(String),
+    /// A file of the host, bound read-only at [`DEV_INIT`].
+    Host(PathBuf),
+}
+
+impl InitBinary {
+    /// Find `path` in `rootfs`, or else on the host.
+    pub fn locate(rootfs: &Path, path: &str) -> Option<Self> {
+        if validate::resolve_in_rootfs(rootfs, Path::new(path)).is_ok_and(|p| p.is_file()) {
+            Some(Self::Rootfs(path.to_string()))
+        } else if Path::new(path).is_file() {
+            Some(Self::Host(PathBuf::from(path)))
+        } else {
+            None
+        }
+    }
+
+    /// Open a binary of the host, to bind it with [`bind_init`] once the
+    /// host root is gone; `None` for a binary of the rootfs.
+    pub fn open_host(&self) -> Result<Option<fs::File>> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let Self::Host(path) = self else {
+            return Ok(None);
+        };
+        fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH)
+            .open(path)
+            .map(Some)
+            .with_context(|| format!("failed to open --init-path {}", path.display()))
+    }
+
+    /// The path the container runs the binary from.
+    pub fn container_path(&self) -> &str {
+        match self {
+            Self::Rootfs(path) => path,
+            Self::Host(_) => DEV_INIT,
+        }
+    }
+}
+
+/// Describe the mounts `init_container` performs for `config`, with its rootfs
+/// at `rootfs` staged at `staging`, in order.
+pub fn mount_plan(config: &ContainerConfig, rootfs: &Path, staging: &Path) -> Vec<MountPlan> {
+    let has_etc_hostname = validate::resolve_in_rootfs(rootfs, Path::new(ETC_HOSTNAME))
+        .is_ok_and(|path| path.is_file());
+    let host_init = config
+        .init_path
+        .as_ref()
+        .filter(|path| matches!(InitBinary::locate(rootfs, path), Some(InitBinary::Host(_))));
+    let hostname_file = staging.with_file_name(state::HOSTNAME_FILE);
+    let rootfs = rootfs.display().to_string();
+    let staging = staging.display().to_string();
+    let plan = |source: &str, target: &str, fstype: Option<&str>, options: Option<&str>| {
+        MountPlan {
+            source: source.to_string(),
+            target: target.to_string(),
+            fstype: fstype.map(str::to_string),
+            options: options.map(str::to_string),
+        }
+    };
+    let switch_root = if config.no_pivot { "move,chroot" } else { "pivot_root" };
+
+    let propagation = format!("r{}", config.mount_propagation);
+    let mut proc_options = "nosuid,nodev,noexec".to_string();
+    if let Some(data) = config.proc_opts.data() {
+        proc_options.push(',');
+        proc_options.push_str(data);
+    }
+    let shm_options = format!(
+        "nosuid,nodev,noexec,mode=1777,size={}",
+        config.dev_shm_size()
+    );
+    let mut mounts = vec![
+        plan("/", "/", None, Some(&propagation)),
+        plan(&rootfs, &staging, None, Some("rbind")),
+    ];
+    if has_etc_hostname {
+        let source = hostname_file.display().to_string();
+        mounts.push(plan(&source, ETC_HOSTNAME, None, Some("bind")));
+    }
+    if let Some(timezone) = &config.timezone {
+        let source = timezone.host_file().display().to_string();
+        mounts.push(plan(&source, ETC_LOCALTIME, None, Some("bind,ro")));
+    }
+    mounts.extend([
+        plan(&staging, "/", None, Some(switch_root)),
+        plan("proc", "/proc", Some("proc"), Some(&proc_options)),
+        plan("tmpfs", "/dev", Some("tmpfs"), Some("nosuid,mode=0755,size=65536k")),
+        plan("shm", DEV_SHM, Some("tmpfs"), Some(&shm_options)),
+    ]);
+    if config.userns == UsernsMode::KeepId {
+        for (path, _, _) in DEV_NODES {
+            mounts.push(plan(path, path, None, Some("bind")));
+        }
+    }
+    if config.attach_stdin && stdin_is_terminal() {
+        mounts.push(plan("<terminal on stdin>", DEV_CONSOLE, None, Some("bind")));
+    }
+    if let Some(path) = host_init {
+        mounts.push(plan(path, DEV_INIT, None, Some("bind,ro")));
+    }
+    for tmpfs in &config.tmpfs {
+        let mut options = tmpfs.data(config.tmpfs_default_size());
+        for flag in &tmpfs.flags {
+            options.push(',');
+            options.push_str(flag);
+        }
+        mounts.push(plan("tmpfs", &tmpfs.path, Some("tmpfs"), Some(&options)));
+    }
+    if config.proc_opts.read_only {
+        mounts.push(plan("proc", "/proc", None, Some("remount,ro")));
+    }
+    mounts
+}
+
+/// Describe the mounts `mount_image_overlay` performs for an image rootfs
+/// with its overlay at `overlay_dir`.
+pub fn image_mount_plan(image: &Path, format: ImageFormat, overlay_dir: &Path) -> Vec<MountPlan> {
+    let dir = |name: &str| overlay_dir.join(name).display().to_string();
+    vec![
+        MountPlan {
+            source: format!("<loop device for {}>", image.display()),
+            target: dir("lower"),
+            fstype: Some(format.fstype().to_string()),
+            options: Some("ro".to_string()),
+        },
+        MountPlan {
+            source: "overlay".to_string(),
+            target: dir("merged"),
+            fstype: Some("overlay".to_string()),
+            options: Some(format!(
+                "lowerdir={},upperdir={},workdir={}",
+                dir("lower"),
+                dir("upper"),
+                dir("work")
+            )),
+        },
+    ]
+}
+
+/// The mounts a container's setup has made so far, so that a failure can say
+/// how far it got.
+#[derive(Debug, Default)]
+pub struct MountLog {
+    done: Vec<String>,
+}
+
+impl MountLog {
+    /// Record a mount that succeeded, described by its target and kind.
+    pub fn record(&mut self, mount: impl Into<String>) {
+        self.done.push(mount.into());
+    }
+
+    /// Add the mounts made before the failure `e` to it.
+    pub fn annotate(&self, e: anyhow::Error) -> anyhow::Error {
+        if self.done.is_empty() {
+            e.context("mount setup failed before any mount was made")
+        } else {
+            e.context(format!("mount setup failed after mounting {}", self.done.join(", ")))
+        }
+    }
+}
+
+/// Set the propagation of the entire mount tree. Private, the default, keeps
+/// our changes from leaking to the host.
+pub fn set_root_propagation(propagation: MountPropagation) -> Result<()> {
+    let flag = match propagation {
+        MountPropagation::Private => MsFlags::MS_PRIVATE,
+        MountPropagation::Slave => MsFlags::MS_SLAVE,
+        MountPropagation::Shared => MsFlags::MS_SHARED,
+    };
     mount(
         None::<&str>,
         "/",
         None::<&str>,
-        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        MsFlags::MS_REC | flag,
         None::<&str>,
     )
-    .context("failed to make / private recursively")?;
+    .with_context(|| format!("failed to make / {propagation} recursively"))?;
     Ok(())
 }
 
-/// Bind-mount the rootfs onto itself so it becomes a mount point
-/// (required for `pivot_root`).
-pub fn bind_mount_rootfs(rootfs: &Path) -> Result<()> {
+/// Make the mount holding the parent directory of `path` private, not
+/// recursively, and return its mount point.
+pub fn make_parent_mount_private(path: &Path) -> Result<String> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")
+        .context("failed to read /proc/self/mountinfo")?;
+    let parent = path.parent().unwrap_or(path);
+    let point = mount_points(&mountinfo)
+        .filter(|p| parent.starts_with(p))
+        .max_by_key(|p| Path::new(p).components().count())
+        .unwrap_or_else(|| "/".to_string());
+    mount(
+        None::<&str>,
+        point.as_str(),
+        None::<&str>,
+        MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )
+    .with_context(|| format!("failed to make {point} private"))?;
+    Ok(point)
+}
+
+/// Bind-mount the rootfs onto a staging directory (under the container's
+/// state directory) so it becomes a mount point for `pivot_root` without
+/// writing anything into the user's rootfs.
+pub fn bind_mount_rootfs(rootfs: &Path, staging: &Path) -> Result<()> {
     mount(
         Some(rootfs),
-        rootfs,
+        staging,
         None::<&str>,
         MsFlags::MS_BIND | MsFlags::MS_REC,
         None::<&str>,
     )
-    .with_context(|| format!("failed to bind-mount rootfs {}", rootfs.display()))?;
+    .with_context(|| {
+        format!(
+            "failed to bind-mount rootfs {} at {}",
+            rootfs.display(),
+            staging.display()
+        )
+    })?;
     Ok(())
 }
 
-/// Perform `pivot_root` to make `new_root` the new `/` and put the old root under
-/// `new_root/.pivot_old`. Then unmount and remove the old root.
-pub fn pivot_root(new_root: &Path) -> Result<()> {
-    let put_old = new_root.join(".pivot_old");
-    fs::create_dir_all(&put_old)
-        .with_context(|| format!("failed to create {}", put_old.display()))?;
+/// Bind-mount the container's own hostname file over `/etc/hostname` of the
+/// staged rootfs, so the shared rootfs itself is never written. Skipped if
+/// the rootfs has no `/etc/hostname` file to mount over. Returns whether it
+/// was mounted.
+pub fn bind_hostname_file(source: &Path, staging: &Path) -> Result<bool> {
+    let target = validate::resolve_in_rootfs(staging, Path::new(ETC_HOSTNAME))?;
+    if !target.is_file() {
+        return Ok(false);
+    }
+    mount(
+        Some(source),
+        &target,
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    )
+    .with_context(|| format!("failed to bind-mount {} at {ETC_HOSTNAME}", source.display()))?;
+    Ok(true)
+}
 
-    nix::unistd::pivot_root(new_root, &put_old).with_context(|| {
+/// Bind the host file of `timezone` read-only over `/etc/localtime` in the
+/// rootfs staged at `staging`, creating the file (and `/etc`) if the rootfs
+/// lacks them.
+pub fn bind_localtime(timezone: &Timezone, staging: &Path) -> Result<()> {
+    let source = timezone.host_file();
+    let target = validate::resolve_in_rootfs(staging, Path::new(ETC_LOCALTIME))?;
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&target)
+        .with_context(|| format!("failed to create {ETC_LOCALTIME} in the rootfs"))?;
+    mount(Some(&source), &target, None, MsFlags::MS_BIND, None).with_context(|| {
         format!(
-            "pivot_root({}, {}) failed",
-            new_root.display(),
-            put_old.display()
+            "failed to bind-mount {} at {ETC_LOCALTIME}",
+            source.display()
         )
     })?;
+    mount(
+        None::<&str>,
+        &target,
+        None,
+        MsFlags::MS_REMOUNT | MsFlags::MS_BIND | MsFlags::MS_RDONLY,
+        None,
+    )
+    .with_context(|| format!("failed to make {ETC_LOCALTIME} read-only"))
+}
 
-    // After pivot_root, `/.pivot_old` is the old root.
-    nix::unistd::chdir("/").context("chdir / after pivot_root")?;
+/// Make `new_root` the new `/` and detach the old root.
+///
+/// Uses `pivot_root(".", ".")`: the old root ends up stacked on top of the
+/// new one and is lazily unmounted, so no `put_old` directory has to be
+/// created inside the rootfs. Where `pivot_root` fails with `EINVAL` (e.g.
+/// the current root is a ramfs, as inside some containers), falls back to
+/// [`move_root`] with a warning if `allow_chroot` is set
+/// (`--allow-chroot-fallback`), else fails. Returns whether it fell back.
+pub fn pivot_root(
+    new_root: &Path,
+    allow_chroot: bool,
+    propagation: MountPropagation,
+) -> Result<bool> {
+    nix::unistd::chdir(new_root)
+        .with_context(|| format!("failed to chdir to {}", new_root.display()))?;
 
-    umount_old_root("/.pivot_old")?;
-    Ok(())
-}
+    debug!("pivot_root to {}", new_root.display());
+    match nix::unistd::pivot_root(".", ".") {
+        Ok(()) => {}
+        Err(Errno::EINVAL) if allow_chroot => {
+            eprintln!(
+                "warning: pivot_root({}) is not possible on this host; falling back to chroot, \
+                 which isolates less: host mounts above the rootfs stay underneath it",
+                new_root.display()
+            );
+            return move_root(new_root, propagation).map(|()| true);
+        }
+        Err(Errno::EINVAL) => bail!(
+            "pivot_root({}) is not possible on this host, e.g. because / is a ramfs; pass \
+             --allow-chroot-fallback to fall back to chroot, which isolates less, or \
+             --no-pivot to always use it",
+            new_root.display()
+        ),
+        Err(e) => {
+            error!("pivot_root to {} failed: {e}", new_root.display());
+            return Err(e).with_context(|| format!("pivot_root({}) failed", new_root.display()));
+        }
+    }
 
-/// Unmount the old root and remove the directory.
-fn umount_old_root(path: &str) -> Result<()> {
-    umount2(path, MntFlags::MNT_DETACH)
-        .with_context(|| format!("failed to unmount old root at {path}"))?;
-    fs::remove_dir(path)
-        .with_context(|| format!("failed to remove old root directory {path}"))?;
-    Ok(())
+    // The old root is now mounted on top of `/`; detach it, after making it
+    // a slave so that with shared propagation the unmount stays ours.
+    mount(
+        None::<&str>,
+        ".",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_SLAVE,
+        None::<&str>,
+    )
+    .context("failed to make old root a slave")?;
+    umount2(".", MntFlags::MNT_DETACH).context("failed to unmount old root")?;
+    nix::unistd::chdir("/").context("chdir / after pivot_root")?;
+    Ok(false)
 }
 
-/// Mount `/proc` inside the new root.
-pub fn mount_proc(rootfs: &Path) -> Result<()> {
-    let proc_dir = rootfs.join("proc");
-    fs::create_dir_all(&proc_dir)
-        .with_context(|| format!("failed to create {}", proc_dir.display()))?;
-
+/// Make `new_root` the new `/` without `pivot_root`: detach every host mount
+/// that is not on the way to `new_root`, move `new_root` onto `/` and
+/// `chroot` into it.
+///
+/// Mounts that are ancestors of `new_root` cannot be detached and stay
+/// underneath, unreachable from inside the chroot. The tree keeps
+/// `propagation`, which cannot be shared.
+pub fn move_root(new_root: &Path, propagation: MountPropagation) -> Result<()> {
+    // Detaching host mounts must not propagate back to the host, and a mount
+    // whose parent is shared cannot be moved.
+    let flag = match propagation {
+        MountPropagation::Private => MsFlags::MS_PRIVATE,
+        MountPropagation::Slave => MsFlags::MS_SLAVE,
+        MountPropagation::Shared => {
+            bail!("--mount-propagation shared cannot be used when the rootfs is chrooted into")
+        }
+    };
     mount(
-        Some("proc"),
-        &proc_dir,
-        Some("proc"),
-        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | flag,
         None::<&str>,
     )
-    .with_context(|| format!("failed to mount proc at {}", proc_dir.display()))?;
+    .with_context(|| format!("failed to make / {propagation} recursively"))?;
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")
+        .context("failed to read /proc/self/mountinfo")?;
+    for point in mounts_to_detach(&mountinfo, new_root) {
+        // Already gone with a detached parent, or busy: nothing more to do.
+        let _ = umount2(point.as_str(), MntFlags::MNT_DETACH);
+    }
+
+    nix::unistd::chdir(new_root)
+        .with_context(|| format!("failed to chdir to {}", new_root.display()))?;
+    mount(Some("."), "/", None::<&str>, MsFlags::MS_MOVE, None::<&str>)
+        .with_context(|| format!("failed to move {} onto /", new_root.display()))?;
+    debug!("chroot to {}", new_root.display());
+    nix::unistd::chroot(".").context("failed to chroot into new root")?;
+    nix::unistd::chdir("/").context("chdir / after chroot")?;
     Ok(())
 }
 
+/// Mount points in `mountinfo` that are neither `new_root`, one of its
+/// ancestors nor below it, deepest first so children go before parents.
+fn mounts_to_detach(mountinfo: &str, new_root: &Path) -> Vec<String> {
+    let mut points: Vec<String> = mount_points(mountinfo)
+        .filter(|p| {
+            let p = Path::new(p);
+            !new_root.starts_with(p) && !p.starts_with(new_root)
+        })
+        .collect();
+    let depth = |p: &String| Path::new(p).components().count();
+    points.sort_by(|a, b| depth(b).cmp(&depth(a)).then_with(|| a.cmp(b)));
+    points.dedup();
+    points
+}
+
 /// Mount `/proc` at `/proc` (used after pivot_root when `/` is already the new root).
-pub fn mount_proc_in_new_root() -> Result<()> {
+pub fn mount_proc_in_new_root(options: ProcOptions) -> Result<()> {
     let proc_dir = Path::new("/proc");
     fs::create_dir_all(proc_dir).context("failed to create /proc")?;
 
@@ -88,15 +439,28 @@ pub fn mount_proc_in_new_root() -> Result<()> {
         Some("proc"),
         proc_dir,
         Some("proc"),
-        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
-        None::<&str>,
+        PROC_FLAGS,
+        options.data(),
     )
     .context("failed to mount proc at /proc")?;
     Ok(())
 }
 
+/// Remount `/proc` read-only (`--proc-opts ro`). Done once `--sysctl`
+/// settings are written, which needs it writable.
+pub fn remount_proc_read_only() -> Result<()> {
+    mount(
+        None::<&str>,
+        "/proc",
+        None,
+        PROC_FLAGS | MsFlags::MS_REMOUNT | MsFlags::MS_BIND | MsFlags::MS_RDONLY,
+        None,
+    )
+    .context("failed to remount /proc read-only")
+}
+
 /// Mount a minimal `/dev` with devtmpfs.
-pub fn mount_dev_in_new_root() -> Result<()> {
+pub fn mount_dev_in_new_root(host_nodes: Option<&HostDevNodes>) -> Result<()> {
     let dev_dir = Path::new("/dev");
     fs::create_dir_all(dev_dir).context("failed to create /dev")?;
 
@@ -109,28 +473,487 @@ pub fn mount_dev_in_new_root() -> Result<()> {
     )
     .context("failed to mount tmpfs on /dev")?;
 
-    // Create essential device nodes (null, zero, urandom, tty).
-    create_dev_nodes()?;
+    // Create essential device nodes (null, zero, urandom, tty), or bind the
+    // host's where that is not allowed.
+    match host_nodes {
+        Some(nodes) => nodes.bind()?,
+        None => create_dev_nodes()?,
+    }
+    create_stdio_symlinks()?;
 
     Ok(())
 }
 
+/// Mount the container's `/dev/shm`: a tmpfs of `size` bytes that anyone
+/// may create files in.
+pub fn mount_shm(size: u64) -> Result<()> {
+    fs::create_dir_all(DEV_SHM).context("failed to create /dev/shm")?;
+    mount(
+        Some("shm"),
+        DEV_SHM,
+        Some("tmpfs"),
+        SHM_FLAGS,
+        Some(format!("mode=1777,size={size}").as_str()),
+    )
+    .context("failed to mount tmpfs on /dev/shm")
+}
+
+/// The `/dev` symlinks to the calling process's open files, as on a normal
+/// system.
+const STDIO_SYMLINKS: [(&str, &str); 4] = [
+    ("/dev/fd", "/proc/self/fd"),
+    ("/dev/stdin", "/proc/self/fd/0"),
+    ("/dev/stdout", "/proc/self/fd/1"),
+    ("/dev/stderr", "/proc/self/fd/2"),
+];
+
+/// Create the `/dev/fd` and `/dev/std{in,out,err}` symlinks.
+fn create_stdio_symlinks() -> Result<()> {
+    for (link, target) in STDIO_SYMLINKS {
+        std::os::unix::fs::symlink(target, link)
+            .with_context(|| format!("failed to create {link}"))?;
+    }
+    Ok(())
+}
+
+/// Whether the container's stdin, inherited from the CLI, is a terminal, in
+/// which case it is also the container's `/dev/console`.
+pub fn stdin_is_terminal() -> bool {
+    std::io::stdin().is_terminal()
+}
+
+/// Bind the terminal on stdin over `/dev/console`, so programs writing to the
+/// console reach it. It is reached through `/proc/self/fd/0`, as its own
+/// path is on the host's `/dev/pts`, which the container cannot see.
+pub fn bind_console() -> Result<()> {
+    let console = Path::new(DEV_CONSOLE);
+    fs::File::create(console).context("failed to create /dev/console")?;
+    mount(
+        Some(Path::new("/proc/self/fd/0")),
+        console,
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    )
+    .context("failed to bind the terminal over /dev/console")
+}
+
+/// Bind the host's `--init-path`, opened as `init` before the host root
+/// went away, read-only at [`DEV_INIT`].
+pub fn bind_init(init: &fs::File) -> Result<()> {
+    fs::File::create(DEV_INIT).context("failed to create /dev/init")?;
+    let source = format!("/proc/self/fd/{}", init.as_raw_fd());
+    mount(
+        Some(source.as_str()),
+        DEV_INIT,
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    )
+    .context("failed to bind the --init-path at /dev/init")?;
+    mount(
+        None::<&str>,
+        DEV_INIT,
+        None::<&str>,
+        MsFlags::MS_REMOUNT | MsFlags::MS_BIND | MsFlags::MS_RDONLY,
+        None::<&str>,
+    )
+    .context("failed to make /dev/init read-only")
+}
+
+/// The minimal device nodes of the container's /dev, with their major and
+/// minor numbers.
+const DEV_NODES: [(&str, u64, u64); 4] = [
+    ("/dev/null", 1, 3),
+    ("/dev/zero", 1, 5),
+    ("/dev/urandom", 1, 9),
+    ("/dev/tty", 5, 0),
+];
+
 /// Create minimal device nodes inside the container's /dev.
 fn create_dev_nodes() -> Result<()> {
     use nix::sys::stat;
 
     let perm = stat::Mode::from_bits_truncate(0o666);
-    let devices = [
-        ("/dev/null", nix::sys::stat::makedev(1, 3)),
-        ("/dev/zero", nix::sys::stat::makedev(1, 5)),
-        ("/dev/urandom", nix::sys::stat::makedev(1, 9)),
-        ("/dev/tty", nix::sys::stat::makedev(5, 0)),
-    ];
-
-    for (path, dev) in &devices {
+    for (path, major, minor) in DEV_NODES {
         // mknod may fail if not root or if devtmpfs already provides it; ignore error.
-        let _ = stat::mknod(Path::new(path), stat::SFlag::S_IFCHR, perm, *dev);
+        let dev = stat::makedev(major, minor);
+        let _ = stat::mknod(Path::new(path), stat::SFlag::S_IFCHR, perm, dev);
+    }
+
+    Ok(())
+}
+
+/// The host's minimal device nodes, opened before the host root goes away
+/// so they can be bound into a `/dev` in which nodes cannot be created: a
+/// user namespace's root may not `mknod`.
+pub struct HostDevNodes(Vec<(&'static str, fs::File)>);
+
+impl HostDevNodes {
+    pub fn open() -> Result<Self> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let mut nodes = Vec::new();
+        for (path, _, _) in DEV_NODES {
+            // O_PATH: opening /dev/tty for real fails without a terminal.
+            let node = fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_PATH)
+                .open(path)
+                .with_context(|| format!("failed to open the host's {path}"))?;
+            nodes.push((path, node));
+        }
+        Ok(Self(nodes))
+    }
+
+    /// Bind each node over an empty file at its path in the new root.
+    fn bind(&self) -> Result<()> {
+        for (path, node) in &self.0 {
+            fs::File::create(path).with_context(|| format!("failed to create {path}"))?;
+            let source = format!("/proc/self/fd/{}", node.as_raw_fd());
+            mount(
+                Some(source.as_str()),
+                *path,
+                None::<&str>,
+                MsFlags::MS_BIND,
+                None::<&str>,
+            )
+            .with_context(|| format!("failed to bind the host's {path}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Mount a `--tmpfs` at its path in the new root, creating the mount point
+/// if needed.
+pub fn mount_tmpfs(tmpfs: &TmpfsMount, default_size: u64) -> Result<()> {
+    let target = Path::new(&tmpfs.path);
+    fs::create_dir_all(target)
+        .with_context(|| format!("failed to create tmpfs mount point {}", tmpfs.path))?;
+
+    let mut flags = MsFlags::empty();
+    for flag in &tmpfs.flags {
+        flags |= match flag.as_str() {
+            "noexec" => MsFlags::MS_NOEXEC,
+            "nosuid" => MsFlags::MS_NOSUID,
+            "nodev" => MsFlags::MS_NODEV,
+            "ro" => MsFlags::MS_RDONLY,
+            _ => MsFlags::empty(),
+        };
+    }
+    mount(
+        Some("tmpfs"),
+        target,
+        Some("tmpfs"),
+        flags,
+        Some(tmpfs.data(default_size).as_str()),
+    )
+    .with_context(|| format!("failed to mount tmpfs at {}", tmpfs.path))?;
+    Ok(())
+}
+
+/// A host device node to recreate inside the container.
+pub struct DeviceNode {
+    path: PathBuf,
+    kind: SFlag,
+    rdev: u64,
+    mode: u32,
+}
+
+/// Stat the host side of each `--device` mapping. Must run before
+/// `pivot_root`, while host paths are still reachable.
+pub fn device_nodes(devices: &[DeviceMapping]) -> Result<Vec<DeviceNode>> {
+    devices
+        .iter()
+        .map(|device| {
+            let meta = fs::metadata(&device.host_path)
+                .with_context(|| format!("cannot access device '{}'", device.host_path))?;
+            let kind = if meta.file_type().is_block_device() {
+                SFlag::S_IFBLK
+            } else {
+                SFlag::S_IFCHR
+            };
+            // Drop the access bits that the mapping does not grant.
+            let mut mode = meta.mode() & 0o777;
+            if !device.permissions.contains('r') {
+                mode &= !0o444;
+            }
+            if !device.permissions.contains('w') {
+                mode &= !0o222;
+            }
+            Ok(DeviceNode {
+                path: PathBuf::from(&device.container_path),
+                kind,
+                rdev: meta.rdev(),
+                mode,
+            })
+        })
+        .collect()
+}
+
+/// Create the passed-through device nodes inside the container's `/dev`.
+///
+/// cgroup v2 only restricts device access when a BPF device program is
+/// attached, which craterun does not do, so the node is all that is needed.
+pub fn create_device_nodes(nodes: &[DeviceNode]) -> Result<()> {
+    for node in nodes {
+        if let Some(parent) = node.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let mode = Mode::from_bits_truncate(node.mode);
+        debug!("mknod {} mode {:o}", node.path.display(), node.mode);
+        mknod(&node.path, node.kind, mode, node.rdev)
+            .with_context(|| format!("failed to create device node {}", node.path.display()))?;
+        // mknod is subject to the umask; set the intended mode explicitly.
+        fs::set_permissions(&node.path, fs::Permissions::from_mode(node.mode))
+            .with_context(|| format!("failed to chmod {}", node.path.display()))?;
+    }
+    Ok(())
+}
+
+/// Mount the filesystem image attached to `loop_device` read-only at
+/// `overlay_dir/lower` and stack a writable overlay on it at
+/// `overlay_dir/merged`, which becomes the container's rootfs.
+pub fn mount_image_overlay(
+    loop_device: &Path,
+    format: ImageFormat,
+    overlay_dir: &Path,
+) -> Result<PathBuf> {
+    let [lower, upper, work, merged] =
+        ["lower", "upper", "work", "merged"].map(|dir| overlay_dir.join(dir));
+    for dir in [&lower, &upper, &work, &merged] {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+
+    mount(
+        Some(loop_device),
+        &lower,
+        Some(format.fstype()),
+        MsFlags::MS_RDONLY,
+        None::<&str>,
+    )
+    .with_context(|| {
+        format!(
+            "failed to mount {} image from {} (is the {} module available?)",
+            format.fstype(),
+            loop_device.display(),
+            format.fstype()
+        )
+    })?;
+
+    let options = format!(
+        "lowerdir={},upperdir={},workdir={}",
+        lower.display(),
+        upper.display(),
+        work.display()
+    );
+    mount(
+        Some("overlay"),
+        &merged,
+        Some("overlay"),
+        MsFlags::empty(),
+        Some(options.as_str()),
+    )
+    .with_context(|| format!("failed to mount overlay at {}", merged.display()))?;
+    Ok(merged)
+}
+
+/// Mount the filesystem that keeps a size-limited writable layer at
+/// `overlay_dir`, for [`mount_image_overlay`] to create its directories in:
+/// the ext4 filesystem on `device`, or without one a tmpfs of `size` bytes.
+pub fn mount_storage(device: Option<&Path>, size: u64, overlay_dir: &Path) -> Result<()> {
+    fs::create_dir_all(overlay_dir)
+        .with_context(|| format!("failed to create {}", overlay_dir.display()))?;
+    let result = match device {
+        Some(device) => mount(
+            Some(device),
+            overlay_dir,
+            Some("ext4"),
+            MsFlags::empty(),
+            None::<&str>,
+        ),
+        None => mount(
+            Some("tmpfs"),
+            overlay_dir,
+            Some("tmpfs"),
+            MsFlags::empty(),
+            Some(format!("size={size},mode=0700").as_str()),
+        ),
+    };
+    result.with_context(|| format!("failed to mount storage at {}", overlay_dir.display()))
+}
+
+/// Tear down an overlay-backed container's filesystem: lazily unmount
+/// `merged/`, the image at `lower/` and the storage of a size-limited
+/// writable layer if they are still mounted, then remove `upper/`, `work/`
+/// and `merged/`. A missing overlay directory is not an error.
+pub fn teardown_overlay(overlay_dir: &Path) -> Result<()> {
+    if !overlay_dir.exists() {
+        return Ok(());
+    }
+
+    for path in [
+        overlay_dir.join("merged"),
+        overlay_dir.join("lower"),
+        overlay_dir.into(),
+    ] {
+        if is_mount_point(&path)? {
+            umount2(&path, MntFlags::MNT_DETACH)
+                .with_context(|| format!("failed to unmount {}", path.display()))?;
+        }
     }
 
+    fs::remove_dir_all(overlay_dir)
+        .with_context(|| format!("failed to remove overlay dir {}", overlay_dir.display()))?;
     Ok(())
 }
+
+/// Check whether `path` is a mount point in the current mount namespace.
+pub fn is_mount_point(path: &Path) -> Result<bool> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")
+        .context("failed to read /proc/self/mountinfo")?;
+    let mounted = mount_points(&mountinfo).any(|p| Path::new(&p) == path);
+    Ok(mounted)
+}
+
+/// `mount(2)`, with a debug event for every call and an error event on
+/// failure.
+fn mount<S, T>(
+    source: Option<&S>,
+    target: &T,
+    fstype: Option<&str>,
+    flags: MsFlags,
+    data: Option<&str>,
+) -> nix::Result<()>
+where
+    S: NixPath + Debug + ?Sized,
+    T: NixPath + Debug + ?Sized,
+{
+    debug!("mount {source:?} on {target:?} type {fstype:?} flags {flags:?} data {data:?}");
+    nix::mount::mount(source, target, fstype, flags, data)
+        .inspect_err(|e| error!("mount {source:?} on {target:?} failed: {e}"))
+}
+
+/// `umount2(2)`, logged like [`mount`].
+fn umount2<T: NixPath + Debug + ?Sized>(target: &T, flags: MntFlags) -> nix::Result<()> {
+    debug!("umount {target:?} flags {flags:?}");
+    nix::mount::umount2(target, flags).inspect_err(|e| error!("umount {target:?} failed: {e}"))
+}
+
+/// Iterate over the mount points listed in a `mountinfo` file.
+fn mount_points(mountinfo: &str) -> impl Iterator<Item = String> + '_ {
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(4))
+        .map(unescape_mountinfo)
+}
+
+/// Undo the octal escaping (`\040` for space, etc.) used in `mountinfo`.
+fn unescape_mountinfo(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 3 < bytes.len() {
+            if let Ok(c) = u8::from_str_radix(&field[i + 1..i + 4], 8) {
+                out.push(c);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn init_binaries_prefer_the_rootfs() {
+        let rootfs = tempfile::tempdir().unwrap();
+        fs::create_dir_all(rootfs.path().join("sbin")).unwrap();
+        fs::write(rootfs.path().join("sbin/tini"), "").unwrap();
+        std::os::unix::fs::symlink("/sbin/tini", rootfs.path().join("init")).unwrap();
+
+        for path in ["/sbin/tini", "/init"] {
+            let init = InitBinary::locate(rootfs.path(), path).unwrap();
+            assert!(matches!(init, InitBinary::Rootfs(_)), "{path}");
+            assert_eq!(init.container_path(), path);
+        }
+        let init = InitBinary::locate(rootfs.path(), "/bin/sh").unwrap();
+        assert!(matches!(&init, InitBinary::Host(path) if path == Path::new("/bin/sh")));
+        assert_eq!(init.container_path(), DEV_INIT);
+        assert!(InitBinary::locate(rootfs.path(), "/sbin/no-such-init").is_none());
+    }
+
+    #[test]
+    fn mount_log_reports_progress() {
+        let mut log = MountLog::default();
+        let err = log.annotate(anyhow::anyhow!("EPERM"));
+        assert_eq!(
+            format!("{err:#}"),
+            "mount setup failed before any mount was made: EPERM"
+        );
+
+        log.record("/ (rprivate)");
+        log.record("/proc (proc)");
+        let err = log.annotate(anyhow::anyhow!("failed to mount tmpfs on /dev"));
+        assert_eq!(
+            format!("{err:#}"),
+            "mount setup failed after mounting / (rprivate), /proc (proc): \
+             failed to mount tmpfs on /dev"
+        );
+    }
+
+    const MOUNTINFO: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+35 22 0:31 / /var/lib/craterun/abc/overlay/merged rw - overlay overlay rw
+36 22 0:32 / /mnt/with\\040space rw - tmpfs tmpfs rw
+";
+
+    #[test]
+    fn parses_mount_points() {
+        let points: Vec<String> = mount_points(MOUNTINFO).collect();
+        assert_eq!(
+            points,
+            vec!["/", "/var/lib/craterun/abc/overlay/merged", "/mnt/with space"]
+        );
+    }
+
+    #[test]
+    fn detaches_host_mounts_outside_new_root() {
+        let mountinfo = "\
+22 1 8:1 / / rw - ext4 /dev/sda1 rw
+23 22 8:2 / /var rw - ext4 /dev/sda2 rw
+24 22 0:5 / /proc rw - proc proc rw
+25 24 0:6 / /proc/sys/fs/binfmt_misc rw - binfmt_misc binfmt_misc rw
+26 23 8:1 / /var/lib/craterun/abc/rootfs rw - ext4 /dev/sda1 rw
+27 26 0:7 / /var/lib/craterun/abc/rootfs/mnt rw - tmpfs tmpfs rw
+28 23 0:8 / /var/log rw - tmpfs tmpfs rw
+";
+        assert_eq!(
+            mounts_to_detach(mountinfo, Path::new("/var/lib/craterun/abc/rootfs")),
+            vec!["/proc/sys/fs/binfmt_misc", "/var/log", "/proc"]
+        );
+    }
+
+    #[test]
+    fn teardown_removes_overlay_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let overlay = tmp.path().join("overlay");
+        for dir in ["upper/etc", "work/work", "merged"] {
+            fs::create_dir_all(overlay.join(dir)).unwrap();
+        }
+        fs::write(overlay.join("upper/etc/hosts"), "127.0.0.1 localhost").unwrap();
+
+        teardown_overlay(&overlay).unwrap();
+        assert!(!overlay.exists());
+
+        // Tearing down a container without an overlay is a no-op.
+        teardown_overlay(&overlay).unwrap();
+    }
+}
@@ -1,29 +1,352 @@
-use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::unix::io::BorrowedFd;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, error};
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
 use nix::sched::CloneFlags;
+use nix::sys::wait::waitpid;
+use nix::unistd::{self, ForkResult, Gid, Pid, Uid};
+
+use crate::core::model::{CgroupNsMode, ContainerConfig, NamespaceKind, UsernsMode};
+use crate::core::userns::{self, IdMap, IdMappings};
+
+/// Debian/Ubuntu knob that disables unprivileged user namespaces when `0`.
+pub const USERNS_CLONE_SYSCTL: &str = "/proc/sys/kernel/unprivileged_userns_clone";
+/// Per-user limit on user namespaces; `0` disables them for everyone.
+const MAX_USERNS_SYSCTL: &str = "/proc/sys/user/max_user_namespaces";
+/// Ubuntu's AppArmor knob that denies unconfined programs user namespaces
+/// when `1`.
+const APPARMOR_USERNS_SYSCTL: &str = "/proc/sys/kernel/apparmor_restrict_unprivileged_userns";
 
 /// Return the set of namespace flags we want for a new container.
 ///
-/// We use: mount, pid, UTS, IPC, and network.
-/// Network namespace isolation is included; the container gets a new, empty
-/// network stack (loopback only). If you need host networking pass `--net=host`
-/// in a future version.
-pub fn container_clone_flags() -> CloneFlags {
-    CloneFlags::CLONE_NEWNS
-        | CloneFlags::CLONE_NEWPID
-        | CloneFlags::CLONE_NEWUTS
-        | CloneFlags::CLONE_NEWIPC
-        | CloneFlags::CLONE_NEWNET
+/// The container always gets its own mount namespace, and its own PID, UTS,
+/// IPC and network namespaces unless `--pid`, `--uts`, `--ipc` or `--net`
+/// select the host's or another container's. A new network namespace is
+/// empty (loopback only). Another container's namespaces are joined with
+/// [`join_namespace`] instead. A user namespace, for `--userns keep-id`, is
+/// created first with [`unshare_user_namespace`]. A cgroup namespace, unless
+/// `--cgroup-ns host`, is rooted at the cgroup the process is in when it is
+/// created, so it must already be in the container's.
+pub fn container_clone_flags(config: &ContainerConfig) -> CloneFlags {
+    let user = match config.userns {
+        UsernsMode::KeepId => CloneFlags::CLONE_NEWUSER,
+        UsernsMode::Host => CloneFlags::empty(),
+    };
+    let cgroup = match config.cgroup_ns {
+        CgroupNsMode::Private => CloneFlags::CLONE_NEWCGROUP,
+        CgroupNsMode::Host => CloneFlags::empty(),
+    };
+    NamespaceKind::ALL
+        .into_iter()
+        .filter(|&kind| config.namespace(kind).is_new())
+        .fold(CloneFlags::CLONE_NEWNS | user | cgroup, |flags, kind| {
+            flags | clone_flag(kind)
+        })
+}
+
+/// The `clone(2)` flag that creates a namespace of `kind`.
+pub fn clone_flag(kind: NamespaceKind) -> CloneFlags {
+    match kind {
+        NamespaceKind::Net => CloneFlags::CLONE_NEWNET,
+        NamespaceKind::Pid => CloneFlags::CLONE_NEWPID,
+        NamespaceKind::Ipc => CloneFlags::CLONE_NEWIPC,
+        NamespaceKind::Uts => CloneFlags::CLONE_NEWUTS,
+    }
+}
+
+/// Return the short names (as found under `/proc/<pid>/ns/`) of the
+/// namespaces selected by `flags`.
+pub fn namespace_names(flags: CloneFlags) -> Vec<&'static str> {
+    let known = [
+        (CloneFlags::CLONE_NEWUSER, "user"),
+        (CloneFlags::CLONE_NEWNS, "mnt"),
+        (CloneFlags::CLONE_NEWPID, "pid"),
+        (CloneFlags::CLONE_NEWUTS, "uts"),
+        (CloneFlags::CLONE_NEWIPC, "ipc"),
+        (CloneFlags::CLONE_NEWNET, "net"),
+        (CloneFlags::CLONE_NEWCGROUP, "cgroup"),
+    ];
+    known
+        .iter()
+        .filter(|(flag, _)| flags.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect()
 }
 
 /// Call `unshare(2)` with the given flags. Used when we fork first and then
 /// unshare in the child.
 pub fn unshare_namespaces(flags: CloneFlags) -> Result<()> {
-    nix::sched::unshare(flags).context("unshare failed — are you running as root?")?;
+    debug!("unshare {:?}", namespace_names(flags));
+    nix::sched::unshare(flags).map_err(|errno| unshare_error(errno, flags))
+}
+
+/// What the host allows, as far as it explains a failed `unshare(2)`.
+#[derive(Debug, Default)]
+struct UnshareEnv {
+    root: bool,
+    /// [`USERNS_CLONE_SYSCTL`], on kernels that have it.
+    userns_clone: Option<bool>,
+    /// [`MAX_USERNS_SYSCTL`].
+    max_user_namespaces: Option<u64>,
+    /// [`APPARMOR_USERNS_SYSCTL`], on kernels that have it.
+    apparmor_restricted: Option<bool>,
+}
+
+impl UnshareEnv {
+    fn probe() -> Self {
+        let read = |path: &str| fs::read_to_string(path).ok()?.trim().parse::<u64>().ok();
+        Self {
+            root: unistd::geteuid().is_root(),
+            userns_clone: read(USERNS_CLONE_SYSCTL).map(|v| v != 0),
+            max_user_namespaces: read(MAX_USERNS_SYSCTL),
+            apparmor_restricted: read(APPARMOR_USERNS_SYSCTL).map(|v| v != 0),
+        }
+    }
+}
+
+/// The error for `unshare(2)` of `flags` failing with `errno`, explaining
+/// the likely cause from the host's state.
+fn unshare_error(errno: Errno, flags: CloneFlags) -> anyhow::Error {
+    let names = namespace_names(flags);
+    error!("unshare {names:?} failed: {errno}");
+    let (cause, hint) = unshare_diagnosis(errno, flags, &UnshareEnv::probe());
+    let mut message = format!("unshare {names:?} failed: {errno}");
+    if let Some(cause) = cause {
+        message.push_str(&format!("; {cause}"));
+    }
+    if let Some(hint) = hint {
+        message.push_str(&format!("\nhint: {hint}"));
+    }
+    anyhow::anyhow!(message)
+}
+
+/// The likely cause of `unshare(2)` of `flags` failing with `errno` on a
+/// host in state `env`, and what to do about it.
+fn unshare_diagnosis(
+    errno: Errno,
+    flags: CloneFlags,
+    env: &UnshareEnv,
+) -> (Option<String>, Option<&'static str>) {
+    let user = flags.contains(CloneFlags::CLONE_NEWUSER);
+    match errno {
+        Errno::EPERM | Errno::ENOSPC if user && env.max_user_namespaces == Some(0) => (
+            Some(format!(
+                "user namespaces are disabled ({MAX_USERNS_SYSCTL} is 0)"
+            )),
+            Some("sysctl -w user.max_user_namespaces=15000"),
+        ),
+        Errno::ENOSPC if user => (
+            Some("the limit on user namespaces is reached".into()),
+            Some("raise user.max_user_namespaces"),
+        ),
+        Errno::EPERM if env.root => (
+            Some("not permitted even as root".into()),
+            Some(
+                "craterun needs CAP_SYS_ADMIN; if it runs inside a container, give that \
+                 container the capability or a seccomp profile that allows unshare(2)",
+            ),
+        ),
+        Errno::EPERM if user && env.userns_clone == Some(false) => (
+            Some(format!(
+                "unprivileged user namespaces are disabled ({USERNS_CLONE_SYSCTL} is 0)"
+            )),
+            Some("sysctl -w kernel.unprivileged_userns_clone=1, or run craterun as root"),
+        ),
+        Errno::EPERM if user && env.apparmor_restricted == Some(true) => (
+            Some(format!(
+                "AppArmor denies unprivileged user namespaces ({APPARMOR_USERNS_SYSCTL} is 1)"
+            )),
+            Some(
+                "sysctl -w kernel.apparmor_restrict_unprivileged_userns=0, or run craterun as root",
+            ),
+        ),
+        Errno::EPERM if user => (
+            Some("creating a user namespace was refused".into()),
+            Some("run craterun as root"),
+        ),
+        Errno::EPERM => (
+            Some("creating namespaces needs CAP_SYS_ADMIN".into()),
+            Some(
+                "run craterun as root, or with --userns keep-id to create them in a user namespace",
+            ),
+        ),
+        Errno::EINVAL => (
+            Some("the kernel may not support one of these namespaces".into()),
+            None,
+        ),
+        _ => (None, None),
+    }
+}
+
+/// Move into a new user namespace with `mappings` and become its root, so
+/// that the namespaces unshared next belong to it.
+///
+/// A process cannot map IDs other than its own into the namespace it has
+/// just entered, so a helper forked beforehand, still in the host's, writes
+/// the maps once told to. As root it writes them itself; otherwise
+/// `newuidmap(1)` and `newgidmap(1)` check them against the user's
+/// subordinate ranges.
+pub fn unshare_user_namespace(mappings: &IdMappings) -> Result<()> {
+    let (go_read, go_write) = unistd::pipe2(OFlag::O_CLOEXEC).context("failed to create pipe")?;
+    let (err_read, err_write) = unistd::pipe2(OFlag::O_CLOEXEC).context("failed to create pipe")?;
+    let pid = unistd::getpid();
+
+    // SAFETY: we are single-threaded; the helper only writes files, runs
+    // the map helpers and exits.
+    match unsafe { unistd::fork() }.context("fork of the ID map helper failed")? {
+        ForkResult::Child => {
+            drop((go_write, err_read));
+            let mut go = [0u8; 1];
+            let result = match File::from(go_read).read(&mut go) {
+                Ok(1) => write_id_maps(pid, mappings),
+                // Nothing to map: the unshare failed.
+                _ => Ok(()),
+            };
+            let code = match result {
+                Ok(()) => 0,
+                Err(e) => {
+                    let _ = File::from(err_write).write_all(format!("{e:#}").as_bytes());
+                    1
+                }
+            };
+            // SAFETY: exit without running the parent's destructors.
+            unsafe { libc::_exit(code) };
+        }
+        ForkResult::Parent { child } => {
+            drop((go_read, err_write));
+            debug!("unshare [\"user\"]");
+            let unshared = nix::sched::unshare(CloneFlags::CLONE_NEWUSER);
+            let mut go = File::from(go_write);
+            if unshared.is_ok() {
+                let _ = go.write_all(b"1");
+            }
+            drop(go);
+            let mut message = String::new();
+            let _ = File::from(err_read).read_to_string(&mut message);
+            let _ = waitpid(child, None);
+
+            unshared.map_err(|errno| unshare_error(errno, CloneFlags::CLONE_NEWUSER))?;
+            if !message.is_empty() {
+                bail!("failed to map the user namespace's IDs: {message}");
+            }
+        }
+    }
+
+    unistd::setresgid(Gid::from_raw(0), Gid::from_raw(0), Gid::from_raw(0))
+        .context("failed to become GID 0 in the user namespace")?;
+    unistd::setresuid(Uid::from_raw(0), Uid::from_raw(0), Uid::from_raw(0))
+        .context("failed to become UID 0 in the user namespace")?;
+    Ok(())
+}
+
+/// Write the UID and GID maps of the user namespace of process `pid`.
+fn write_id_maps(pid: Pid, mappings: &IdMappings) -> Result<()> {
+    let maps = [
+        ("uid_map", "newuidmap", &mappings.uid_map),
+        ("gid_map", "newgidmap", &mappings.gid_map),
+    ];
+    for (file, helper, map) in maps {
+        if unistd::geteuid().is_root() {
+            let path = format!("/proc/{pid}/{file}");
+            debug!("{path}: {map:?}");
+            fs::write(&path, userns::map_file_contents(map))
+                .with_context(|| format!("failed to write {path}"))?;
+        } else {
+            run_map_helper(helper, pid, map)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run `newuidmap` or `newgidmap` to write `map` for process `pid`.
+fn run_map_helper(helper: &str, pid: Pid, map: &[IdMap]) -> Result<()> {
+    let mut args = vec![pid.to_string()];
+    for line in map {
+        args.extend([line.inside, line.outside, line.count].map(|id| id.to_string()));
+    }
+    debug!("{helper} {}", args.join(" "));
+    let output = Command::new(helper)
+        .args(&args)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("failed to run {helper} (is the uidmap package installed?)"))?;
+    if !output.status.success() {
+        bail!(
+            "{helper} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Join the namespace referred to by `ns` (a `/proc/<pid>/ns/*` file), which
+/// must be of the type given by `flag`.
+pub fn join_namespace(ns: BorrowedFd<'_>, flag: CloneFlags) -> Result<()> {
+    debug!("setns {:?}", namespace_names(flag));
+    nix::sched::setns(ns, flag)
+        .inspect_err(|e| error!("setns {:?} failed: {e}", namespace_names(flag)))
+        .context("setns failed")?;
     Ok(())
 }
 
 /// Set the hostname inside a UTS namespace.
 pub fn set_hostname(name: &str) -> Result<()> {
+    debug!("sethostname {name}");
     nix::unistd::sethostname(name).context("sethostname failed")?;
     Ok(())
 }
+
+/// Set the NIS domain name inside a UTS namespace.
+pub fn set_domainname(name: &str) -> Result<()> {
+    debug!("setdomainname {name}");
+    // SAFETY: the pointer and length describe `name`, which outlives the call.
+    let rc = unsafe { libc::setdomainname(name.as_ptr().cast(), name.len()) };
+    nix::errno::Errno::result(rc).context("setdomainname failed")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unshare_diagnoses() {
+        let user = CloneFlags::CLONE_NEWUSER;
+        let mount = CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWPID;
+        let hint = |errno, flags, env| unshare_diagnosis(errno, flags, &env).1.unwrap();
+
+        let blocked = UnshareEnv {
+            userns_clone: Some(false),
+            ..Default::default()
+        };
+        assert!(hint(Errno::EPERM, user, blocked).contains("unprivileged_userns_clone=1"));
+        let disabled = UnshareEnv {
+            max_user_namespaces: Some(0),
+            ..Default::default()
+        };
+        assert!(hint(Errno::ENOSPC, user, disabled).contains("max_user_namespaces"));
+        let apparmor = UnshareEnv {
+            apparmor_restricted: Some(true),
+            ..Default::default()
+        };
+        assert!(hint(Errno::EPERM, user, apparmor).contains("apparmor"));
+
+        // Without a user namespace, an unprivileged user needs one.
+        let unprivileged = UnshareEnv::default();
+        assert!(hint(Errno::EPERM, mount, unprivileged).contains("--userns keep-id"));
+        let root = UnshareEnv {
+            root: true,
+            ..Default::default()
+        };
+        assert!(hint(Errno::EPERM, mount, root).contains("CAP_SYS_ADMIN"));
+        assert_eq!(
+            unshare_diagnosis(Errno::ENOMEM, mount, &UnshareEnv::default()),
+            (None, None)
+        );
+    }
+}
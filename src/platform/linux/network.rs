@@ -0,0 +1,169 @@
+use std::fs::File;
+use std::net::Ipv4Addr;
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+
+use crate::core::model::MacAddress;
+use crate::core::network::NetworkInfo;
+
+/// Name of the container's end of its veth pair.
+const CONTAINER_INTERFACE: &str = "eth0";
+
+/// Create the host bridge of `network` if it does not exist yet, and make
+/// sure it has the gateway address and is up.
+pub fn ensure_bridge(network: &NetworkInfo) -> Result<()> {
+    let bridge = network.bridge.as_str();
+    if !bridge_exists(bridge) {
+        // Another `run` may create it at the same time.
+        if let Err(e) = ip(&["link", "add", bridge, "type", "bridge"]) {
+            if !bridge_exists(bridge) {
+                return Err(e);
+            }
+        }
+    }
+    let gateway = format!("{}/{}", network.gateway(), network.subnet.prefix_len);
+    ip(&["addr", "replace", &gateway, "dev", bridge])?;
+    ip(&["link", "set", bridge, "up"])
+}
+
+/// Delete the host bridge of `network`, if it exists. The host ends of the
+/// veth pairs of containers still attached are left without a bridge.
+pub fn remove_bridge(network: &NetworkInfo) -> Result<()> {
+    if bridge_exists(&network.bridge) {
+        ip(&["link", "del", &network.bridge])?;
+    }
+    Ok(())
+}
+
+fn bridge_exists(bridge: &str) -> bool {
+    Path::new("/sys/class/net").join(bridge).exists()
+}
+
+/// A container's place on a network's bridge, opened before the container's
+/// network namespace is created.
+pub struct BridgeLink {
+    pub network: NetworkInfo,
+    pub ip: Ipv4Addr,
+    pub mac: MacAddress,
+    /// The host's network namespace, in which the veth pair is created.
+    host_ns: File,
+}
+
+impl BridgeLink {
+    pub fn new(network: NetworkInfo, ip: Ipv4Addr, mac: MacAddress) -> Result<Self> {
+        let host_ns =
+            File::open("/proc/self/ns/net").context("failed to open the host network namespace")?;
+        Ok(Self {
+            network,
+            ip,
+            mac,
+            host_ns,
+        })
+    }
+
+    /// Connect the current network namespace, the container's, to the
+    /// network's bridge: a veth pair with the host end on the bridge and `eth0` here,
+    /// with the container's addresses and a default route via the bridge.
+    pub fn connect(&self, container_id: &str) -> Result<()> {
+        let host_veth = veth_name(container_id);
+        let pid = std::process::id().to_string();
+        // Created in the host's namespace, with our end moved straight here.
+        run(
+            self.in_host_ns(),
+            &[
+                "link",
+                "add",
+                &host_veth,
+                "type",
+                "veth",
+                "peer",
+                "name",
+                CONTAINER_INTERFACE,
+                "netns",
+                &pid,
+            ],
+        )?;
+        run(
+            self.in_host_ns(),
+            &[
+                "link",
+                "set",
+                &host_veth,
+                "master",
+                &self.network.bridge,
+                "up",
+            ],
+        )?;
+
+        let mac = self.mac.to_string();
+        let address = format!("{}/{}", self.ip, self.network.subnet.prefix_len);
+        ip(&["link", "set", "lo", "up"])?;
+        ip(&["link", "set", CONTAINER_INTERFACE, "address", &mac])?;
+        ip(&["addr", "add", &address, "dev", CONTAINER_INTERFACE])?;
+        ip(&["link", "set", CONTAINER_INTERFACE, "up"])?;
+        let gateway = self.network.gateway().to_string();
+        ip(&["route", "add", "default", "via", &gateway])
+    }
+
+    /// An `ip` command that runs in the host's network namespace.
+    fn in_host_ns(&self) -> Command {
+        let fd = self.host_ns.as_raw_fd();
+        let mut cmd = Command::new("ip");
+        // SAFETY: only setns(2), which is async-signal-safe, runs between fork
+        // and exec.
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::setns(fd, libc::CLONE_NEWNET) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        cmd
+    }
+}
+
+/// Name of the host end of a container's veth pair: at most 15 bytes, the
+/// kernel's limit for interface names.
+fn veth_name(container_id: &str) -> String {
+    format!("veth{}", &container_id[..container_id.len().min(11)])
+}
+
+/// Run `ip` with `args` in the current network namespace.
+fn ip(args: &[&str]) -> Result<()> {
+    run(Command::new("ip"), args)
+}
+
+/// Run `cmd`, an `ip` command, with `args`, failing with its error output.
+fn run(mut cmd: Command, args: &[&str]) -> Result<()> {
+    debug!("ip {}", args.join(" "));
+    let output = cmd
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .context("failed to run ip (is iproute2 installed?)")?;
+    if !output.status.success() {
+        bail!(
+            "ip {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn veth_names_fit_the_kernel_limit() {
+        assert_eq!(veth_name("0123456789abcdef"), "veth0123456789a");
+        assert_eq!(veth_name("0123456789abcdef").len(), 15);
+    }
+}
@@ -1,24 +1,294 @@
+use std::cell::Cell;
 use std::ffi::CString;
 use std::fs::{self, File};
-use std::io::Read;
-use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
-use std::path::Path;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsFd, AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context, Result};
+use log::{debug, error};
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::resource::{setrlimit, Resource, RLIM_INFINITY};
 use nix::sys::signal::Signal;
+use nix::sched::CloneFlags;
+use nix::sys::stat::Mode;
 use nix::sys::wait::{waitpid, WaitStatus};
-use nix::unistd::{self, ForkResult, Pid};
+use nix::unistd::{self, ForkResult, Gid, Pid, Uid};
+use serde::{Deserialize, Serialize};
 
-use crate::core::model::ContainerConfig;
-use crate::core::state;
-use crate::platform::linux::{cgroups, mounts, namespaces};
+use crate::core::error::LaunchError;
+use crate::core::model::{
+    CgroupPlan, ContainerConfig, ContainerExit, ContainerMeta, ContainerStatus, ContainerUser,
+    HealthState, ImageFormat, JoinedNamespace, MountPropagation, NamespaceKind, NamespaceMode,
+    RunPlan, StorageLimit, Sysctl, Ulimit, UlimitKind, Umask, UserSpec, UsernsMode, DEFAULT_PATH,
+    EXIT_CODE_UNKNOWN, RLIM_UNLIMITED,
+};
+use crate::core::network::{load_network, DEFAULT_NETWORK};
+use crate::core::userns::{self, Caller, IdMappings};
+use crate::core::{elf, env, ipam, state, user, validate};
+use crate::platform::linux::health::HealthMonitor;
+use crate::platform::linux::mounts::{InitBinary, MountLog};
+use crate::platform::linux::network::{self, BridgeLink};
+use crate::platform::linux::slirp::{self, Slirp};
+use crate::platform::linux::{cgroups, loopdev, mounts, namespaces, storage};
+
+/// `oom_score_adj` of the monitor (parent) process. Just above -1000, which
+/// would exempt it from the OOM killer entirely.
+const MONITOR_OOM_SCORE_ADJ: i32 = -999;
+
+/// How often `run` calls back while a setup phase takes its time, in
+/// milliseconds.
+pub const PROGRESS_TICK_MS: u16 = 100;
+
+/// How often `stop` checks whether the container has exited.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long `stop` waits for the container to exit after SIGKILL.
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How the monitored child process ended.
+struct ChildExit {
+    /// Exit code, or 128+signal for signal death.
+    code: i32,
+    signal: Option<Signal>,
+    core_dumped: bool,
+}
 
 /// Outcome of running a container.
 pub struct RunResult {
-    /// The container's assigned ID.
-    pub container_id: String,
     /// The exit code of the container's init process (or 128+signal for signal death).
     pub exit_code: i32,
+    /// Whether it exited with that code or was killed by a signal.
+    pub exit: ContainerExit,
+}
+
+/// A step of container setup, reported as it begins so that `run` can show
+/// what a slow setup is waiting for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SetupPhase {
+    /// Attaching and mounting an image rootfs under its overlay.
+    Image,
+    Cgroups,
+    Namespaces,
+    /// Staging the rootfs and mounting `/proc`, `/dev` and `--tmpfs`.
+    Mounts,
+}
+
+impl SetupPhase {
+    /// What is being done, for display.
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Image => "mounting the image",
+            Self::Cgroups => "configuring cgroups",
+            Self::Namespaces => "setting up namespaces",
+            Self::Mounts => "mounting filesystems",
+        }
+    }
+}
+
+/// Report sent from the container child to the parent over the setup pipe:
+/// a setup phase beginning, or the error that ended setup.
+#[derive(Debug, Serialize, Deserialize)]
+enum ChildReport {
+    Phase(SetupPhase),
+    /// The command could not be exec'd.
+    Launch(LaunchError),
+    /// Any other setup failure, already formatted.
+    Setup(String),
+}
+
+impl ChildReport {
+    fn from_error(e: &anyhow::Error) -> Self {
+        match e.downcast_ref::<LaunchError>() {
+            Some(launch) => Self::Launch(launch.clone()),
+            None => Self::Setup(format!("{e:#}")),
+        }
+    }
+}
+
+/// The user and full environment of the container command, the namespaces
+/// it joins and the network helper it needs. The first two depend on the
+/// rootfs's `/etc/passwd`, so this is resolved once the rootfs is available.
+struct Launch {
+    user: Option<ContainerUser>,
+    env: Vec<String>,
+    /// Namespaces of other containers to join instead of creating them.
+    shared: Vec<SharedNamespace>,
+    /// The `slirp4netns` binary, for `--net slirp`.
+    slirp: Option<PathBuf>,
+    /// The container's address on the host bridge, for `--net bridge`.
+    /// Allocated once the container has an ID.
+    bridge: Option<BridgeLink>,
+    /// The ID maps of the container's user namespace, for `--userns keep-id`.
+    id_mappings: Option<IdMappings>,
+    /// The `--init-path` binary, run as PID 1 with the command as its
+    /// arguments.
+    init: Option<InitBinary>,
+}
+
+impl Launch {
+    fn resolve(config: &ContainerConfig, rootfs: &Path) -> Result<Self> {
+        let caller = match config.userns {
+            UsernsMode::KeepId => Some(Caller::current()?),
+            UsernsMode::Host => None,
+        };
+        let user = match (&config.user, &caller) {
+            (Some(spec), _) => Some(user::resolve_user(rootfs, spec)?),
+            // keep-id runs the command as the calling user by default.
+            (None, Some(caller)) => {
+                let spec = UserSpec {
+                    user: caller.uid.to_string(),
+                    group: Some(caller.gid.to_string()),
+                };
+                Some(user::resolve_user(rootfs, &spec)?)
+            }
+            (None, None) => None,
+        };
+        let env = env::with_defaults(default_env(config, user.as_ref()), &config.env);
+        let shared = SharedNamespace::open_all(config)?;
+        Ok(Self {
+            user,
+            env,
+            shared,
+            slirp: slirp_binary(config)?,
+            bridge: None,
+            id_mappings: id_mappings(config)?,
+            init: init_binary(config, rootfs)?,
+        })
+    }
+
+    /// The command to exec as PID 1: the command, or the `--init-path`
+    /// binary with the command as its arguments.
+    fn pid1_cmd(&self, config: &ContainerConfig) -> Vec<String> {
+        match &self.init {
+            Some(init) => std::iter::once(init.container_path().to_string())
+                .chain(config.cmd.iter().cloned())
+                .collect(),
+            None => config.cmd.clone(),
+        }
+    }
+
+    /// The mode to record for namespaces of `kind`, with a joined
+    /// container's full ID.
+    fn namespace(&self, config: &ContainerConfig, kind: NamespaceKind) -> NamespaceMode {
+        match self.shared.iter().find(|shared| shared.kind == kind) {
+            Some(shared) => NamespaceMode::Container(shared.id.clone()),
+            None => config.namespace(kind).clone(),
+        }
+    }
+}
+
+/// Locate `config`'s `--init-path` in `rootfs`, or else on the host, and
+/// check that it is executable.
+fn init_binary(config: &ContainerConfig, rootfs: &Path) -> Result<Option<InitBinary>> {
+    let Some(path) = &config.init_path else {
+        return Ok(None);
+    };
+    let Some(init) = InitBinary::locate(rootfs, path) else {
+        bail!("--init-path {path} is neither in the rootfs nor on the host");
+    };
+    let file = match &init {
+        InitBinary::Rootfs(_) => validate::resolve_in_rootfs(rootfs, Path::new(path))?,
+        InitBinary::Host(file) => file.clone(),
+    };
+    let mode = fs::metadata(&file)
+        .with_context(|| format!("failed to stat {}", file.display()))?
+        .permissions()
+        .mode();
+    if mode & 0o111 == 0 {
+        bail!(
+            "--init-path {path} is not executable (mode {:o})",
+            mode & 0o7777
+        );
+    }
+    Ok(Some(init))
+}
+
+/// The ID maps of `config`'s user namespace, if it gets one.
+fn id_mappings(config: &ContainerConfig) -> Result<Option<IdMappings>> {
+    match config.userns {
+        UsernsMode::KeepId => userns::keep_id(&Caller::current()?).map(Some),
+        UsernsMode::Host => Ok(None),
+    }
+}
+
+/// The `slirp4netns` binary if `config` needs one.
+fn slirp_binary(config: &ContainerConfig) -> Result<Option<PathBuf>> {
+    match config.net {
+        NamespaceMode::Slirp => slirp::find_binary().map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Reserve an address on the chosen network for container `id` if `config`
+/// asks for `--net bridge`, creating the network's bridge if needed.
+fn connect_bridge(id: &str, config: &ContainerConfig) -> Result<Option<BridgeLink>> {
+    if config.net != NamespaceMode::Bridge {
+        return Ok(None);
+    }
+    let name = config.network.as_deref().unwrap_or(DEFAULT_NETWORK);
+    let network = load_network(name)?;
+    network::ensure_bridge(&network)
+        .with_context(|| format!("failed to set up the bridge of network '{name}'"))?;
+    let ip = ipam::allocate(&network, id, config.ip)?;
+    let mac = config.mac_address.unwrap_or_else(|| ipam::mac_address(ip));
+    match BridgeLink::new(network, ip, mac) {
+        Ok(link) => Ok(Some(link)),
+        Err(e) => {
+            let _ = ipam::release(name, id);
+            Err(e)
+        }
+    }
+}
+
+/// A namespace of another container, for `--net`, `--pid`, `--ipc` or
+/// `--uts` `container:<id>`. Opened before forking so the child can join it.
+struct SharedNamespace {
+    kind: NamespaceKind,
+    /// Full ID of the container whose namespace is joined.
+    id: String,
+    ns: File,
+}
+
+impl SharedNamespace {
+    /// Open the namespaces `config` selects from other containers. Those
+    /// containers must be running.
+    fn open_all(config: &ContainerConfig) -> Result<Vec<Self>> {
+        let mut shared = Vec::new();
+        for kind in NamespaceKind::ALL {
+            let NamespaceMode::Container(prefix) = config.namespace(kind) else {
+                continue;
+            };
+            let id = state::resolve_id(prefix).with_context(|| format!("--{kind}"))?;
+            let mut meta = state::load_meta(&id)?;
+            state::refresh_status(&mut meta)?;
+            if meta.status != ContainerStatus::Running {
+                bail!("--{kind}: container {id} is not running");
+            }
+            // The recorded PID is outside the container's PID namespace, which
+            // it only created for its children.
+            let file = match kind {
+                NamespaceKind::Pid => "pid_for_children",
+                _ => kind.name(),
+            };
+            let path = format!("/proc/{}/ns/{file}", meta.pid);
+            let ns = File::open(&path).with_context(|| format!("failed to open {path}"))?;
+            shared.push(Self { kind, id, ns });
+        }
+        Ok(shared)
+    }
+
+    fn joined(&self) -> JoinedNamespace {
+        JoinedNamespace {
+            kind: self.kind,
+            container: self.id.clone(),
+        }
+    }
 }
 
 /// Launch a container: fork, unshare, setup mounts/cgroups, exec.
@@ -27,24 +297,74 @@ pub struct RunResult {
 ///
 /// This function calls `fork()`. The child performs `exec`. This is safe as
 /// long as no other threads are running at fork time — we call this very early.
-pub fn run_container(config: &ContainerConfig) -> Result<RunResult> {
-    validate_rootfs(&config.rootfs)?;
+///
+/// `on_progress` is called with each [`SetupPhase`] as it begins, and with
+/// `None` every [`PROGRESS_TICK_MS`] while a phase takes its time. `on_start`
+/// is called with the container's metadata once the child has signalled
+/// readiness and the metadata has been saved, before waiting for the
+/// container.
+pub fn run_container(
+    config: &ContainerConfig,
+    mut on_progress: impl FnMut(Option<SetupPhase>),
+    on_start: impl FnOnce(&ContainerMeta) -> Result<()>,
+) -> Result<RunResult> {
+    let rootfs = validate::validate_config(config)?;
+    cgroups::check_cpuset(&config.limits)?;
+    // The command of an image can only be checked once it is mounted.
+    let format = validate::image_format(&rootfs)?;
+    if format.is_none() {
+        preflight_command(config, &rootfs)?;
+    }
 
     let container_id = crate::core::id::generate_id();
-    let rootfs = fs::canonicalize(&config.rootfs)
-        .with_context(|| format!("failed to canonicalize rootfs path '{}'", config.rootfs))?;
 
     // Create log files before forking.
     let container_dir = state::container_dir(&container_id)?;
     fs::create_dir_all(&container_dir)?;
+    let unstarted = Unstarted::new(&container_id);
     let stdout_file = File::create(container_dir.join(state::STDOUT_LOG))
         .context("failed to create stdout.log")?;
     let stderr_file = File::create(container_dir.join(state::STDERR_LOG))
         .context("failed to create stderr.log")?;
+    let staging = state::rootfs_mount_dir(&container_id)?;
+    fs::create_dir_all(&staging)
+        .with_context(|| format!("failed to create {}", staging.display()))?;
+    fs::write(state::hostname_file(&container_id)?, format!("{}\n", config.hostname))
+        .context("failed to write hostname file")?;
+
+    // An image rootfs is attached to a loop device and mounted under an
+    // overlay, which the container then uses like a directory rootfs.
+    let (rootfs, loop_device) = match format {
+        Some(format) => {
+            on_progress(Some(SetupPhase::Image));
+            let (merged, device) =
+                setup_image_rootfs(&container_id, &rootfs, format, config.storage.as_ref())?;
+            (merged, Some(device))
+        }
+        None => (rootfs, None),
+    };
+    let checked = match loop_device {
+        Some(_) => preflight_command(config, &rootfs),
+        None => Ok(()),
+    };
+    let launch = match checked
+        .and_then(|()| Launch::resolve(config, &rootfs))
+        .and_then(|mut launch| {
+            launch.bridge = connect_bridge(&container_id, config)?;
+            Ok(launch)
+        }) {
+        Ok(launch) => launch,
+        Err(e) => {
+            let _ = teardown_rootfs(&container_id, loop_device.as_deref());
+            return Err(e);
+        }
+    };
 
     // Set up a pipe for the child to signal readiness / report errors.
-    // pipe() returns (read_end, write_end) as OwnedFd.
-    let (read_fd, write_fd) = nix::unistd::pipe().context("failed to create pipe")?;
+    // Both ends are close-on-exec: a successful `execve` in the container
+    // closes the last write end, so EOF without a report means "ready".
+    let (read_fd, write_fd) =
+        nix::unistd::pipe2(OFlag::O_CLOEXEC).context("failed to create pipe")?;
 
     // Convert OwnedFds to raw fds immediately. We manage lifetime manually
     // across the fork boundary — OwnedFd drop semantics don't work across fork.
@@ -54,21 +374,40 @@ pub fn run_container(config: &ContainerConfig) -> Result<RunResult> {
     // SAFETY: We fork here. The child will exec or _exit.
     match unsafe { unistd::fork() }.context("fork failed")? {
         ForkResult::Parent { child } => {
+            debug!("forked container process {child} for {container_id}");
             // Close write end in parent.
             unsafe { libc::close(write_raw) };
             // Wrap read end in a File (takes ownership).
             let reader = unsafe { File::from_raw_fd(read_raw) };
-            parent_process(child, &container_id, config, reader)
+            parent_process(
+                child,
+                &unstarted,
+                config,
+                &launch,
+                loop_device.as_deref(),
+                SetupPipe {
+                    reader,
+                    on_progress,
+                },
+                on_start,
+            )
         }
         ForkResult::Child => {
             // Close read end in child.
             unsafe { libc::close(read_raw) };
             // In the child: any error is sent via the pipe before _exit(1).
-            let result =
-                child_process(config, &rootfs, &container_id, &stdout_file, &stderr_file);
+            let result = child_process(
+                config,
+                &launch,
+                &rootfs,
+                &container_id,
+                &stdout_file,
+                &stderr_file,
+                write_raw,
+            );
             if let Err(e) = &result {
-                let msg = format!("{e:#}");
-                let _ = unsafe { libc::write(write_raw, msg.as_ptr() as *const _, msg.len()) };
+                error!("container setup failed: {e:#}");
+                write_report(write_raw, &ChildReport::from_error(e));
             }
             // Close write end to signal parent (EOF on read end).
             unsafe { libc::close(write_raw) };
@@ -77,91 +416,451 @@ pub fn run_container(config: &ContainerConfig) -> Result<RunResult> {
     }
 }
 
+/// The default environment of `config`'s command: [`env::default_env`], with
+/// `TZ` pointing at the `/etc/localtime` a `--timezone` is bound over. A zone
+/// name would need a time zone database inside the rootfs, which e.g.
+/// Alpine's lacks.
+fn default_env(config: &ContainerConfig, user: Option<&ContainerUser>) -> Vec<(String, String)> {
+    let mut defaults = env::default_env(&config.hostname, user);
+    if config.timezone.is_some() {
+        defaults.push(("TZ".to_string(), ":/etc/localtime".to_string()));
+    }
+    defaults
+}
+
+/// Validate `config` and describe what `run_container` would do with it,
+/// without forking or touching namespaces, mounts or cgroups.
+pub fn plan_container(config: &ContainerConfig) -> Result<RunPlan> {
+    let rootfs = validate::validate_config(config)?;
+    // An image can only be looked into once mounted, so its command is not
+    // checked here.
+    let mut mounts = Vec::new();
+    let (container_root, launch) = match validate::image_format(&rootfs)? {
+        Some(format) => {
+            let overlay = state::overlay_dir("<container-id>")?;
+            mounts.extend(mounts::image_mount_plan(&rootfs, format, &overlay));
+            let launch = Launch {
+                user: None,
+                env: env::with_defaults(default_env(config, None), &config.env),
+                shared: SharedNamespace::open_all(config)?,
+                slirp: slirp_binary(config)?,
+                bridge: None,
+                id_mappings: id_mappings(config)?,
+                init: None,
+            };
+            (overlay.join("merged"), launch)
+        }
+        None => {
+            preflight_command(config, &rootfs)?;
+            let launch = Launch::resolve(config, &rootfs)?;
+            (rootfs.clone(), launch)
+        }
+    };
+    mounts.extend(mounts::mount_plan(
+        config,
+        &container_root,
+        &state::rootfs_mount_dir("<container-id>")?,
+    ));
+
+    cgroups::check_cpuset(&config.limits)?;
+    cgroups::check_available(&config.limits)?;
+
+    let limits = cgroups::cgroup_limits(&config.limits)
+        .into_iter()
+        .map(|(file, value)| (file.to_string(), value))
+        .collect();
+
+    Ok(RunPlan {
+        config: config.clone(),
+        rootfs: rootfs.display().to_string(),
+        namespaces: namespaces::namespace_names(namespaces::container_clone_flags(config))
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        mounts,
+        cgroup: CgroupPlan {
+            path: cgroups::cgroup_path("<container-id>").display().to_string(),
+            limits,
+        },
+        user: launch.user,
+        id_mappings: launch.id_mappings,
+        joined: launch.shared.iter().map(SharedNamespace::joined).collect(),
+        env: launch.env,
+    })
+}
+
+/// Unless `--no-preflight` is given, check before forking that the command
+/// exists in `rootfs` and can be executed, so that a missing one is reported
+/// with the names closest to it rather than by the container. A `--tmpfs`
+/// mounted where the command is looked up hides what the rootfs has there,
+/// so a failed check only warns then.
+fn preflight_command(config: &ContainerConfig, rootfs: &Path) -> Result<()> {
+    if config.no_preflight {
+        return Ok(());
+    }
+    let Err(e) = validate::validate_command(rootfs, &config.cmd) else {
+        return Ok(());
+    };
+    let program = config.cmd.first().map_or("", String::as_str);
+    let dirs: Vec<PathBuf> = if program.contains('/') {
+        let path = Path::new("/").join(program);
+        path.parent().map(Path::to_path_buf).into_iter().collect()
+    } else {
+        DEFAULT_PATH.split(':').map(PathBuf::from).collect()
+    };
+    let shadowed = config
+        .tmpfs
+        .iter()
+        .any(|tmpfs| dirs.iter().any(|dir| dir.starts_with(&tmpfs.path)));
+    if !shadowed {
+        return Err(e);
+    }
+    eprintln!("warning: {e:#} (ignored: a --tmpfs mount covers where it is looked up)");
+    Ok(())
+}
+
+/// Attach `image` to a loop device and mount it under the container's
+/// overlay. Returns the overlay's merged directory and the loop device.
+fn setup_image_rootfs(
+    container_id: &str,
+    image: &Path,
+    format: ImageFormat,
+    storage: Option<&StorageLimit>,
+) -> Result<(PathBuf, PathBuf)> {
+    let device = loopdev::attach(image, false)?;
+    let overlay_dir = state::overlay_dir(container_id)?;
+    let limited = match storage {
+        Some(limit) => {
+            let image = state::storage_image(container_id)?;
+            storage::limit_writable_layer(container_id, &overlay_dir, &image, limit)
+        }
+        None => Ok(()),
+    };
+    match limited.and_then(|()| mounts::mount_image_overlay(&device.path, format, &overlay_dir)) {
+        Ok(merged) => Ok((merged, device.path.clone())),
+        Err(e) => {
+            let _ = teardown_rootfs(container_id, Some(&device.path));
+            Err(e)
+        }
+    }
+}
+
+/// Tear down a container's overlay (if any), with the size limit of its
+/// writable layer, and detach its loop device (if any).
+pub fn teardown_rootfs(container_id: &str, loop_device: Option<&Path>) -> Result<()> {
+    let overlay_dir = state::overlay_dir(container_id)?;
+    let image = state::storage_image(container_id)?;
+    storage::release_writable_layer(&overlay_dir, &image)?;
+    mounts::teardown_overlay(&overlay_dir)?;
+    if let Some(device) = loop_device {
+        loopdev::detach(device)?;
+    }
+    Ok(())
+}
+
 fn parent_process(
     child: Pid,
-    container_id: &str,
+    unstarted: &Unstarted,
     config: &ContainerConfig,
-    mut reader: File,
+    launch: &Launch,
+    loop_device: Option<&Path>,
+    setup: SetupPipe<impl FnMut(Option<SetupPhase>)>,
+    on_start: impl FnOnce(&ContainerMeta) -> Result<()>,
 ) -> Result<RunResult> {
-    // Read any error message from the child through the pipe.
-    let mut buf = String::new();
-    reader.read_to_string(&mut buf).ok();
-    drop(reader);
+    let container_id = unstarted.id.as_str();
+    let created_at = chrono::Utc::now();
 
-    if !buf.is_empty() {
-        bail!("container child setup failed: {buf}");
+    // Keep the monitor alive under memory pressure so the container is never
+    // orphaned. Set after the fork, so the container does not inherit it.
+    if let Err(e) = set_oom_score_adj(MONITOR_OOM_SCORE_ADJ) {
+        eprintln!("warning: failed to protect monitor from the OOM killer: {e:#}");
     }
 
+    // Block until the container has exec'd (EOF) or reported an error.
+    if let Some(report) = setup.wait()? {
+        // The container never started, so nothing else will clean up after it.
+        let _ = wait_for_child(child);
+        let _ = teardown_rootfs(container_id, loop_device);
+        if let Some(link) = &launch.bridge {
+            let _ = ipam::release(&link.network.name, container_id);
+        }
+        match report {
+            ChildReport::Phase(_) => unreachable!("phases are passed on above"),
+            ChildReport::Launch(e) => return Err(e.into()),
+            ChildReport::Setup(msg) => bail!("container child setup failed: {msg}"),
+        }
+    }
+
+    // The container's network namespace exists now; connect it to the host's
+    // network before announcing the container.
+    let slirp = match &launch.slirp {
+        Some(binary) => {
+            match Slirp::start(binary, container_id, child.as_raw() as u32, &config.ports) {
+                Ok(slirp) => Some(slirp),
+                Err(e) => {
+                    abort_container(child, container_id, loop_device);
+                    return Err(e);
+                }
+            }
+        }
+        None => None,
+    };
+
     // Save metadata.
-    let meta = crate::core::model::ContainerMeta {
+    let mut meta = ContainerMeta {
+        schema_version: crate::core::model::META_SCHEMA_VERSION,
         id: container_id.to_string(),
+        name: config.name.clone(),
+        service: config.service.clone(),
         rootfs: config.rootfs.clone(),
         cmd: config.cmd.clone(),
         pid: child.as_raw() as u32,
         exit_code: None,
-        created_at: chrono::Utc::now(),
+        created_at,
+        started_at: Some(chrono::Utc::now()),
+        finished_at: None,
         status: crate::core::model::ContainerStatus::Running,
         hostname: config.hostname.clone(),
-        memory_limit: config.memory,
-        cpu_limit: config.cpu.clone(),
-        pids_limit: config.pids,
+        memory_limit: None,
+        memory_limit_spec: None,
+        memory_swappiness: None,
+        cpu_limit: None,
+        pids_limit: None,
+        pids_limit_hits: 0,
+        cpuset_cpus: None,
+        cpuset_mems: None,
+        ulimits: config.ulimits.clone(),
+        oom_score_adj: config.oom_score_adj,
+        umask: config.umask,
+        timezone: config.timezone.clone(),
+        devices: config.devices.clone(),
+        tmpfs: config.tmpfs.clone(),
+        shm_size: config.shm_size,
+        mount_propagation: config.mount_propagation,
+        proc_opts: config.proc_opts,
+        keyring: config.keyring,
+        userns: config.userns,
+        id_mappings: launch.id_mappings.clone(),
+        cgroup_ns: config.cgroup_ns,
+        loop_device: loop_device.map(|d| d.display().to_string()),
+        storage: config.storage,
+        sysctls: config.sysctls.clone(),
+        env: launch.env.clone(),
+        env_host: config.env_host,
+        user: launch.user.clone(),
+        domainname: config.domainname.clone(),
+        net: launch.namespace(config, NamespaceKind::Net),
+        pid_ns: launch.namespace(config, NamespaceKind::Pid),
+        ipc: launch.namespace(config, NamespaceKind::Ipc),
+        uts: launch.namespace(config, NamespaceKind::Uts),
+        network: launch.bridge.as_ref().map(|link| link.network.name.clone()),
+        ip_address: launch.bridge.as_ref().map(|link| link.ip),
+        mac_address: launch.bridge.as_ref().map(|link| link.mac),
+        ports: config.ports.clone(),
+        slirp_pid: slirp.as_ref().map(Slirp::pid),
+        stop_signal: config.stop_signal.clone(),
+        init_path: config.init_path.clone(),
+        argv0: config.argv0.clone(),
+        no_pivot: config.no_pivot,
+        allow_chroot_fallback: config.allow_chroot_fallback,
+        no_preflight: config.no_preflight,
+        attach_stdin: config.attach_stdin,
+        signal: None,
+        core_dumped: false,
+        health: config.health.as_ref().map(HealthState::new),
+        invocation: config.invocation.clone(),
     };
-    state::save_meta(&meta)?;
+    meta.apply_limits(&config.limits);
+    // The container runs now: if it cannot be announced, it is killed and
+    // recorded as stopped rather than left running without a monitor.
+    let saved = state::save_meta(&meta);
+    if saved.is_ok() {
+        unstarted.disarm();
+    }
+    if let Err(e) = saved.and_then(|()| on_start(&meta)) {
+        let exit = abort_container(child, container_id, loop_device);
+        if let Some(link) = &launch.bridge {
+            let _ = ipam::release(&link.network.name, container_id);
+        }
+        if let Some(slirp) = slirp {
+            slirp.stop();
+        }
+        meta.status = ContainerStatus::Stopped;
+        meta.exit_code = Some(exit.as_ref().map_or(EXIT_CODE_UNKNOWN, |exit| exit.code));
+        meta.signal = exit
+            .and_then(|exit| exit.signal)
+            .map(|s| s.as_str().to_string());
+        meta.finished_at = Some(chrono::Utc::now());
+        meta.pid = 0;
+        meta.slirp_pid = None;
+        let _ = state::save_meta(&meta);
+        return Err(e);
+    }
+
+    let health = config.health.as_ref().and_then(|check| {
+        HealthMonitor::start(container_id, check)
+            .map_err(|e| eprintln!("warning: health checks disabled: {e:#}"))
+            .ok()
+    });
 
     // Wait for the child.
-    let exit_code = wait_for_child(child)?;
+    let exit = wait_for_child(child)?;
+    if let Some(health) = health {
+        health.stop();
+    }
+    if let Some(slirp) = slirp {
+        slirp.stop();
+    }
+    let exit_code = exit.code;
 
-    // Update metadata.
+    // Update metadata. A signal that killed the container's init is recorded
+    // by the child in the signal file; one that killed the child itself
+    // (e.g. `rm --force`) is seen here.
+    let lock = state::lock_meta(container_id)?;
     let mut meta = state::load_meta(container_id)?;
     meta.status = crate::core::model::ContainerStatus::Stopped;
     meta.exit_code = Some(exit_code);
+    match exit.signal {
+        Some(signal) => {
+            meta.signal = Some(signal.as_str().to_string());
+            meta.core_dumped = exit.core_dumped;
+        }
+        None => {
+            if let Some((signal, core_dumped)) = state::read_signal(container_id)? {
+                meta.signal = Some(signal);
+                meta.core_dumped = core_dumped;
+            }
+        }
+    }
+    meta.finished_at = Some(chrono::Utc::now());
     meta.pid = 0;
+    meta.slirp_pid = None;
+    meta.pids_limit_hits = cgroups::pids_limit_hits(container_id);
+    if meta.pids_limit_hits > 0 {
+        let limit = meta.pids_limit.map_or("max".to_string(), |max| max.to_string());
+        eprintln!(
+            "warning: pids limit reached ({limit}); {} fork(s) failed",
+            meta.pids_limit_hits
+        );
+    }
     state::save_meta(&meta)?;
+    drop(lock);
+    state::write_exit_code(container_id, exit_code)?;
+    let exit = meta.exit().unwrap_or(ContainerExit::Exited(exit_code));
+
+    // Exec sessions outlive the container's init when it shares the host's
+    // PID namespace; kill them before their root is unmounted.
+    let _ = cgroups::kill_cgroup(container_id);
+
+    // Tear down the overlay first so no busy mount keeps the cgroup or the
+    // state directory alive.
+    teardown_rootfs(container_id, loop_device)?;
 
     // Clean up cgroup.
     let _ = cgroups::remove_cgroup(container_id);
 
-    Ok(RunResult {
-        container_id: container_id.to_string(),
-        exit_code,
-    })
+    Ok(RunResult { exit_code, exit })
+}
+
+/// Kill a container that started but could not be set up, and clean up
+/// after it. Returns how the child exited, if it could be waited for.
+fn abort_container(
+    child: Pid,
+    container_id: &str,
+    loop_device: Option<&Path>,
+) -> Option<ChildExit> {
+    if let Ok(Some(init)) = container_init(child.as_raw() as u32) {
+        let _ = send_signal(init, Signal::SIGKILL);
+    }
+    let exit = wait_for_child(child).ok();
+    let _ = cgroups::kill_cgroup(container_id);
+    let _ = teardown_rootfs(container_id, loop_device);
+    let _ = cgroups::remove_cgroup(container_id);
+    exit
 }
 
 fn child_process(
     config: &ContainerConfig,
+    launch: &Launch,
     rootfs: &Path,
     container_id: &str,
     stdout_file: &File,
     stderr_file: &File,
+    report_fd: RawFd,
 ) -> Result<()> {
-    // 1. Unshare namespaces.
-    let flags = namespaces::container_clone_flags();
-    namespaces::unshare_namespaces(flags)?;
-
-    // 2. Set up cgroup and place ourselves into it BEFORE fork into PID namespace.
-    let cg_path = cgroups::setup_cgroup(
-        container_id,
-        config.memory,
-        config.cpu.as_deref(),
-        config.pids,
-    )?;
+    // 1. Set up cgroup and place ourselves into it BEFORE fork into PID
+    // namespace, and before a user namespace takes away our rights to it.
+    write_report(report_fd, &ChildReport::Phase(SetupPhase::Cgroups));
+    let cg_path = cgroups::setup_cgroup(container_id, &config.limits)?;
     cgroups::add_process(&cg_path, std::process::id())?;
 
+    // Opened now, as init's pivot_root also moves our root away from the
+    // state directory, which a user namespace's root may not write to.
+    let mut signal_file = File::create(state::signal_file(container_id)?)
+        .context("failed to create signal file")?;
+    // Inherited by init and its command. Set while the host's /proc is ours,
+    // as a user namespace cannot lower the score and `--proc-opts ro` makes
+    // the container's /proc read-only.
+    if let Some(adj) = config.oom_score_adj {
+        set_oom_score_adj(adj)?;
+    }
+    if !config.attach_stdin {
+        detach_stdin()?;
+    }
+
+    // 2. Unshare namespaces, joining shared ones instead of creating them. A
+    // joined PID namespace, like a new one, only applies to our children.
+    write_report(report_fd, &ChildReport::Phase(SetupPhase::Namespaces));
+    for shared in &launch.shared {
+        namespaces::join_namespace(shared.ns.as_fd(), namespaces::clone_flag(shared.kind))
+            .with_context(|| {
+                format!("failed to join {} namespace of {}", shared.kind, shared.id)
+            })?;
+    }
+    let mut flags = namespaces::container_clone_flags(config);
+    if let Some(mappings) = &launch.id_mappings {
+        namespaces::unshare_user_namespace(mappings)?;
+        flags.remove(CloneFlags::CLONE_NEWUSER);
+    }
+    namespaces::unshare_namespaces(flags)?;
+    if let Some(link) = &launch.bridge {
+        link.connect(container_id)
+            .context("failed to connect the container to the bridge")?;
+    }
+
     // 3. Fork again to enter the PID namespace (the child of this fork gets PID 1).
     match unsafe { unistd::fork() }.context("inner fork (pid namespace) failed")? {
         ForkResult::Parent { child } => {
+            debug!("forked container init {child}");
+            // Only the container init reports from here on; drop our copy of
+            // the pipe so the parent sees EOF as soon as init execs.
+            unsafe { libc::close(report_fd) };
             // Wait for the grandchild (container init).
             let status = waitpid(child, None).context("waitpid on container init")?;
+            debug!("container init ended: {status:?}");
             let code = match status {
                 WaitStatus::Exited(_, c) => c,
-                WaitStatus::Signaled(_, sig, _) => 128 + sig as i32,
+                WaitStatus::Signaled(_, sig, core_dumped) => {
+                    // Our own exit status can only carry the signal number.
+                    let record = state::signal_record(sig.as_str(), core_dumped);
+                    let _ = signal_file.write_all(record.as_bytes());
+                    128 + sig as i32
+                }
                 _ => 1,
             };
             std::process::exit(code);
         }
         ForkResult::Child => {
             // This is PID 1 inside the new PID namespace.
-            init_container(config, rootfs, stdout_file, stderr_file)?;
+            init_container(
+                config,
+                launch,
+                rootfs,
+                container_id,
+                stdout_file,
+                stderr_file,
+                report_fd,
+            )?;
             unreachable!("exec should have replaced this process");
         }
     }
@@ -169,99 +868,416 @@ fn child_process(
 
 fn init_container(
     config: &ContainerConfig,
+    launch: &Launch,
     rootfs: &Path,
+    container_id: &str,
     stdout_file: &File,
     stderr_file: &File,
+    report_fd: RawFd,
 ) -> Result<()> {
-    // Set hostname.
-    namespaces::set_hostname(&config.hostname)?;
-
-    // Mount setup: make tree private, bind-mount rootfs, mount /proc, pivot_root.
-    mounts::make_mount_private()?;
-    mounts::bind_mount_rootfs(rootfs)?;
-    mounts::mount_proc(rootfs)?;
-    mounts::pivot_root(rootfs)?;
-    mounts::mount_proc_in_new_root()?;
-    mounts::mount_dev_in_new_root()?;
-
-    // Redirect stdout/stderr to log files.
+    // Set hostname, unless the UTS namespace is not ours to change.
+    if config.uts.is_new() {
+        namespaces::set_hostname(&config.hostname)?;
+        if let Some(domainname) = &config.domainname {
+            namespaces::set_domainname(domainname)?;
+        }
+    }
+
+    // Everything is mounted in the container's own mount namespace, which
+    // goes away with it, so a failure leaves nothing to unwind; the error
+    // says how far setup got.
+    let mut mounts_done = MountLog::default();
+    write_report(report_fd, &ChildReport::Phase(SetupPhase::Mounts));
+    setup_mounts(
+        config,
+        rootfs,
+        container_id,
+        launch.init.as_ref(),
+        &mut mounts_done,
+    )
+    .map_err(|e| mounts_done.annotate(e))?;
+    apply_sysctls(&config.sysctls)?;
+    if config.proc_opts.read_only {
+        mounts::remount_proc_read_only()?;
+    }
+
+    // Redirect stdout/stderr to log files, logging to the CLI's stderr.
+    crate::util::logging::keep_stderr()?;
     nix::unistd::dup2(stdout_file.as_raw_fd(), 1).context("dup2 stdout")?;
     nix::unistd::dup2(stderr_file.as_raw_fd(), 2).context("dup2 stderr")?;
 
+    apply_ulimits(&config.ulimits)?;
+    if let Some(mask) = config.umask {
+        set_umask(mask);
+    }
+    if config.keyring.is_private() {
+        join_session_keyring()?;
+    }
+    // Last, since everything above needs root.
+    if let Some(user) = &launch.user {
+        switch_user(user)?;
+    }
+
     // Exec the user command.
-    let cmd = &config.cmd;
-    if cmd.is_empty() {
+    if config.cmd.is_empty() {
         bail!("no command specified");
     }
 
-    let program = CString::new(cmd[0].as_str())
-        .with_context(|| format!("invalid command: '{}'", cmd[0]))?;
-    let args: Vec<CString> = cmd
-        .iter()
-        .map(|a| CString::new(a.as_str()).context("invalid argument"))
-        .collect::<Result<_>>()?;
+    let env = env_cstrings(&launch.env)?;
+    exec_command(&launch.pid1_cmd(config), config.argv0.as_deref(), &env)
+}
 
-    // Set minimal environment.
-    let env: Vec<CString> = vec![
-        CString::new("PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin").unwrap(),
-        CString::new(format!("HOSTNAME={}", config.hostname)).unwrap(),
-        CString::new("TERM=xterm").unwrap(),
-        CString::new("HOME=/root").unwrap(),
-    ];
+/// Make tree private, stage the rootfs, pivot_root, mount /proc, /dev, a
+/// host `init` and the `--tmpfs` mounts, recording each mount in `done`.
+fn setup_mounts(
+    config: &ContainerConfig,
+    rootfs: &Path,
+    container_id: &str,
+    init: Option<&InitBinary>,
+    done: &mut MountLog,
+) -> Result<()> {
+    let staging = state::rootfs_mount_dir(container_id)?;
+    // Host device nodes and a host init must be opened before the host root
+    // goes away.
+    let device_nodes = mounts::device_nodes(&config.devices)?;
+    let host_init = match init {
+        Some(init) => init.open_host()?,
+        None => None,
+    };
+    let host_dev_nodes = match config.userns {
+        UsernsMode::KeepId => Some(mounts::HostDevNodes::open()?),
+        UsernsMode::Host => None,
+    };
+    mounts::set_root_propagation(config.mount_propagation)?;
+    done.record(format!("/ (r{})", config.mount_propagation));
+    mounts::bind_mount_rootfs(rootfs, &staging)?;
+    done.record(format!(
+        "{} (rbind of {})",
+        staging.display(),
+        rootfs.display()
+    ));
+    if config.mount_propagation == MountPropagation::Shared {
+        // pivot_root refuses a new root whose parent mount is shared.
+        let parent = mounts::make_parent_mount_private(&staging)?;
+        done.record(format!("{parent} (private)"));
+    }
+    if mounts::bind_hostname_file(&state::hostname_file(container_id)?, &staging)? {
+        done.record("/etc/hostname (bind)");
+    }
+    if let Some(timezone) = &config.timezone {
+        mounts::bind_localtime(timezone, &staging)?;
+        done.record(format!(
+            "/etc/localtime (read-only bind of {})",
+            timezone.host_file().display()
+        ));
+    }
+    if config.no_pivot {
+        mounts::move_root(&staging, config.mount_propagation)?;
+        done.record("/ (move, chroot)");
+    } else if mounts::pivot_root(
+        &staging,
+        config.allow_chroot_fallback,
+        config.mount_propagation,
+    )? {
+        done.record("/ (move, chroot)");
+    } else {
+        done.record("/ (pivot_root)");
+    }
+    mounts::mount_proc_in_new_root(config.proc_opts)?;
+    done.record("/proc (proc)");
+    mounts::mount_dev_in_new_root(host_dev_nodes.as_ref())?;
+    done.record("/dev (tmpfs)");
+    mounts::mount_shm(config.dev_shm_size())?;
+    done.record("/dev/shm (tmpfs)");
+    if mounts::stdin_is_terminal() {
+        mounts::bind_console()?;
+        done.record("/dev/console (bind of the terminal on stdin)");
+    }
+    mounts::create_device_nodes(&device_nodes)?;
+    if let (Some(file), Some(init)) = (&host_init, init) {
+        mounts::bind_init(file)?;
+        done.record(format!(
+            "{} (read-only bind of {})",
+            init.container_path(),
+            config.init_path.as_deref().unwrap_or_default()
+        ));
+    }
+    for tmpfs in &config.tmpfs {
+        mounts::mount_tmpfs(tmpfs, config.tmpfs_default_size())?;
+        done.record(format!("{} (tmpfs)", tmpfs.path));
+    }
+    Ok(())
+}
 
-    nix::unistd::execve(&program, &args, &env)
-        .with_context(|| format!("execve '{}' failed", cmd[0]))?;
+/// Switch to `user`: supplementary groups, then GID, then UID. Fails unless
+/// every step succeeds and root cannot be regained afterwards.
+pub fn switch_user(user: &ContainerUser) -> Result<()> {
+    debug!("switch to uid {} gid {} groups {:?}", user.uid, user.gid, user.groups);
+    let groups: Vec<Gid> = user.groups.iter().map(|&g| Gid::from_raw(g)).collect();
+    unistd::setgroups(&groups).context("failed to set supplementary groups")?;
+    unistd::setgid(Gid::from_raw(user.gid))
+        .with_context(|| format!("failed to switch to GID {}", user.gid))?;
+    unistd::setuid(Uid::from_raw(user.uid))
+        .with_context(|| format!("failed to switch to UID {}", user.uid))?;
 
-    unreachable!();
+    if user.uid != 0 && unistd::setuid(Uid::from_raw(0)).is_ok() {
+        bail!(
+            "failed to drop privileges: root could be regained after switching to UID {}",
+            user.uid
+        );
+    }
+    Ok(())
 }
 
-/// Wait for a child process and return its exit code.
-fn wait_for_child(pid: Pid) -> Result<i32> {
-    loop {
-        match waitpid(pid, None) {
-            Ok(WaitStatus::Exited(_, code)) => return Ok(code),
-            Ok(WaitStatus::Signaled(_, sig, _)) => return Ok(128 + sig as i32),
-            Ok(_) => continue,
-            Err(nix::errno::Errno::EINTR) => continue,
-            Err(e) => bail!("waitpid failed: {e}"),
+/// Convert a resolved `KEY=VALUE` environment for `execve`.
+pub fn env_cstrings(env: &[String]) -> Result<Vec<CString>> {
+    env.iter()
+        .map(|var| CString::new(var.as_str()).with_context(|| format!("invalid env '{var}'")))
+        .collect()
+}
+
+/// Exec `cmd` in the current root, with argv[0] replaced by `argv0` if given.
+/// A bare command name is looked up in [`DEFAULT_PATH`](crate::core::model::DEFAULT_PATH), like `execvp(3)`.
+///
+/// Only returns on failure. Failures to find the command, or the ELF loader
+/// or `#!` interpreter it needs, are [`LaunchError`]s.
+pub fn exec_command(cmd: &[String], argv0: Option<&str>, env: &[CString]) -> Result<()> {
+    let Some(path) = validate::find_command(Path::new("/"), &cmd[0])? else {
+        return Err(LaunchError::NotFound(cmd[0].clone()).into());
+    };
+    let program =
+        CString::new(path.as_str()).with_context(|| format!("invalid command: '{}'", cmd[0]))?;
+    let args = exec_args(cmd, argv0)?;
+
+    debug!("execve {path} {args:?}");
+    match nix::unistd::execve(&program, &args, env) {
+        Err(Errno::ENOENT) => {
+            // The file exists, so what is missing is its interpreter.
+            if let Ok(Some(interpreter)) = elf::read_interpreter(Path::new(&path)) {
+                if !Path::new(&interpreter).exists() {
+                    return Err(LaunchError::InterpreterNotFound {
+                        command: cmd[0].clone(),
+                        interpreter,
+                    }
+                    .into());
+                }
+            }
+            Err(LaunchError::NotFound(cmd[0].clone()).into())
         }
+        Err(Errno::EACCES) => Err(LaunchError::NotExecutable(cmd[0].clone()).into()),
+        Err(e) => Err(e).with_context(|| format!("execve '{}' failed", cmd[0])),
+        Ok(_) => unreachable!(),
     }
 }
 
-/// Validate that the rootfs path is safe and looks correct.
-fn validate_rootfs(rootfs: &str) -> Result<()> {
-    if rootfs.is_empty() {
-        bail!("rootfs path must not be empty");
+/// Apply `--ulimit` settings to the current process; they are inherited
+/// across `execve`.
+pub fn apply_ulimits(ulimits: &[Ulimit]) -> Result<()> {
+    for ulimit in ulimits {
+        let resource = match ulimit.kind {
+            UlimitKind::Nofile => Resource::RLIMIT_NOFILE,
+            UlimitKind::Nproc => Resource::RLIMIT_NPROC,
+            UlimitKind::Core => Resource::RLIMIT_CORE,
+            UlimitKind::Stack => Resource::RLIMIT_STACK,
+            UlimitKind::Memlock => Resource::RLIMIT_MEMLOCK,
+        };
+        let value = |v: u64| if v == RLIM_UNLIMITED { RLIM_INFINITY } else { v };
+        debug!("setrlimit {ulimit}");
+        setrlimit(resource, value(ulimit.soft), value(ulimit.hard))
+            .with_context(|| format!("failed to set ulimit {ulimit}"))?;
     }
+    Ok(())
+}
 
-    let path = Path::new(rootfs);
+/// Write `--sysctl` values under the container's freshly mounted `/proc`.
+fn apply_sysctls(sysctls: &[Sysctl]) -> Result<()> {
+    for sysctl in sysctls {
+        debug!("sysctl {sysctl}");
+        fs::write(sysctl.proc_path(), &sysctl.value)
+            .with_context(|| format!("failed to set sysctl {sysctl}"))?;
+    }
+    Ok(())
+}
 
-    // Refuse dangerous paths.
-    let canon = if path.exists() {
-        fs::canonicalize(path)
-            .with_context(|| format!("cannot canonicalize rootfs path '{rootfs}'"))?
-    } else {
-        bail!("rootfs path '{rootfs}' does not exist");
+/// Point stdin at `/dev/null`, so the container does not read craterun's
+/// terminal or pipe unless `--attach-stdin` asks for it.
+fn detach_stdin() -> Result<()> {
+    let null = File::open("/dev/null").context("failed to open /dev/null")?;
+    nix::unistd::dup2(null.as_raw_fd(), 0).context("dup2 stdin")?;
+    Ok(())
+}
+
+/// Set the file mode creation mask of this process, which `exec` keeps.
+pub fn set_umask(mask: Umask) {
+    debug!("umask {mask}");
+    nix::sys::stat::umask(Mode::from_bits_truncate(mask.0));
+}
+
+/// Join a fresh, anonymous session keyring, so that keys of the session
+/// craterun was started from cannot be read or altered. Nothing to do on
+/// kernels without keyrings.
+pub fn join_session_keyring() -> Result<()> {
+    // SAFETY: a NULL name asks for a new anonymous keyring; no memory is
+    // passed to the kernel.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            libc::KEYCTL_JOIN_SESSION_KEYRING,
+            std::ptr::null::<libc::c_char>(),
+        )
     };
+    match Errno::result(ret) {
+        Ok(serial) => {
+            debug!("joined session keyring {serial}");
+            Ok(())
+        }
+        Err(Errno::ENOSYS) => {
+            debug!("kernel has no keyrings");
+            Ok(())
+        }
+        Err(e) => Err(e).context("failed to create a session keyring"),
+    }
+}
+
+/// Set the OOM killer score adjustment of the current process.
+pub fn set_oom_score_adj(adj: i32) -> Result<()> {
+    debug!("oom_score_adj {adj}");
+    fs::write("/proc/self/oom_score_adj", adj.to_string())
+        .with_context(|| format!("failed to set oom_score_adj to {adj}"))
+}
+
+/// Build the argv for `execve`: `cmd`, with argv[0] replaced by `argv0` if
+/// given. The program path itself is always `cmd[0]`.
+fn exec_args(cmd: &[String], argv0: Option<&str>) -> Result<Vec<CString>> {
+    let first = argv0.unwrap_or(&cmd[0]);
+    std::iter::once(first)
+        .chain(cmd[1..].iter().map(String::as_str))
+        .map(|a| CString::new(a).with_context(|| format!("invalid argument: '{a}'")))
+        .collect()
+}
 
-    if canon == Path::new("/") {
-        bail!("refusing to use '/' as rootfs — this would destroy the host");
+/// Send a length-prefixed report to the parent. Best effort: the child is
+/// about to exit either way.
+fn write_report(fd: RawFd, report: &ChildReport) {
+    let msg = serde_json::to_vec(report).unwrap_or_default();
+    let mut frame = (msg.len() as u32).to_le_bytes().to_vec();
+    frame.extend_from_slice(&msg);
+    let _ = unsafe { libc::write(fd, frame.as_ptr() as *const _, frame.len()) };
+}
+
+/// The state of a container that has no metadata yet: its directory and
+/// cgroup are removed when dropped, unless disarmed once the metadata is
+/// saved, so that a `run` failing before then leaves nothing behind.
+struct Unstarted {
+    id: String,
+    armed: Cell<bool>,
+}
+
+impl Unstarted {
+    fn new(id: &str) -> Self {
+        Unstarted {
+            id: id.to_string(),
+            armed: Cell::new(true),
+        }
     }
 
-    // Check it looks like a filesystem root (has bin/ or usr/ or etc/).
-    let looks_like_root = canon.join("bin").is_dir()
-        || canon.join("usr").is_dir()
-        || canon.join("etc").is_dir();
+    fn disarm(&self) {
+        self.armed.set(false);
+    }
+}
 
-    if !looks_like_root {
-        bail!(
-            "rootfs '{}' does not look like a filesystem root (no bin/, usr/, or etc/ found). \
-             Please provide a path to an extracted rootfs (e.g. Alpine minirootfs).",
-            canon.display()
-        );
+impl Drop for Unstarted {
+    fn drop(&mut self) {
+        // Nothing is removed while anything may still be mounted in it.
+        if self.armed.get() && teardown_rootfs(&self.id, None).is_ok() {
+            let _ = cgroups::remove_cgroup(&self.id);
+            let _ = state::remove_container_dir(&self.id);
+        }
     }
+}
 
-    Ok(())
+/// The parent's end of the setup pipe, and where the phases the child
+/// reports on it go.
+struct SetupPipe<F> {
+    reader: File,
+    on_progress: F,
+}
+
+impl<F: FnMut(Option<SetupPhase>)> SetupPipe<F> {
+    /// Wait for the child's final report, or EOF once the container command
+    /// was exec'd, passing on the phases reported before it. Calls
+    /// `on_progress` with `None` every [`PROGRESS_TICK_MS`] in between.
+    fn wait(mut self) -> Result<Option<ChildReport>> {
+        let mut fds = [PollFd::new(self.reader.as_fd(), PollFlags::POLLIN)];
+        loop {
+            match poll(&mut fds, PROGRESS_TICK_MS) {
+                Ok(0) => {
+                    (self.on_progress)(None);
+                    continue;
+                }
+                Ok(_) => {}
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(e).context("failed to wait on setup pipe"),
+            }
+            match read_report(&mut &self.reader)? {
+                Some(ChildReport::Phase(phase)) => (self.on_progress)(Some(phase)),
+                report => return Ok(report),
+            }
+        }
+    }
+}
+
+/// Read the next report from the setup pipe. `None` means the pipe was
+/// closed without a further report, i.e. the container command was exec'd.
+fn read_report(reader: &mut impl Read) -> Result<Option<ChildReport>> {
+    let mut len = [0u8; 4];
+    let mut filled = 0;
+    while filled < len.len() {
+        match reader.read(&mut len[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => bail!("container child setup failed: truncated report"),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e).context("failed to read from setup pipe"),
+        }
+    }
+
+    let mut msg = vec![0; u32::from_le_bytes(len) as usize];
+    reader
+        .read_exact(&mut msg)
+        .context("container child setup failed: truncated report")?;
+    let report = serde_json::from_slice(&msg).with_context(|| {
+        format!(
+            "container child setup failed: {}",
+            String::from_utf8_lossy(&msg)
+        )
+    })?;
+    Ok(Some(report))
+}
+
+/// Wait for a child process and return its exit code.
+fn wait_for_child(pid: Pid) -> Result<ChildExit> {
+    loop {
+        match waitpid(pid, None) {
+            Ok(WaitStatus::Exited(_, code)) => {
+                return Ok(ChildExit {
+                    code,
+                    signal: None,
+                    core_dumped: false,
+                })
+            }
+            Ok(WaitStatus::Signaled(_, sig, core_dumped)) => {
+                return Ok(ChildExit {
+                    code: 128 + sig as i32,
+                    signal: Some(sig),
+                    core_dumped,
+                })
+            }
+            Ok(_) => continue,
+            Err(Errno::EINTR) => continue,
+            Err(e) => bail!("waitpid failed: {e}"),
+        }
+    }
 }
 
 /// Send SIGKILL to a running container process.
@@ -276,3 +1292,132 @@ pub fn kill_container(pid: u32) -> Result<()> {
     let _ = waitpid(pid, None);
     Ok(())
 }
+
+/// Stop the running container whose recorded process is `pid`: send `signal`
+/// to its command and, if the container is still running after `timeout`,
+/// SIGKILL. Returns whether SIGKILL was needed.
+///
+/// As PID 1 of its namespace, the command only receives signals it handles;
+/// one it ignores or leaves at the default action is escalated.
+pub fn stop_container(pid: u32, signal: Signal, timeout: Duration) -> Result<bool> {
+    let Some(init) = container_init(pid)? else {
+        return Ok(false);
+    };
+    debug!("sending {signal} to container init {init}");
+    if !send_signal(init, signal)? || wait_for_exit(pid, timeout) {
+        return Ok(false);
+    }
+    debug!("container init {init} still running after {timeout:?}, sending SIGKILL");
+    send_signal(init, Signal::SIGKILL)?;
+    if !wait_for_exit(pid, KILL_TIMEOUT) {
+        bail!("container process {pid} did not exit after SIGKILL");
+    }
+    Ok(true)
+}
+
+/// Kill the running container whose recorded process is `pid` with SIGKILL
+/// and wait for it to be gone, as `stop` does once its timeout has passed.
+pub fn kill_running(pid: u32) -> Result<()> {
+    stop_container(pid, Signal::SIGKILL, KILL_TIMEOUT).map(drop)
+}
+
+/// The container's command: the only child of its recorded process. `None`
+/// once it has exited.
+fn container_init(pid: u32) -> Result<Option<Pid>> {
+    let path = format!("/proc/{pid}/task/{pid}/children");
+    let children = match fs::read_to_string(&path) {
+        Ok(children) => children,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {path}")),
+    };
+    Ok(children
+        .split_whitespace()
+        .next()
+        .and_then(|child| child.parse().ok())
+        .map(Pid::from_raw))
+}
+
+/// Send `signal` to `pid`. Returns false if the process is already gone.
+fn send_signal(pid: Pid, signal: Signal) -> Result<bool> {
+    match nix::sys::signal::kill(pid, signal) {
+        Ok(()) => Ok(true),
+        Err(Errno::ESRCH) => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("failed to send {signal} to process {pid}")),
+    }
+}
+
+/// Wait up to `timeout` for process `pid` to be gone (reaped by the monitor).
+/// Returns whether it is.
+fn wait_for_exit(pid: u32, timeout: Duration) -> bool {
+    let start = Instant::now();
+    while state::pid_alive(pid) {
+        if start.elapsed() >= timeout {
+            return false;
+        }
+        thread::sleep(STOP_POLL_INTERVAL);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_args_with_argv0_override() {
+        let cmd = vec!["/bin/busybox".to_string(), "-c".to_string()];
+        assert_eq!(
+            exec_args(&cmd, None).unwrap(),
+            [c"/bin/busybox".to_owned(), c"-c".to_owned()]
+        );
+        assert_eq!(
+            exec_args(&cmd, Some("sh")).unwrap(),
+            [c"sh".to_owned(), c"-c".to_owned()]
+        );
+    }
+
+    #[test]
+    fn report_round_trip_over_pipe() {
+        let (read_fd, write_fd) = nix::unistd::pipe2(OFlag::O_CLOEXEC).unwrap();
+        let report = ChildReport::Launch(LaunchError::NotFound("/bin/nope".into()));
+        write_report(write_fd.as_raw_fd(), &report);
+        drop(write_fd);
+
+        let mut reader = File::from(read_fd);
+        match read_report(&mut reader).unwrap() {
+            Some(ChildReport::Launch(LaunchError::NotFound(cmd))) => assert_eq!(cmd, "/bin/nope"),
+            other => panic!("unexpected report: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn phases_come_before_the_final_report() {
+        let (read_fd, write_fd) = nix::unistd::pipe2(OFlag::O_CLOEXEC).unwrap();
+        for phase in [SetupPhase::Cgroups, SetupPhase::Mounts] {
+            write_report(write_fd.as_raw_fd(), &ChildReport::Phase(phase));
+        }
+        write_report(write_fd.as_raw_fd(), &ChildReport::Setup("boom".into()));
+        drop(write_fd);
+
+        let mut reader = File::from(read_fd);
+        let mut reports = Vec::new();
+        while let Some(report) = read_report(&mut reader).unwrap() {
+            reports.push(format!("{report:?}"));
+        }
+        assert_eq!(reports, ["Phase(Cgroups)", "Phase(Mounts)", "Setup(\"boom\")"]);
+    }
+
+    #[test]
+    fn closed_pipe_means_ready() {
+        let (read_fd, write_fd) = nix::unistd::pipe2(OFlag::O_CLOEXEC).unwrap();
+        drop(write_fd);
+        let mut reader = File::from(read_fd);
+        assert!(read_report(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_report_is_an_error() {
+        let mut reader: &[u8] = &[10, 0, 0, 0, b'{'];
+        assert!(read_report(&mut reader).is_err());
+    }
+}
@@ -0,0 +1,280 @@
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use serde_json::{json, Value};
+
+use crate::core::model::PortMapping;
+use crate::core::state;
+
+/// The helper providing `--net slirp`, looked up in `PATH`.
+const SLIRP_BINARY: &str = "slirp4netns";
+
+/// The device the helper creates in the container's network namespace.
+const TAP_DEVICE: &str = "tap0";
+
+/// Address the helper gives the container; published ports forward to it.
+const CONTAINER_ADDR: &str = "10.0.2.100";
+
+/// How long the helper may take to configure the network, in milliseconds.
+const READY_TIMEOUT_MS: u16 = 10_000;
+
+/// How long the helper may take to exit once told to.
+const EXIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often an exiting helper is polled.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Find `slirp4netns` in `PATH`.
+pub fn find_binary() -> Result<PathBuf> {
+    find_binary_in(&std::env::var_os("PATH").unwrap_or_default())
+}
+
+fn find_binary_in(path: &OsStr) -> Result<PathBuf> {
+    std::env::split_paths(path)
+        .map(|dir| dir.join(SLIRP_BINARY))
+        .find(|candidate| is_executable(candidate))
+        .with_context(|| {
+            format!(
+                "--net slirp needs {SLIRP_BINARY}, which was not found in PATH; install it \
+                 (e.g. `apt install slirp4netns` or `dnf install slirp4netns`)"
+            )
+        })
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// A running `slirp4netns` connecting a container's network namespace to the
+/// host's network. It exits when told to or when its parent, the monitor,
+/// dies.
+pub struct Slirp {
+    child: Child,
+    /// The helper exits once this is closed (`--exit-fd`).
+    exit: OwnedFd,
+}
+
+impl Slirp {
+    /// Start `binary` for the network namespace of process `pid` of container
+    /// `id`, wait until it has configured the container's network and
+    /// forward `ports` into it.
+    pub fn start(binary: &Path, id: &str, pid: u32, ports: &[PortMapping]) -> Result<Self> {
+        let socket = state::slirp_socket(id)?;
+        let log_path = state::log_path(id, state::SLIRP_LOG)?;
+        let log = File::create(&log_path).context("failed to create slirp.log")?;
+        let (ready_read, ready_write) =
+            nix::unistd::pipe2(OFlag::O_CLOEXEC).context("failed to create pipe")?;
+        let (exit_read, exit_write) =
+            nix::unistd::pipe2(OFlag::O_CLOEXEC).context("failed to create pipe")?;
+        let inherited = [ready_write.as_raw_fd(), exit_read.as_raw_fd()];
+
+        let mut cmd = Command::new(binary);
+        cmd.args(["--configure", "--mtu=65520", "--disable-host-loopback"])
+            .arg(format!("--ready-fd={}", inherited[0]))
+            .arg(format!("--exit-fd={}", inherited[1]))
+            .arg("--api-socket")
+            .arg(&socket)
+            .arg(pid.to_string())
+            .arg(TAP_DEVICE)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone().context("failed to duplicate slirp.log")?)
+            .stderr(log);
+        // SAFETY: only fcntl, which is async-signal-safe, runs between fork
+        // and exec.
+        unsafe {
+            cmd.pre_exec(move || {
+                for fd in inherited {
+                    if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        let child = cmd
+            .spawn()
+            .with_context(|| format!("failed to run {}", binary.display()))?;
+        drop((ready_write, exit_read));
+        debug!("started {SLIRP_BINARY} {} for container {id}", child.id());
+
+        let slirp = Self {
+            child,
+            exit: exit_write,
+        };
+        let setup = wait_ready(ready_read).and_then(|()| {
+            ports
+                .iter()
+                .try_for_each(|port| publish_port(&socket, port))
+        });
+        if let Err(e) = setup {
+            slirp.stop();
+            return Err(e.context(format!(
+                "{SLIRP_BINARY} failed (see {})",
+                log_path.display()
+            )));
+        }
+        Ok(slirp)
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Tell the helper to exit and wait for it, killing it if it takes too
+    /// long.
+    pub fn stop(self) {
+        let Self { mut child, exit } = self;
+        drop(exit);
+        let start = Instant::now();
+        loop {
+            match child.try_wait() {
+                Ok(None) if start.elapsed() < EXIT_TIMEOUT => thread::sleep(POLL_INTERVAL),
+                Ok(None) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break;
+                }
+                Ok(Some(_)) | Err(_) => break,
+            }
+        }
+        debug!("{SLIRP_BINARY} {} stopped", child.id());
+    }
+}
+
+/// Wait for the helper to report on `ready` that the network is configured.
+fn wait_ready(ready: OwnedFd) -> Result<()> {
+    let mut fds = [PollFd::new(ready.as_fd(), PollFlags::POLLIN)];
+    if poll(&mut fds, READY_TIMEOUT_MS).context("failed to wait for the network")? == 0 {
+        bail!("network not ready after {}s", READY_TIMEOUT_MS / 1000);
+    }
+    let mut byte = [0u8; 1];
+    if File::from(ready).read(&mut byte).unwrap_or(0) == 0 {
+        bail!("{SLIRP_BINARY} exited before the network was ready");
+    }
+    Ok(())
+}
+
+/// Forward `port` from the host into the container through the helper's API
+/// socket.
+fn publish_port(socket: &Path, port: &PortMapping) -> Result<()> {
+    let request = json!({
+        "execute": "add_hostfwd",
+        "arguments": {
+            "proto": port.protocol,
+            "host_addr": port.host_addr,
+            "host_port": port.host_port,
+            "guest_addr": CONTAINER_ADDR,
+            "guest_port": port.container_port,
+        },
+    });
+    let reply = api_request(socket, &request).with_context(|| format!("--publish {port}"))?;
+    if let Some(error) = reply.get("error") {
+        let desc = error
+            .get("desc")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown error");
+        bail!("--publish {port}: {desc}");
+    }
+    debug!("published {port}");
+    Ok(())
+}
+
+/// Send one request to the helper's API socket and return its reply.
+fn api_request(socket: &Path, request: &Value) -> Result<Value> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("failed to connect to {}", socket.display()))?;
+    stream.write_all(request.to_string().as_bytes())?;
+    stream.shutdown(Shutdown::Write)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    serde_json::from_str(&reply).with_context(|| format!("invalid API reply '{reply}'"))
+}
+
+/// Kill the helper `pid` recorded for a container whose monitor died
+/// without stopping it. A PID since reused by another program is left alone.
+pub fn kill_leaked(pid: u32) {
+    let comm = fs::read_to_string(format!("/proc/{pid}/comm")).unwrap_or_default();
+    if comm.trim_end() == SLIRP_BINARY {
+        debug!("killing leaked {SLIRP_BINARY} {pid}");
+        let _ = nix::sys::signal::kill(Pid::from_raw(pid as i32), Signal::SIGKILL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    /// Serve one API request on `socket` with `reply`, returning the request.
+    fn fake_api(socket: &Path, reply: &'static str) -> thread::JoinHandle<Value> {
+        let listener = UnixListener::bind(socket).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            stream.read_to_string(&mut request).unwrap();
+            stream.write_all(reply.as_bytes()).unwrap();
+            serde_json::from_str(&request).unwrap()
+        })
+    }
+
+    #[test]
+    fn publish_port_sends_hostfwd_request() {
+        let tmp = tempfile::tempdir().unwrap();
+        let socket = tmp.path().join("api.sock");
+        let server = fake_api(&socket, r#"{"return": {"id": 1}}"#);
+
+        publish_port(&socket, &"127.0.0.1:8080:80/udp".parse().unwrap()).unwrap();
+        assert_eq!(
+            server.join().unwrap(),
+            json!({
+                "execute": "add_hostfwd",
+                "arguments": {
+                    "proto": "udp",
+                    "host_addr": "127.0.0.1",
+                    "host_port": 8080,
+                    "guest_addr": "10.0.2.100",
+                    "guest_port": 80,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn publish_port_reports_api_errors() {
+        let tmp = tempfile::tempdir().unwrap();
+        let socket = tmp.path().join("api.sock");
+        let server = fake_api(
+            &socket,
+            r#"{"error": {"desc": "bad request: add_hostfwd"}}"#,
+        );
+
+        let err = publish_port(&socket, &"8080:80".parse().unwrap()).unwrap_err();
+        server.join().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "--publish 0.0.0.0:8080:80/tcp: bad request: add_hostfwd"
+        );
+    }
+
+    #[test]
+    fn missing_binary_has_install_hint() {
+        let err = find_binary_in(OsStr::new("/nonexistent")).unwrap_err();
+        assert!(err.to_string().contains("apt install slirp4netns"), "{err}");
+    }
+}
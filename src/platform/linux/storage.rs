@@ -0,0 +1,240 @@
+//! Size limits of a container's writable layer (`--storage-size`).
+
+use std::fs::{self, File};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use nix::errno::Errno;
+
+use super::{loopdev, mounts};
+use crate::core::model::{StorageDriver, StorageLimit};
+
+// ioctl request numbers from <linux/fs.h>.
+const FS_IOC_FSGETXATTR: libc::c_ulong = 0x801C_581F;
+const FS_IOC_FSSETXATTR: libc::c_ulong = 0x401C_5820;
+
+/// New files and directories take the project ID of their parent.
+const FS_XFLAG_PROJINHERIT: u32 = 0x200;
+
+/// Quota type of project quotas, from <linux/quota.h>.
+const PRJQUOTA: libc::c_int = 2;
+
+/// Unit of the block limits of `struct dqblk` (`QIF_DQBLKSIZE`).
+const QUOTA_BLOCK_SIZE: u64 = 1024;
+
+/// Project IDs of containers have this bit set, keeping them clear of the
+/// small IDs administrators assign in /etc/projid.
+const PROJECT_ID_BASE: u32 = 0x4000_0000;
+
+/// `struct fsxattr` from <linux/fs.h>.
+#[repr(C)]
+#[derive(Default)]
+struct FsXattr {
+    fsx_xflags: u32,
+    fsx_extsize: u32,
+    fsx_nextents: u32,
+    fsx_projid: u32,
+    fsx_cowextsize: u32,
+    fsx_pad: [u8; 8],
+}
+
+/// Prepare `overlay_dir`, before the overlay's directories are created in
+/// it, so that the writable layer cannot grow past `limit`: set a project
+/// quota on it, or mount a filesystem of the size on it, kept in `image`
+/// for the `loop` driver. Writes past the limit fail with ENOSPC (EDQUOT
+/// under an ext4 project quota).
+pub fn limit_writable_layer(
+    container_id: &str,
+    overlay_dir: &Path,
+    image: &Path,
+    limit: &StorageLimit,
+) -> Result<()> {
+    match limit.driver {
+        StorageDriver::Quota => set_project_quota(container_id, overlay_dir, limit.size),
+        StorageDriver::Loop => {
+            make_ext4_image(image, limit.size)?;
+            let device = loopdev::attach(image, true)?;
+            mounts::mount_storage(Some(&device.path), limit.size, overlay_dir).inspect_err(|_| {
+                let _ = loopdev::detach(&device.path);
+            })
+        }
+        StorageDriver::Tmpfs => mounts::mount_storage(None, limit.size, overlay_dir),
+    }
+}
+
+/// Undo [`limit_writable_layer`] before `overlay_dir` is removed: drop its
+/// project quota, if it has one, and remove `image`, which a filesystem
+/// still mounted from it keeps open until it is unmounted.
+pub fn release_writable_layer(overlay_dir: &Path, image: &Path) -> Result<()> {
+    // Only a directory whose filesystem has project IDs can have a quota.
+    if let Ok(dir) = File::open(overlay_dir) {
+        let id = get_fsxattr(&dir).map_or(0, |attr| attr.fsx_projid);
+        if id & PROJECT_ID_BASE != 0 {
+            set_block_limit(&dir, id, 0).with_context(|| {
+                format!(
+                    "failed to drop the project quota of {}",
+                    overlay_dir.display()
+                )
+            })?;
+        }
+    }
+    match fs::remove_file(image) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("failed to remove {}", image.display())),
+    }
+}
+
+/// The project ID of a container's writable layer, from its hex ID.
+fn project_id(container_id: &str) -> u32 {
+    let hex = &container_id[..container_id.len().min(8)];
+    u32::from_str_radix(hex, 16).unwrap_or(0) | PROJECT_ID_BASE
+}
+
+/// Give the new, empty `dir` the container's project ID, inherited by
+/// everything created in it, and limit the project to `size` bytes.
+fn set_project_quota(container_id: &str, dir: &Path, size: u64) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let file = File::open(dir).with_context(|| format!("failed to open {}", dir.display()))?;
+    let id = project_id(container_id);
+    let result = get_fsxattr(&file)
+        .and_then(|mut attr| {
+            attr.fsx_projid = id;
+            attr.fsx_xflags |= FS_XFLAG_PROJINHERIT;
+            set_fsxattr(&file, &attr)
+        })
+        .and_then(|()| set_block_limit(&file, id, size.div_ceil(QUOTA_BLOCK_SIZE)));
+    match result {
+        Ok(()) => {
+            debug!("project {id} of {} limited to {size} bytes", dir.display());
+            Ok(())
+        }
+        Err(e) => bail!(
+            "cannot set a project quota on {}: {e}; --storage-driver quota needs project \
+             quotas enabled on its filesystem (xfs, or ext4 mounted with prjquota), else use \
+             --storage-driver loop or tmpfs",
+            dir.display()
+        ),
+    }
+}
+
+/// Set the hard block limit of project `id` on the filesystem of the open
+/// directory `dir`, in [`QUOTA_BLOCK_SIZE`] blocks; 0 removes it.
+fn set_block_limit(dir: &File, id: u32, blocks: u64) -> nix::Result<()> {
+    // SAFETY: all-zero is a valid `dqblk`.
+    let mut quota: libc::dqblk = unsafe { std::mem::zeroed() };
+    quota.dqb_bhardlimit = blocks;
+    quota.dqb_valid = libc::QIF_BLIMITS;
+    // SAFETY: quotactl_fd(2) reads the `dqblk`, which outlives the call.
+    Errno::result(unsafe {
+        libc::syscall(
+            libc::SYS_quotactl_fd,
+            dir.as_raw_fd(),
+            libc::QCMD(libc::Q_SETQUOTA, PRJQUOTA),
+            id,
+            &mut quota as *mut libc::dqblk,
+        )
+    })
+    .map(drop)
+}
+
+fn get_fsxattr(file: &File) -> nix::Result<FsXattr> {
+    let mut attr = FsXattr::default();
+    // SAFETY: FS_IOC_FSGETXATTR fills in the `fsxattr`, which outlives the
+    // call.
+    Errno::result(unsafe {
+        libc::ioctl(
+            file.as_raw_fd(),
+            FS_IOC_FSGETXATTR,
+            &mut attr as *mut FsXattr,
+        )
+    })?;
+    Ok(attr)
+}
+
+fn set_fsxattr(file: &File, attr: &FsXattr) -> nix::Result<()> {
+    // SAFETY: FS_IOC_FSSETXATTR reads the `fsxattr`, which outlives the call.
+    Errno::result(unsafe {
+        libc::ioctl(file.as_raw_fd(), FS_IOC_FSSETXATTR, attr as *const FsXattr)
+    })
+    .map(drop)
+}
+
+/// Create `image` as a sparse file of `size` bytes holding an empty ext4
+/// filesystem. It has no journal, which a throwaway layer does not need,
+/// and no blocks reserved for root, so all of `size` is usable.
+fn make_ext4_image(image: &Path, size: u64) -> Result<()> {
+    File::create(image)
+        .and_then(|file| file.set_len(size))
+        .with_context(|| format!("failed to create {}", image.display()))?;
+    let output = Command::new("mkfs.ext4")
+        .args(["-q", "-F", "-m", "0", "-O", "^has_journal"])
+        .arg(image)
+        .stdin(Stdio::null())
+        .output()
+        .context("failed to run mkfs.ext4 (is e2fsprogs installed?)")?;
+    if !output.status.success() {
+        let _ = fs::remove_file(image);
+        bail!(
+            "mkfs.ext4 failed for {}: {}",
+            image.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fsxattr_matches_kernel_layout() {
+        assert_eq!(std::mem::size_of::<FsXattr>(), 28);
+    }
+
+    #[test]
+    fn project_ids_are_kept_clear_of_small_ones() {
+        assert_eq!(project_id("0000000a5eed0000"), PROJECT_ID_BASE | 0xa);
+        assert_eq!(project_id("ffffffff00000000"), 0xffff_ffff);
+        assert_ne!(project_id("00000000"), 0);
+    }
+
+    #[test]
+    fn writes_past_the_limit_fail() {
+        for driver in [
+            StorageDriver::Quota,
+            StorageDriver::Loop,
+            StorageDriver::Tmpfs,
+        ] {
+            let tmp = tempfile::tempdir().unwrap();
+            let overlay = tmp.path().join("overlay");
+            let image = tmp.path().join("overlay.img");
+            let limit = StorageLimit {
+                size: 4 << 20,
+                driver,
+            };
+            // Quotas, loop devices and mounts need privileges and support
+            // from the host; skip the drivers that are unavailable.
+            if let Err(e) = limit_writable_layer("5eed0000", &overlay, &image, &limit) {
+                eprintln!("SKIP: cannot set up {driver} storage here: {e:#}");
+                let _ = mounts::teardown_overlay(&overlay);
+                continue;
+            }
+
+            let err = fs::write(overlay.join("big"), vec![0u8; 8 << 20]).unwrap_err();
+            let released = release_writable_layer(&overlay, &image);
+            mounts::teardown_overlay(&overlay).unwrap();
+            released.unwrap();
+            let errno = err.raw_os_error();
+            assert!(
+                errno == Some(libc::ENOSPC) || errno == Some(libc::EDQUOT),
+                "{driver}: {err}"
+            );
+            assert!(!overlay.exists() && !image.exists(), "{driver}");
+        }
+    }
+}
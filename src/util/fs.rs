@@ -1,4 +1,5 @@
 use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
 use std::path::Path;
 
 use anyhow::{Context, Result};
@@ -23,3 +24,137 @@ pub fn write_file(path: &Path, contents: &str) -> Result<()> {
     fs::write(path, contents)
         .with_context(|| format!("failed to write {}", path.display()))
 }
+
+/// Replace the file at `path` with `contents` atomically: they are written
+/// to a temporary file beside it, which is then renamed over it, so readers
+/// see either the old or the new contents, never part of them.
+pub fn write_file_atomic(path: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        ensure_dir(parent)?;
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{name}.{}.tmp", std::process::id()));
+    let written = fs::write(&tmp, contents).and_then(|()| fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written.with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+pub fn free_space(path: &Path) -> Result<u64> {
+    let stat = nix::sys::statvfs::statvfs(path)
+        .with_context(|| format!("failed to stat the filesystem of {}", path.display()))?;
+    Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+/// Size of the chunks `tail_lines` reads backwards from the end of a file.
+const TAIL_CHUNK: usize = 8192;
+
+/// Return the last `n` lines of the file at `path`, without their line
+/// endings, reading it backwards from the end in chunks so that a huge file
+/// costs no more than the lines returned. A final line without a newline
+/// counts as a line; invalid UTF-8 is replaced.
+pub fn tail_lines(path: &Path, n: usize) -> Result<Vec<String>> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    tail_chunked(&mut file, n, TAIL_CHUNK)
+        .with_context(|| format!("failed to read {}", path.display()))
+}
+
+fn tail_chunked<R: Read + Seek>(file: &mut R, n: usize, chunk: usize) -> io::Result<Vec<String>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    if n == 0 || len == 0 {
+        return Ok(Vec::new());
+    }
+
+    // Collect chunks from the end until they hold the newline before the
+    // first wanted line. A newline ending the file ends the last line
+    // rather than starting another, so it is not counted.
+    let mut tail = Vec::new();
+    let mut pos = len;
+    let mut newlines = 0;
+    while pos > 0 && newlines < n {
+        let start = pos.saturating_sub(chunk as u64);
+        let mut buf = vec![0; (pos - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut buf)?;
+        let counted = if pos == len { buf.len() - 1 } else { buf.len() };
+        newlines += buf[..counted].iter().filter(|&&b| b == b'\n').count();
+        buf.extend_from_slice(&tail);
+        tail = buf;
+        pos = start;
+    }
+
+    if tail.last() == Some(&b'\n') {
+        tail.pop();
+    }
+    let lines: Vec<&[u8]> = tail.split(|&b| b == b'\n').collect();
+    let skip = lines.len().saturating_sub(n);
+    Ok(lines[skip..]
+        .iter()
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn tail(contents: &str, n: usize, chunk: usize) -> Vec<String> {
+        tail_chunked(&mut Cursor::new(contents.as_bytes()), n, chunk).unwrap()
+    }
+
+    #[test]
+    fn tail_across_chunks() {
+        let contents: String = (0..1000).map(|i| format!("line {i}\n")).collect();
+        for chunk in [1, 3, 7, 64, TAIL_CHUNK] {
+            assert_eq!(
+                tail(&contents, 2, chunk),
+                ["line 998", "line 999"],
+                "{chunk}"
+            );
+            assert_eq!(tail(&contents, 1000, chunk).len(), 1000, "{chunk}");
+        }
+    }
+
+    #[test]
+    fn tail_of_large_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.log");
+        let contents: String = (0..100_000).map(|i| format!("line {i}\n")).collect();
+        assert!(contents.len() > 10 * TAIL_CHUNK);
+        fs::write(&path, contents).unwrap();
+
+        let lines = tail_lines(&path, 3).unwrap();
+        assert_eq!(lines, ["line 99997", "line 99998", "line 99999"]);
+    }
+
+    #[test]
+    fn tail_without_trailing_newline() {
+        assert_eq!(tail("a\nb\nc", 2, 2), ["b", "c"]);
+        assert_eq!(tail("a\nb\nc\n", 2, 2), ["b", "c"]);
+        // Empty lines count, but not the end of the last line.
+        assert_eq!(tail("a\n\n", 5, 4), ["a", ""]);
+    }
+
+    #[test]
+    fn tail_more_lines_than_the_file_has() {
+        assert_eq!(tail("a\nb\n", 10, 1), ["a", "b"]);
+        assert_eq!(tail("only", 10, 3), ["only"]);
+        assert!(tail("", 10, 3).is_empty());
+        assert!(tail("a\nb\n", 0, 3).is_empty());
+    }
+
+    #[test]
+    fn write_file_atomic_replaces_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("meta/metadata.json");
+        write_file_atomic(&path, "old").unwrap();
+        write_file_atomic(&path, "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        // No temporary file is left beside it.
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+}
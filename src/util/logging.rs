@@ -0,0 +1,111 @@
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use anyhow::{bail, Context, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+use nix::fcntl::{fcntl, FcntlArg};
+
+/// Environment variable selecting the log level when no flag does.
+pub const LOG_ENV: &str = "CRATERUN_LOG";
+
+/// The fd log lines are written to: stderr until [`keep_stderr`].
+static LOG_FD: AtomicI32 = AtomicI32::new(libc::STDERR_FILENO);
+
+static LOGGER: Logger = Logger;
+
+/// Writes each event as one line to [`LOG_FD`], tagged with the process ID
+/// since the monitor and the container's setup processes share it.
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "{} {:<5} craterun[{}] {}: {}\n",
+            chrono::Utc::now().format("%H:%M:%S%.3f"),
+            record.level(),
+            std::process::id(),
+            record.target().trim_start_matches("craterun::"),
+            record.args()
+        );
+        // A single unbuffered write: no lock that a fork could leave held,
+        // and lines from different processes do not interleave.
+        // SAFETY: the pointer and length describe `line`.
+        let _ = unsafe {
+            libc::write(
+                LOG_FD.load(Ordering::Relaxed),
+                line.as_ptr().cast(),
+                line.len(),
+            )
+        };
+    }
+
+    fn flush(&self) {}
+}
+
+/// Pick the log level: `--log-level` if given, else `-v` (debug) or `-vv`
+/// (trace), else the level named by [`LOG_ENV`]. Off by default.
+pub fn level(explicit: Option<LevelFilter>, verbose: u8, env: Option<&str>) -> Result<LevelFilter> {
+    if let Some(level) = explicit {
+        return Ok(level);
+    }
+    match (verbose, env) {
+        (1, _) => Ok(LevelFilter::Debug),
+        (2.., _) => Ok(LevelFilter::Trace),
+        (0, Some(s)) if !s.is_empty() => match s.parse() {
+            Ok(level) => Ok(level),
+            Err(_) => bail!(
+                "invalid {LOG_ENV} '{s}': expected off, error, warn, info, debug or trace"
+            ),
+        },
+        (0, _) => Ok(LevelFilter::Off),
+    }
+}
+
+/// Install the logger with the level picked by [`level`] from the flags and
+/// the environment.
+pub fn init(explicit: Option<LevelFilter>, verbose: u8) -> Result<()> {
+    let env = std::env::var(LOG_ENV).ok();
+    let level = level(explicit, verbose, env.as_deref())?;
+    log::set_logger(&LOGGER).context("logger already installed")?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// Keep logging to the current stderr after it is redirected, e.g. to the
+/// container's log file: log to a close-on-exec duplicate of it from now on.
+/// The duplicate is closed when the process execs.
+pub fn keep_stderr() -> Result<()> {
+    if log::max_level() == LevelFilter::Off {
+        return Ok(());
+    }
+    let fd: RawFd = fcntl(libc::STDERR_FILENO, FcntlArg::F_DUPFD_CLOEXEC(3))
+        .context("failed to duplicate stderr for logging")?;
+    LOG_FD.store(fd, Ordering::Relaxed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_precedence() {
+        assert_eq!(level(None, 0, None).unwrap(), LevelFilter::Off);
+        assert_eq!(level(None, 0, Some("")).unwrap(), LevelFilter::Off);
+        assert_eq!(level(None, 0, Some("INFO")).unwrap(), LevelFilter::Info);
+        assert_eq!(level(None, 1, Some("info")).unwrap(), LevelFilter::Debug);
+        assert_eq!(level(None, 3, None).unwrap(), LevelFilter::Trace);
+        let explicit = Some(LevelFilter::Error);
+        assert_eq!(level(explicit, 2, Some("trace")).unwrap(), LevelFilter::Error);
+
+        let err = level(None, 0, Some("loud")).unwrap_err();
+        assert!(err.to_string().contains(LOG_ENV), "{err}");
+    }
+}
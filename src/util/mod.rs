@@ -1 +1,4 @@
 pub mod fs;
+pub mod logging;
+pub mod signal;
+pub mod units;
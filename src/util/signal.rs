@@ -0,0 +1,54 @@
+use anyhow::{bail, Result};
+use nix::sys::signal::Signal;
+
+/// Parse a signal given by name or number: `TERM`, `SIGTERM`, `sigterm` and
+/// `15` all give `SIGTERM`.
+///
+/// Names are matched case-insensitively against the signals `nix` knows on
+/// this platform, with or without the `SIG` prefix.
+pub fn parse_signal(s: &str) -> Result<Signal> {
+    let s = s.trim();
+    if let Ok(number) = s.parse::<i32>() {
+        return match Signal::try_from(number) {
+            Ok(signal) => Ok(signal),
+            Err(_) => bail!("invalid signal '{s}': no signal with number {number}"),
+        };
+    }
+
+    let upper = s.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    match Signal::iterator().find(|signal| &signal.as_str()[3..] == name) {
+        Some(signal) => Ok(signal),
+        None => bail!("invalid signal '{s}': expected a name like TERM or SIGKILL, or a number"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_with_and_without_prefix() {
+        for s in ["TERM", "SIGTERM", "term", "sigterm", "SigTerm", " TERM "] {
+            assert_eq!(parse_signal(s).unwrap(), Signal::SIGTERM, "{s}");
+        }
+        assert_eq!(parse_signal("KILL").unwrap(), Signal::SIGKILL);
+        assert_eq!(parse_signal("hup").unwrap(), Signal::SIGHUP);
+        assert_eq!(parse_signal("SIGUSR1").unwrap(), Signal::SIGUSR1);
+        assert_eq!(parse_signal("winch").unwrap(), Signal::SIGWINCH);
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_signal("9").unwrap(), Signal::SIGKILL);
+        assert_eq!(parse_signal("15").unwrap(), Signal::SIGTERM);
+        assert_eq!(parse_signal("1").unwrap(), Signal::SIGHUP);
+    }
+
+    #[test]
+    fn rejects_invalid_signals() {
+        for bad in ["", "SIG", "TERMINATE", "SIGFOO", "0", "-9", "999", "1.5", "SIG15"] {
+            assert!(parse_signal(bad).is_err(), "{bad} should be rejected");
+        }
+    }
+}
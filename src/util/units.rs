@@ -0,0 +1,277 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+
+/// A byte count parsed from a human-readable size such as `64M`, keeping the
+/// original spelling for display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByteSize {
+    pub bytes: u64,
+    pub original: String,
+}
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(Self {
+            bytes: parse_bytes(s)?,
+            original: s.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.original)
+    }
+}
+
+/// Parse a byte count with an optional binary unit suffix.
+///
+/// Plain integers are bytes. Suffixes are case-insensitive and all powers of
+/// 1024 (as in Docker): `k`, `ki`, `kb`, `kib` = KiB, and likewise for `m`,
+/// `g` and `t`. A bare `b` means bytes.
+pub fn parse_bytes(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, unit) = s.split_at(split);
+    if digits.is_empty() {
+        bail!("invalid size '{s}': expected a number with an optional unit (e.g. 64M)");
+    }
+
+    let value: u64 = digits
+        .parse()
+        .with_context(|| format!("invalid size '{s}': number too large"))?;
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "ki" | "kb" | "kib" => 1 << 10,
+        "m" | "mi" | "mb" | "mib" => 1 << 20,
+        "g" | "gi" | "gb" | "gib" => 1 << 30,
+        "t" | "ti" | "tb" | "tib" => 1 << 40,
+        _ => bail!("invalid size '{s}': unknown unit '{unit}' (use k, M, G or T)"),
+    };
+
+    value
+        .checked_mul(multiplier)
+        .with_context(|| format!("invalid size '{s}': overflows a 64-bit byte count"))
+}
+
+/// Parse a duration with an optional unit suffix: `ms`, `s`, `m` or `h`.
+/// Plain integers are seconds.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, unit) = s.split_at(split);
+    if digits.is_empty() {
+        bail!("invalid duration '{s}': expected a number with an optional unit (e.g. 30s)");
+    }
+
+    let value: u64 = digits
+        .parse()
+        .with_context(|| format!("invalid duration '{s}': number too large"))?;
+    let millis_per_unit: u64 = match unit {
+        "ms" => 1,
+        "" | "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        _ => bail!("invalid duration '{s}': unknown unit '{unit}' (use ms, s, m or h)"),
+    };
+
+    let millis = value
+        .checked_mul(millis_per_unit)
+        .with_context(|| format!("invalid duration '{s}': too large"))?;
+    Ok(Duration::from_millis(millis))
+}
+
+/// Format a duration compactly for display, e.g. `45s`, `3m12s`, `2h5m`,
+/// `3d4h`. Only the two most significant units are shown.
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (days, hours, mins, secs) = (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{days}d{hours}h")
+    } else if hours > 0 {
+        format!("{hours}h{mins}m")
+    } else if mins > 0 {
+        format!("{mins}m{secs}s")
+    } else {
+        format!("{secs}s")
+    }
+}
+
+/// Format a duration approximately in words, like Docker's `ps`, e.g.
+/// `Less than a second`, `45 seconds`, `About a minute`, `3 hours`,
+/// `2 weeks`.
+pub fn format_duration_words(d: Duration) -> String {
+    let secs = d.as_secs();
+    // Hours and above are rounded to the nearest unit.
+    let hours = (secs + 1_800) / 3_600;
+    match secs {
+        0 => "Less than a second".to_string(),
+        1 => "1 second".to_string(),
+        2..=59 => format!("{secs} seconds"),
+        60..=119 => "About a minute".to_string(),
+        120..=3_599 => format!("{} minutes", secs / 60),
+        _ if hours == 1 => "About an hour".to_string(),
+        _ if hours < 48 => format!("{hours} hours"),
+        _ if hours < 24 * 7 * 2 => format!("{} days", hours / 24),
+        _ if hours < 24 * 30 * 2 => format!("{} weeks", hours / 24 / 7),
+        _ if hours < 24 * 365 * 2 => format!("{} months", hours / 24 / 30),
+        _ => format!("{} years", hours / 24 / 365),
+    }
+}
+
+/// Format how long before `now` the time `then` was, e.g. `Just now`,
+/// `45 seconds ago`, `About an hour ago`, `3 days ago`. Times more than
+/// `absolute_after` ago are shown as their date instead, e.g. `2024-06-15`.
+/// A `then` after `now`, from clock skew, reads as `Just now`.
+pub fn format_time_ago(
+    then: DateTime<Utc>,
+    now: DateTime<Utc>,
+    absolute_after: Duration,
+) -> String {
+    let age = (now - then).to_std().unwrap_or_default();
+    if age > absolute_after {
+        then.format("%Y-%m-%d").to_string()
+    } else if age < Duration::from_secs(1) {
+        "Just now".to_string()
+    } else {
+        format!("{} ago", format_duration_words(age))
+    }
+}
+
+/// Format a byte count compactly for display with binary units, e.g.
+/// `512B`, `1.5K`, `64.0M`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return format!("{bytes}B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1}{}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_words() {
+        let words = |secs| format_duration_words(Duration::from_secs(secs));
+        assert_eq!(words(0), "Less than a second");
+        assert_eq!(words(1), "1 second");
+        assert_eq!(words(45), "45 seconds");
+        assert_eq!(words(90), "About a minute");
+        assert_eq!(words(3 * 60 + 59), "3 minutes");
+        assert_eq!(words(3_600 + 1_799), "About an hour");
+        assert_eq!(words(2 * 3_600), "2 hours");
+        assert_eq!(words(3 * 86_400), "3 days");
+        assert_eq!(words(20 * 86_400), "2 weeks");
+        assert_eq!(words(90 * 86_400), "3 months");
+        assert_eq!(words(800 * 86_400), "2 years");
+    }
+
+    #[test]
+    fn time_ago() {
+        let now: DateTime<Utc> = "2025-06-15T12:00:00Z".parse().unwrap();
+        let year = Duration::from_secs(365 * 86_400);
+        let ago = |secs: i64| format_time_ago(now - chrono::Duration::seconds(secs), now, year);
+        assert_eq!(ago(0), "Just now");
+        // Clock skew never shows a negative or zero age.
+        assert_eq!(ago(-30), "Just now");
+        assert_eq!(ago(1), "1 second ago");
+        assert_eq!(ago(59), "59 seconds ago");
+        assert_eq!(ago(60), "About a minute ago");
+        assert_eq!(ago(5 * 60), "5 minutes ago");
+        assert_eq!(ago(3 * 86_400), "3 days ago");
+        assert_eq!(ago(90 * 86_400), "3 months ago");
+        assert_eq!(ago(365 * 86_400), "12 months ago");
+        assert_eq!(ago(365 * 86_400 + 1), "2024-06-15");
+    }
+
+    #[test]
+    fn bytes_plain_integers() {
+        assert_eq!(parse_bytes("0").unwrap(), 0);
+        assert_eq!(parse_bytes("67108864").unwrap(), 67108864);
+        assert_eq!(parse_bytes("512b").unwrap(), 512);
+    }
+
+    #[test]
+    fn bytes_units() {
+        assert_eq!(parse_bytes("1k").unwrap(), 1024);
+        assert_eq!(parse_bytes("1K").unwrap(), 1024);
+        assert_eq!(parse_bytes("1Ki").unwrap(), 1024);
+        assert_eq!(parse_bytes("64M").unwrap(), 64 << 20);
+        assert_eq!(parse_bytes("64Mi").unwrap(), 64 << 20);
+        assert_eq!(parse_bytes("128m").unwrap(), 128 << 20);
+        assert_eq!(parse_bytes("10MB").unwrap(), 10 << 20);
+        assert_eq!(parse_bytes("1G").unwrap(), 1 << 30);
+        assert_eq!(parse_bytes("1GiB").unwrap(), 1 << 30);
+        assert_eq!(parse_bytes("2T").unwrap(), 2 << 40);
+    }
+
+    #[test]
+    fn bytes_rejects_garbage() {
+        assert!(parse_bytes("").is_err());
+        assert!(parse_bytes("M").is_err());
+        assert!(parse_bytes("-1").is_err());
+        assert!(parse_bytes("1.5G").is_err());
+        assert!(parse_bytes("10X").is_err());
+    }
+
+    #[test]
+    fn bytes_overflow() {
+        assert!(parse_bytes("18446744073709551616").is_err());
+        assert!(parse_bytes("17179869184G").is_err());
+        assert_eq!(parse_bytes("16777215T").unwrap(), 16777215 << 40);
+        assert!(parse_bytes("16777216T").is_err());
+    }
+
+    #[test]
+    fn byte_size_keeps_original() {
+        let size: ByteSize = "64M".parse().unwrap();
+        assert_eq!(size.bytes, 64 << 20);
+        assert_eq!(size.to_string(), "64M");
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("99999999999999999999").is_err());
+        assert!(parse_duration("18446744073709551615h").is_err());
+    }
+
+    #[test]
+    fn duration_formatting() {
+        assert_eq!(format_duration(Duration::from_millis(400)), "0s");
+        assert_eq!(format_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(format_duration(Duration::from_secs(192)), "3m12s");
+        assert_eq!(format_duration(Duration::from_secs(2 * 3600 + 5 * 60 + 9)), "2h5m");
+        assert_eq!(format_duration(Duration::from_secs(3 * 86_400 + 4 * 3600)), "3d4h");
+    }
+
+    #[test]
+    fn byte_formatting() {
+        assert_eq!(format_bytes(0), "0B");
+        assert_eq!(format_bytes(1023), "1023B");
+        assert_eq!(format_bytes(1536), "1.5K");
+        assert_eq!(format_bytes(64 << 20), "64.0M");
+        assert_eq!(format_bytes(3 << 40), "3.0T");
+        assert_eq!(format_bytes(5000 << 40), "5000.0T");
+    }
+}
@@ -1,21 +1,21 @@
-/// Integration smoke test for CrateRun.
-///
-/// This test requires:
-/// 1. Running on Linux.
-/// 2. Running as root (or with sufficient privileges for namespaces + cgroups).
-/// 3. An Alpine minirootfs extracted at `tests/rootfs/` (or the path set in
-///    `CRATERUN_TEST_ROOTFS`).
-///
-/// In CI, the workflow downloads and extracts the rootfs before running tests.
-/// Locally, you can prepare it with:
-///
-/// ```bash
-/// mkdir -p tests/rootfs
-/// curl -L https://dl-cdn.alpinelinux.org/alpine/v3.20/releases/x86_64/alpine-minirootfs-3.20.3-x86_64.tar.gz \
-///     | tar -xz -C tests/rootfs
-/// ```
-///
-/// The test is skipped if not running as root or if the rootfs is missing.
+//! Integration smoke test for CrateRun.
+//!
+//! This test requires:
+//! 1. Running on Linux.
+//! 2. Running as root (or with sufficient privileges for namespaces + cgroups).
+//! 3. An Alpine minirootfs extracted at `tests/rootfs/` (or the path set in
+//!    `CRATERUN_TEST_ROOTFS`).
+//!
+//! In CI, the workflow downloads and extracts the rootfs before running tests.
+//! Locally, you can prepare it with:
+//!
+//! ```bash
+//! mkdir -p tests/rootfs
+//! curl -L https://dl-cdn.alpinelinux.org/alpine/v3.20/releases/x86_64/alpine-minirootfs-3.20.3-x86_64.tar.gz \
+//!     | tar -xz -C tests/rootfs
+//! ```
+//!
+//! The test is skipped if not running as root or if the rootfs is missing.
 
 use std::path::Path;
 use std::process::Command;
@@ -50,6 +50,26 @@ fn can_run() -> bool {
     true
 }
 
+/// Recursively list `dir` as `(relative path, size)` pairs, without following
+/// symlinks.
+fn snapshot_tree(dir: &Path) -> Vec<(std::path::PathBuf, u64)> {
+    let mut out = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(current) = stack.pop() {
+        for entry in std::fs::read_dir(&current).unwrap() {
+            let entry = entry.unwrap();
+            let meta = entry.path().symlink_metadata().unwrap();
+            let rel = entry.path().strip_prefix(dir).unwrap().to_path_buf();
+            out.push((rel, meta.len()));
+            if meta.is_dir() {
+                stack.push(entry.path());
+            }
+        }
+    }
+    out.sort();
+    out
+}
+
 fn nix_is_root() -> bool {
     #[cfg(target_os = "linux")]
     {
@@ -97,9 +117,9 @@ fn smoke_echo() {
         output.status.code()
     );
 
-    // The container ID is printed to stdout. Logs go to files.
+    // The container ID is printed to stderr at start. Logs go to files.
     // Verify there's a container ID (16 hex chars) on the first line.
-    let first_line = stdout.lines().next().unwrap_or("");
+    let first_line = stderr.lines().next().unwrap_or("");
     assert!(
         first_line.len() >= 16
             && first_line
@@ -182,7 +202,7 @@ fn smoke_ps_shows_stopped() {
 
     let ps_stdout = String::from_utf8_lossy(&ps_output.stdout);
     assert!(
-        ps_stdout.contains("stopped"),
+        ps_stdout.contains("Exited (0)"),
         "ps should show stopped container, got:\n{ps_stdout}"
     );
 }
@@ -204,7 +224,7 @@ fn smoke_rm_removes_container() {
         .output()
         .expect("failed to run craterun");
 
-    let container_id = String::from_utf8_lossy(&output.stdout)
+    let container_id = String::from_utf8_lossy(&output.stderr)
         .lines()
         .next()
         .unwrap_or("")
@@ -296,3 +316,1590 @@ fn smoke_refuses_root_as_rootfs() {
         "error message should warn about using / as rootfs, got: {stderr}"
     );
 }
+
+#[test]
+fn smoke_missing_command_exits_127() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--", "/no/such/binary"])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+
+    assert_eq!(
+        output.status.code(),
+        Some(127),
+        "missing command should exit 127, stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn smoke_non_executable_command_exits_126() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    // /etc/passwd exists in every rootfs but is not executable.
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--", "/etc/passwd"])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+
+    assert_eq!(
+        output.status.code(),
+        Some(126),
+        "non-executable command should exit 126, stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn smoke_cidfile() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+    let cidfile = tmp_home.path().join("cid");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--cidfile"])
+        .arg(&cidfile)
+        .args(["--", "/bin/true"])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+
+    assert!(output.status.success());
+    let cid = std::fs::read_to_string(&cidfile).expect("cidfile should be written");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.lines().next(), Some(cid.as_str()));
+    assert!(
+        output.stdout.is_empty(),
+        "stdout should not carry the container ID"
+    );
+}
+
+/// `--pidfile` holds the container's PID while it runs and is removed when it
+/// exits.
+#[test]
+fn smoke_pidfile() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+    let pidfile = tmp_home.path().join("pid");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--pidfile"])
+        .arg(&pidfile)
+        .args(["--", "/bin/sleep", "2"])
+        .env("HOME", tmp_home.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run craterun");
+
+    let mut line = String::new();
+    BufReader::new(child.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let id = line.trim().to_string();
+    let pid = std::fs::read_to_string(&pidfile).expect("pidfile should be written");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["inspect", &id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun inspect");
+    let meta: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(pid.trim(), meta["pid"].to_string());
+
+    assert!(child.wait().unwrap().success());
+    assert!(!pidfile.exists(), "pidfile should be removed on exit");
+}
+
+/// A `--pidfile` that cannot be written fails the run without leaving the
+/// container running or its cgroup behind.
+#[test]
+fn smoke_pidfile_unwritable() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+    let pidfile = tmp_home.path().join("missing/pid");
+    // Names the container among those of other tests.
+    let marker = format!("{}", std::process::id() as u64 + 1_000_000);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--pidfile"])
+        .arg(&pidfile)
+        .args(["--", "/bin/sleep", &marker])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("failed to create pidfile"), "{stderr}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["--format", "json", "ps"])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun ps");
+    let containers: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    let container = containers
+        .iter()
+        .find(|c| c["command"][1] == marker.as_str())
+        .expect("the container should be recorded");
+    let id = container["id"].as_str().unwrap();
+    assert_eq!(container["status"], "stopped", "{container}");
+    assert!(!Path::new(&format!("/sys/fs/cgroup/craterun/{id}")).exists());
+
+    let _ = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["rm", id])
+        .env("HOME", tmp_home.path())
+        .output();
+}
+
+#[test]
+fn smoke_attach_stdin() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--attach-stdin",
+            "--rootfs",
+            &rootfs,
+            "--",
+            "/bin/cat",
+        ])
+        .env("HOME", tmp_home.path())
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run craterun");
+    // Dropping the pipe closes it: `cat` must see EOF and exit.
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"piped through\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    assert_eq!(
+        String::from_utf8_lossy(&log_output.stdout),
+        "piped through\n"
+    );
+
+    // Without the flag, `cat` reads /dev/null even though craterun's stdin
+    // stays open.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--", "/bin/cat"])
+        .env("HOME", tmp_home.path())
+        .stdin(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("failed to run craterun");
+    let stdin = child.stdin.take();
+    assert!(child.wait().unwrap().success());
+    drop(stdin);
+}
+
+#[test]
+fn smoke_readiness_reported_before_exit() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use std::time::{Duration, Instant};
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let start = Instant::now();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--", "/bin/sleep", "5"])
+        .env("HOME", tmp_home.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run craterun");
+
+    // The container ID is printed once the child signals readiness, which
+    // must not wait for the container to exit.
+    let mut line = String::new();
+    BufReader::new(child.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let elapsed = start.elapsed();
+
+    assert_eq!(line.trim().len(), 16, "expected container ID, got: '{line}'");
+    assert!(
+        elapsed < Duration::from_secs(3),
+        "readiness took {elapsed:?}; the parent is blocking until container exit"
+    );
+
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn smoke_rootfs_left_untouched() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+    let before = snapshot_tree(Path::new(&rootfs));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--", "/bin/true"])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    assert!(output.status.success());
+
+    let after = snapshot_tree(Path::new(&rootfs));
+    assert_eq!(before, after, "running a container must not modify the rootfs");
+    assert!(!Path::new(&rootfs).join(".pivot_old").exists());
+}
+
+#[test]
+fn smoke_update_rewrites_cgroup_limits() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--pids", "100", "--", "/bin/sleep", "5"])
+        .env("HOME", tmp_home.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run craterun");
+
+    let mut line = String::new();
+    BufReader::new(child.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let id = line.trim().to_string();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["update", &id, "--pids", "200"])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun update");
+    assert!(
+        output.status.success(),
+        "update failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let pids_max = std::fs::read_to_string(format!("/sys/fs/cgroup/craterun/{id}/pids.max"))
+        .expect("failed to read pids.max");
+    assert_eq!(pids_max.trim(), "200");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["inspect", &id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun inspect");
+    let meta: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(meta["pids_limit"], 200);
+
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn smoke_ulimit_nofile() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--rootfs",
+            &rootfs,
+            "--ulimit",
+            "nofile=1234:2048",
+            "--",
+            "/bin/sh",
+            "-c",
+            "ulimit -n",
+        ])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    assert_eq!(String::from_utf8_lossy(&log_output.stdout).trim(), "1234");
+}
+
+#[test]
+fn smoke_no_core() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--rootfs",
+            &rootfs,
+            "--no-core",
+            "--",
+            "/bin/sh",
+            "-c",
+            "grep 'core file size' /proc/self/limits",
+        ])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    let limits = String::from_utf8_lossy(&log_output.stdout);
+    let values: Vec<&str> = limits.split_whitespace().skip(4).take(2).collect();
+    assert_eq!(values, ["0", "0"], "limits: {limits}");
+}
+
+#[test]
+fn smoke_oom_score_adj() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--rootfs",
+            &rootfs,
+            "--oom-score-adj",
+            "500",
+            // Set even though the container's /proc is read-only.
+            "--proc-opts",
+            "ro",
+            "--",
+            "/bin/cat",
+            "/proc/self/oom_score_adj",
+        ])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    assert_eq!(String::from_utf8_lossy(&log_output.stdout).trim(), "500");
+}
+
+#[test]
+fn smoke_umask() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--rootfs",
+            &rootfs,
+            "--umask",
+            "0077",
+            "--",
+            "/bin/sh",
+            "-c",
+            "umask && touch /tmp/umask-test && stat -c %a /tmp/umask-test",
+        ])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    assert_eq!(
+        String::from_utf8_lossy(&log_output.stdout).trim(),
+        "0077\n600"
+    );
+}
+
+#[test]
+fn smoke_timezone() {
+    if !can_run() || !Path::new("/usr/share/zoneinfo/Asia/Tokyo").exists() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--rootfs",
+            &rootfs,
+            "--timezone",
+            "Asia/Tokyo",
+            "--",
+            "/bin/sh",
+            "-c",
+            "date +%Z && echo $TZ && touch /etc/localtime || echo read-only",
+        ])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", "--stdout", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    assert_eq!(
+        String::from_utf8_lossy(&log_output.stdout).trim(),
+        "JST\n:/etc/localtime\nread-only"
+    );
+}
+
+#[test]
+fn smoke_device_passthrough() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    // /dev/full is not among the default nodes, so it only exists if passed through.
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--rootfs",
+            &rootfs,
+            "--device",
+            "/dev/full:/dev/myfull:rw",
+            "--",
+            "/bin/sh",
+            "-c",
+            "test -c /dev/myfull && head -c 4 /dev/myfull | wc -c",
+        ])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    assert_eq!(String::from_utf8_lossy(&log_output.stdout).trim(), "4");
+}
+
+#[test]
+fn smoke_console_and_stdio_symlinks() {
+    use std::io::Read;
+    use std::os::fd::FromRawFd;
+
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    // A terminal on stdin becomes the container's /dev/console.
+    let (mut master, slave) = {
+        let (mut master, mut slave) = (0, 0);
+        let ret = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(ret, 0, "openpty failed");
+        unsafe {
+            (
+                std::fs::File::from_raw_fd(master),
+                std::fs::File::from_raw_fd(slave),
+            )
+        }
+    };
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--rootfs",
+            &rootfs,
+            "--",
+            "/bin/sh",
+            "-c",
+            "echo hi > /dev/console && readlink /dev/stdout /dev/fd",
+        ])
+        .env("HOME", tmp_home.path())
+        .stdin(slave)
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let mut console = [0u8; 64];
+    let n = master.read(&mut console).unwrap();
+    assert_eq!(String::from_utf8_lossy(&console[..n]).trim(), "hi");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    assert_eq!(
+        String::from_utf8_lossy(&log_output.stdout).trim(),
+        "/proc/self/fd/1\n/proc/self/fd"
+    );
+}
+
+#[test]
+fn smoke_no_pivot_chroot() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--rootfs",
+            &rootfs,
+            "--no-pivot",
+            "--",
+            "/bin/sh",
+            "-c",
+            "test -f /etc/alpine-release && echo ok",
+        ])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    assert_eq!(String::from_utf8_lossy(&log_output.stdout).trim(), "ok");
+}
+
+/// `--no-pivot` keeps the tree private by default, as `pivot_root` does,
+/// rather than a slave receiving the host's mount events.
+#[test]
+fn smoke_no_pivot_keeps_propagation() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--no-pivot", "--"])
+        .args(["/bin/sh", "-c", "grep -c master: /proc/self/mountinfo"])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    assert_eq!(String::from_utf8_lossy(&log_output.stdout).trim(), "0");
+}
+
+#[test]
+fn smoke_proc_opts() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    // Under ro even root cannot write /proc/sys; under hidepid=2 nobody
+    // cannot see root's sleep.
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--rootfs",
+            &rootfs,
+            "--proc-opts",
+            "ro,hidepid=2",
+            "--",
+            "/bin/sh",
+            "-c",
+            "echo 1 > /proc/sys/kernel/panic || echo read-only; \
+             sleep 30 & su -s /bin/sh nobody -c \"test -e /proc/$! || echo hidden\"; kill $!",
+        ])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    let logs = String::from_utf8_lossy(&log_output.stdout);
+    assert_eq!(logs.trim(), "read-only\nhidden", "logs: {logs}");
+}
+
+#[test]
+fn smoke_tmpfs_mount() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--rootfs",
+            &rootfs,
+            "--tmpfs",
+            "/tmp:size=1m,noexec",
+            "--",
+            "/bin/sh",
+            "-c",
+            "grep '/tmp tmpfs' /proc/mounts | grep -c noexec && echo x > /tmp/f && cat /tmp/f",
+        ])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    assert_eq!(String::from_utf8_lossy(&log_output.stdout).trim(), "1\nx");
+
+    assert!(!Path::new(&rootfs).join("tmp/f").exists());
+}
+
+#[test]
+fn smoke_sysctl() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--rootfs",
+            &rootfs,
+            "--sysctl",
+            "net.ipv4.ip_unprivileged_port_start=80",
+            "--",
+            "/bin/cat",
+            "/proc/sys/net/ipv4/ip_unprivileged_port_start",
+        ])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    assert_eq!(String::from_utf8_lossy(&log_output.stdout).trim(), "80");
+}
+
+#[test]
+fn smoke_bare_command_uses_path() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--", "sh", "-c", "exit 7"])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    assert_eq!(
+        output.status.code(),
+        Some(7),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn smoke_env_precedence() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+    let env_file = tmp_home.path().join("app.env");
+    std::fs::write(&env_file, "# app settings\nLEVEL=file\nFROM_FILE=1\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--preserve-env", "CRATERUN_HOST_VAR"])
+        .arg("--env-file")
+        .arg(&env_file)
+        .args(["--env", "LEVEL=flag", "--", "/bin/sh", "-c"])
+        .arg("echo $CRATERUN_HOST_VAR $LEVEL $FROM_FILE $HOME ${CRATERUN_OTHER:-unset}")
+        .env("HOME", tmp_home.path())
+        .env("CRATERUN_HOST_VAR", "host")
+        .env("CRATERUN_OTHER", "leaked")
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    assert_eq!(
+        String::from_utf8_lossy(&log_output.stdout).trim(),
+        "host flag 1 /root unset"
+    );
+}
+
+#[test]
+fn smoke_user() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--rootfs",
+            &rootfs,
+            "--user",
+            "nobody",
+            "--",
+            "/bin/sh",
+            "-c",
+            "id; echo $HOME $USER",
+        ])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    let stdout = String::from_utf8_lossy(&log_output.stdout);
+    assert!(stdout.contains("uid=65534(nobody) gid=65534(nobody)"), "got: {stdout}");
+    assert!(stdout.contains("/ nobody"), "got: {stdout}");
+}
+
+#[test]
+fn smoke_hostname_file_and_domainname() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+    let etc_hostname = Path::new(&rootfs).join("etc/hostname");
+    let before = std::fs::read_to_string(&etc_hostname).ok();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args([
+            "run",
+            "--rootfs",
+            &rootfs,
+            "--hostname",
+            "web-1",
+            "--domainname",
+            "example.internal",
+            "--",
+            "/bin/sh",
+            "-c",
+            "cat /etc/hostname /proc/sys/kernel/domainname",
+        ])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    let stdout = String::from_utf8_lossy(&log_output.stdout);
+    if before.is_some() {
+        assert_eq!(stdout.trim(), "web-1\nexample.internal");
+    } else {
+        assert!(stdout.contains("example.internal"), "got: {stdout}");
+    }
+    assert_eq!(std::fs::read_to_string(&etc_hostname).ok(), before);
+}
+
+#[test]
+fn smoke_net_shares_container_namespace() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--", "/bin/sleep", "5"])
+        .env("HOME", tmp_home.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run craterun");
+
+    let mut line = String::new();
+    BufReader::new(child.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let target = line.trim().to_string();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["inspect", &target])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun inspect");
+    let meta: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let target_ns = std::fs::read_link(format!("/proc/{}/ns/net", meta["pid"])).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--net", &format!("container:{}", &target[..8])])
+        .args(["--", "/bin/sh", "-c", "readlink /proc/self/ns/net"])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    let stdout = String::from_utf8_lossy(&log_output.stdout);
+    assert_eq!(stdout.trim(), target_ns.to_str().unwrap());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["inspect", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun inspect");
+    let meta: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(meta["net"], format!("container:{target}"));
+
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn smoke_records_terminating_signal() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--", "/bin/sleep", "30"])
+        .env("HOME", tmp_home.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run craterun");
+
+    let mut line = String::new();
+    BufReader::new(child.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let id = line.trim().to_string();
+
+    let inspect = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["inspect", &id])
+            .env("HOME", tmp_home.path())
+            .output()
+            .expect("failed to run craterun inspect");
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+
+    // The container's init is the only child of the recorded process. PID 1
+    // of a namespace can only be killed from outside it.
+    let pid = inspect()["pid"].as_u64().unwrap();
+    let children = std::fs::read_to_string(format!("/proc/{pid}/task/{pid}/children")).unwrap();
+    let init = children.split_whitespace().next().expect("container init");
+    assert!(Command::new("kill").args(["-KILL", init]).status().unwrap().success());
+    child.wait().unwrap();
+
+    let meta = inspect();
+    assert_eq!(meta["exit_code"], 137);
+    assert_eq!(meta["signal"], "SIGKILL");
+    assert!(meta.get("core_dumped").is_none(), "{meta}");
+}
+
+#[test]
+fn smoke_health_check() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--health-cmd", "test -x /bin/sleep"])
+        .args(["--health-interval", "500ms", "--", "/bin/sleep", "3"])
+        .env("HOME", tmp_home.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run craterun");
+
+    let mut line = String::new();
+    BufReader::new(child.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let id = line.trim().to_string();
+
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+    let ps_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .arg("ps")
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun ps");
+    let ps_stdout = String::from_utf8_lossy(&ps_output.stdout);
+    assert!(ps_stdout.contains("(healthy)"), "got:\n{ps_stdout}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["inspect", &id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun inspect");
+    let meta: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(meta["health"]["status"], "healthy");
+    assert_eq!(meta["health"]["last_exit_code"], 0);
+
+    assert!(child.wait().unwrap().success());
+}
+
+/// `-v` logs the setup steps of both the monitor and the container process
+/// to stderr, including those after the container's stderr is redirected.
+#[test]
+fn smoke_verbose_logging() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["-v", "run", "--rootfs", &rootfs, "--", "/bin/true"])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+    for event in ["cgroup", "unshare", "mount", "execve /bin/true"] {
+        assert!(stderr.contains(event), "no {event:?} event in: {stderr}");
+    }
+    assert!(stderr.lines().any(|l| l.contains(" DEBUG craterun[")), "{stderr}");
+}
+
+/// Forks refused by `pids.max` are counted and reported when the container
+/// exits.
+#[test]
+fn smoke_pids_limit_reached() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--pids", "4", "--"])
+        .args(["/bin/sh", "-c", "for i in 1 2 3 4 5 6; do sleep 1 & done; wait"])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("pids limit reached (4)"), "stderr: {stderr}");
+
+    let id = stderr.lines().next().unwrap_or("").trim();
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["inspect", id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun inspect");
+    let meta: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(meta["pids_limit"], 4);
+    assert!(meta["pids_limit_hits"].as_u64().unwrap_or(0) > 0, "{meta}");
+}
+
+/// `--pid`, `--uts` and `--ipc` join another container's or the host's
+/// namespaces.
+#[test]
+fn smoke_shares_pid_uts_and_ipc_namespaces() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--hostname=web-7", "--", "/bin/sleep", "5"])
+        .env("HOME", tmp_home.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run craterun");
+
+    let mut line = String::new();
+    BufReader::new(child.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let target = line.trim().to_string();
+    let shared = format!("container:{target}");
+
+    let script = "tr '\\0' ' ' < /proc/1/cmdline; echo; \
+                  cat /proc/sys/kernel/hostname; readlink /proc/self/ns/ipc";
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--pid", &shared, "--uts", &shared])
+        .args(["--ipc", "host", "--", "/bin/sh", "-c", script])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "stderr: {stderr}");
+
+    let container_id = stderr.lines().next().unwrap_or("").trim();
+    let log_output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun logs");
+    let stdout = String::from_utf8_lossy(&log_output.stdout);
+    let host_ipc = std::fs::read_link("/proc/self/ns/ipc").unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "got: {stdout}");
+    assert_eq!(lines[0].trim(), "/bin/sleep 5");
+    assert_eq!(lines[1], "web-7");
+    assert_eq!(lines[2], host_ipc.to_str().unwrap());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["inspect", container_id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .expect("failed to run craterun inspect");
+    let meta: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(meta["pid_ns"], shared);
+    assert_eq!(meta["uts"], shared);
+    assert_eq!(meta["ipc"], "host");
+    assert_eq!(meta["hostname"], "web-7");
+
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn smoke_stop_signal() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+    let craterun = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(args)
+            .env("HOME", tmp_home.path())
+            .output()
+            .expect("failed to run craterun")
+    };
+    let start = |args: &[&str]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--rootfs", &rootfs])
+            .args(args)
+            .env("HOME", tmp_home.path())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to run craterun");
+        let mut line = String::new();
+        BufReader::new(child.stderr.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        (child, line.trim().to_string())
+    };
+
+    // A command that handles the stop signal exits on its own.
+    let script = "trap 'exit 7' USR1; while true; do sleep 0.1; done";
+    let (mut child, id) = start(&["--stop-signal", "usr1", "--", "/bin/sh", "-c", script]);
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    let output = craterun(&["stop", "-t", "10", &id]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Stopped"));
+    child.wait().unwrap();
+    let meta: serde_json::Value =
+        serde_json::from_slice(&craterun(&["inspect", &id]).stdout).unwrap();
+    assert_eq!(meta["stop_signal"], "SIGUSR1");
+    assert_eq!(meta["exit_code"], 7);
+
+    // PID 1 ignores an unhandled SIGTERM, so it is killed after the timeout.
+    let (mut child, id) = start(&["--", "/bin/sleep", "30"]);
+    let output = craterun(&["stop", "--time", "1", &id]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Killed"));
+    child.wait().unwrap();
+    let meta: serde_json::Value =
+        serde_json::from_slice(&craterun(&["inspect", &id]).stdout).unwrap();
+    assert!(meta.get("stop_signal").is_none(), "{meta}");
+    assert_eq!(meta["signal"], "SIGKILL");
+
+    let output = craterun(&["stop", &id]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not running"));
+}
+
+#[test]
+fn smoke_exec_detach() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+    let craterun = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(args)
+            .env("HOME", tmp_home.path())
+            .output()
+            .expect("failed to run craterun")
+    };
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--", "/bin/sleep", "30"])
+        .env("HOME", tmp_home.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run craterun");
+    let mut line = String::new();
+    BufReader::new(child.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let id = line.trim().to_string();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+
+    // The session ID is printed once the command has started.
+    let output = craterun(&[
+        "exec",
+        "--detach",
+        &id,
+        "--",
+        "/bin/sh",
+        "-c",
+        "echo $$; exit 3",
+    ]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let session = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+
+    let output = craterun(&["--format", "json", "exec", "--list", &id]);
+    let sessions: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(sessions[0]["id"], session.as_str());
+    assert_eq!(sessions[0]["exit_code"], 3);
+    // The command ran in the container's PID namespace.
+    let log = std::fs::read_to_string(sessions[0]["log"].as_str().unwrap()).unwrap();
+    assert_ne!(log.trim(), sessions[0]["pid"].to_string(), "log: {log}");
+
+    // Foreground sessions are recorded too, without a log.
+    let output = craterun(&["exec", &id, "--", "/bin/sh", "-c", "exit 5"]);
+    assert_eq!(output.status.code(), Some(5));
+    let output = craterun(&["--format", "json", "exec", "--list", &id]);
+    let sessions: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(sessions[1]["exit_code"], 5);
+    assert!(sessions[1]["log"].is_null());
+    let details: serde_json::Value =
+        serde_json::from_slice(&craterun(&["inspect", &id]).stdout).unwrap();
+    assert_eq!(details["exec_sessions"], 2);
+
+    assert!(craterun(&["stop", "--time", "1", &id]).status.success());
+    child.wait().unwrap();
+}
+
+#[test]
+fn smoke_exec_stays_in_container_root() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+    let craterun = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(args)
+            .env("HOME", tmp_home.path())
+            .output()
+            .expect("failed to run craterun")
+    };
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--", "/bin/sleep", "30"])
+        .env("HOME", tmp_home.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run craterun");
+    let mut line = String::new();
+    BufReader::new(child.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let id = line.trim().to_string();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+
+    // Neither the root's parent nor PID 1's root leads out of the rootfs.
+    let output = craterun(&[
+        "exec",
+        &id,
+        "--",
+        "/bin/sh",
+        "-c",
+        "ls /; echo --; ls /..; echo --; ls /proc/1/root",
+    ]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let listings: Vec<&str> = stdout.split("--\n").collect();
+    assert_eq!(listings.len(), 3, "stdout: {stdout}");
+    assert_eq!(listings[0], listings[1], "stdout: {stdout}");
+    assert_eq!(listings[0], listings[2], "stdout: {stdout}");
+
+    assert!(craterun(&["stop", "--time", "1", &id]).status.success());
+    child.wait().unwrap();
+}
+
+#[test]
+fn smoke_private_session_keyring() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+    let rootfs = rootfs_path();
+    if !Path::new(&rootfs).join("bin/keyctl").exists() {
+        eprintln!("Skipping keyring test (no keyctl in the rootfs)");
+        return;
+    }
+
+    // SAFETY: KEYCTL_GET_KEYRING_ID takes no pointers.
+    let host = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            libc::KEYCTL_GET_KEYRING_ID,
+            libc::KEY_SPEC_SESSION_KEYRING,
+            1,
+        )
+    };
+    assert!(
+        host > 0,
+        "no session keyring: {}",
+        std::io::Error::last_os_error()
+    );
+
+    let tmp_home = tempfile::tempdir().unwrap();
+    let session_keyring = |keyring: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--rootfs", &rootfs, "--keyring", keyring, "--"])
+            .args(["/bin/keyctl", "describe", "@s"])
+            .env("HOME", tmp_home.path())
+            .output()
+            .expect("failed to run craterun");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{stderr}");
+        let id = stderr.lines().next().unwrap_or("").trim().to_string();
+        let logs = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["logs", &id])
+            .env("HOME", tmp_home.path())
+            .output()
+            .unwrap();
+        // e.g. "739473274: alswrv-----v------------     0     0 keyring: _ses"
+        let stdout = String::from_utf8_lossy(&logs.stdout);
+        let serial = stdout.split(':').next().unwrap_or("");
+        serial.trim().parse::<i64>().unwrap()
+    };
+
+    assert_ne!(session_keyring("private"), host);
+    assert_eq!(session_keyring("host"), host);
+}
+
+#[test]
+fn smoke_cgroup_namespace() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+    let own_cgroup = |mode: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--rootfs", &rootfs, "--cgroup-ns", mode, "--"])
+            .args(["/bin/cat", "/proc/self/cgroup"])
+            .env("HOME", tmp_home.path())
+            .output()
+            .expect("failed to run craterun");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{stderr}");
+        let id = stderr.lines().next().unwrap_or("").trim().to_string();
+        let logs = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["logs", &id])
+            .env("HOME", tmp_home.path())
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&logs.stdout).trim().to_string()
+    };
+
+    // The container's cgroup is the root of its namespace.
+    assert_eq!(own_cgroup("private"), "0::/");
+    let host = own_cgroup("host");
+    assert!(host.contains("/craterun"), "cgroup: {host}");
+}
+
+#[test]
+fn smoke_userns_keep_id() {
+    if !can_run() {
+        eprintln!("Skipping integration test (prerequisites not met)");
+        return;
+    }
+    let owners = [Some("1000".to_string()), user_1000()];
+    let has_range = |file: &str| {
+        let contents = std::fs::read_to_string(file).unwrap_or_default();
+        contents
+            .lines()
+            .any(|l| owners.contains(&l.split(':').next().map(str::to_string)))
+    };
+    if !has_range("/etc/subuid") || !has_range("/etc/subgid") {
+        eprintln!("Skipping keep-id test (no subordinate IDs for UID 1000)");
+        return;
+    }
+
+    let rootfs = rootfs_path();
+    let tmp_home = tempfile::tempdir().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", &rootfs, "--userns", "keep-id", "--"])
+        .args([
+            "/bin/sh",
+            "-c",
+            "id -u; id -g; cat /proc/self/uid_map; echo x > /dev/null",
+        ])
+        .env("HOME", tmp_home.path())
+        .env("SUDO_UID", "1000")
+        .env("SUDO_GID", "1000")
+        .output()
+        .expect("failed to run craterun");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+
+    let id = stderr.lines().next().unwrap_or("").trim();
+    let logs = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["logs", id])
+        .env("HOME", tmp_home.path())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&logs.stdout);
+    let lines: Vec<Vec<&str>> = stdout
+        .lines()
+        .map(|l| l.split_whitespace().collect())
+        .collect();
+    assert_eq!(lines[0], ["1000"], "{stdout}");
+    assert_eq!(lines[1], ["1000"], "{stdout}");
+    assert!(lines.contains(&vec!["1000", "1000", "1"]), "{stdout}");
+}
+
+/// The name of the host user with UID 1000, if any.
+fn user_1000() -> Option<String> {
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd
+        .lines()
+        .find(|l| l.split(':').nth(2) == Some("1000"))
+        .and_then(|l| l.split(':').next())
+        .map(str::to_string)
+}
@@ -1,6 +1,12 @@
-/// Tests for CLI argument parsing and configuration validation.
+//! Tests for CLI argument parsing and configuration validation.
 
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Mutex;
+
+/// Held by tests that add the newest containers of the state directory,
+/// which as root all tests share, so they do not see each other's.
+static NEWEST_CONTAINERS: Mutex<()> = Mutex::new(());
 
 /// Verify the binary can print help without error.
 #[test]
@@ -62,6 +68,670 @@ fn cli_ps_empty() {
     );
 }
 
+/// Verify `ps --summary` appends totals, in text and JSON.
+#[test]
+fn cli_ps_summary() {
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["ps", "--summary"])
+        .output()
+        .expect("failed to execute craterun ps");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(" running, "), "got: {stdout}");
+    assert!(stdout.contains("Disk usage: "), "got: {stdout}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["--format", "json", "ps", "--summary"])
+        .output()
+        .expect("failed to execute craterun ps");
+    assert!(output.status.success());
+    let value: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("output should be JSON");
+    assert!(value["containers"].is_array(), "{value}");
+    assert!(value["summary"]["disk_usage"].is_u64(), "{value}");
+}
+
+/// Verify `--format json` makes `ps` and `images` print JSON arrays.
+#[test]
+fn cli_format_json() {
+    let tmp = tempfile::tempdir().unwrap();
+    for command in ["ps", "images"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["--format", "json", command])
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun");
+        assert!(output.status.success(), "{command} failed");
+        let value: serde_json::Value =
+            serde_json::from_slice(&output.stdout).expect("output should be JSON");
+        let items = value.as_array().expect("output should be an array");
+        if command == "ps" {
+            for item in items {
+                assert!(item["id"].is_string(), "{item}");
+                assert!(item["status"].is_string(), "{item}");
+            }
+        }
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["--format", "yaml", "ps"])
+        .output()
+        .expect("failed to execute craterun ps");
+    assert!(!output.status.success());
+}
+
+/// The state directory craterun uses when run with `HOME` set to `home`.
+fn state_dir(home: &Path) -> PathBuf {
+    if nix::unistd::geteuid().is_root() {
+        PathBuf::from("/var/lib/craterun")
+    } else {
+        home.join(".craterun")
+    }
+}
+
+/// Verify containers with unreadable metadata are listed as broken by `ps`,
+/// reported by `inspect`, and removed by `rm --force` and `prune --broken`.
+#[test]
+fn cli_broken_containers() {
+    let tmp = tempfile::tempdir().unwrap();
+    let ids = ["badc0ffee0000001", "badc0ffee0000002"];
+    for id in ids {
+        let dir = state_dir(tmp.path()).join(id);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("metadata.json"), "{garbage").unwrap();
+    }
+    let craterun = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(args)
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun")
+    };
+
+    let output = craterun(&["ps"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let row = stdout.lines().find(|l| l.starts_with(ids[0]));
+    assert!(row.is_some_and(|r| r.contains("broken")), "got: {stdout}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("cannot load container"), "got: {stderr}");
+
+    let output = craterun(&["inspect", ids[0]]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("failed to parse container metadata"),
+        "got: {stderr}"
+    );
+
+    let output = craterun(&["rm", ids[0]]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--force"), "got: {stderr}");
+
+    let output = craterun(&["rm", "--force", ids[0]]);
+    assert!(output.status.success());
+    assert!(!state_dir(tmp.path()).join(ids[0]).exists());
+
+    let output = craterun(&["prune", "--broken"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("Removed container {}", ids[1])),
+        "got: {stdout}"
+    );
+    assert!(!state_dir(tmp.path()).join(ids[1]).exists());
+}
+
+/// Verify `inspect` of several containers prints an array of those it can
+/// find, leaving out the others with a warning unless `--strict` is given.
+#[test]
+fn cli_inspect_several_containers() {
+    let tmp = tempfile::tempdir().unwrap();
+    let ids = ["1a5ec7000000a001", "1a5ec7000000a002"];
+    let state = state_dir(tmp.path());
+    let _dirs = RemoveOnDrop(ids.iter().map(|id| state.join(id)).collect());
+    for (i, id) in ids.iter().enumerate() {
+        let dir = state.join(id);
+        std::fs::create_dir_all(&dir).unwrap();
+        let meta = format!(
+            r#"{{"id":"{id}","name":"inspect-{i}","rootfs":"/tmp/rootfs","cmd":["/bin/true"],
+            "pid":0,"exit_code":0,"created_at":"2025-01-01T00:00:00Z","status":"stopped",
+            "hostname":"craterun","memory_limit":null,"cpu_limit":null,"pids_limit":null}}"#
+        );
+        std::fs::write(dir.join("metadata.json"), meta).unwrap();
+    }
+    let inspect = |args: &str| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .arg("inspect")
+            .args(args.split(' '))
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun inspect")
+    };
+
+    let output = inspect("1a5ec7000000a001");
+    assert!(output.status.success());
+    let single: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(single["id"], ids[0]);
+
+    let output = inspect("1a5ec7000000a001 inspect-1 1a5ec7000000ffff");
+    assert!(output.status.success());
+    let several: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let found: Vec<&str> = several
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|meta| meta["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(found, ids);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("leaving out '1a5ec7000000ffff'"),
+        "{stderr}"
+    );
+
+    let output = inspect("--strict 1a5ec7000000a001 1a5ec7000000ffff");
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("no container found"), "{stderr}");
+}
+
+/// Removes the given container directories when dropped, even if a test
+/// fails.
+struct RemoveOnDrop(Vec<PathBuf>);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        for dir in &self.0 {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// Verify `ps` lists thousands of containers quickly, in a stable order,
+/// and only writes a corrected status with `--sync`.
+#[test]
+fn cli_ps_many_containers() {
+    const COUNT: usize = 5000;
+    let tmp = tempfile::tempdir().unwrap();
+    let ids: Vec<String> = (0..COUNT).map(|i| format!("5eed{i:012x}")).collect();
+    let state = state_dir(tmp.path());
+    let dirs = RemoveOnDrop(ids.iter().map(|id| state.join(id)).collect());
+    for (i, dir) in dirs.0.iter().enumerate() {
+        std::fs::create_dir_all(dir).unwrap();
+        // Every tenth claims to run with a PID that cannot exist.
+        let (status, pid) = if i % 10 == 0 {
+            ("running", 4_000_000_000u32)
+        } else {
+            ("stopped", 0)
+        };
+        let meta = format!(
+            r#"{{"id":"{}","rootfs":"/tmp/rootfs","cmd":["/bin/true"],"pid":{pid},
+            "exit_code":null,"created_at":"2025-01-01T00:00:00Z","status":"{status}",
+            "hostname":"craterun","memory_limit":null,"cpu_limit":null,"pids_limit":null}}"#,
+            ids[i]
+        );
+        std::fs::write(dir.join("metadata.json"), meta).unwrap();
+    }
+    let ps = |args: &[&str]| {
+        let start = std::time::Instant::now();
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .arg("ps")
+            .args(args)
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun ps");
+        assert!(output.status.success());
+        (String::from_utf8(output.stdout).unwrap(), start.elapsed())
+    };
+
+    let (first, elapsed) = ps(&[]);
+    assert!(
+        elapsed < std::time::Duration::from_secs(10),
+        "ps took {elapsed:?}"
+    );
+    // Rows of other tests' containers come and go, and their CREATED ages.
+    let ours = |output: &str| -> Vec<String> {
+        output
+            .lines()
+            .filter(|line| line.starts_with("5eed"))
+            .map(str::to_string)
+            .collect()
+    };
+    let listed: Vec<String> = ours(&first)
+        .iter()
+        .map(|line| line.split_whitespace().next().unwrap().to_string())
+        .collect();
+    assert_eq!(listed, ids);
+    assert_eq!(ours(&ps(&[]).0), ours(&first), "ps output should be stable");
+
+    let meta_of_first = || std::fs::read_to_string(dirs.0[0].join("metadata.json")).unwrap();
+    assert!(meta_of_first().contains(r#""status":"running""#));
+    ps(&["--sync"]);
+    assert!(meta_of_first().contains(r#""status": "stopped""#));
+}
+
+/// Verify `ps` orders containers newest first by default, and by `--sort`,
+/// `--reverse` and `-n` otherwise, in text and JSON alike.
+#[test]
+fn cli_ps_sort() {
+    let _newest = NEWEST_CONTAINERS.lock().unwrap_or_else(|e| e.into_inner());
+    let tmp = tempfile::tempdir().unwrap();
+    let state = state_dir(tmp.path());
+    // Created in the far future, so other tests' containers sort after them.
+    let containers = [
+        ("50f7000000000001", "2030-01-02T00:00:00Z", "stopped"),
+        ("50f7000000000002", "2030-01-03T00:00:00Z", "stopped"),
+        ("50f7000000000003", "2030-01-01T00:00:00Z", "running"),
+    ];
+    let names = [r#""zeta""#, "null", r#""alpha""#];
+    let broken = "50f70000000000ff";
+    let dirs = RemoveOnDrop(
+        containers
+            .iter()
+            .map(|(id, ..)| state.join(id))
+            .chain([state.join(broken)])
+            .collect(),
+    );
+    for (((id, created, status), name), dir) in containers.iter().zip(names).zip(&dirs.0) {
+        std::fs::create_dir_all(dir).unwrap();
+        // The PID of the test runner keeps the running one running.
+        let meta = format!(
+            r#"{{"id":"{id}","name":{name},"rootfs":"/tmp/rootfs","cmd":["/bin/true"],
+            "pid":{},"exit_code":null,"created_at":"{created}","status":"{status}",
+            "hostname":"craterun","memory_limit":null,"cpu_limit":null,"pids_limit":null}}"#,
+            std::process::id()
+        );
+        std::fs::write(dir.join("metadata.json"), meta).unwrap();
+    }
+    std::fs::create_dir_all(&dirs.0[3]).unwrap();
+    std::fs::write(dirs.0[3].join("metadata.json"), "{").unwrap();
+
+    let ps = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .arg("ps")
+            .args(args)
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun ps");
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let listed = |args: &[&str]| -> Vec<String> {
+        ps(args)
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .filter(|id| id.starts_with("50f7"))
+            .map(|id| id[13..].to_string())
+            .collect()
+    };
+
+    assert_eq!(listed(&[]), ["002", "001", "003", "0ff"]);
+    assert_eq!(listed(&["--reverse"]), ["0ff", "003", "001", "002"]);
+    assert_eq!(listed(&["--sort", "status"]), ["003", "002", "001", "0ff"]);
+    assert_eq!(listed(&["--sort", "name"]), ["003", "001", "002", "0ff"]);
+    assert_eq!(listed(&["--sort", "id"]), ["001", "002", "003", "0ff"]);
+    assert_eq!(listed(&["-n", "2"]), ["002", "001"]);
+    assert_eq!(listed(&["--sort", "id", "--last", "0"]), [] as [&str; 0]);
+
+    // Created in the future as far as the clock knows, which is no reason
+    // to show a negative age.
+    assert!(ps(&["-n", "1"]).contains(" Just now "));
+    assert!(ps(&["-n", "1", "--no-humanize"]).contains(" 2030-01-03 00:00:00 UTC "));
+
+    let json: serde_json::Value =
+        serde_json::from_str(&ps(&["--format", "json", "--last", "2"])).unwrap();
+    let ids: Vec<&str> = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(ids, ["50f7000000000002", "50f7000000000001"]);
+}
+
+/// Verify `-l` and the ID `latest` pick the most recently created container,
+/// and that `-l` cannot be combined with an ID.
+#[test]
+fn cli_latest_container() {
+    let _newest = NEWEST_CONTAINERS.lock().unwrap_or_else(|e| e.into_inner());
+    let tmp = tempfile::tempdir().unwrap();
+    let state = state_dir(tmp.path());
+    // Created in the far future, so no other test's container is newer.
+    let containers = [
+        ("1a7e000000000001", "2031-01-02T00:00:00Z"),
+        ("1a7e000000000002", "2031-01-01T00:00:00Z"),
+    ];
+    let dirs = RemoveOnDrop(containers.iter().map(|(id, _)| state.join(id)).collect());
+    for ((id, created), dir) in containers.iter().zip(&dirs.0) {
+        std::fs::create_dir_all(dir).unwrap();
+        let meta = format!(
+            r#"{{"id":"{id}","rootfs":"/tmp/rootfs","cmd":["/bin/true"],"pid":0,
+            "exit_code":0,"created_at":"{created}","status":"stopped",
+            "hostname":"craterun","memory_limit":null,"cpu_limit":null,"pids_limit":null}}"#
+        );
+        std::fs::write(dir.join("metadata.json"), meta).unwrap();
+    }
+
+    let craterun = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(args)
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun")
+    };
+    for args in [["inspect", "-l"], ["inspect", "latest"]] {
+        let output = craterun(&args);
+        assert!(output.status.success(), "{args:?}");
+        let meta: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(meta["id"], "1a7e000000000001", "{args:?}");
+    }
+
+    let output = craterun(&["inspect", "-l", "1a7e000000000002"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("cannot be used with"), "{stderr}");
+}
+
+/// Verify `commit` saves a directory rootfs as an image recording where it
+/// came from, and chains the command history of committed images.
+#[test]
+fn cli_commit() {
+    let tmp = tempfile::tempdir().unwrap();
+    let rootfs = fake_rootfs();
+    std::fs::write(rootfs.path().join("motd"), "hello").unwrap();
+    let base = format!("test-commit-{}", std::process::id());
+    let derived = format!("{base}-2");
+    let craterun = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(args)
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun")
+    };
+    let state = state_dir(tmp.path());
+    let dirs = RemoveOnDrop(vec![
+        state.join("c0aa000000000001"),
+        state.join("c0aa000000000002"),
+    ]);
+    let write_meta = |dir: &PathBuf, rootfs: &str, cmd: &str| {
+        std::fs::create_dir_all(dir).unwrap();
+        let id = dir.file_name().unwrap().to_str().unwrap();
+        let meta = format!(
+            r#"{{"id":"{id}","rootfs":"{rootfs}","cmd":["{cmd}"],"pid":0,
+            "exit_code":0,"created_at":"2025-01-01T00:00:00Z","status":"stopped",
+            "hostname":"craterun","memory_limit":null,"cpu_limit":null,"pids_limit":null}}"#
+        );
+        std::fs::write(dir.join("metadata.json"), meta).unwrap();
+    };
+
+    write_meta(&dirs.0[0], rootfs.path().to_str().unwrap(), "/bin/setup");
+    let output = craterun(&["commit", "c0aa000000000001", &base]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let image_rootfs = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let motd = std::fs::read_to_string(Path::new(&image_rootfs).join("motd")).unwrap();
+    assert_eq!(motd, "hello");
+    let output = craterun(&["commit", "c0aa000000000001", &base]);
+    assert!(!output.status.success(), "the image name is taken");
+
+    write_meta(&dirs.0[1], &image_rootfs, "/bin/more");
+    let output = craterun(&["--format", "json", "commit", "c0aa000000000002", &derived]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let image: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let provenance = &image["provenance"];
+    assert_eq!(provenance["container"], "c0aa000000000002");
+    assert_eq!(provenance["parent_image"], base.as_str());
+    assert_eq!(
+        provenance["history"],
+        serde_json::json!([["/bin/setup"], ["/bin/more"]])
+    );
+
+    drop(dirs);
+    for name in [&derived, &base] {
+        assert!(craterun(&["rmi", name]).status.success());
+    }
+}
+
+/// Verify `rm --filter` removes the matching containers, together with any
+/// given by ID, and refuses running ones without `--force`.
+#[test]
+fn cli_rm_filter() {
+    let tmp = tempfile::tempdir().unwrap();
+    let state = state_dir(tmp.path());
+    // A running container is faked with this process's PID.
+    let pid = std::process::id();
+    let containers = [
+        ("f11e000000000001", "rm-filter-a", 0, "stopped"),
+        ("f11e000000000002", "rm-filter-a", 0, "stopped"),
+        ("f11e000000000003", "rm-filter-b", 0, "stopped"),
+        ("f11e000000000004", "rm-filter-b", pid, "running"),
+    ];
+    let dirs = RemoveOnDrop(containers.iter().map(|c| state.join(c.0)).collect());
+    for ((id, network, pid, status), dir) in containers.iter().zip(&dirs.0) {
+        std::fs::create_dir_all(dir).unwrap();
+        let meta = format!(
+            r#"{{"id":"{id}","rootfs":"/tmp/rootfs","cmd":["/bin/true"],"pid":{pid},
+            "exit_code":null,"created_at":"2025-01-01T00:00:00Z","status":"{status}",
+            "hostname":"craterun","memory_limit":null,"cpu_limit":null,"pids_limit":null,
+            "network":"{network}"}}"#
+        );
+        std::fs::write(dir.join("metadata.json"), meta).unwrap();
+    }
+    let rm = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["--format", "json", "rm"])
+            .args(args)
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun rm")
+    };
+    let removed = |args: &[&str]| -> Vec<String> {
+        let output = rm(args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{args:?}: {stderr}");
+        let results: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+        results.iter().map(|r| r["id"].as_str().unwrap().to_string()).collect()
+    };
+
+    // Nothing is removed while a match is running.
+    let output = rm(&["--filter", "network=rm-filter-b"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("f11e000000000004"), "{stderr}");
+    assert!(dirs.0[2].exists());
+
+    let stopped = removed(&[
+        "--filter",
+        "network=rm-filter-b",
+        "--filter",
+        "status=stopped",
+    ]);
+    assert_eq!(stopped, ["f11e000000000003"]);
+
+    // IDs and filters combine, each container removed once.
+    let ids = removed(&["f11e000000000001", "--filter", "network=rm-filter-a"]);
+    assert_eq!(ids, ["f11e000000000001", "f11e000000000002"]);
+    assert!(dirs.0[3].exists());
+
+    let output = rm(&["--filter", "label=env=test"]);
+    assert!(!output.status.success());
+}
+
+/// Verify `diff` explains why a container without a live overlay has no
+/// changes to show.
+#[test]
+fn cli_diff_needs_a_running_image_container() {
+    let tmp = tempfile::tempdir().unwrap();
+    let state = state_dir(tmp.path());
+    let containers = [
+        ("d1ff000000000001", ""),
+        ("d1ff000000000002", r#","loop_device":"/dev/loop7""#),
+    ];
+    let dirs = RemoveOnDrop(containers.iter().map(|c| state.join(c.0)).collect());
+    for ((id, extra), dir) in containers.iter().zip(&dirs.0) {
+        std::fs::create_dir_all(dir).unwrap();
+        let meta = format!(
+            r#"{{"id":"{id}","rootfs":"/tmp/rootfs","cmd":["/bin/true"],"pid":0,
+            "exit_code":0,"created_at":"2025-01-01T00:00:00Z","status":"stopped",
+            "hostname":"craterun","memory_limit":null,"cpu_limit":null,"pids_limit":null{extra}}}"#
+        );
+        std::fs::write(dir.join("metadata.json"), meta).unwrap();
+    }
+    let diff = |id: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["diff", id])
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun diff");
+        assert!(!output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    let stderr = diff("d1ff000000000001");
+    assert!(stderr.contains("directory rootfs /tmp/rootfs"), "{stderr}");
+    let stderr = diff("d1ff000000000002");
+    assert!(stderr.contains("has exited"), "{stderr}");
+}
+
+/// Verify `system df --detailed` reports each container's logs and warns
+/// about the ones over `--max-log-size`.
+#[test]
+fn cli_system_df() {
+    let tmp = tempfile::tempdir().unwrap();
+    let ids = ["d15c000000000001", "d15c000000000002"];
+    let state = state_dir(tmp.path());
+    let dirs = RemoveOnDrop(ids.iter().map(|id| state.join(id)).collect());
+    let storage = ["", r#","storage":{"size":1073741824,"driver":"loop"}"#];
+    for ((id, dir), storage) in ids.iter().zip(&dirs.0).zip(storage) {
+        std::fs::create_dir_all(dir.join("execs")).unwrap();
+        let meta = format!(
+            r#"{{"id":"{id}","rootfs":"/tmp/rootfs","cmd":["/bin/true"],"pid":0,
+            "exit_code":0,"created_at":"2025-01-01T00:00:00Z","status":"stopped",
+            "hostname":"craterun","memory_limit":null,"cpu_limit":null,"pids_limit":null
+            {storage}}}"#
+        );
+        std::fs::write(dir.join("metadata.json"), meta).unwrap();
+    }
+    std::fs::write(dirs.0[0].join("stdout.log"), [b'x'; 3000]).unwrap();
+    std::fs::write(dirs.0[0].join("execs/0001.log"), [b'x'; 100]).unwrap();
+    // A sparse storage image counts by the blocks it has allocated.
+    let image = std::fs::File::create(dirs.0[1].join("overlay.img")).unwrap();
+    image.set_len(1 << 30).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["--format", "json", "system", "df", "--detailed"])
+        .args(["--max-log-size", "2K"])
+        .env("HOME", tmp.path())
+        .output()
+        .expect("failed to execute craterun system df");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("d15c000000000001 has 3.0K"), "{stderr}");
+    assert!(!stderr.contains("d15c000000000002"), "{stderr}");
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let details = report["container_details"].as_array().unwrap();
+    let usage = |id: &str| details.iter().find(|c| c["id"] == id).unwrap().clone();
+    let (first, second) = (usage(ids[0]), usage(ids[1]));
+    assert_eq!(first["logs"], 3100);
+    assert_eq!(first["status"], "stopped");
+    assert_eq!(second["logs"], 0);
+    assert!(second["size"].as_u64().unwrap() > 0);
+    assert!(second["writable"].as_u64().unwrap() < 1 << 20);
+    assert_eq!(first["storage_limit"], serde_json::Value::Null);
+    assert_eq!(second["storage_limit"], 1 << 30);
+    assert!(report["containers"]["reclaimable"].as_u64().unwrap() >= 3100);
+}
+
+/// Verify `system info --format json` reports the build, the host and the
+/// containers by status.
+#[test]
+fn cli_system_info() {
+    let tmp = tempfile::tempdir().unwrap();
+    let id = "1f0f000000000001";
+    let dirs = RemoveOnDrop(vec![state_dir(tmp.path()).join(id)]);
+    std::fs::create_dir_all(&dirs.0[0]).unwrap();
+    let meta = format!(
+        r#"{{"id":"{id}","rootfs":"/tmp/rootfs","cmd":["/bin/true"],"pid":0,
+        "exit_code":0,"created_at":"2025-01-01T00:00:00Z","status":"stopped",
+        "hostname":"craterun","memory_limit":null,"cpu_limit":null,"pids_limit":null}}"#
+    );
+    std::fs::write(dirs.0[0].join("metadata.json"), meta).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["--format", "json", "system", "info"])
+        .env("HOME", tmp.path())
+        .output()
+        .expect("failed to execute craterun system info");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    assert!(["v2", "hybrid", "v1", "unmounted"].contains(&info["cgroup_mode"].as_str().unwrap()));
+    assert!(info["user_namespaces"].is_boolean(), "{info}");
+    assert!(
+        info["containers"]["stopped"].as_u64().unwrap() >= 1,
+        "{info}"
+    );
+    assert_eq!(info["default_network"]["name"], "bridge");
+}
+
+/// Verify `logs --tail` prints the last lines of each stream, or of both
+/// merged with `--combine`.
+#[test]
+fn cli_logs_tail() {
+    let tmp = tempfile::tempdir().unwrap();
+    let id = "7a11000000000001";
+    let dirs = RemoveOnDrop(vec![state_dir(tmp.path()).join(id)]);
+    let dir = &dirs.0[0];
+    std::fs::create_dir_all(dir).unwrap();
+    let meta = format!(
+        r#"{{"id":"{id}","rootfs":"/tmp/rootfs","cmd":["/bin/true"],"pid":0,
+        "exit_code":0,"created_at":"2025-01-01T00:00:00Z","status":"stopped",
+        "hostname":"craterun","memory_limit":null,"cpu_limit":null,"pids_limit":null}}"#
+    );
+    std::fs::write(dir.join("metadata.json"), meta).unwrap();
+    let stdout: String = (1..=20_000).map(|i| format!("out {i}\n")).collect();
+    std::fs::write(dir.join("stdout.log"), stdout).unwrap();
+    std::fs::write(dir.join("stderr.log"), "err 1\nerr 2").unwrap();
+
+    let logs = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .arg("logs")
+            .args(args)
+            .arg(id)
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun logs");
+        assert!(output.status.success());
+        (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+
+    assert_eq!(
+        logs(&["--tail", "2"]),
+        ("out 19999\nout 20000\n".into(), "err 1\nerr 2\n".into())
+    );
+    assert_eq!(
+        logs(&["--tail", "3", "--combine"]).0,
+        "out 20000\nerr 1\nerr 2\n"
+    );
+    assert_eq!(logs(&["--tail", "0"]), (String::new(), String::new()));
+}
+
 /// Verify `rm` with a non-existent ID fails gracefully.
 #[test]
 fn cli_rm_nonexistent() {
@@ -80,6 +750,120 @@ fn cli_rm_nonexistent() {
     );
 }
 
+/// Verify `stop` with a non-existent ID fails gracefully.
+#[test]
+fn cli_stop_nonexistent() {
+    let tmp = tempfile::tempdir().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["stop", "-t", "1", "deadbeef"])
+        .env("HOME", tmp.path())
+        .output()
+        .expect("failed to execute craterun stop");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("no container found"),
+        "should report no container found, got: {stderr}"
+    );
+}
+
+/// Verify `stop --all` and `kill --all` act on every running container
+/// matching the filters, and report the ones that failed.
+#[test]
+fn cli_stop_and_kill_all() {
+    let tmp = tempfile::tempdir().unwrap();
+    let state = state_dir(tmp.path());
+    // A running container is faked with a shell waiting for its command,
+    // which `stop` and `kill` signal, reaped by a thread once it exits.
+    let fake_container = || {
+        let mut child = Command::new("sh")
+            .args(["-c", "sleep 30; true"])
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        let children = format!("/proc/{pid}/task/{pid}/children");
+        while std::fs::read_to_string(&children).unwrap().is_empty() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        std::thread::spawn(move || child.wait());
+        pid
+    };
+    let containers = [
+        ("b01c000000000001", "bulk-a", fake_container(), "running"),
+        ("b01c000000000002", "bulk-a", fake_container(), "running"),
+        ("b01c000000000003", "bulk-a", 0, "stopped"),
+        ("b01c000000000004", "bulk-b", fake_container(), "running"),
+        ("b01c000000000005", "bulk-b", fake_container(), "running"),
+    ];
+    let dirs = RemoveOnDrop(containers.iter().map(|c| state.join(c.0)).collect());
+    for ((id, network, pid, status), dir) in containers.iter().zip(&dirs.0) {
+        // The last one cannot be stopped, only killed.
+        let signal = if id.ends_with('5') {
+            "SIGBOGUS"
+        } else {
+            "SIGTERM"
+        };
+        std::fs::create_dir_all(dir).unwrap();
+        let meta = format!(
+            r#"{{"id":"{id}","rootfs":"/tmp/rootfs","cmd":["/bin/true"],"pid":{pid},
+            "exit_code":null,"created_at":"2025-01-01T00:00:00Z","status":"{status}",
+            "hostname":"craterun","memory_limit":null,"cpu_limit":null,"pids_limit":null,
+            "network":"{network}","stop_signal":"{signal}"}}"#
+        );
+        std::fs::write(dir.join("metadata.json"), meta).unwrap();
+    }
+    let craterun = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(args)
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun")
+    };
+    let succeeded = |output: &std::process::Output| {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{stderr}");
+    };
+
+    let output = craterun(&["--format=json", "stop", "--all", "--filter=network=bulk-a"]);
+    succeeded(&output);
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        results,
+        serde_json::json!([
+            {"id": "b01c000000000001", "result": "stopped"},
+            {"id": "b01c000000000002", "result": "stopped"},
+        ])
+    );
+
+    // One container with a bad stop signal fails the whole command, after
+    // the others are stopped; kill does not need the signal.
+    let output = craterun(&["stop", "--all", "--filter", "network=bulk-b"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "Stopped container b01c000000000004\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed to stop 1 of 2"), "{stderr}");
+    let output = craterun(&["kill", "--all", "--filter", "network=bulk-b"]);
+    succeeded(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "Killed container b01c000000000005\n");
+
+    // Nothing matching is left running.
+    let output = craterun(&["--format=json", "kill", "--all", "--filter=network=bulk-a"]);
+    succeeded(&output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "[]");
+
+    for args in [
+        &["stop", "--filter", "network=bulk-a", "b01c000000000001"][..],
+        &["stop", "--filter", "network=bulk-a"],
+        &["kill", "--all", "b01c000000000001"],
+        &["kill", "--all", "--latest"],
+    ] {
+        assert_eq!(craterun(args).status.code(), Some(2), "{args:?}");
+    }
+}
+
 /// Verify `logs` with a non-existent ID fails gracefully.
 #[test]
 fn cli_logs_nonexistent() {
@@ -97,3 +881,1300 @@ fn cli_logs_nonexistent() {
         "should report no container found, got: {stderr}"
     );
 }
+
+/// Build a minimal fake rootfs containing an executable `/bin/sh`.
+fn fake_rootfs() -> tempfile::TempDir {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let sh = tmp.path().join("bin/sh");
+    std::fs::create_dir_all(tmp.path().join("bin")).unwrap();
+    std::fs::write(&sh, "").unwrap();
+    std::fs::set_permissions(&sh, std::fs::Permissions::from_mode(0o755)).unwrap();
+    tmp
+}
+
+/// Verify `run --dry-run` rejects a missing rootfs without creating anything.
+#[test]
+fn cli_dry_run_rejects_missing_rootfs() {
+    let tmp = tempfile::tempdir().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--dry-run", "--rootfs", "/nonexistent", "--", "/bin/sh"])
+        .env("HOME", tmp.path())
+        .output()
+        .expect("failed to execute craterun run --dry-run");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("does not exist"), "got: {stderr}");
+    assert!(!tmp.path().join(".craterun").exists(), "dry run must not create state");
+}
+
+/// Verify `run --dry-run` reports a command missing from the rootfs.
+#[test]
+fn cli_dry_run_rejects_missing_command() {
+    let rootfs = fake_rootfs();
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--dry-run", "--rootfs"])
+        .arg(rootfs.path())
+        .args(["--", "/bin/bash"])
+        .output()
+        .expect("failed to execute craterun run --dry-run");
+
+    assert_eq!(output.status.code(), Some(127));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("not found in rootfs"), "got: {stderr}");
+
+    // A typo gets the commands named most like it.
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--dry-run", "--rootfs"])
+        .arg(rootfs.path())
+        .args(["--", "/bin/hs"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("did you mean /bin/sh?"), "got: {stderr}");
+
+    // A --tmpfs over the command's directory makes it a warning, and
+    // --no-preflight skips the check.
+    for args in [&["--tmpfs", "/bin"][..], &["--no-preflight"]] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--dry-run", "--rootfs"])
+            .arg(rootfs.path())
+            .args(args)
+            .args(["--", "/bin/bash"])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!stderr.contains("craterun: command"), "{args:?}: {stderr}");
+        let warned = stderr.contains("warning:");
+        assert_eq!(warned, args[0] == "--tmpfs", "{args:?}: {stderr}");
+    }
+}
+
+/// Verify a `run` failing after its container directory is created, here on
+/// an image that cannot be mounted, leaves no container behind.
+#[test]
+fn cli_failed_run_leaves_nothing() {
+    let tmp = tempfile::tempdir().unwrap();
+    let image = tmp.path().join("broken.squashfs");
+    std::fs::write(&image, b"hsqs, and nothing a squashfs would need").unwrap();
+    let hostname = format!("unstarted-{}", std::process::id());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--hostname", &hostname, "--rootfs"])
+        .arg(&image)
+        .args(["--", "/bin/sh"])
+        .env("HOME", tmp.path())
+        .output()
+        .expect("failed to execute craterun run");
+    assert!(!output.status.success());
+
+    let Ok(entries) = std::fs::read_dir(state_dir(tmp.path())) else {
+        return;
+    };
+    for entry in entries {
+        let hostname_file = entry.unwrap().path().join("hostname");
+        let written = std::fs::read_to_string(&hostname_file).unwrap_or_default();
+        assert_ne!(
+            written.trim(),
+            hostname,
+            "{} was left behind",
+            hostname_file.display()
+        );
+    }
+}
+
+/// Verify `run --init-path` needs an executable found in the rootfs or on
+/// the host, binding a host one at /dev/init.
+#[test]
+fn cli_run_init_path() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let rootfs = fake_rootfs();
+    let init = rootfs.path().join("bin/init");
+    std::fs::write(&init, "").unwrap();
+    std::fs::set_permissions(&init, std::fs::Permissions::from_mode(0o644)).unwrap();
+    let dry_run = |init_path: &str| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--dry-run", "--init-path", init_path, "--rootfs"])
+            .arg(rootfs.path())
+            .args(["--", "/bin/sh"])
+            .output()
+            .expect("failed to execute craterun run --dry-run")
+    };
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--init-path", "/bin/sh", "--argv0", "sh", "--rootfs"])
+        .arg(rootfs.path())
+        .args(["--", "/bin/sh"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+
+    for (init_path, error) in [
+        ("bin/init", "expected an absolute path"),
+        ("/no/such/init", "neither in the rootfs nor on the host"),
+        ("/bin/init", "is not executable (mode 644)"),
+    ] {
+        let output = dry_run(init_path);
+        assert!(!output.status.success(), "{init_path}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(error), "{init_path}: {stderr}");
+    }
+
+    // /bin/sh is in the rootfs; /usr/bin/env only on the host.
+    for (init_path, bound) in [("/bin/sh", false), ("/usr/bin/env", true)] {
+        let output = dry_run(init_path);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!stderr.contains("--init-path"), "{init_path}: {stderr}");
+        if !std::path::Path::new("/sys/fs/cgroup/cgroup.controllers").exists() {
+            continue;
+        }
+        let plan: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let mounts = plan["mounts"].as_array().unwrap();
+        let binds_init = mounts.iter().any(|m| m["target"] == "/dev/init");
+        assert_eq!(binds_init, bound, "{init_path}");
+    }
+}
+
+/// Verify `run --cmd-file` takes the command from a file or stdin, one
+/// argument per line or NUL-separated with `--null`.
+#[test]
+fn cli_run_cmd_file() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let rootfs = fake_rootfs();
+    let dir = tempfile::tempdir().unwrap();
+    let empty = dir.path().join("empty");
+    std::fs::write(&empty, "").unwrap();
+    let bash = dir.path().join("bash");
+    std::fs::write(&bash, "/bin/bash\n-c\necho 'a b'\n").unwrap();
+
+    let missing = "'/bin/bash' not found in rootfs";
+    for (args, stdin, expected) in [
+        (vec![empty.to_str().unwrap()], "", "no command given"),
+        (vec![bash.to_str().unwrap()], "", missing),
+        (vec!["-"], "/bin/bash\n", missing),
+        (vec!["-", "--null"], "/bin/bash\0-c\0x\0", missing),
+        (vec!["-"], "/bin/bash\0-c\n", "contains a NUL byte"),
+        (vec!["-", "--attach-stdin"], "", "cannot also be attached"),
+    ] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--dry-run", "--rootfs"])
+            .arg(rootfs.path())
+            .arg("--cmd-file")
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to execute craterun run --dry-run");
+        let mut pipe = child.stdin.take().unwrap();
+        pipe.write_all(stdin.as_bytes()).unwrap();
+        drop(pipe);
+        let output = child.wait_with_output().unwrap();
+
+        assert!(!output.status.success(), "{args:?} should be rejected");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(expected), "{args:?}: {stderr}");
+    }
+
+    // The file replaces the command given after `--`.
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--dry-run", "--rootfs"])
+        .arg(rootfs.path())
+        .arg("--cmd-file")
+        .arg(&bash)
+        .args(["--", "/bin/sh"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+/// Verify `run --config` takes the container's settings from a file, under
+/// the flags given, and that `config validate` checks such a file.
+#[test]
+fn cli_run_config_file() {
+    let rootfs = fake_rootfs();
+    let dir = tempfile::tempdir().unwrap();
+    let write = |name: &str, text: &str| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, text).unwrap();
+        path
+    };
+    let craterun = |args: &[&std::ffi::OsStr]| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(args)
+            .output()
+            .expect("failed to execute craterun")
+    };
+    let app = write(
+        "app.toml",
+        &format!(
+            "rootfs = {:?}\ncmd = [\"/bin/app\"]\n\n[limits]\nmemory = \"64M\"\n",
+            rootfs.path()
+        ),
+    );
+    let bad = write("bad.json", r#"{"limits": {"memory": "lots"}}"#);
+    let no_cmd = write("no-cmd.toml", &format!("rootfs = {:?}\n", rootfs.path()));
+
+    let output = craterun(&["config".as_ref(), "validate".as_ref(), app.as_os_str()]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("is valid"));
+    let output = craterun(&["config".as_ref(), "validate".as_ref(), bad.as_os_str()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("`limits.memory`: invalid size 'lots'"), "{stderr}");
+
+    // The rootfs and command come from the file unless given, so the
+    // file's command is what the preflight check looks for.
+    for (args, expected) in [
+        (&[][..], "'/bin/app' not found in rootfs"),
+        (&["--", "/bin/cli"][..], "'/bin/cli' not found in rootfs"),
+    ] {
+        let mut argv = vec!["run".as_ref(), "--dry-run".as_ref(), "--config".as_ref()];
+        argv.push(app.as_os_str());
+        argv.extend(args.iter().map(std::ffi::OsStr::new));
+        let output = craterun(&argv);
+        assert!(!output.status.success(), "{args:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(expected), "{args:?}: {stderr}");
+    }
+
+    let output = craterun(&["run".as_ref(), "--config".as_ref(), no_cmd.as_os_str()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("no command to run"), "{stderr}");
+}
+
+/// Verify `up` and `down` check the project file before touching anything,
+/// that `down` has nothing to do for a project that is not up, and that
+/// `run --name` refuses names that would read as IDs.
+#[test]
+fn cli_up_down_project_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let craterun = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(args)
+            .current_dir(dir.path())
+            .output()
+            .expect("failed to execute craterun")
+    };
+    let service = "    rootfs: /\n    cmd: [/bin/true]\n";
+    std::fs::write(
+        dir.path().join("cycle.yaml"),
+        format!(
+            "name: 5eedcyc\nservices:\n  web:\n{service}    depends_on: [db]\n  \
+             db:\n{service}    depends_on: [web]\n"
+        ),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("craterun.yaml"),
+        format!("name: 5eedidle\nservices:\n  web:\n{service}"),
+    )
+    .unwrap();
+
+    for command in ["up", "down"] {
+        let output = craterun(&[command, "-f", "cycle.yaml"]);
+        assert!(!output.status.success(), "{command}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("cycle among services web, db"), "{stderr}");
+    }
+
+    let output = craterun(&["down"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let run = "run --dry-run --name beef --rootfs / -- /bin/true";
+    let output = craterun(&run.split(' ').collect::<Vec<_>>());
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("invalid container name 'beef'"), "{stderr}");
+}
+
+/// Kills a child process when dropped, even if a test fails.
+struct KillOnDrop(std::process::Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Verify `craterun api` refuses to replace a file that is not a socket.
+#[test]
+fn cli_api_keeps_other_files() {
+    let tmp = tempfile::tempdir().unwrap();
+    let socket = tmp.path().join("api.sock");
+    std::fs::write(&socket, "keep").unwrap();
+    let server = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .arg("api")
+        .arg("--socket")
+        .arg(&socket)
+        .env("HOME", tmp.path())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("failed to execute craterun api");
+    let mut server = KillOnDrop(server);
+    let started = std::time::Instant::now();
+    // A server that took the path over would serve until killed.
+    let status = loop {
+        if let Some(status) = server.0.try_wait().unwrap() {
+            break status;
+        }
+        assert!(
+            started.elapsed().as_secs() < 10,
+            "craterun api replaced the file"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    };
+    assert!(!status.success());
+    let mut stderr = String::new();
+    std::io::Read::read_to_string(&mut server.0.stderr.take().unwrap(), &mut stderr).unwrap();
+    assert!(stderr.contains("exists and is not a socket"), "{stderr}");
+    assert_eq!(std::fs::read_to_string(&socket).unwrap(), "keep");
+}
+
+/// Verify the socket of `craterun api` is only ever accessible to its owner,
+/// even under a umask that would open it to everyone.
+#[test]
+fn cli_api_socket_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let socket = tmp.path().join("api.sock");
+    let server = Command::new("sh")
+        .arg("-c")
+        .arg(r#"umask 000 && exec "$0" api --socket "$1""#)
+        .arg(env!("CARGO_BIN_EXE_craterun"))
+        .arg(&socket)
+        .env("HOME", tmp.path())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("failed to execute craterun api");
+    let _server = KillOnDrop(server);
+    let started = std::time::Instant::now();
+    // The mode as soon as the socket appears, before anything could fix it.
+    let mode = loop {
+        if let Ok(meta) = std::fs::symlink_metadata(&socket) {
+            break meta.permissions().mode();
+        }
+        assert!(
+            started.elapsed().as_secs() < 10,
+            "craterun api did not listen"
+        );
+    };
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+/// Verify `craterun api` answers each method over its socket, reporting
+/// bad requests and failed commands as JSON-RPC errors, and that `--host`
+/// sends commands to it.
+#[test]
+fn cli_api_server() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let ids = ["a910000000000001", "a910000000000002"];
+    let state = state_dir(tmp.path());
+    let _dirs = RemoveOnDrop(ids.iter().map(|id| state.join(id)).collect());
+    for id in ids {
+        let dir = state.join(id);
+        std::fs::create_dir_all(&dir).unwrap();
+        let meta = format!(
+            r#"{{"id":"{id}","rootfs":"/tmp/rootfs","cmd":["/bin/true"],"pid":0,
+            "exit_code":3,"created_at":"2025-01-01T00:00:00Z","status":"stopped",
+            "hostname":"craterun","memory_limit":null,"cpu_limit":null,"pids_limit":null}}"#
+        );
+        std::fs::write(dir.join("metadata.json"), meta).unwrap();
+        std::fs::write(dir.join("stdout.log"), "out 1\nout 2\n").unwrap();
+        std::fs::write(dir.join("stderr.log"), "err 1\n").unwrap();
+    }
+
+    let socket = tmp.path().join("api.sock");
+    let server = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .arg("api")
+        .arg("--socket")
+        .arg(&socket)
+        .env("HOME", tmp.path())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("failed to execute craterun api");
+    let _server = KillOnDrop(server);
+    let started = std::time::Instant::now();
+    let stream = loop {
+        match UnixStream::connect(&socket) {
+            Ok(stream) => break stream,
+            Err(_) if started.elapsed().as_secs() < 10 => {
+                std::thread::sleep(std::time::Duration::from_millis(20))
+            }
+            Err(e) => panic!("craterun api did not listen: {e}"),
+        }
+    };
+    let mode = std::os::unix::fs::PermissionsExt::mode(&socket.metadata().unwrap().permissions());
+    assert_eq!(mode & 0o777, 0o600);
+
+    // Returns the notifications sent before the response, then the response.
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    let mut call = |request: &str| {
+        writeln!(writer, "{request}").unwrap();
+        let mut notifications = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let message: serde_json::Value = serde_json::from_str(&line).unwrap();
+            if message.get("id").is_some() {
+                return (notifications, message);
+            }
+            notifications.push(message["params"].clone());
+        }
+    };
+    let request = |id: u32, method: &str, params: &str| {
+        format!(r#"{{"jsonrpc":"2.0","id":{id},"method":"{method}","params":{params}}}"#)
+    };
+
+    let (_, response) = call(&request(1, "ps", "{}"));
+    let listed: Vec<&str> = response["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|meta| meta["id"].as_str())
+        .collect();
+    assert!(ids.iter().all(|id| listed.contains(id)), "{response}");
+
+    let (_, response) = call(&request(2, "inspect", r#"{"id":"a910000000000001"}"#));
+    assert_eq!(response["id"], 2);
+    assert_eq!(response["result"]["id"], ids[0]);
+
+    let (_, response) = call(&request(3, "stop", r#"{"id":"a910000000000001"}"#));
+    assert_eq!(response["error"]["code"], -32000);
+    let message = response["error"]["message"].as_str().unwrap();
+    assert!(message.contains("not running"), "{message}");
+
+    let (_, response) = call(&request(4, "wait", r#"{"id":"a910000000000001"}"#));
+    assert_eq!(response["result"]["exit_code"], 3);
+
+    let params = r#"{"id":"a910000000000002","tail":1}"#;
+    let (lines, response) = call(&request(5, "logs", params));
+    assert_eq!(
+        lines,
+        [
+            serde_json::json!({ "stream": "stdout", "line": "out 2" }),
+            serde_json::json!({ "stream": "stderr", "line": "err 1" }),
+        ]
+    );
+    assert_eq!(response["result"]["lines"], 2);
+    let params = r#"{"id":"a910000000000002","stderr":true,"follow":true}"#;
+    let (lines, _) = call(&request(6, "logs", params));
+    assert_eq!(lines.len(), 1);
+
+    let (_, response) = call(&request(7, "run", r#"{"config":{"rootfs":"/"}}"#));
+    let message = response["error"]["message"].as_str().unwrap();
+    assert!(message.contains("no `cmd` to run"), "{message}");
+    let (_, response) = call(&request(8, "ps", r#"{"all":true}"#));
+    assert_eq!(response["error"]["code"], -32602);
+    let (_, response) = call(&request(9, "start", "{}"));
+    assert_eq!(response["error"]["code"], -32601);
+    let (_, response) = call("{");
+    assert_eq!(response["error"]["code"], -32700);
+
+    // Notifications, requests without an ID, get no response, not even an
+    // error.
+    let notification = |method: &str| format!(r#"{{"jsonrpc":"2.0","method":"{method}"}}"#);
+    let requests = [
+        notification("ps"),
+        notification("start"),
+        request(10, "ps", "{}"),
+    ];
+    let (_, response) = call(&requests.join("\n"));
+    assert_eq!(response["id"], 10);
+    writer
+        .set_read_timeout(Some(std::time::Duration::from_millis(200)))
+        .unwrap();
+    let mut line = String::new();
+    assert!(reader.read_line(&mut line).is_err(), "unexpected {line}");
+
+    let host = format!("unix://{}", socket.display());
+    let craterun = |args: &str| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["--host", &host])
+            .args(args.split(' '))
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun --host")
+    };
+    let output = craterun("logs --tail 1 --stdout a910000000000002");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "out 2\n");
+    let output = craterun("rm a910000000000001");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Removed container a910000000000001\n"
+    );
+    assert!(!state.join(ids[0]).exists());
+    let output = craterun("inspect a910000000000001 a910000000000002");
+    assert!(output.status.success());
+    let several: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(several.as_array().unwrap().len(), 1);
+    let output = craterun("check");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("can be used with --host"), "{stderr}");
+}
+
+/// Verify `run --dry-run` validates the hostname, cpu string, ulimits, OOM
+/// score adjustment, devices, tmpfs mounts, sysctls, environment and user.
+#[test]
+fn cli_dry_run_rejects_bad_limits() {
+    let rootfs = fake_rootfs();
+    for args in [
+        ["--hostname", "bad host"],
+        ["--cpu", "fast"],
+        ["--cpu", "100000"],
+        ["--ulimit", "nofile=2048:1024"],
+        ["--ulimit", "bogus=1"],
+        ["--core-limit", "lots"],
+        ["--oom-score-adj", "1001"],
+        ["--oom-score-adj", "-1001"],
+        ["--device", "/etc/passwd"],
+        ["--device", "/dev/null:/tmp/null"],
+        ["--tmpfs", "tmp"],
+        ["--tmpfs", "/tmp:exec"],
+        ["--sysctl", "vm.swappiness=10"],
+        ["--env", "NOVALUE"],
+        ["--env", "1X=y"],
+        ["--env-file", "/nonexistent.env"],
+        ["--preserve-env", "A=B"],
+        ["--user", "a:b:c"],
+        ["--user", "ghost"],
+        ["--domainname", "bad_domain"],
+        ["--net", "macvlan"],
+        ["--pid", "bridge"],
+        ["--mac-address", "01:00:5e:00:00:01"],
+        ["--net", "container:nonexistent"],
+        ["--pid", "container:nonexistent"],
+        ["--ipc", "shared"],
+        ["--uts", "container:"],
+        ["--stop-signal", "SIGBOGUS"],
+        ["--pid", "slirp"],
+        ["--publish", "8080:80"],
+        ["--publish", "80"],
+        ["--stop-signal", "0"],
+        ["--mount-propagation", "rshared"],
+        ["--proc-opts", "hidepid=1"],
+        ["--keyring", "session"],
+        ["--userns", "auto"],
+        ["--cgroup-ns", "none"],
+        ["--umask", "0800"],
+        ["--timezone", "../etc/passwd"],
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--dry-run", "--rootfs"])
+            .arg(rootfs.path())
+            .args(args)
+            .args(["--", "/bin/sh"])
+            .output()
+            .expect("failed to execute craterun run --dry-run");
+
+        assert!(!output.status.success(), "{args:?} should be rejected");
+    }
+}
+
+/// Verify settings that would change a shared namespace are refused.
+#[test]
+fn cli_run_rejects_changes_to_shared_namespaces() {
+    let rootfs = fake_rootfs();
+    for (args, expected) in [
+        (&["--uts", "host", "--hostname", "web"][..], "--hostname cannot be set"),
+        (&["--uts", "host", "--domainname", "example"], "--domainname cannot be set"),
+        (&["--ipc", "host", "--sysctl", "kernel.shmmax=1024"], "ipc namespace is shared"),
+        (&["--net", "host", "--sysctl", "net.ipv4.ip_forward=1"], "net namespace is shared"),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--dry-run", "--rootfs"])
+            .arg(rootfs.path())
+            .args(args)
+            .args(["--", "/bin/sh"])
+            .output()
+            .expect("failed to execute craterun run --dry-run");
+
+        assert!(!output.status.success(), "{args:?} should be rejected");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(expected), "{args:?}: {stderr}");
+    }
+}
+
+/// Verify `--net slirp` explains how to get a missing slirp4netns and checks
+/// published ports.
+#[test]
+fn cli_run_slirp_checks() {
+    let rootfs = fake_rootfs();
+    let empty_path = tempfile::tempdir().unwrap();
+    for (args, expected) in [
+        (&["--net", "slirp"][..], "install it"),
+        (
+            &["--net", "slirp", "-p", "8080:80", "-p", "8080:8080/tcp"],
+            "already published",
+        ),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--dry-run", "--rootfs"])
+            .arg(rootfs.path())
+            .args(args)
+            .args(["--", "/bin/sh"])
+            .env("PATH", empty_path.path())
+            .output()
+            .expect("failed to execute craterun run --dry-run");
+
+        assert!(!output.status.success(), "{args:?} should be rejected");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(expected), "{args:?}: {stderr}");
+    }
+}
+
+/// Verify `--ip` and `--mac-address` need `--net bridge` and an address the
+/// network can hand out, and `--network` an existing network.
+#[test]
+fn cli_run_bridge_checks() {
+    let rootfs = fake_rootfs();
+    for (args, expected) in [
+        (&["--ip", "10.77.0.42"][..], "--ip needs --net bridge"),
+        (
+            &["--mac-address", "02:42:ac:11:00:02"],
+            "--mac-address needs --net bridge",
+        ),
+        (
+            &["--net", "bridge", "--ip", "192.168.1.5"],
+            "outside the subnet 10.77.0.0/24",
+        ),
+        (
+            &["--net", "bridge", "--ip", "10.77.0.1"],
+            "address of the bridge",
+        ),
+        (&["--network", "nosuchnet"], "no network named 'nosuchnet'"),
+        (&["--network", "Bad/Name"], "invalid network name"),
+        (
+            &["--net", "host", "--network", "bridge"],
+            "cannot be used with",
+        ),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--dry-run", "--rootfs"])
+            .arg(rootfs.path())
+            .args(args)
+            .args(["--", "/bin/sh"])
+            .output()
+            .expect("failed to execute craterun run --dry-run");
+
+        assert!(!output.status.success(), "{args:?} should be rejected");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(expected), "{args:?}: {stderr}");
+    }
+}
+
+/// Verify `--userns keep-id` refuses what its root cannot set up and needs
+/// a subordinate ID range for the caller.
+#[test]
+fn cli_run_keep_id_checks() {
+    let rootfs = fake_rootfs();
+    for (args, expected) in [
+        (
+            &["--net", "bridge"][..],
+            "--userns keep-id cannot be used with --net bridge",
+        ),
+        (
+            &["--device", "/dev/null"],
+            "--device cannot be used with --userns keep-id",
+        ),
+        (&[], "no range for 4242 in /etc/subuid"),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--dry-run", "--userns", "keep-id", "--rootfs"])
+            .arg(rootfs.path())
+            .args(args)
+            .args(["--", "/bin/sh"])
+            .env("SUDO_UID", "4242")
+            .env("SUDO_GID", "4242")
+            .output()
+            .expect("failed to execute craterun run --dry-run");
+
+        assert!(!output.status.success(), "{args:?} should be rejected");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(expected), "{args:?}: {stderr}");
+    }
+}
+
+/// Verify `--timezone` needs a zone the host has.
+#[test]
+fn cli_run_timezone_must_exist() {
+    let rootfs = fake_rootfs();
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--dry-run", "--timezone", "Nowhere/Atlantis"])
+        .arg("--rootfs")
+        .arg(rootfs.path())
+        .args(["--", "/bin/sh"])
+        .output()
+        .expect("failed to execute craterun run --dry-run");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("unknown time zone 'Nowhere/Atlantis'"),
+        "{stderr}"
+    );
+}
+
+/// Verify `network` refuses bad subnets and keeps the default network.
+#[test]
+fn cli_network_checks() {
+    for (args, expected) in [
+        (
+            &["create", "web", "--subnet", "10.88.0.1/24"][..],
+            "host bits are set",
+        ),
+        (
+            &["create", "web", "--subnet", "10.88.0.0/31"],
+            "between 8 and 30",
+        ),
+        (&["create", "web"], "--subnet"),
+        (&["rm", "bridge"], "cannot be removed"),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .arg("network")
+            .args(args)
+            .output()
+            .expect("failed to execute craterun network");
+
+        assert!(!output.status.success(), "{args:?} should be rejected");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(expected), "{args:?}: {stderr}");
+    }
+}
+
+/// Verify `run --dry-run` prints the plan as JSON when cgroups v2 is available.
+#[test]
+fn cli_dry_run_prints_plan() {
+    let rootfs = fake_rootfs();
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--dry-run", "--memory", "1048576", "--tmpfs", "/tmp:noexec"])
+        .args(["--shm-size", "1G", "--rootfs"])
+        .arg(rootfs.path())
+        .args(["--", "/bin/sh"])
+        .output()
+        .expect("failed to execute craterun run --dry-run");
+
+    if !std::path::Path::new("/sys/fs/cgroup/cgroup.controllers").exists() {
+        assert!(!output.status.success(), "dry run should require cgroups v2");
+        return;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let plan: serde_json::Value = serde_json::from_str(&stdout).expect("plan should be JSON");
+    assert_eq!(plan["config"]["cmd"][0], "/bin/sh");
+    assert_eq!(plan["config"]["invocation"][0], "run");
+    assert_eq!(plan["config"]["invocation"][2], "--memory");
+    assert_eq!(plan["cgroup"]["limits"]["memory.max"], "1048576");
+    assert_eq!(plan["cgroup"]["limits"]["pids.max"], "1024", "default pids limit");
+    let mounts = plan["mounts"].as_array().unwrap();
+    let shm = mounts.iter().find(|m| m["target"] == "/dev/shm").unwrap();
+    assert_eq!(
+        shm["options"],
+        "nosuid,nodev,noexec,mode=1777,size=1073741824"
+    );
+    let tmpfs = mounts.last().unwrap();
+    assert_eq!(tmpfs["target"], "/tmp");
+    assert_eq!(tmpfs["options"], "size=1048576,noexec");
+}
+
+/// Verify the health check flags are validated and need `--health-cmd`.
+#[test]
+fn cli_health_flags() {
+    let rootfs = fake_rootfs();
+    for args in [
+        &["--health-interval", "5s"][..],
+        &["--health-retries", "2"],
+        &["--health-cmd", "true", "--health-interval", "0s"],
+        &["--health-cmd", "true", "--health-interval", "soon"],
+        &["--health-cmd", "true", "--health-retries", "0"],
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--dry-run", "--rootfs"])
+            .arg(rootfs.path())
+            .args(args)
+            .args(["--", "/bin/sh"])
+            .output()
+            .expect("failed to execute craterun run --dry-run");
+
+        assert!(!output.status.success(), "{args:?} should be rejected");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("--health"), "{args:?}: {stderr}");
+    }
+}
+
+/// Verify an unknown `CRATERUN_LOG` level is rejected and that logging is
+/// off unless asked for.
+#[test]
+fn cli_log_level() {
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .arg("ps")
+        .env("CRATERUN_LOG", "loud")
+        .output()
+        .expect("failed to execute craterun ps");
+    assert!(!output.status.success(), "an unknown level should be rejected");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("CRATERUN_LOG"), "{stderr}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["--log-level", "loud", "ps"])
+        .output()
+        .expect("failed to execute craterun ps");
+    assert!(!output.status.success(), "an unknown --log-level should be rejected");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .arg("ps")
+        .env_remove("CRATERUN_LOG")
+        .output()
+        .expect("failed to execute craterun ps");
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+/// Verify `check --json`, also spelled `doctor`, reports every host check as
+/// machine-readable JSON.
+#[test]
+fn cli_check_json() {
+    let tmp = tempfile::tempdir().unwrap();
+    for command in ["check", "doctor"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args([command, "--json"])
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun check --json");
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let results: Vec<serde_json::Value> =
+            serde_json::from_str(&stdout).expect("check output should be JSON");
+        let names: Vec<&str> = results.iter().filter_map(|r| r["name"].as_str()).collect();
+        for expected in [
+            "kernel",
+            "cgroup-v2",
+            "cgroup-delegation",
+            "privileges",
+            "overlayfs",
+            "proc-mount",
+            "state-dir",
+        ] {
+            assert!(names.contains(&expected), "missing check {expected}: {names:?}");
+        }
+
+        let any_failed = results.iter().any(|r| r["status"] == "fail");
+        assert_eq!(output.status.success(), !any_failed);
+    }
+}
+
+/// Verify `run --cidfile` refuses to overwrite an existing file.
+#[test]
+fn cli_run_refuses_existing_cidfile() {
+    let tmp = tempfile::tempdir().unwrap();
+    let cidfile = tmp.path().join("cid");
+    std::fs::write(&cidfile, "old").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--rootfs", "/nonexistent", "--cidfile"])
+        .arg(&cidfile)
+        .args(["--", "/bin/sh"])
+        .env("HOME", tmp.path())
+        .output()
+        .expect("failed to execute craterun run");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("already exists"), "got: {stderr}");
+    assert_eq!(std::fs::read_to_string(&cidfile).unwrap(), "old");
+}
+
+/// Verify `--pidfile` refuses a file naming a running process or holding
+/// no PID, and replaces one left behind by a dead process.
+#[test]
+fn cli_run_pidfile_handling() {
+    let tmp = tempfile::tempdir().unwrap();
+    let pidfile = tmp.path().join("pid");
+    let run = || {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--rootfs", "/nonexistent", "--pidfile"])
+            .arg(&pidfile)
+            .args(["--", "/bin/sh"])
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun run")
+    };
+
+    for contents in [format!("{}\n", std::process::id()), "garbage".to_string()] {
+        std::fs::write(&pidfile, &contents).unwrap();
+        let output = run();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("already exists"), "got: {stderr}");
+        assert_eq!(std::fs::read_to_string(&pidfile).unwrap(), contents);
+    }
+
+    // Larger than any PID the kernel hands out.
+    std::fs::write(&pidfile, "4194305\n").unwrap();
+    let output = run();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("replacing stale pidfile"), "got: {stderr}");
+    assert!(!pidfile.exists());
+}
+
+/// Verify `--memory` rejects malformed sizes at parse time.
+#[test]
+fn cli_run_rejects_bad_memory_size() {
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--memory", "64Q", "--rootfs", "/nonexistent", "--", "/bin/sh"])
+        .output()
+        .expect("failed to execute craterun run");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--memory"), "got: {stderr}");
+}
+
+/// Verify `--cpu` and `--cpus` cannot be combined.
+#[test]
+fn cli_run_rejects_cpu_and_cpus_together() {
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--cpus", "0.5", "--cpu", "50000 100000"])
+        .args(["--rootfs", "/nonexistent", "--", "/bin/sh"])
+        .output()
+        .expect("failed to execute craterun run");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("cannot be used with"), "got: {stderr}");
+}
+
+/// Verify `--allow-chroot-fallback` cannot be combined with `--no-pivot`,
+/// which always uses `chroot`.
+#[test]
+fn cli_run_rejects_chroot_fallback_with_no_pivot() {
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--no-pivot", "--allow-chroot-fallback"])
+        .args(["--rootfs", "/nonexistent", "--", "/bin/sh"])
+        .output()
+        .expect("failed to execute craterun run");
+
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("cannot be used with"), "got: {stderr}");
+}
+
+/// Verify `--no-core` and `--core-limit` exclude each other and `--ulimit core`.
+#[test]
+fn cli_run_rejects_conflicting_core_limits() {
+    for args in [
+        ["--no-core", "--core-limit", "1M"].as_slice(),
+        ["--no-core", "--ulimit", "core=1"].as_slice(),
+        ["--core-limit", "1M", "--ulimit", "core=0"].as_slice(),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .arg("run")
+            .args(args)
+            .args(["--rootfs", "/nonexistent", "--", "/bin/sh"])
+            .output()
+            .expect("failed to execute craterun run");
+
+        assert!(!output.status.success(), "{args:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("cannot be used with") || stderr.contains("cannot be combined"),
+            "{args:?}: {stderr}"
+        );
+    }
+}
+
+/// Verify `--cpuset-cpus` rejects malformed lists and CPUs that are not online.
+#[test]
+fn cli_run_validates_cpuset() {
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--cpuset-cpus", "3-1", "--rootfs", "/nonexistent", "--", "/bin/sh"])
+        .output()
+        .expect("failed to execute craterun run");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("reversed"), "got: {stderr}");
+
+    let rootfs = fake_rootfs();
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--dry-run", "--cpuset-cpus", "0,100000", "--rootfs"])
+        .arg(rootfs.path())
+        .args(["--", "/bin/sh"])
+        .output()
+        .expect("failed to execute craterun run --dry-run");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("not online"), "got: {stderr}");
+}
+
+/// Verify `--memory-swappiness` explains that cgroup v2 can only honor 0.
+#[test]
+fn cli_run_memory_swappiness() {
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["run", "--memory-swappiness", "60"])
+        .args(["--rootfs", "/nonexistent", "--", "/bin/sh"])
+        .output()
+        .expect("failed to execute craterun run");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("memory.swap.max=0"), "got: {stderr}");
+}
+
+/// Verify `--storage-size` needs an image rootfs, and `--storage-driver`
+/// needs `--storage-size`.
+#[test]
+fn cli_run_storage_size() {
+    let rootfs = fake_rootfs();
+    let rootfs = rootfs.path().to_str().unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--dry-run", "--rootfs", rootfs])
+            .args(args)
+            .args(["--", "/bin/sh"])
+            .output()
+            .expect("failed to execute craterun run");
+        assert!(!output.status.success(), "{args:?} should be rejected");
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    let stderr = run(&["--storage-size", "1G"]);
+    assert!(stderr.contains("needs a squashfs or EROFS"), "{stderr}");
+    let stderr = run(&["--storage-driver", "tmpfs"]);
+    assert!(stderr.contains("--storage-size"), "{stderr}");
+    let stderr = run(&["--storage-size", "1G", "--storage-driver", "btrfs"]);
+    assert!(stderr.contains("expected 'quota'"), "{stderr}");
+}
+
+/// Verify `--shm-size` takes a size greater than zero.
+#[test]
+fn cli_run_shm_size() {
+    let rootfs = fake_rootfs();
+    for (size, error) in [
+        ("0", "--shm-size must be more than 0"),
+        ("lots", "invalid size"),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", "--dry-run", "--shm-size", size, "--rootfs"])
+            .arg(rootfs.path())
+            .args(["--", "/bin/sh"])
+            .output()
+            .expect("failed to execute craterun run");
+        assert!(
+            !output.status.success(),
+            "--shm-size {size} should be rejected"
+        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(error), "{stderr}");
+    }
+}
+
+/// Verify `update` shares `run`'s limit parsing and requires at least one limit.
+#[test]
+fn cli_update_validates_limits() {
+    for args in [
+        &["update", "0123456789abcdef"][..],
+        &["update", "0123456789abcdef", "--memory", "12Q"],
+        &["update", "0123456789abcdef", "--cpu", "1", "--cpus", "1"],
+        &["update", "0123456789abcdef", "--cpuset-cpus", "3-1"],
+        &["update", "0123456789abcdef", "--pids", "0"],
+        &["update", "0123456789abcdef", "--memory-swappiness", "101"],
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(args)
+            .output()
+            .expect("failed to execute craterun update");
+
+        assert!(!output.status.success(), "{args:?} should be rejected");
+    }
+}
+
+/// Verify `update` waits for the metadata lock and applies its limits to the
+/// metadata saved meanwhile, such as an exit recorded by the monitor.
+#[test]
+fn cli_update_holds_the_metadata_lock() {
+    let tmp = tempfile::tempdir().unwrap();
+    let id = "0b5e000000000001";
+    let dirs = RemoveOnDrop(vec![state_dir(tmp.path()).join(id)]);
+    let dir = &dirs.0[0];
+    std::fs::create_dir_all(dir).unwrap();
+    let meta = |exit_code: i32| {
+        format!(
+            r#"{{"id":"{id}","rootfs":"/tmp/rootfs","cmd":["/bin/true"],"pid":0,
+            "exit_code":{exit_code},"created_at":"2025-01-01T00:00:00Z","status":"stopped",
+            "hostname":"craterun","memory_limit":null,"cpu_limit":null,"pids_limit":null}}"#
+        )
+    };
+    std::fs::write(dir.join("metadata.json"), meta(0)).unwrap();
+
+    let lock = std::fs::File::create(dir.join("metadata.lock")).unwrap();
+    lock.lock().unwrap();
+    let mut update = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["update", id, "--memory", "64M"])
+        .env("HOME", tmp.path())
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .expect("failed to execute craterun update");
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    assert!(update.try_wait().unwrap().is_none(), "update should wait");
+    std::fs::write(dir.join("metadata.json"), meta(7)).unwrap();
+    drop(lock);
+    assert!(update.wait().unwrap().success());
+
+    let saved: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(dir.join("metadata.json")).unwrap()).unwrap();
+    assert_eq!(saved["exit_code"], 7);
+    assert_eq!(saved["memory_limit"], 64 << 20);
+}
+
+/// Verify `logs` rejects conflicting stream selections.
+#[test]
+fn cli_logs_stream_flags_conflict() {
+    for flags in [
+        &["--stdout", "--stderr"][..],
+        &["--combine", "--stdout"],
+        &["--clear", "--combine"],
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["logs", "0123456789abcdef"])
+            .args(flags)
+            .output()
+            .expect("failed to execute craterun logs");
+
+        assert!(!output.status.success(), "{flags:?} should be rejected");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+    }
+}
+
+/// Verify `--json-errors` reports failures as JSON on stderr, before or after
+/// the subcommand.
+#[test]
+fn cli_json_errors() {
+    let tmp = tempfile::tempdir().unwrap();
+    for args in [&["--json-errors", "rm", "deadbeef"][..], &["rm", "deadbeef", "--json-errors"]] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(args)
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun rm");
+
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let error: serde_json::Value =
+            serde_json::from_str(&stderr).expect("error should be JSON");
+        assert!(error["error"].as_str().unwrap().contains("no container found"), "{error}");
+        assert!(error["context"].is_array(), "{error}");
+    }
+}
+
+/// Verify `import --verify` rejects malformed digests, `images` prints its
+/// header and `rmi` rejects unknown images.
+#[test]
+fn cli_import_and_images() {
+    let tmp = tempfile::tempdir().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["import", "/nonexistent.tar.gz", "alpine", "--verify", "sha256:abc"])
+        .env("HOME", tmp.path())
+        .output()
+        .expect("failed to execute craterun import");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("64 hex digits"), "got: {stderr}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .arg("images")
+        .env("HOME", tmp.path())
+        .output()
+        .expect("failed to execute craterun images");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("NAME"), "got: {stdout}");
+    assert!(stdout.contains("CREATED"), "got: {stdout}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+        .args(["rmi", "no-such-image"])
+        .env("HOME", tmp.path())
+        .output()
+        .expect("failed to execute craterun rmi");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("no image named 'no-such-image'"), "got: {stderr}");
+}
+
+/// Verify `run --image` excludes `--rootfs`, `--pull` needs `--image`,
+/// `--pull never` refuses images that are not there and `pull` rejects
+/// malformed references.
+#[test]
+fn cli_run_image_flags() {
+    let tmp = tempfile::tempdir().unwrap();
+    let craterun = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(args)
+            .env("HOME", tmp.path())
+            .output()
+            .expect("failed to execute craterun")
+    };
+    for args in [
+        "--image alpine --rootfs /tmp",
+        "--rootfs /tmp --pull always",
+        "--image alpine --pull sometimes",
+    ] {
+        let args: Vec<&str> = ["run"]
+            .into_iter()
+            .chain(args.split(' '))
+            .chain(["--", "/bin/sh"])
+            .collect();
+        let output = craterun(&args);
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+    }
+
+    let output = craterun(&[
+        "run",
+        "--image",
+        "localhost:1/no/such:image",
+        "--pull",
+        "never",
+        "--dry-run",
+        "--",
+        "/bin/sh",
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--pull never"), "got: {stderr}");
+
+    let output = craterun(&["pull", "Alpine:3.20"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("invalid image reference"), "got: {stderr}");
+}
+
+/// Verify `--hostname` is validated while parsing, before the rootfs is looked
+/// at.
+#[test]
+fn cli_run_rejects_bad_hostname_at_parse_time() {
+    for hostname in ["bad host", "-web", &"a".repeat(65)] {
+        let output = Command::new(env!("CARGO_BIN_EXE_craterun"))
+            .args(["run", &format!("--hostname={hostname}"), "--rootfs", "/nonexistent"])
+            .args(["--", "/bin/sh"])
+            .output()
+            .expect("failed to execute craterun run");
+
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("--hostname"), "got: {stderr}");
+        assert!(!stderr.contains("does not exist"), "got: {stderr}");
+    }
+}
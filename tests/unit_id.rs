@@ -1,7 +1,7 @@
-/// Unit tests for container ID generation.
-///
-/// The core module tests live inline (in core/id.rs), but these external tests
-/// demonstrate that the public API works from outside the crate.
+//! Unit tests for container ID generation.
+//!
+//! The core module tests live inline (in core/id.rs), but these external tests
+//! demonstrate that the public API works from outside the crate.
 
 use std::collections::HashSet;
 